@@ -5,22 +5,43 @@ use crate::auth::user_register::{
     api_auth_user_register, api_auth_user_register_after, api_auth_user_register_after_captcha,
     api_auth_user_register_after_captcha_verify,
 };
+use crate::repos::archive::api_repos_archive;
 use crate::repos::commits::api_repos_commit_list;
 use crate::repos::data::api_repo_data;
+use crate::repos::deploy_key::{
+    api_repos_deploy_key_add, api_repos_deploy_key_delete, api_repos_deploy_key_list,
+};
 use crate::repos::init::{
     api_repo_init, api_repo_init_before, api_repo_init_owner_select, api_repo_init_storage,
 };
+use crate::repos::integrity::{
+    api_repos_integrity, api_repos_integrity_repair, api_repos_integrity_verify,
+};
+use crate::repos::lfs::api_repos_lfs_stats;
+use crate::repos::maintenance::{api_repos_maintenance, api_repos_maintenance_enqueue};
+use crate::repos::partial_clone::api_repos_partial_clone;
+use crate::repos::protection::{
+    api_repos_protection_delete, api_repos_protection_lfs_locks, api_repos_protection_list,
+    api_repos_protection_upsert,
+};
+use crate::repos::quota::api_repos_quota;
 use crate::repos::recommend::api_repos_recommend;
 use crate::repos::refs::{api_repos_refs_delete, api_repos_refs_list};
 use crate::repos::star::{api_repos_star_repo, api_repos_unstar_repo};
+use crate::repos::storage::api_repos_storage;
 use crate::repos::tree::api_repos_tree;
 use crate::repos::watch::{api_repos_unwatch_repo, api_repos_watch_repo};
+use crate::ssh::host_key::api_ssh_host_keys;
 use crate::user::settings::access_key::{
     api_user_setting_access_key_delete, api_user_setting_access_key_insert,
     api_user_setting_access_key_list,
 };
 use crate::user::settings::avatar::api_setting_avatar_upload;
 use crate::user::settings::basic::{api_setting_basic, api_setting_basic_get};
+use crate::user::settings::quota::api_user_setting_quota;
+use crate::user::settings::ssh_ca::{
+    api_user_setting_ssh_ca_delete, api_user_setting_ssh_ca_insert, api_user_setting_ssh_ca_list,
+};
 use crate::user::settings::ssh_key::{
     api_user_setting_ssh_key_delete, api_user_setting_ssh_key_insert, api_user_setting_ssh_key_list,
 };
@@ -38,7 +59,10 @@ use session::config::{PersistentSession, SessionLifecycle, TtlExtensionPolicy};
 use session::{SessionMiddleware, SessionStorage};
 use sha2::{Digest, Sha512};
 use tracing::info;
+use git::service::GitServer;
 use git::transport::http::info::git_refs;
+use git::transport::http::lfs::{lfs_batch, lfs_download, lfs_upload, lfs_verify};
+use git::transport::http::lfs_lock::{lfs_lock_create, lfs_lock_list, lfs_lock_verify, lfs_unlock};
 use git::transport::http::receive_pack::git_receive_pack;
 use git::transport::http::upload_pack::git_upload_pack;
 use crate::guard::git::git_guard;
@@ -74,6 +98,11 @@ impl AppApiService {
             key = [0; 64].to_vec();
         }
         let cfg = self.config.clone();
+        let git = GitServer {
+            db: db.clone(),
+            config: cfg.clone(),
+            redis: redis.clone(),
+        };
         HttpServer::new(move || {
             actix_web::App::new()
                 .app_data(web::Data::new(storage.clone()))
@@ -81,6 +110,7 @@ impl AppApiService {
                 .app_data(web::Data::new(db.clone()))
                 .app_data(web::Data::new(redis.clone()))
                 .app_data(web::Data::new(cfg.clone()))
+                .app_data(web::Data::new(git.clone()))
                 .wrap(
                     SessionMiddleware::builder(storage.clone(), Key::from(&key.clone()))
                         .session_lifecycle(SessionLifecycle::PersistentSession(
@@ -133,6 +163,7 @@ impl AppApiService {
                             .route("/basic", web::get().to(api_setting_basic_get))
                             .route("/basic", web::post().to(api_setting_basic))
                             .route("/avatar", web::post().to(api_setting_avatar_upload))
+                            .route("/quota", web::get().to(api_user_setting_quota))
                             .service(
                                 scope("/ssh-key")
                                     .route("", web::get().to(api_user_setting_ssh_key_list))
@@ -142,6 +173,15 @@ impl AppApiService {
                                         web::delete().to(api_user_setting_ssh_key_delete),
                                     ),
                             )
+                            .service(
+                                scope("/ssh-ca")
+                                    .route("", web::get().to(api_user_setting_ssh_ca_list))
+                                    .route("", web::post().to(api_user_setting_ssh_ca_insert))
+                                    .route(
+                                        "/{uid}",
+                                        web::delete().to(api_user_setting_ssh_ca_delete),
+                                    ),
+                            )
                             .service(
                                 scope("/access-key")
                                     .route("", web::get().to(api_user_setting_access_key_list))
@@ -153,6 +193,7 @@ impl AppApiService {
                             ),
                     ),
                 )
+                .service(scope("/ssh").route("/host-keys", web::get().to(api_ssh_host_keys)))
                 .service(
                     scope("/users").service(
                         scope("/{username}")
@@ -184,6 +225,53 @@ impl AppApiService {
                                             web::delete().to(api_repos_refs_delete),
                                         ),
                                 )
+                                .service(
+                                    scope("/protection")
+                                        .route("", web::get().to(api_repos_protection_list))
+                                        .route("", web::post().to(api_repos_protection_upsert))
+                                        .route(
+                                            "/lfs-locks",
+                                            web::post().to(api_repos_protection_lfs_locks),
+                                        )
+                                        .route(
+                                            "/{uid}",
+                                            web::delete().to(api_repos_protection_delete),
+                                        ),
+                                )
+                                .route("/lfs/stats", web::get().to(api_repos_lfs_stats))
+                                .route(
+                                    "/partial-clone",
+                                    web::post().to(api_repos_partial_clone),
+                                )
+                                .route("/quota", web::get().to(api_repos_quota))
+                                .route("/maintenance", web::get().to(api_repos_maintenance))
+                                .route(
+                                    "/maintenance",
+                                    web::post().to(api_repos_maintenance_enqueue),
+                                )
+                                .service(
+                                    scope("/integrity")
+                                        .route("", web::get().to(api_repos_integrity))
+                                        .route(
+                                            "/verify",
+                                            web::post().to(api_repos_integrity_verify),
+                                        )
+                                        .route(
+                                            "/repair",
+                                            web::post().to(api_repos_integrity_repair),
+                                        ),
+                                )
+                                .route("/storage", web::get().to(api_repos_storage))
+                                .route("/archive/{file:.*}", web::get().to(api_repos_archive))
+                                .service(
+                                    scope("/deploy-keys")
+                                        .route("", web::get().to(api_repos_deploy_key_list))
+                                        .route("", web::post().to(api_repos_deploy_key_add))
+                                        .route(
+                                            "/{uid}",
+                                            web::delete().to(api_repos_deploy_key_delete),
+                                        ),
+                                )
                                 .service(
                                     scope("/commit/{ref_name}")
                                         .route("", web::get().to(api_repos_commit_list)),
@@ -217,6 +305,20 @@ impl AppApiService {
                         .route("/git-upload-pack", web::post().to(git_upload_pack))
                         .route("/git-receive-pack", web::post().to(git_receive_pack))
                         .route("/info/refs", web::get().to(git_refs))
+                        .service(
+                            scope("/info/lfs/objects")
+                                .route("/batch", web::post().to(lfs_batch))
+                                .route("/verify", web::post().to(lfs_verify))
+                                .route("/{oid}", web::get().to(lfs_download))
+                                .route("/{oid}", web::put().to(lfs_upload)),
+                        )
+                        .service(
+                            scope("/info/lfs/locks")
+                                .route("", web::get().to(lfs_lock_list))
+                                .route("", web::post().to(lfs_lock_create))
+                                .route("/verify", web::post().to(lfs_lock_verify))
+                                .route("/{id}/unlock", web::post().to(lfs_unlock)),
+                        )
                 )
         )
         .service(web_ui::web_ui)
@@ -226,6 +328,7 @@ impl AppApiService {
 
 pub mod auth;
 pub mod repos;
+pub mod ssh;
 pub mod user;
 pub mod users;
 pub mod guard;
\ No newline at end of file
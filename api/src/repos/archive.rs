@@ -0,0 +1,36 @@
+use crate::AppStatus;
+use actix_web::{HttpRequest, Responder, web};
+use git::service::GitServer;
+use git::transport::http::archive::git_archive;
+use serde::Deserialize;
+use session::Session;
+
+#[derive(Deserialize)]
+pub struct ArchiveQuery {
+    pub path: Option<String>,
+}
+
+pub async fn api_repos_archive(
+    request: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<ArchiveQuery>,
+    core: AppStatus,
+    git: web::Data<GitServer>,
+    session: Session,
+) -> impl Responder {
+    let (owner, repo, file) = path.into_inner();
+    let user = match core.user_context(session).await {
+        Ok(context) => core.user_context_find_by_uid(context.user_uid).await.ok(),
+        Err(_) => None,
+    };
+    git_archive(
+        &request,
+        &git,
+        &owner,
+        &repo,
+        &file,
+        query.into_inner().path,
+        user,
+    )
+    .await
+}
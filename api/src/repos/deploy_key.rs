@@ -0,0 +1,41 @@
+use crate::AppStatus;
+use actix_web::web::Json;
+use actix_web::{Responder, web};
+use core::repos::deploy_key::DeployKeyParam;
+use error::AppResult;
+use sea_orm::prelude::Uuid;
+use session::Session;
+
+pub async fn api_repos_deploy_key_list(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_deploy_key_list(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_deploy_key_add(
+    path: web::Path<(String, String)>,
+    param: Json<DeployKeyParam>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_deploy_key_add(&namespace, &repo_name, param.into_inner(), session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_deploy_key_delete(
+    path: web::Path<(String, String, Uuid)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name, key_uid) = path.into_inner();
+    core.repo_deploy_key_delete(&namespace, &repo_name, key_uid, session)
+        .await
+        .into_response()
+}
@@ -0,0 +1,37 @@
+use crate::AppStatus;
+use actix_web::{Responder, web};
+use error::AppResult;
+use session::Session;
+
+pub async fn api_repos_integrity(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_integrity(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_integrity_verify(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_integrity_verify(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_integrity_repair(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_integrity_repair(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
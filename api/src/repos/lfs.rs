@@ -0,0 +1,15 @@
+use crate::AppStatus;
+use actix_web::{Responder, web};
+use error::AppResult;
+use session::Session;
+
+pub async fn api_repos_lfs_stats(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_lfs_stats(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
@@ -0,0 +1,29 @@
+use crate::AppStatus;
+use actix_web::web::Json;
+use actix_web::{Responder, web};
+use core::repos::maintenance::MaintenanceParam;
+use error::AppResult;
+use session::Session;
+
+pub async fn api_repos_maintenance(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_maintenance(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_maintenance_enqueue(
+    path: web::Path<(String, String)>,
+    param: Json<MaintenanceParam>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_maintenance_enqueue(&namespace, &repo_name, param.into_inner(), session)
+        .await
+        .into_response()
+}
@@ -1,8 +1,17 @@
+pub mod archive;
 pub mod commits;
 pub mod data;
+pub mod deploy_key;
 pub mod init;
+pub mod integrity;
+pub mod lfs;
+pub mod maintenance;
+pub mod partial_clone;
+pub mod protection;
+pub mod quota;
 pub mod recommend;
 pub mod refs;
 pub mod star;
+pub mod storage;
 pub mod tree;
 pub mod watch;
@@ -0,0 +1,18 @@
+use crate::AppStatus;
+use actix_web::web::Json;
+use actix_web::{Responder, web};
+use core::repos::partial_clone::PartialCloneParam;
+use error::AppResult;
+use session::Session;
+
+pub async fn api_repos_partial_clone(
+    path: web::Path<(String, String)>,
+    param: Json<PartialCloneParam>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_partial_clone(&namespace, &repo_name, param.into_inner(), session)
+        .await
+        .into_response()
+}
@@ -0,0 +1,53 @@
+use crate::AppStatus;
+use actix_web::web::Json;
+use actix_web::{Responder, web};
+use core::repos::protection::{BranchProtectionParam, LfsLockParam};
+use error::AppResult;
+use sea_orm::prelude::Uuid;
+use session::Session;
+
+pub async fn api_repos_protection_list(
+    path: web::Path<(String, String)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_protection_list(&namespace, &repo_name, session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_protection_upsert(
+    path: web::Path<(String, String)>,
+    param: Json<BranchProtectionParam>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_protection_upsert(&namespace, &repo_name, param.into_inner(), session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_protection_delete(
+    path: web::Path<(String, String, Uuid)>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name, rule_uid) = path.into_inner();
+    core.repo_protection_delete(&namespace, &repo_name, rule_uid, session)
+        .await
+        .into_response()
+}
+
+pub async fn api_repos_protection_lfs_locks(
+    path: web::Path<(String, String)>,
+    param: Json<LfsLockParam>,
+    core: AppStatus,
+    session: Session,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    core.repo_protection_lfs_locks(&namespace, &repo_name, param.into_inner(), session)
+        .await
+        .into_response()
+}
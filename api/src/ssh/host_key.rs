@@ -0,0 +1,7 @@
+use crate::AppStatus;
+use actix_web::Responder;
+use error::AppResult;
+
+pub async fn api_ssh_host_keys(core: AppStatus) -> impl Responder {
+    core.ssh_host_keys().await.into_response()
+}
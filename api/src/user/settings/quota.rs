@@ -0,0 +1,8 @@
+use crate::AppStatus;
+use actix_web::Responder;
+use error::AppResult;
+use session::Session;
+
+pub async fn api_user_setting_quota(core: AppStatus, session: Session) -> impl Responder {
+    core.setting_quota(session).await.into_response()
+}
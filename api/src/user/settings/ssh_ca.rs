@@ -0,0 +1,31 @@
+use crate::AppStatus;
+use actix_web::Responder;
+use actix_web::web::{Json, Path};
+use core::settings::ssh_ca::SettingSshCaInsertParam;
+use error::AppResult;
+use session::Session;
+use uuid::Uuid;
+
+pub async fn api_user_setting_ssh_ca_insert(
+    core: AppStatus,
+    session: Session,
+    param: Json<SettingSshCaInsertParam>,
+) -> impl Responder {
+    core.setting_ssh_ca_insert(session, param.into_inner())
+        .await
+        .into_response()
+}
+
+pub async fn api_user_setting_ssh_ca_list(core: AppStatus, session: Session) -> impl Responder {
+    core.setting_ssh_ca_list(session).await.into_response()
+}
+
+pub async fn api_user_setting_ssh_ca_delete(
+    core: AppStatus,
+    session: Session,
+    uid: Path<Uuid>,
+) -> impl Responder {
+    core.setting_ssh_ca_delete(session, uid.into_inner())
+        .await
+        .into_response()
+}
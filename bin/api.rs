@@ -26,6 +26,7 @@ pub async fn api() -> Result<(), AppError> {
         redis: redis.clone(),
     };
     core.init_service().await?;
+    tokio::spawn(core.clone().integrity_worker());
     let session = SessionStorage::new(database.clone(), redis.clone());
     let api = AppApiService {
         core,
@@ -38,6 +39,9 @@ pub async fn api() -> Result<(), AppError> {
         config,
         redis,
     };
+    git.check_lfs_storages()?;
+    tokio::spawn(git.clone().maintenance_worker());
+    tokio::spawn(git.clone().stats_worker());
     let git = git::transport::ssh::SSHHandle::new(git);
     tokio::select! {
         r = git.run_ssh() => {
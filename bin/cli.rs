@@ -25,9 +25,20 @@ pub enum Commands {
     Log,
     #[command(about = "Migrate database")]
     Migration,
+    #[command(about = "Serve repositories as a git storage node")]
+    StorageNode,
+    #[command(about = "Move a repository to another storage while it stays online")]
+    MigrateRepo {
+        #[arg(help = "The repository, as namespace/name")]
+        repo: String,
+        #[arg(help = "Name of the storage to move it to")]
+        storage: String,
+    },
 }
 
+pub mod migrate_repo;
 pub mod migration;
+pub mod storage_node;
 
 #[derive(Subcommand)]
 pub enum ConfigCommand {
@@ -64,6 +75,10 @@ pub async fn cli() -> Result<(), AppError> {
         Commands::Stop => {}
         Commands::Log => {}
         Commands::Migration => migration::migration().await,
+        Commands::StorageNode => storage_node::storage_node().await?,
+        Commands::MigrateRepo { repo, storage } => {
+            migrate_repo::migrate_repo(&repo, &storage).await?
+        }
     }
     Ok(())
 }
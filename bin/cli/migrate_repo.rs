@@ -0,0 +1,59 @@
+use config::AppConfig;
+use error::AppError;
+use git::service::GitServer;
+use git::service::storage_migration::MIGRATION_SUCCEEDED;
+use std::io;
+use std::time::Duration;
+
+/// Runs the migration in this process and prints its progress every second.
+pub async fn migrate_repo(repo: &str, storage: &str) -> Result<(), AppError> {
+    tracing_subscriber::fmt().init();
+    let Some((namespace, repo_name)) = repo.split_once('/') else {
+        return Err(AppError::from(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "expected the repository as namespace/name",
+        )));
+    };
+    let config = AppConfig::init();
+    let git = GitServer {
+        db: config.database.conn().await,
+        redis: config.redis.conn().await,
+        config,
+    };
+    let repo = git.find_repo(namespace, repo_name).await?;
+    let job = git.start_storage_migration(repo.uid, storage).await?;
+    println!(
+        "Moving {}/{} from {} to {}",
+        namespace, repo_name, job.source, job.target
+    );
+    let uid = job.uid;
+    let run = tokio::spawn({
+        let git = git.clone();
+        async move { git.run_storage_migration(job).await }
+    });
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    while !run.is_finished() {
+        interval.tick().await;
+        if let Ok(migration) = git.find_storage_migration(uid).await {
+            println!(
+                "{}: {} of about {} bytes",
+                migration.phase, migration.bytes_copied, migration.bytes_total
+            );
+        }
+    }
+    let migration = run.await.map_err(io::Error::from)??;
+    if migration.status != MIGRATION_SUCCEEDED {
+        return Err(AppError::from(io::Error::other(format!(
+            "migration failed: {}",
+            migration.error.unwrap_or_default()
+        ))));
+    }
+    println!(
+        "{}/{} is on storage {}",
+        namespace, repo_name, migration.target
+    );
+    if let Some(warning) = migration.error {
+        println!("warning: {}", warning);
+    }
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use config::AppConfig;
+use error::AppError;
+use git::storage::node::StorageNode;
+
+pub async fn storage_node() -> Result<(), AppError> {
+    tracing_subscriber::fmt().init();
+    let config = AppConfig::init();
+    let node = &config.git.node;
+    StorageNode::new(node.path.clone(), config.git.clone())
+        .serve(&node.listen)
+        .await?;
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -7,8 +8,439 @@ pub struct AppGitConfig {
     pub storage: Vec<AppGitStorage>,
     #[serde(rename = "default")]
     pub default: AppGitStorage,
+    #[serde(rename = "protocol_v2", default = "default_protocol_v2")]
+    pub protocol_v2: bool,
+    #[serde(rename = "backend", default = "default_backend")]
+    pub backend: GitPackBackend,
+    #[serde(rename = "lock", default)]
+    pub lock: AppGitLock,
+    #[serde(rename = "lfs", default)]
+    pub lfs: AppGitLfs,
+    #[serde(rename = "limits", default)]
+    pub limits: AppGitLimits,
+    #[serde(rename = "pack_cache", default)]
+    pub pack_cache: AppGitPackCache,
+    #[serde(rename = "partial_clone", default)]
+    pub partial_clone: AppGitPartialClone,
+    #[serde(rename = "archive", default)]
+    pub archive: AppGitArchive,
+    #[serde(rename = "quota", default)]
+    pub quota: AppGitQuota,
+    #[serde(rename = "maintenance", default)]
+    pub maintenance: AppGitMaintenance,
+    #[serde(rename = "integrity", default)]
+    pub integrity: AppGitIntegrity,
+    #[serde(rename = "node", default)]
+    pub node: AppGitStorageNode,
+    #[serde(rename = "placement", default)]
+    pub placement: AppGitPlacement,
 }
 
+fn default_protocol_v2() -> bool {
+    true
+}
+
+fn default_backend() -> GitPackBackend {
+    GitPackBackend::Subprocess
+}
+
+/// Smart protocol implementation, `native` serves upload-pack and receive-pack in-process
+/// and keeps forking git for everything else.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum GitPackBackend {
+    #[serde(rename = "subprocess")]
+    Subprocess,
+    #[serde(rename = "native")]
+    Native,
+}
+
+/// Repository lock settings, in seconds. The lease is renewed while the lock is held so
+/// it only runs out when the holder died.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub struct AppGitLock {
+    #[serde(rename = "timeout", default = "default_lock_timeout")]
+    pub timeout: u64,
+    #[serde(rename = "lease", default = "default_lock_lease")]
+    pub lease: u64,
+}
+
+fn default_lock_timeout() -> u64 {
+    30
+}
+
+fn default_lock_lease() -> u64 {
+    30
+}
+
+impl Default for AppGitLock {
+    fn default() -> Self {
+        Self {
+            timeout: default_lock_timeout(),
+            lease: default_lock_lease(),
+        }
+    }
+}
+
+/// Caps on pack sessions (clone, fetch, push and ref advertisements) running at once over
+/// HTTP and SSH together, `0` for no cap. Sessions over a cap wait up to `queue_timeout`
+/// seconds for a slot. Anonymous users are capped per IP address instead of per user and
+/// may start `anonymous_per_minute` clones, fetches or archive downloads a minute from one
+/// address. The address is that of the peer, `X-Forwarded-For` is only believed from the
+/// proxies listed in `trusted_proxies`.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitLimits {
+    #[serde(rename = "global", default = "default_limit_global")]
+    pub global: usize,
+    #[serde(rename = "per_user", default = "default_limit_per_user")]
+    pub per_user: usize,
+    #[serde(rename = "per_repo", default = "default_limit_per_repo")]
+    pub per_repo: usize,
+    #[serde(rename = "queue_timeout", default = "default_limit_queue_timeout")]
+    pub queue_timeout: u64,
+    #[serde(rename = "anonymous_per_minute", default = "default_limit_anonymous")]
+    pub anonymous_per_minute: u32,
+    #[serde(rename = "trusted_proxies", default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+fn default_limit_global() -> usize {
+    64
+}
+
+fn default_limit_per_user() -> usize {
+    8
+}
+
+fn default_limit_per_repo() -> usize {
+    16
+}
+
+fn default_limit_queue_timeout() -> u64 {
+    30
+}
+
+fn default_limit_anonymous() -> u32 {
+    60
+}
+
+impl Default for AppGitLimits {
+    fn default() -> Self {
+        Self {
+            global: default_limit_global(),
+            per_user: default_limit_per_user(),
+            per_repo: default_limit_per_repo(),
+            queue_timeout: default_limit_queue_timeout(),
+            anonymous_per_minute: default_limit_anonymous(),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+/// Upload-pack responses kept on local disk under `path`, keyed by the repository refs and
+/// the negotiation request, so clones of a hot repository skip pack generation. Entries are
+/// evicted least recently used first once they take more than `max_size` bytes. Serves
+/// HTTP fetches and protocol v2 fetches over SSH; SSH sessions on protocol v0 negotiate
+/// statefully and bypass it.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitPackCache {
+    #[serde(rename = "enabled", default)]
+    pub enabled: bool,
+    #[serde(rename = "path", default = "default_pack_cache_path")]
+    pub path: PathBuf,
+    #[serde(rename = "max_size", default = "default_pack_cache_max_size")]
+    pub max_size: u64,
+}
+
+fn default_pack_cache_path() -> PathBuf {
+    PathBuf::from("./data/cache/pack-objects")
+}
+
+fn default_pack_cache_max_size() -> u64 {
+    10 * 1024 * 1024 * 1024
+}
+
+impl Default for AppGitPackCache {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_pack_cache_path(),
+            max_size: default_pack_cache_max_size(),
+        }
+    }
+}
+
+/// Partial clone filters upload-pack accepts for repositories that turn partial clone on.
+/// `filters` lists the allowed kinds out of `blob:none`, `blob:limit`, `tree`, `sparse:oid`,
+/// `object:type` and `combine`; `tree_max_depth` caps the depth of `tree:<depth>`. The native
+/// backend does not filter, such repositories are served by git upload-pack.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitPartialClone {
+    #[serde(rename = "enabled", default = "default_partial_clone_enabled")]
+    pub enabled: bool,
+    #[serde(rename = "filters", default = "default_partial_clone_filters")]
+    pub filters: Vec<String>,
+    #[serde(rename = "tree_max_depth", default)]
+    pub tree_max_depth: Option<u64>,
+}
+
+fn default_partial_clone_enabled() -> bool {
+    true
+}
+
+fn default_partial_clone_filters() -> Vec<String> {
+    ["blob:none", "blob:limit", "tree", "sparse:oid"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect()
+}
+
+impl Default for AppGitPartialClone {
+    fn default() -> Self {
+        Self {
+            enabled: default_partial_clone_enabled(),
+            filters: default_partial_clone_filters(),
+            tree_max_depth: None,
+        }
+    }
+}
+
+/// Snapshot downloads. Generated archives are kept under `path` by the tree they were made
+/// of, evicted least recently used first beyond `max_size` bytes, unless `cache` is off.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitArchive {
+    #[serde(rename = "cache", default = "default_archive_cache")]
+    pub cache: bool,
+    #[serde(rename = "path", default = "default_archive_path")]
+    pub path: PathBuf,
+    #[serde(rename = "max_size", default = "default_archive_max_size")]
+    pub max_size: u64,
+}
+
+fn default_archive_cache() -> bool {
+    true
+}
+
+fn default_archive_path() -> PathBuf {
+    PathBuf::from("./data/cache/archives")
+}
+
+fn default_archive_max_size() -> u64 {
+    2 * 1024 * 1024 * 1024
+}
+
+impl Default for AppGitArchive {
+    fn default() -> Self {
+        Self {
+            cache: default_archive_cache(),
+            path: default_archive_path(),
+            max_size: default_archive_max_size(),
+        }
+    }
+}
+
+/// Storage quotas in bytes, git objects and LFS objects together, `0` for no quota. A
+/// repository may take `per_repo`, all repositories of a user `per_user`. Pushes are turned
+/// down once a quota is used up and packs larger than what is left are refused.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, Eq, PartialEq)]
+pub struct AppGitQuota {
+    #[serde(rename = "per_user", default)]
+    pub per_user: u64,
+    #[serde(rename = "per_repo", default)]
+    pub per_repo: u64,
+}
+
+/// Repository housekeeping, checked for every `interval` seconds. Repositories pushed to
+/// `push_threshold` times since their last maintenance get an incremental repack, those
+/// pushed to and last maintained more than `max_age` seconds ago a full gc. A job may run
+/// for `timeout` seconds; unreachable objects are pruned once older than `prune_expire`, in
+/// the format of `git gc --prune`.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitMaintenance {
+    #[serde(rename = "enabled", default = "default_maintenance_enabled")]
+    pub enabled: bool,
+    #[serde(rename = "interval", default = "default_maintenance_interval")]
+    pub interval: u64,
+    #[serde(rename = "push_threshold", default = "default_maintenance_pushes")]
+    pub push_threshold: u32,
+    #[serde(rename = "max_age", default = "default_maintenance_max_age")]
+    pub max_age: u64,
+    #[serde(rename = "timeout", default = "default_maintenance_timeout")]
+    pub timeout: u64,
+    #[serde(rename = "prune_expire", default = "default_maintenance_prune_expire")]
+    pub prune_expire: String,
+}
+
+fn default_maintenance_enabled() -> bool {
+    true
+}
+
+fn default_maintenance_interval() -> u64 {
+    300
+}
+
+fn default_maintenance_pushes() -> u32 {
+    50
+}
+
+fn default_maintenance_max_age() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_maintenance_timeout() -> u64 {
+    3600
+}
+
+fn default_maintenance_prune_expire() -> String {
+    "2.weeks.ago".to_string()
+}
+
+impl Default for AppGitMaintenance {
+    fn default() -> Self {
+        Self {
+            enabled: default_maintenance_enabled(),
+            interval: default_maintenance_interval(),
+            push_threshold: default_maintenance_pushes(),
+            max_age: default_maintenance_max_age(),
+            timeout: default_maintenance_timeout(),
+            prune_expire: default_maintenance_prune_expire(),
+        }
+    }
+}
+
+/// Integrity verification, every repository on a configured storage is checked once per
+/// `interval` seconds, `git fsck` may take `timeout` seconds. Repositories found damaged are
+/// reported by email to the `alert` addresses.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitIntegrity {
+    #[serde(rename = "enabled", default = "default_integrity_enabled")]
+    pub enabled: bool,
+    #[serde(rename = "interval", default = "default_integrity_interval")]
+    pub interval: u64,
+    #[serde(rename = "timeout", default = "default_integrity_timeout")]
+    pub timeout: u64,
+    #[serde(rename = "alert", default)]
+    pub alert: Vec<String>,
+}
+
+fn default_integrity_enabled() -> bool {
+    true
+}
+
+fn default_integrity_interval() -> u64 {
+    24 * 3600
+}
+
+fn default_integrity_timeout() -> u64 {
+    3600
+}
+
+impl Default for AppGitIntegrity {
+    fn default() -> Self {
+        Self {
+            enabled: default_integrity_enabled(),
+            interval: default_integrity_interval(),
+            timeout: default_integrity_timeout(),
+            alert: vec![],
+        }
+    }
+}
+
+/// Settings of `jzfs storage-node`, serving the repositories under `path` to the frontends
+/// on `listen`, `host:port` or `unix:/path/to/socket`. Frontends present `secret`, the
+/// `secret` of their storage entries, and the node does not start without one. It is sent
+/// in the clear, the node still belongs on a private network or a socket only the
+/// frontends can reach.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitStorageNode {
+    #[serde(rename = "listen", default = "default_node_listen")]
+    pub listen: String,
+    #[serde(rename = "path", default = "default_node_path")]
+    pub path: PathBuf,
+    #[serde(rename = "secret", default)]
+    pub secret: String,
+}
+
+fn default_node_listen() -> String {
+    "127.0.0.1:7170".to_string()
+}
+
+fn default_node_path() -> PathBuf {
+    PathBuf::from("./data/repo")
+}
+
+impl Default for AppGitStorageNode {
+    fn default() -> Self {
+        Self {
+            listen: default_node_listen(),
+            path: default_node_path(),
+            secret: String::new(),
+        }
+    }
+}
+
+/// Which storage a new repository goes to. Repositories of an owner in `pins` always go to
+/// the storage pinned for it, the rest are placed by `policy` among the storages with a
+/// `weight` above zero.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, Eq, PartialEq)]
+pub struct AppGitPlacement {
+    #[serde(rename = "policy", default)]
+    pub policy: PlacementPolicy,
+    #[serde(rename = "pins", default)]
+    pub pins: Vec<AppGitPin>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, Eq, PartialEq)]
+pub enum PlacementPolicy {
+    /// The storage holding the fewest bytes of repositories.
+    #[default]
+    #[serde(rename = "least-used")]
+    LeastUsed,
+    /// Spreads repositories over the storages in proportion to their weight.
+    #[serde(rename = "weighted")]
+    Weighted,
+}
+
+/// New repositories of the user or team `owner` go to `storage`.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitPin {
+    #[serde(rename = "owner")]
+    pub owner: String,
+    #[serde(rename = "storage")]
+    pub storage: String,
+}
+
+/// LFS objects of at least `chunk_threshold` bytes are stored as content-defined chunks
+/// shared by every object and repository on the storage, `0` keeps all objects whole.
+/// `http_url` is where clients reach the HTTP server, e.g. `https://git.example.com`, handed
+/// to SSH clients by `git-lfs-authenticate`; empty means the api host and port.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct AppGitLfs {
+    #[serde(rename = "chunk_threshold", default = "default_chunk_threshold")]
+    pub chunk_threshold: u64,
+    #[serde(rename = "http_url", default)]
+    pub http_url: String,
+}
+
+fn default_chunk_threshold() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl Default for AppGitLfs {
+    fn default() -> Self {
+        Self {
+            chunk_threshold: default_chunk_threshold(),
+            http_url: String::new(),
+        }
+    }
+}
+
+/// A `remote` storage keeps its repositories on the storage node listening at `address`,
+/// `host:port` or `unix:/path/to/socket`, and `path` is the repository root on that node.
+/// `secret` is the one the node is configured with. LFS objects are not served by the node,
+/// they live under `lfs_path` on this host, `<path>/lfs` when it is unset. A remote storage
+/// must set it, to storage every frontend shares, as any frontend may be asked for an
+/// object another one took the upload of. `weight` is the share of new repositories the
+/// storage takes, `0` for none, e.g. while it is drained.
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct AppGitStorage {
     #[serde(rename = "name")]
@@ -17,6 +449,18 @@ pub struct AppGitStorage {
     pub path: PathBuf,
     #[serde(rename = "type")]
     pub storage_type: Option<GitStorageType>,
+    #[serde(rename = "address", default)]
+    pub address: Option<String>,
+    #[serde(rename = "secret", default)]
+    pub secret: String,
+    #[serde(rename = "lfs_path", default)]
+    pub lfs_path: Option<PathBuf>,
+    #[serde(rename = "weight", default = "default_storage_weight")]
+    pub weight: u32,
+}
+
+fn default_storage_weight() -> u32 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -38,7 +482,24 @@ impl Default for AppGitConfig {
                 name: "default".to_string(),
                 path: PathBuf::from("./data/repo"),
                 storage_type: Some(GitStorageType::Local),
+                address: None,
+                secret: String::new(),
+                lfs_path: None,
+                weight: default_storage_weight(),
             },
+            protocol_v2: default_protocol_v2(),
+            backend: default_backend(),
+            lock: AppGitLock::default(),
+            lfs: AppGitLfs::default(),
+            limits: AppGitLimits::default(),
+            pack_cache: AppGitPackCache::default(),
+            partial_clone: AppGitPartialClone::default(),
+            archive: AppGitArchive::default(),
+            quota: AppGitQuota::default(),
+            maintenance: AppGitMaintenance::default(),
+            integrity: AppGitIntegrity::default(),
+            node: AppGitStorageNode::default(),
+            placement: AppGitPlacement::default(),
         }
     }
 }
@@ -1,11 +1,36 @@
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
+/// `host_keys` are OpenSSH private key files, offered to clients in order; files that do
+/// not exist are generated on start, the algorithm taken from the file name (`ed25519`,
+/// `ecdsa` or `rsa`). To rotate a key, list the new file after the old one so its
+/// fingerprint can be published, then move it to the front. `ed25519_hex` is the older
+/// inline key and is offered before all files when set.
+///
+/// `trusted_user_ca_keys` are OpenSSH public keys of CAs trusted site-wide: a user
+/// certificate they signed signs in as the user its principal names. Users can add CAs
+/// for their own account on top. `revoked_keys` is an OpenSSH KRL file, read on every
+/// sign-in, for revoking certificates and keys.
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct AppSshConfig {
     pub enabled: bool,
     pub host: String,
     pub port: u32,
+    #[serde(default)]
     pub ed25519_hex: String,
+    #[serde(default = "default_host_keys")]
+    pub host_keys: Vec<PathBuf>,
+    #[serde(default)]
+    pub trusted_user_ca_keys: Vec<String>,
+    #[serde(default)]
+    pub revoked_keys: Option<PathBuf>,
+}
+
+fn default_host_keys() -> Vec<PathBuf> {
+    ["ed25519", "ecdsa", "rsa"]
+        .iter()
+        .map(|x| PathBuf::from(format!("./data/ssh/ssh_host_{}_key", x)))
+        .collect()
 }
 
 impl Default for AppSshConfig {
@@ -14,7 +39,10 @@ impl Default for AppSshConfig {
             enabled: false,
             host: "0.0.0.0".to_string(),
             port: 30322,
-            ed25519_hex: "...".to_string(),
+            ed25519_hex: String::new(),
+            host_keys: default_host_keys(),
+            trusted_user_ca_keys: vec![],
+            revoked_keys: None,
         }
     }
 }
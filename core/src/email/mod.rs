@@ -5,3 +5,4 @@ pub mod captcha;
 pub const CAPTCHA_KET: &str = "captcha";
 pub const CAPTCHA_TEMPLATE: &str = include_str!("./template/captcha.html");
 pub const ALLOW_NEXT: &str = "allow_next";
+pub const INTEGRITY_ALERT_TEMPLATE: &str = include_str!("./template/integrity_alert.html");
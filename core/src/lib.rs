@@ -3,6 +3,7 @@ use bb8::Pool;
 use bb8_redis::RedisConnectionManager;
 use config::AppConfig;
 use error::AppError;
+use git::service::GitServer;
 use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
 
@@ -23,6 +24,7 @@ pub mod pull_requests;
 pub mod reactions;
 pub mod repos;
 pub mod settings;
+pub mod ssh;
 pub mod users;
 pub mod wikis;
 
@@ -31,6 +33,13 @@ impl AppCore {
         EmailThread::init(self.config.email.clone()).await;
         Ok(())
     }
+    pub(crate) fn git_server(&self) -> GitServer {
+        GitServer {
+            db: self.db.clone(),
+            config: self.config.clone(),
+            redis: self.redis.clone(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -3,6 +3,7 @@ use anyhow::anyhow;
 use database::entity::{git_commit, git_refs, user_repo};
 use error::AppError;
 use git::GitContext;
+use git::service::protection::BranchProtection;
 use sea_orm::EntityTrait;
 use sea_orm::QueryFilter;
 use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, IntoActiveModel, TransactionTrait};
@@ -63,12 +64,18 @@ impl AppCore {
             .all(&self.db)
             .await
         {
-            if relate.len() <= 0 {
+            if relate.is_empty() {
                 return Err(AppError::from(anyhow!("permission denied")));
             }
         } else {
             return Err(AppError::from(anyhow!("permission denied")));
         }
+        let protection = BranchProtection::load(&self.db, repo.uid).await?;
+        protection
+            .check_delete(branch_name)
+            .and_then(|_| protection.check_pusher(branch_name, Some(user.user_uid)))
+            .map_err(|e| AppError::from(anyhow!(e)))?;
+        let _lock = self.git_server().lock_repo_on(&repo).await?;
         let txn = self.db.begin().await?;
         let branch = git_refs::Entity::find()
             .filter(
@@ -94,7 +101,8 @@ impl AppCore {
             commit_active.delete(&txn).await?;
         }
         let git = GitContext::try_from((repo, self.config.git.clone()))?;
-        git.refs_delete(branch_name)?;
+        let name = branch_name.to_string();
+        git.blocking(move |git| git.refs_delete(&name)).await?;
         txn.commit().await?;
         Ok(())
     }
@@ -0,0 +1,110 @@
+use crate::AppCore;
+use crate::settings::sshkey::ssh_key_content;
+use anyhow::anyhow;
+use database::entity::{deploy_keys, ssh_keys};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use session::Session;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DeployKeyParam {
+    pub title: String,
+    pub content: String,
+    /// Read-only keys may clone and fetch, the others may also push.
+    #[serde(default = "default_read_only")]
+    pub read_only: bool,
+}
+
+fn default_read_only() -> bool {
+    true
+}
+
+impl AppCore {
+    pub async fn repo_deploy_key_list(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<Vec<deploy_keys::Model>, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        Ok(deploy_keys::Entity::find()
+            .filter(deploy_keys::Column::RepoUid.eq(repo.uid))
+            .order_by_desc(deploy_keys::Column::CreatedAt)
+            .all(&self.db)
+            .await?)
+    }
+    /// Adds a deploy key to the repository. A key already registered to a user cannot be a
+    /// deploy key too, the SSH server would not know which one is meant.
+    pub async fn repo_deploy_key_add(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        param: DeployKeyParam,
+        session: Session,
+    ) -> Result<deploy_keys::Model, AppError> {
+        let user = self.user_context(session.clone()).await?;
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        if param.title.trim().is_empty() {
+            return Err(AppError::from(anyhow!("Deploy key title is required")));
+        }
+        let (content, fingerprint) = ssh_key_content(&param.content)?;
+        if ssh_keys::Entity::find()
+            .filter(ssh_keys::Column::Fingerprint.eq(fingerprint.as_str()))
+            .one(&self.db)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::from(anyhow!(
+                "SSH key is already in use by a user"
+            )));
+        }
+        if deploy_keys::Entity::find()
+            .filter(deploy_keys::Column::RepoUid.eq(repo.uid))
+            .filter(deploy_keys::Column::Fingerprint.eq(fingerprint.as_str()))
+            .one(&self.db)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::from(anyhow!("Deploy key already exists")));
+        }
+        let active = deploy_keys::ActiveModel {
+            uid: Set(Uuid::now_v7()),
+            repo_uid: Set(repo.uid),
+            title: Set(param.title.trim().to_string()),
+            fingerprint: Set(fingerprint),
+            content: Set(content),
+            read_only: Set(param.read_only),
+            created_by: Set(user.user_uid),
+            created_at: Set(Utc::now().naive_utc()),
+            last_used_at: Set(None),
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+    pub async fn repo_deploy_key_delete(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        key_uid: Uuid,
+        session: Session,
+    ) -> Result<(), AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let result = deploy_keys::Entity::delete_many()
+            .filter(deploy_keys::Column::RepoUid.eq(repo.uid))
+            .filter(deploy_keys::Column::Uid.eq(key_uid))
+            .exec(&self.db)
+            .await?;
+        if result.rows_affected == 0 {
+            return Err(AppError::from(anyhow!("Deploy key not found")));
+        }
+        Ok(())
+    }
+}
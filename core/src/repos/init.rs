@@ -87,6 +87,7 @@ impl AppCore {
         let select = self.repo_init_select_owner(session).await?;
         let txn = self.db.begin().await?;
         if let Some(owner) = select.iter().find(|x| x.uid == param.owner_uid) {
+            let storage = self.git_server().place_repo(&owner.username).await?;
             let repo_uid = Uuid::now_v7();
             let repo = git_repo::ActiveModel {
                 uid: Set(repo_uid),
@@ -96,7 +97,13 @@ impl AppCore {
                 is_private: Set(param.repo_is_private),
                 created_at: Set(Utc::now().naive_utc()),
                 updated_at: Set(Utc::now().naive_utc()),
-                storage: Set("default".to_string()),
+                storage: Set(storage),
+                lfs_lock_enforced: Set(false),
+                partial_clone: Set(false),
+                size: Set(0),
+                pushes_since_maintenance: Set(0),
+                maintained_at: Set(None),
+                verified_at: Set(None),
                 description: Set(if param.repo_description.is_empty() {
                     None
                 } else {
@@ -125,8 +132,12 @@ impl AppCore {
                 // TODO
             }
             let git = GitContext::try_from((model, self.config.git.clone()))?;
-            git.init()?;
-            git.refs_exchange_head(&format!("refs/heads/{}", param.repo_default_branch))?;
+            let head = format!("refs/heads/{}", param.repo_default_branch);
+            git.blocking(move |git| {
+                git.init()?;
+                git.refs_exchange_head(&head)
+            })
+            .await?;
         };
         txn.commit().await?;
         Ok(())
@@ -0,0 +1,134 @@
+use crate::AppCore;
+use crate::email::INTEGRITY_ALERT_TEMPLATE;
+use crate::email::email_thread::{EmailTask, EmailThread};
+use database::entity::{git_repo, repo_integrity_reports};
+use error::AppError;
+use git::service::integrity::{
+    IntegrityFinding, IntegrityStatus, TRIGGER_MANUAL, TRIGGER_SCHEDULE,
+};
+use lettre::Address;
+use lettre::message::Mailbox;
+use log::{error, warn};
+use session::Session;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How often the worker looks for repositories due for verification.
+const INTEGRITY_POLL: Duration = Duration::from_secs(60);
+
+impl AppCore {
+    pub async fn repo_integrity(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<IntegrityStatus, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        IntegrityStatus::load(&self.db, &repo).await
+    }
+    pub async fn repo_integrity_verify(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<repo_integrity_reports::Model, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        self.verify_repo(&repo, TRIGGER_MANUAL).await
+    }
+    /// Rebuilds the database index of the repository from disk and verifies it again.
+    pub async fn repo_integrity_repair(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<repo_integrity_reports::Model, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let report = self.git_server().repair_repo(&repo).await?;
+        self.integrity_alert(&repo, &report).await;
+        Ok(report)
+    }
+    /// Verifies every repository on a configured storage once per
+    /// `git.integrity.interval` seconds. Returns at once when verification is off.
+    pub async fn integrity_worker(self) {
+        if !self.config.git.integrity.enabled {
+            return;
+        }
+        let git = self.git_server();
+        let mut interval = tokio::time::interval(INTEGRITY_POLL);
+        loop {
+            interval.tick().await;
+            loop {
+                match git.claim_verification().await {
+                    Ok(Some(repo)) => {
+                        if let Err(e) = self.verify_repo(&repo, TRIGGER_SCHEDULE).await {
+                            error!("verification of {} failed: {}", repo.uid, e.msg);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("verification claim failed: {}", e.msg);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    async fn verify_repo(
+        &self,
+        repo: &git_repo::Model,
+        trigger: &str,
+    ) -> Result<repo_integrity_reports::Model, AppError> {
+        let report = self.git_server().verify_repo(repo, trigger).await?;
+        self.integrity_alert(repo, &report).await;
+        Ok(report)
+    }
+    /// Mails the findings of a failed verification to `git.integrity.alert`.
+    async fn integrity_alert(
+        &self,
+        repo: &git_repo::Model,
+        report: &repo_integrity_reports::Model,
+    ) {
+        if report.healthy {
+            return;
+        }
+        let name = format!("{}/{}", repo.namespace, repo.repo_name);
+        error!("{} failed verification, report {}", name, report.uid);
+        let findings = serde_json::from_value::<Vec<IntegrityFinding>>(report.findings.clone())
+            .unwrap_or_default()
+            .iter()
+            .map(|x| format!("<li>{}: {}</li>", x.kind.name(), escape_html(&x.detail)))
+            .collect::<String>();
+        let content = INTEGRITY_ALERT_TEMPLATE
+            .replace("{{repo}}", &escape_html(&name))
+            .replace("{{storage}}", &escape_html(&repo.storage))
+            .replace("{{report}}", &report.uid.to_string())
+            .replace("{{findings}}", &findings);
+        for address in &self.config.git.integrity.alert {
+            let Ok(address) = Address::from_str(address) else {
+                warn!("invalid integrity alert address {}", address);
+                continue;
+            };
+            let task = EmailTask {
+                target: Mailbox::new(None, address),
+                content: content.clone(),
+                subject: format!("GitDataAI | {} failed verification", name),
+            };
+            if let Err(e) = EmailThread::sender(task).await {
+                error!("integrity alert not sent: {}", e.msg);
+            }
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
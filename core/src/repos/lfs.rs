@@ -0,0 +1,19 @@
+use crate::AppCore;
+use error::AppError;
+use git::lfs::LfsDedupStats;
+use session::Session;
+
+impl AppCore {
+    /// Storage savings of the repository's LFS objects, visible to its owner and members.
+    pub async fn repo_lfs_stats(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<LfsDedupStats, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        LfsDedupStats::load(&self.db, repo.uid).await
+    }
+}
@@ -0,0 +1,44 @@
+use crate::AppCore;
+use database::entity::repo_maintenance_jobs;
+use error::AppError;
+use git::service::maintenance::{
+    MaintenanceStatus, MaintenanceTask, TRIGGER_MANUAL, enqueue_maintenance,
+};
+use serde::{Deserialize, Serialize};
+use session::Session;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MaintenanceParam {
+    /// Tasks to run, a full gc with a commit-graph when left out.
+    pub tasks: Option<Vec<MaintenanceTask>>,
+}
+
+impl AppCore {
+    pub async fn repo_maintenance(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<MaintenanceStatus, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        MaintenanceStatus::load(&self.db, &repo).await
+    }
+    /// Queues maintenance of the repository, run by the next pass of a maintenance worker.
+    pub async fn repo_maintenance_enqueue(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        param: MaintenanceParam,
+        session: Session,
+    ) -> Result<repo_maintenance_jobs::Model, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let tasks = param
+            .tasks
+            .unwrap_or_else(|| MaintenanceTask::FULL.to_vec());
+        enqueue_maintenance(&self.db, repo.uid, &tasks, TRIGGER_MANUAL).await
+    }
+}
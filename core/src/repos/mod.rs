@@ -1,10 +1,18 @@
 pub mod find;
 pub mod init;
+pub mod integrity;
+pub mod lfs;
+pub mod maintenance;
 pub mod vector_search;
 
 pub mod branch;
 pub mod commit;
 pub mod data;
+pub mod deploy_key;
+pub mod partial_clone;
+pub mod protection;
+pub mod quota;
 pub mod star;
+pub mod storage;
 pub mod tree;
 pub mod watch;
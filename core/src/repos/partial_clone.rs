@@ -0,0 +1,37 @@
+use crate::AppCore;
+use anyhow::anyhow;
+use error::AppError;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{ActiveModelTrait, IntoActiveModel, Set};
+use serde::{Deserialize, Serialize};
+use session::Session;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PartialCloneParam {
+    /// Accept `git clone --filter`, within the filters the server allows.
+    pub enabled: bool,
+}
+
+impl AppCore {
+    pub async fn repo_partial_clone(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        param: PartialCloneParam,
+        session: Session,
+    ) -> Result<(), AppError> {
+        if param.enabled && !self.config.git.partial_clone.enabled {
+            return Err(AppError::from(anyhow!(
+                "partial clone is disabled on this server"
+            )));
+        }
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let mut active = repo.into_active_model();
+        active.partial_clone = Set(param.enabled);
+        active.updated_at = Set(Utc::now().naive_utc());
+        active.update(&self.db).await?;
+        Ok(())
+    }
+}
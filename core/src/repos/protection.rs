@@ -0,0 +1,226 @@
+use crate::AppCore;
+use anyhow::anyhow;
+use database::entity::{branch_protection, branch_protection_pusher, git_repo, user_repo, users};
+use error::AppError;
+use git::service::protection::valid_pattern;
+use sea_orm::prelude::Uuid;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, EntityTrait, IntoActiveModel, QueryFilter, Set,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use session::Session;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BranchProtectionParam {
+    pub pattern: String,
+    #[serde(default = "default_true")]
+    pub block_force_push: bool,
+    #[serde(default = "default_true")]
+    pub block_deletion: bool,
+    #[serde(default)]
+    pub require_linear_history: bool,
+    #[serde(default)]
+    pub restrict_push: bool,
+    /// Usernames allowed to push when `restrict_push` is set.
+    #[serde(default)]
+    pub pushers: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LfsLockParam {
+    /// Refuse pushes touching files locked through LFS by someone else.
+    pub enforced: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BranchProtectionItem {
+    #[serde(flatten)]
+    pub rule: branch_protection::Model,
+    pub pushers: Vec<String>,
+}
+
+impl AppCore {
+    /// Only the owner and members of a repository manage its protection rules.
+    pub(crate) async fn repo_protection_context(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<git_repo::Model, AppError> {
+        let repo = self.repo_find(namespace, repo_name).await?;
+        let user = self.user_context(session).await?;
+        if user.username == repo.namespace {
+            return Ok(repo);
+        }
+        let relate = user_repo::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(user_repo::Column::RepoUid.eq(repo.uid))
+                    .add(user_repo::Column::UserUid.eq(user.user_uid)),
+            )
+            .one(&self.db)
+            .await?;
+        if relate.is_none() {
+            return Err(AppError::from(anyhow!("permission denied")));
+        }
+        Ok(repo)
+    }
+    pub async fn repo_protection_list(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<Vec<BranchProtectionItem>, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let rules = branch_protection::Entity::find()
+            .filter(branch_protection::Column::RepoUid.eq(repo.uid))
+            .all(&self.db)
+            .await?;
+        let pushers = branch_protection_pusher::Entity::find()
+            .filter(branch_protection_pusher::Column::RuleUid.is_in(rules.iter().map(|x| x.uid)))
+            .all(&self.db)
+            .await?;
+        let users = users::Entity::find()
+            .filter(users::Column::Uid.is_in(pushers.iter().map(|x| x.user_uid)))
+            .all(&self.db)
+            .await?;
+        Ok(rules
+            .into_iter()
+            .map(|rule| BranchProtectionItem {
+                pushers: pushers
+                    .iter()
+                    .filter(|x| x.rule_uid == rule.uid)
+                    .filter_map(|x| users.iter().find(|user| user.uid == x.user_uid))
+                    .map(|user| user.username.clone())
+                    .collect(),
+                rule,
+            })
+            .collect())
+    }
+    /// Creates the rule for `param.pattern`, or replaces it when the pattern already has one.
+    pub async fn repo_protection_upsert(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        param: BranchProtectionParam,
+        session: Session,
+    ) -> Result<branch_protection::Model, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        if !valid_pattern(&param.pattern) {
+            return Err(AppError::from(anyhow!("invalid branch pattern")));
+        }
+        let mut pushers = vec![];
+        for username in &param.pushers {
+            let user = users::Entity::find()
+                .filter(users::Column::Username.eq(username))
+                .one(&self.db)
+                .await?
+                .ok_or(AppError::from(anyhow!("user {} not found", username)))?;
+            pushers.push(user.uid);
+        }
+        pushers.sort();
+        pushers.dedup();
+        let now = Utc::now().naive_utc();
+        let txn = self.db.begin().await?;
+        let existing = branch_protection::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(branch_protection::Column::RepoUid.eq(repo.uid))
+                    .add(branch_protection::Column::Pattern.eq(&param.pattern)),
+            )
+            .one(&txn)
+            .await?;
+        let rule = match existing {
+            Some(rule) => {
+                branch_protection_pusher::Entity::delete_many()
+                    .filter(branch_protection_pusher::Column::RuleUid.eq(rule.uid))
+                    .exec(&txn)
+                    .await?;
+                let mut active = rule.into_active_model();
+                active.block_force_push = Set(param.block_force_push);
+                active.block_deletion = Set(param.block_deletion);
+                active.require_linear_history = Set(param.require_linear_history);
+                active.restrict_push = Set(param.restrict_push);
+                active.updated_at = Set(now);
+                active.update(&txn).await?
+            }
+            None => {
+                branch_protection::ActiveModel {
+                    uid: Set(Uuid::new_v4()),
+                    repo_uid: Set(repo.uid),
+                    pattern: Set(param.pattern),
+                    block_force_push: Set(param.block_force_push),
+                    block_deletion: Set(param.block_deletion),
+                    require_linear_history: Set(param.require_linear_history),
+                    restrict_push: Set(param.restrict_push),
+                    created_at: Set(now),
+                    updated_at: Set(now),
+                }
+                .insert(&txn)
+                .await?
+            }
+        };
+        for user_uid in pushers {
+            branch_protection_pusher::ActiveModel {
+                uid: Set(Uuid::new_v4()),
+                rule_uid: Set(rule.uid),
+                user_uid: Set(user_uid),
+                created_at: Set(now),
+            }
+            .insert(&txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(rule)
+    }
+    pub async fn repo_protection_delete(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        rule_uid: Uuid,
+        session: Session,
+    ) -> Result<(), AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let rule = branch_protection::Entity::find_by_id(rule_uid)
+            .filter(branch_protection::Column::RepoUid.eq(repo.uid))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::from(anyhow!("protection rule not found")))?;
+        let txn = self.db.begin().await?;
+        branch_protection_pusher::Entity::delete_many()
+            .filter(branch_protection_pusher::Column::RuleUid.eq(rule.uid))
+            .exec(&txn)
+            .await?;
+        rule.into_active_model().delete(&txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+    pub async fn repo_protection_lfs_locks(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        param: LfsLockParam,
+        session: Session,
+    ) -> Result<(), AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        let mut active = repo.into_active_model();
+        active.lfs_lock_enforced = Set(param.enforced);
+        active.updated_at = Set(Utc::now().naive_utc());
+        active.update(&self.db).await?;
+        Ok(())
+    }
+}
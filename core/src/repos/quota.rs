@@ -0,0 +1,20 @@
+use crate::AppCore;
+use error::AppError;
+use git::service::quota::QuotaUsage;
+use session::Session;
+
+impl AppCore {
+    /// Storage taken by the repository against the per-repository quota, visible to its
+    /// owner and members.
+    pub async fn repo_quota(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<QuotaUsage, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        QuotaUsage::repo(&self.db, &self.config.git.quota, &repo).await
+    }
+}
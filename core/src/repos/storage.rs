@@ -0,0 +1,19 @@
+use crate::AppCore;
+use error::AppError;
+use git::service::storage_migration::StorageStatus;
+use session::Session;
+
+impl AppCore {
+    /// The storage of the repository and the progress of moves between storages.
+    pub async fn repo_storage(
+        &self,
+        namespace: &str,
+        repo_name: &str,
+        session: Session,
+    ) -> Result<StorageStatus, AppError> {
+        let repo = self
+            .repo_protection_context(namespace, repo_name, session)
+            .await?;
+        StorageStatus::load(&self.db, &repo).await
+    }
+}
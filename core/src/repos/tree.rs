@@ -30,12 +30,16 @@ impl AppCore {
         }
         let repo = self.repo_find(namespace, repo_name).await?;
         let git = GitContext::try_from((repo, self.config.git.clone()))?;
-        let tree = git.tree(TreeParam {
-            refs,
-            tree_oid,
-            dir,
-        })?;
-        let tree = git.tree_item_last_commit(tree)?;
+        let tree = git
+            .blocking(move |git| {
+                let tree = git.tree(TreeParam {
+                    refs,
+                    tree_oid,
+                    dir,
+                })?;
+                git.tree_item_last_commit(tree)
+            })
+            .await?;
         if let Ok(mut conn) = self.redis.get().await {
             if let Ok(result) = serde_json::to_string(&tree) {
                 conn.set_ex::<String, String, ()>(cache_key, result, 60)
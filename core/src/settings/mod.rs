@@ -1,4 +1,6 @@
 pub mod access_key;
 pub mod avatar;
 pub mod basic_form;
+pub mod quota;
+pub mod ssh_ca;
 pub mod sshkey;
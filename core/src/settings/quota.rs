@@ -0,0 +1,12 @@
+use crate::AppCore;
+use error::AppError;
+use git::service::quota::QuotaUsage;
+use session::Session;
+
+impl AppCore {
+    /// Storage taken by the current user's repositories against the per-user quota.
+    pub async fn setting_quota(&self, session: Session) -> Result<QuotaUsage, AppError> {
+        let user = self.user_context(session).await?;
+        QuotaUsage::user(&self.db, &self.config.git.quota, &user.username).await
+    }
+}
@@ -0,0 +1,72 @@
+use crate::AppCore;
+use crate::settings::sshkey::ssh_key_content;
+use anyhow::anyhow;
+use database::entity::ssh_cas;
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use session::Session;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SettingSshCaInsertParam {
+    pub title: String,
+    pub content: String,
+}
+
+impl AppCore {
+    /// Trusts a CA to sign SSH certificates for the current user. Certificates it signs
+    /// only sign in when the user's name is one of their principals.
+    pub async fn setting_ssh_ca_insert(
+        &self,
+        session: Session,
+        param: SettingSshCaInsertParam,
+    ) -> Result<ssh_cas::Model, AppError> {
+        let user = self.user_context(session).await?;
+        if param.title.is_empty() || param.title.len() > 50 {
+            return Err(AppError::from(anyhow!(
+                "Title length must be between 1 and 50 characters"
+            )));
+        }
+        let (content, fingerprint) = ssh_key_content(&param.content)?;
+        if ssh_cas::Entity::find()
+            .filter(ssh_cas::Column::UserUid.eq(user.user_uid))
+            .filter(ssh_cas::Column::Fingerprint.eq(fingerprint.as_str()))
+            .one(&self.db)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::from(anyhow!("SSH CA already exists")));
+        }
+        let active = ssh_cas::ActiveModel {
+            uid: Set(Uuid::now_v7()),
+            user_uid: Set(user.user_uid),
+            title: Set(param.title),
+            fingerprint: Set(fingerprint),
+            content: Set(content),
+            created_at: Set(Utc::now().naive_utc()),
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+    pub async fn setting_ssh_ca_list(
+        &self,
+        session: Session,
+    ) -> Result<Vec<ssh_cas::Model>, AppError> {
+        let user = self.user_context(session).await?;
+        Ok(ssh_cas::Entity::find()
+            .filter(ssh_cas::Column::UserUid.eq(user.user_uid))
+            .order_by_desc(ssh_cas::Column::CreatedAt)
+            .all(&self.db)
+            .await?)
+    }
+    pub async fn setting_ssh_ca_delete(&self, session: Session, uid: Uuid) -> Result<(), AppError> {
+        let user = self.user_context(session).await?;
+        ssh_cas::Entity::delete_many()
+            .filter(ssh_cas::Column::UserUid.eq(user.user_uid))
+            .filter(ssh_cas::Column::Uid.eq(uid))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+}
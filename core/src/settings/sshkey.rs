@@ -1,10 +1,11 @@
 use crate::{AppCore, Paginator};
 use anyhow::anyhow;
-use database::entity::ssh_keys;
+use database::entity::{deploy_keys, ssh_keys};
 use error::AppError;
+use git::transport::ssh::key::parse_public_key;
 use sea_orm::PaginatorTrait;
 use sea_orm::prelude::Uuid;
-use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::sqlx::types::chrono::{NaiveDateTime, Utc};
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
 };
@@ -17,6 +18,16 @@ pub struct SettingSshKeyInsertParam {
     pub name: String,
     pub description: Option<String>,
     pub content: String,
+    /// The key stops signing in after this, it never expires when unset.
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// The `<type> <base64>` part of an OpenSSH public key line, as the SSH server sees the key,
+/// and its `SHA256:` fingerprint. Keys the key policy refuses are rejected here.
+pub(crate) fn ssh_key_content(raw: &str) -> Result<(String, String), AppError> {
+    let key = parse_public_key(raw).map_err(|e| AppError::from(anyhow!(e)))?;
+    Ok((key.content, key.fingerprint))
 }
 
 impl AppCore {
@@ -26,27 +37,31 @@ impl AppCore {
         session: Session,
     ) -> Result<(), AppError> {
         let user = self.user_context(session).await?;
+        let (content, finger) = ssh_key_content(&param.content)?;
+        if param
+            .expires_at
+            .is_some_and(|x| x <= Utc::now().naive_utc())
+        {
+            return Err(AppError::from(anyhow!("Expiry must be in the future")));
+        }
         if ssh_keys::Entity::find()
-            .filter(ssh_keys::Column::Content.eq(param.content.clone()))
+            .filter(ssh_keys::Column::Fingerprint.eq(finger.as_str()))
             .one(&self.db)
             .await?
             .is_some()
         {
             return Err(AppError::from(anyhow!("SSH key already exists")));
         }
-        let split = param
-            .content
-            .split(' ')
-            .map(|x| x.to_string())
-            .collect::<Vec<String>>();
-        let content = if split.len() == 3 {
-            format!("{} {}", split[0], split[1])
-        } else if split.len() == 2 {
-            format!("{} {}", split[0], split[1])
-        } else {
-            return Err(AppError::from(anyhow!("Invalid SSH key")));
-        };
-        let finger = format!("SHA256:{}", sha256::digest(content.clone()));
+        if deploy_keys::Entity::find()
+            .filter(deploy_keys::Column::Fingerprint.eq(finger.as_str()))
+            .one(&self.db)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::from(anyhow!(
+                "SSH key is already in use as a deploy key"
+            )));
+        }
         let active = ssh_keys::ActiveModel {
             uid: Set(Uuid::now_v7()),
             user_id: Set(user.user_uid),
@@ -56,6 +71,8 @@ impl AppCore {
             content: Set(content),
             created_at: Set(Utc::now().naive_utc()),
             updated_at: Set(Utc::now().naive_utc()),
+            last_used_at: Set(None),
+            expires_at: Set(param.expires_at),
         };
         active.insert(&self.db).await?;
         Ok(())
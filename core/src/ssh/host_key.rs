@@ -0,0 +1,12 @@
+use crate::AppCore;
+use error::AppError;
+use git::transport::ssh::host_key::{HostKeyInfo, host_key_info, load_host_keys};
+
+impl AppCore {
+    /// Fingerprints of the SSH host keys, for users to check what their client shows on
+    /// the first connection. Keys the SSH server has not generated yet are left out.
+    pub async fn ssh_host_keys(&self) -> Result<Vec<HostKeyInfo>, AppError> {
+        let keys = load_host_keys(&self.config.ssh, false)?;
+        Ok(host_key_info(&keys))
+    }
+}
@@ -17,6 +17,12 @@ pub struct Model {
     pub created_at: DateTime,
     pub updated_at: DateTime,
     pub storage: String,
+    pub lfs_lock_enforced: bool,
+    pub partial_clone: bool,
+    pub size: i64,
+    pub pushes_since_maintenance: i32,
+    pub maintained_at: Option<DateTime>,
+    pub verified_at: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
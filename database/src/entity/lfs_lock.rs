@@ -1,5 +1,3 @@
-//! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.14
-
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -7,9 +5,13 @@ use serde::{Deserialize, Serialize};
 #[sea_orm(table_name = "lfs_locks")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
-    pub id: String,
+    pub id: Uuid,
+    pub repo_uid: Uuid,
     #[sea_orm(column_type = "Text")]
-    pub data: String,
+    pub path: String,
+    pub owner_uid: Uuid,
+    pub ref_name: Option<String>,
+    pub locked_at: DateTime,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
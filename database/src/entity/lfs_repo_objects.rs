@@ -0,0 +1,17 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
+#[sea_orm(table_name = "lfs_repo_objects")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub repo_uid: Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub oid: String,
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
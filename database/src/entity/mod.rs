@@ -1,4 +1,7 @@
+pub mod branch_protection;
+pub mod branch_protection_pusher;
 pub mod cf_scores;
+pub mod deploy_keys;
 pub mod email_verifications;
 pub mod git_blob;
 pub mod git_commit;
@@ -11,9 +14,14 @@ pub mod hybrid_recommendations;
 pub mod lfs_lock;
 pub mod lfs_objects;
 pub mod lfs_relations;
+pub mod lfs_repo_objects;
 pub mod oauth_providers;
 pub mod password_resets;
 pub mod repo_features;
+pub mod repo_integrity_reports;
+pub mod repo_maintenance_jobs;
+pub mod repo_storage_migrations;
+pub mod ssh_cas;
 pub mod ssh_keys;
 pub mod user_access_keys;
 pub mod user_black;
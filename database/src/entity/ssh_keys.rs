@@ -16,6 +16,9 @@ pub struct Model {
     pub content: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+    /// Keys stop signing in after this, never when unset.
+    pub expires_at: Option<DateTime>,
 }
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
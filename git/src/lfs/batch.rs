@@ -0,0 +1,204 @@
+use crate::lfs::store::{LfsStore, valid_oid};
+use crate::lfs::{LfsOperation, LfsPointer};
+use crate::service::GitServer;
+use database::entity::git_repo;
+use error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const LFS_HASH_ALGO: &str = "sha256";
+pub const LFS_TRANSFER: &str = "basic";
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BatchRequest {
+    pub operation: LfsOperation,
+    #[serde(default)]
+    pub transfers: Vec<String>,
+    #[serde(default, rename = "ref")]
+    pub refspec: Option<LfsRef>,
+    pub objects: Vec<LfsPointer>,
+    #[serde(default)]
+    pub hash_algo: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LfsRef {
+    pub name: String,
+}
+
+impl BatchRequest {
+    pub fn supported(&self) -> bool {
+        self.transfers.is_empty() || self.transfers.iter().any(|x| x == LFS_TRANSFER)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BatchResponse {
+    pub transfer: String,
+    pub objects: Vec<BatchObject>,
+    pub hash_algo: String,
+}
+
+/// One object of a batch response. An upload without actions tells the client the
+/// repository already has it.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BatchObject {
+    pub oid: String,
+    pub size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<BatchActions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BatchObjectError>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct BatchActions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download: Option<LfsAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<LfsAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify: Option<LfsAction>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LfsAction {
+    pub href: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub header: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BatchObjectError {
+    pub code: u16,
+    pub message: String,
+}
+
+/// Where the transfer actions of a batch point to: the objects endpoint of the repository
+/// and the headers that authenticate the client there.
+#[derive(Clone, Debug)]
+pub struct LfsLink {
+    pub href: String,
+    pub header: HashMap<String, String>,
+}
+
+impl LfsLink {
+    fn action(&self, path: &str) -> LfsAction {
+        LfsAction {
+            href: format!("{}/{}", self.href, path),
+            header: self.header.clone(),
+        }
+    }
+}
+
+impl BatchObject {
+    fn new(pointer: LfsPointer) -> Self {
+        Self {
+            oid: pointer.oid,
+            size: pointer.size,
+            authenticated: None,
+            actions: None,
+            error: None,
+        }
+    }
+    fn error(pointer: LfsPointer, code: u16, message: &str) -> Self {
+        Self {
+            error: Some(BatchObjectError {
+                code,
+                message: message.to_string(),
+            }),
+            ..Self::new(pointer)
+        }
+    }
+    fn actions(pointer: LfsPointer, link: &LfsLink, actions: BatchActions) -> Self {
+        Self {
+            authenticated: (!link.header.is_empty()).then_some(true),
+            actions: Some(actions),
+            ..Self::new(pointer)
+        }
+    }
+}
+
+impl GitServer {
+    /// Answers a batch request for `repo`, the caller has checked access for the operation.
+    /// Objects count as present only when linked to this repository, so an upload of
+    /// content another repository holds still has to send it once, proving the client has it.
+    pub async fn lfs_batch(
+        &self,
+        repo: &git_repo::Model,
+        request: BatchRequest,
+        link: &LfsLink,
+    ) -> Result<BatchResponse, AppError> {
+        let store = LfsStore::try_from((repo.clone(), self.config.git.clone()))?;
+        // what the quota has left, taken up by each object the client is told to send
+        let mut remaining = match request.operation {
+            LfsOperation::Upload => self.push_quota(repo).await?.remaining,
+            LfsOperation::Download => None,
+        };
+        let mut objects = Vec::with_capacity(request.objects.len());
+        for pointer in request.objects {
+            if !valid_oid(&pointer.oid) || pointer.size < 0 {
+                objects.push(BatchObject::error(pointer, 422, "Invalid object"));
+                continue;
+            }
+            let present = self.lfs_linked(repo.uid, &pointer.oid).await?
+                && self.lfs_size(&store, &pointer.oid).await? == Some(pointer.size as u64);
+            let left = remaining.map(|x| x.checked_sub(pointer.size as u64));
+            let object = match request.operation {
+                LfsOperation::Download if present => {
+                    let actions = BatchActions {
+                        download: Some(link.action(&pointer.oid)),
+                        ..Default::default()
+                    };
+                    BatchObject::actions(pointer, link, actions)
+                }
+                LfsOperation::Download => BatchObject::error(pointer, 404, "Object does not exist"),
+                LfsOperation::Upload if present => BatchObject::new(pointer),
+                LfsOperation::Upload if left == Some(None) => {
+                    BatchObject::error(pointer, 507, "Storage quota exceeded")
+                }
+                LfsOperation::Upload => {
+                    remaining = left.flatten();
+                    let actions = BatchActions {
+                        upload: Some(link.action(&pointer.oid)),
+                        verify: Some(link.action("verify")),
+                        ..Default::default()
+                    };
+                    BatchObject::actions(pointer, link, actions)
+                }
+            };
+            objects.push(object);
+        }
+        Ok(BatchResponse {
+            transfer: LFS_TRANSFER.to_string(),
+            objects,
+            hash_algo: LFS_HASH_ALGO.to_string(),
+        })
+    }
+}
+
+#[test]
+fn test_batch_request() {
+    let request: BatchRequest = serde_json::from_str(
+        r#"{"operation":"upload","transfers":["lfs-standalone-file","basic"],
+        "ref":{"name":"refs/heads/main"},"objects":[{"oid":"12ab","size":3}]}"#,
+    )
+    .unwrap();
+    assert_eq!(request.operation, LfsOperation::Upload);
+    assert!(request.supported());
+    assert_eq!(request.refspec.unwrap().name, "refs/heads/main");
+    let object = BatchObject::error(
+        LfsPointer {
+            oid: "12ab".to_string(),
+            size: 3,
+        },
+        404,
+        "Object does not exist",
+    );
+    assert_eq!(
+        serde_json::to_string(&object).unwrap(),
+        r#"{"oid":"12ab","size":3,"error":{"code":404,"message":"Object does not exist"}}"#
+    );
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+/// Chunk sizes are fixed rather than configurable: the same content has to cut into the
+/// same chunks on every storage and after every restart, or nothing deduplicates.
+pub const CHUNK_MIN: usize = 512 * 1024;
+pub const CHUNK_AVG: usize = 2 * 1024 * 1024;
+pub const CHUNK_MAX: usize = 8 * 1024 * 1024;
+
+/// Gear table of FastCDC, filled from splitmix64 so it needs no 2 KiB literal. Changing it
+/// moves every chunk boundary.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x6a7a_6673_6c66_7321u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// One chunk of a split object, `oid` is the sha256 of the chunk alone.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ChunkRef {
+    pub oid: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// FastCDC with normalized chunking: below the average size a cut needs more zero bits of
+/// the rolling hash than above it, which keeps chunk sizes close to the average. The gear
+/// hash shifts left, so the top bits are the ones that depend on a full window of input.
+#[derive(Clone, Copy, Debug)]
+pub struct Chunker {
+    min: usize,
+    avg: usize,
+    max: usize,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(CHUNK_MIN, CHUNK_AVG, CHUNK_MAX)
+    }
+}
+
+impl Chunker {
+    pub fn new(min: usize, avg: usize, max: usize) -> Self {
+        let bits = avg.max(2).ilog2();
+        Self {
+            min,
+            avg,
+            max,
+            mask_small: !(u64::MAX >> (bits + 1)),
+            mask_large: !(u64::MAX >> (bits - 1)),
+        }
+    }
+    pub fn max(&self) -> usize {
+        self.max
+    }
+    /// Length of the first chunk of `data`. Only a cut below `data.len()` is final, unless
+    /// `data` holds at least `max` bytes or is all that is left of the input.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+        let end = data.len().min(self.max);
+        let normal = self.avg.min(end);
+        let mut hash = 0u64;
+        let mut i = self.min;
+        while i < normal {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & self.mask_small == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        while i < end {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & self.mask_large == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        end
+    }
+}
+
+#[test]
+fn test_chunker() {
+    let chunker = Chunker::new(256, 1024, 4096);
+    let mut state = 1u64;
+    let data = (0..256 * 1024)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as u8
+        })
+        .collect::<Vec<_>>();
+    let split = |data: &[u8]| {
+        let mut chunks = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let len = chunker.cut(rest);
+            assert!(len <= 4096);
+            chunks.push(sha256::digest(&rest[..len]));
+            rest = &rest[len..];
+        }
+        chunks
+    };
+    let chunks = split(&data);
+    assert!(chunks.len() > 64 && chunks.len() < 1024);
+    assert_eq!(chunks, split(&data));
+    // an insert near the start only disturbs the chunks around it
+    let mut edited = data.clone();
+    edited.insert(1000, 42);
+    let edited = split(&edited);
+    let shared = edited.iter().filter(|x| chunks.contains(x)).count();
+    assert!(shared + 4 >= chunks.len());
+}
@@ -0,0 +1,265 @@
+use crate::lfs::batch::LfsRef;
+use crate::service::GitServer;
+use database::entity::{lfs_lock, users};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::sqlx::types::chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, QueryOrder, QuerySelect,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+
+/// Most locks handed out per page, also the page size when the client does not ask for one.
+pub const LOCK_PAGE_LIMIT: u64 = 100;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct LfsLock {
+    pub id: String,
+    pub path: String,
+    pub locked_at: String,
+    pub owner: LfsLockOwner,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct LfsLockOwner {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CreateLockRequest {
+    pub path: String,
+    #[serde(default, rename = "ref")]
+    pub refspec: Option<LfsRef>,
+}
+
+/// Query of `GET locks`, and with `path` and `id` unset the body of `POST locks/verify`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct LockFilter {
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct UnlockRequest {
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default, rename = "ref")]
+    pub refspec: Option<LfsRef>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LockList {
+    pub locks: Vec<LfsLock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LockVerifyList {
+    pub ours: Vec<LfsLock>,
+    pub theirs: Vec<LfsLock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Lock paths are relative to the repository root with `/` separators. Quotes, backslashes
+/// and control characters are refused so the pre-receive hook sees them exactly as git
+/// prints them, one per line.
+pub fn valid_lock_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.len() <= 4096
+        && !path.starts_with('/')
+        && !path.ends_with('/')
+        && path
+            .split('/')
+            .all(|x| !x.is_empty() && x != "." && x != "..")
+        && !path
+            .chars()
+            .any(|c| c.is_control() || c == '"' || c == '\\')
+}
+
+impl GitServer {
+    async fn lfs_lock_views(&self, locks: Vec<lfs_lock::Model>) -> Result<Vec<LfsLock>, AppError> {
+        let owners = users::Entity::find()
+            .filter(users::Column::Uid.is_in(locks.iter().map(|x| x.owner_uid)))
+            .all(&self.db)
+            .await?;
+        Ok(locks
+            .into_iter()
+            .map(|lock| LfsLock {
+                id: lock.id.to_string(),
+                owner: LfsLockOwner {
+                    name: owners
+                        .iter()
+                        .find(|x| x.uid == lock.owner_uid)
+                        .map(|x| x.username.clone())
+                        .unwrap_or_default(),
+                },
+                locked_at: DateTime::<Utc>::from_naive_utc_and_offset(lock.locked_at, Utc)
+                    .to_rfc3339(),
+                path: lock.path,
+            })
+            .collect())
+    }
+    /// One page of the locks of a repository ordered by path. The cursor is the offset of
+    /// the page.
+    async fn lfs_lock_page(
+        &self,
+        repo_uid: Uuid,
+        filter: &LockFilter,
+    ) -> Result<(Vec<lfs_lock::Model>, Option<String>), AppError> {
+        let offset = filter
+            .cursor
+            .as_deref()
+            .and_then(|x| x.parse::<u64>().ok())
+            .unwrap_or(0);
+        let limit = filter
+            .limit
+            .unwrap_or(LOCK_PAGE_LIMIT)
+            .clamp(1, LOCK_PAGE_LIMIT);
+        let mut query = lfs_lock::Entity::find().filter(lfs_lock::Column::RepoUid.eq(repo_uid));
+        if let Some(path) = &filter.path {
+            query = query.filter(lfs_lock::Column::Path.eq(path));
+        }
+        if let Some(id) = &filter.id {
+            let Ok(id) = Uuid::parse_str(id) else {
+                return Ok((vec![], None));
+            };
+            query = query.filter(lfs_lock::Column::Id.eq(id));
+        }
+        let mut locks = query
+            .order_by_asc(lfs_lock::Column::Path)
+            .offset(offset)
+            .limit(limit + 1)
+            .all(&self.db)
+            .await?;
+        let next = if locks.len() as u64 > limit {
+            locks.truncate(limit as usize);
+            Some((offset + limit).to_string())
+        } else {
+            None
+        };
+        Ok((locks, next))
+    }
+    pub async fn lfs_lock_list(
+        &self,
+        repo_uid: Uuid,
+        filter: &LockFilter,
+    ) -> Result<LockList, AppError> {
+        let (locks, next_cursor) = self.lfs_lock_page(repo_uid, filter).await?;
+        Ok(LockList {
+            locks: self.lfs_lock_views(locks).await?,
+            next_cursor,
+        })
+    }
+    /// Splits the locks of a repository into those held by `user_uid` and everyone else's.
+    pub async fn lfs_lock_verify(
+        &self,
+        repo_uid: Uuid,
+        user_uid: Uuid,
+        filter: &LockFilter,
+    ) -> Result<LockVerifyList, AppError> {
+        let (locks, next_cursor) = self.lfs_lock_page(repo_uid, filter).await?;
+        let (ours, theirs): (Vec<_>, Vec<_>) =
+            locks.into_iter().partition(|x| x.owner_uid == user_uid);
+        Ok(LockVerifyList {
+            ours: self.lfs_lock_views(ours).await?,
+            theirs: self.lfs_lock_views(theirs).await?,
+            next_cursor,
+        })
+    }
+    async fn lfs_lock_at(
+        &self,
+        repo_uid: Uuid,
+        path: &str,
+    ) -> Result<Option<lfs_lock::Model>, AppError> {
+        Ok(lfs_lock::Entity::find()
+            .filter(lfs_lock::Column::RepoUid.eq(repo_uid))
+            .filter(lfs_lock::Column::Path.eq(path))
+            .one(&self.db)
+            .await?)
+    }
+    /// Locks `path` for `owner`. A lock someone already holds on it comes back as `Err`.
+    pub async fn lfs_lock_create(
+        &self,
+        repo_uid: Uuid,
+        owner: &users::Model,
+        request: CreateLockRequest,
+    ) -> Result<Result<LfsLock, LfsLock>, AppError> {
+        if let Some(lock) = self.lfs_lock_at(repo_uid, &request.path).await? {
+            return Ok(Err(self.lfs_lock_views(vec![lock]).await?.remove(0)));
+        }
+        let inserted = lfs_lock::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            repo_uid: Set(repo_uid),
+            path: Set(request.path.clone()),
+            owner_uid: Set(owner.uid),
+            ref_name: Set(request.refspec.clone().map(|x| x.name)),
+            locked_at: Set(Utc::now().naive_utc()),
+        }
+        .insert(&self.db)
+        .await;
+        match inserted {
+            Ok(lock) => Ok(Ok(self.lfs_lock_views(vec![lock]).await?.remove(0))),
+            // lost the race for the unique path index
+            Err(e) => match self.lfs_lock_at(repo_uid, &request.path).await? {
+                Some(lock) => Ok(Err(self.lfs_lock_views(vec![lock]).await?.remove(0))),
+                None => Err(e.into()),
+            },
+        }
+    }
+    pub async fn lfs_lock_find(
+        &self,
+        repo_uid: Uuid,
+        id: &str,
+    ) -> Result<Option<lfs_lock::Model>, AppError> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(None);
+        };
+        Ok(lfs_lock::Entity::find_by_id(id)
+            .filter(lfs_lock::Column::RepoUid.eq(repo_uid))
+            .one(&self.db)
+            .await?)
+    }
+    pub async fn lfs_unlock(&self, lock: lfs_lock::Model) -> Result<LfsLock, AppError> {
+        let view = self.lfs_lock_views(vec![lock.clone()]).await?.remove(0);
+        lock.delete(&self.db).await?;
+        Ok(view)
+    }
+    /// Paths of a repository locked by anyone but `except`.
+    pub async fn lfs_locked_paths(
+        &self,
+        repo_uid: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<Vec<String>, AppError> {
+        let mut query = lfs_lock::Entity::find().filter(lfs_lock::Column::RepoUid.eq(repo_uid));
+        if let Some(except) = except {
+            query = query.filter(lfs_lock::Column::OwnerUid.ne(except));
+        }
+        Ok(query
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|x| x.path)
+            .collect())
+    }
+}
+
+#[test]
+fn test_valid_lock_path() {
+    assert!(valid_lock_path("images/big file.psd"));
+    assert!(valid_lock_path("model.bin"));
+    assert!(!valid_lock_path(""));
+    assert!(!valid_lock_path("/etc/passwd"));
+    assert!(!valid_lock_path("a/../b"));
+    assert!(!valid_lock_path("a//b"));
+    assert!(!valid_lock_path("line\nbreak"));
+    assert!(!valid_lock_path("quote\"d"));
+}
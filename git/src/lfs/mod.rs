@@ -0,0 +1,204 @@
+use crate::lfs::chunk::ChunkRef;
+use crate::lfs::store::LfsStore;
+use crate::service::GitServer;
+use database::entity::{lfs_objects, lfs_relations, lfs_repo_objects};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::sqlx::types::chrono;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Set,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub mod batch;
+pub mod chunk;
+pub mod lock;
+pub mod store;
+pub mod token;
+
+pub const LFS_CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
+
+const RELATION_BATCH: usize = 1000;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LfsOperation {
+    Upload,
+    Download,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct LfsPointer {
+    pub oid: String,
+    pub size: i64,
+}
+
+/// The `{"message": …}` body LFS clients show for a failed request.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LfsError {
+    pub message: String,
+}
+
+impl GitServer {
+    /// Whether `oid` was uploaded to, and so may be served from, the repository.
+    pub async fn lfs_linked(&self, repo_uid: Uuid, oid: &str) -> Result<bool, AppError> {
+        let count = lfs_repo_objects::Entity::find()
+            .filter(lfs_repo_objects::Column::RepoUid.eq(repo_uid))
+            .filter(lfs_repo_objects::Column::Oid.eq(oid))
+            .count(&self.db)
+            .await?;
+        Ok(count > 0)
+    }
+    /// The chunks `oid` is split into, empty when it is stored whole.
+    pub async fn lfs_chunks(&self, oid: &str) -> Result<Vec<ChunkRef>, AppError> {
+        Ok(lfs_relations::Entity::find()
+            .filter(lfs_relations::Column::OriOid.eq(oid))
+            .order_by_asc(lfs_relations::Column::Offset)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|x| ChunkRef {
+                oid: x.sub_oid,
+                offset: x.offset as u64,
+                size: x.size as u64,
+            })
+            .collect())
+    }
+    /// Size of `oid` on `store`, `None` unless all of it is there.
+    pub async fn lfs_size(&self, store: &LfsStore, oid: &str) -> Result<Option<u64>, AppError> {
+        let chunks = self.lfs_chunks(oid).await?;
+        Ok(store.size(oid, &chunks).await)
+    }
+    /// Records a verified upload of `oid` to the repository, with the chunks it was split
+    /// into if it was.
+    pub async fn lfs_link(
+        &self,
+        repo_uid: Uuid,
+        oid: &str,
+        size: i64,
+        chunks: &[ChunkRef],
+    ) -> Result<(), AppError> {
+        let txn = self.db.begin().await?;
+        // an object once split stays split, chunking is deterministic so the relations
+        // written by another upload of the same content are the same
+        let mut update = vec![lfs_objects::Column::Size, lfs_objects::Column::Exist];
+        if !chunks.is_empty() {
+            update.push(lfs_objects::Column::Splited);
+        }
+        lfs_objects::Entity::insert(lfs_objects::ActiveModel {
+            oid: Set(oid.to_string()),
+            size: Set(size),
+            exist: Set(true),
+            splited: Set(!chunks.is_empty()),
+        })
+        .on_conflict(
+            OnConflict::column(lfs_objects::Column::Oid)
+                .update_columns(update)
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
+        // a 50 GB object has some 25k chunks, more than one statement may bind
+        for batch in chunks.chunks(RELATION_BATCH) {
+            lfs_relations::Entity::insert_many(batch.iter().map(|chunk| {
+                lfs_relations::ActiveModel {
+                    ori_oid: Set(oid.to_string()),
+                    sub_oid: Set(chunk.oid.clone()),
+                    offset: Set(chunk.offset as i64),
+                    size: Set(chunk.size as i64),
+                }
+            }))
+            .on_conflict(
+                OnConflict::columns([
+                    lfs_relations::Column::OriOid,
+                    lfs_relations::Column::SubOid,
+                    lfs_relations::Column::Offset,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(&txn)
+            .await?;
+        }
+        lfs_repo_objects::Entity::insert(lfs_repo_objects::ActiveModel {
+            repo_uid: Set(repo_uid),
+            oid: Set(oid.to_string()),
+            created_at: Set(chrono::Local::now().naive_local()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                lfs_repo_objects::Column::RepoUid,
+                lfs_repo_objects::Column::Oid,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+/// How much the LFS objects of a repository shrink through chunking. `stored_size` counts
+/// every distinct chunk once, whole objects in full, and chunks shared with other
+/// repositories as if they were this repository's alone.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct LfsDedupStats {
+    pub objects: u64,
+    pub split_objects: u64,
+    pub chunks: u64,
+    pub logical_size: u64,
+    pub stored_size: u64,
+    pub dedup_ratio: f64,
+}
+
+impl LfsDedupStats {
+    pub async fn load(db: &DatabaseConnection, repo_uid: Uuid) -> Result<Self, AppError> {
+        let oids = lfs_repo_objects::Entity::find()
+            .filter(lfs_repo_objects::Column::RepoUid.eq(repo_uid))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|x| x.oid)
+            .collect::<Vec<_>>();
+        let objects = lfs_objects::Entity::find()
+            .filter(lfs_objects::Column::Oid.is_in(oids))
+            .all(db)
+            .await?;
+        let split = objects
+            .iter()
+            .filter(|x| x.splited)
+            .map(|x| x.oid.clone())
+            .collect::<Vec<_>>();
+        let chunks = lfs_relations::Entity::find()
+            .filter(lfs_relations::Column::OriOid.is_in(split.clone()))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|x| (x.sub_oid, x.size as u64))
+            .collect::<HashMap<_, _>>();
+        let mut stats = Self {
+            objects: objects.len() as u64,
+            split_objects: split.len() as u64,
+            chunks: chunks.len() as u64,
+            stored_size: chunks.values().sum(),
+            ..Default::default()
+        };
+        for object in &objects {
+            stats.logical_size += object.size as u64;
+            if !object.splited {
+                stats.stored_size += object.size as u64;
+            }
+        }
+        stats.dedup_ratio = if stats.stored_size == 0 {
+            1.0
+        } else {
+            stats.logical_size as f64 / stats.stored_size as f64
+        };
+        Ok(stats)
+    }
+}
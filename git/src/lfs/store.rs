@@ -0,0 +1,511 @@
+use crate::lfs::chunk::{ChunkRef, Chunker};
+use crate::service::GitServer;
+use anyhow::anyhow;
+use async_stream::stream;
+use bytes::Bytes;
+use config::git::{AppGitConfig, AppGitLfs, AppGitStorage, GitStorageType};
+use database::entity::git_repo;
+use error::AppError;
+use futures_util::{Stream, StreamExt};
+use sea_orm::prelude::Uuid;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+const READ_SIZE: usize = 64 * 1024;
+
+pub type LfsBody = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+/// Content addressed LFS objects of one git storage, laid out like git-lfs does locally:
+/// `<lfs_path>/objects/ab/cd/abcd…`. Repositories on the same storage share objects,
+/// which repository may serve which object is recorded in `lfs_repo_objects`. Large objects
+/// are kept as chunks under `<lfs_path>/chunks/` instead, listed in
+/// `lfs_split_relations`, so versions differing in a few places share most of their bytes.
+#[derive(Clone, Debug)]
+pub struct LfsStore {
+    pub root: PathBuf,
+    pub chunk_threshold: u64,
+}
+
+impl TryFrom<(git_repo::Model, AppGitConfig)> for LfsStore {
+    type Error = AppError;
+    fn try_from(value: (git_repo::Model, AppGitConfig)) -> Result<Self, Self::Error> {
+        let (model, config) = value;
+        let storage = config
+            .storage
+            .iter()
+            .find(|x| x.name == model.storage)
+            .ok_or(AppError::from(anyhow!("storage not found")))?;
+        Self::new(storage, &config.lfs)
+    }
+}
+
+impl LfsStore {
+    /// The objects of `storage`. Those of a remote storage are on this host all the same, so
+    /// it needs an `lfs_path` apart from its repository root, which is on the node.
+    pub fn new(storage: &AppGitStorage, lfs: &AppGitLfs) -> Result<Self, AppError> {
+        let remote = storage.storage_type == Some(GitStorageType::Remote);
+        let root = match &storage.lfs_path {
+            Some(path) if remote && path.starts_with(&storage.path) => {
+                return Err(AppError::from(anyhow!(
+                    "lfs_path of remote storage {} is inside its repository root on the node",
+                    storage.name
+                )));
+            }
+            Some(path) => path.clone(),
+            None if remote => {
+                return Err(AppError::from(anyhow!(
+                    "remote storage {} has no lfs_path for its LFS objects",
+                    storage.name
+                )));
+            }
+            None => storage.path.join("lfs"),
+        };
+        Ok(Self {
+            root,
+            chunk_threshold: lfs.chunk_threshold,
+        })
+    }
+    fn fanout(&self, dir: &str, oid: &str) -> PathBuf {
+        self.root
+            .join(dir)
+            .join(&oid[0..2])
+            .join(&oid[2..4])
+            .join(oid)
+    }
+    pub fn path(&self, oid: &str) -> PathBuf {
+        self.fanout("objects", oid)
+    }
+    pub fn chunk_path(&self, oid: &str) -> PathBuf {
+        self.fanout("chunks", oid)
+    }
+    /// Whether a new object of `size` bytes is stored as chunks.
+    pub fn splits(&self, size: u64) -> bool {
+        self.chunk_threshold > 0 && size >= self.chunk_threshold
+    }
+    /// Size of the stored object, `None` if this storage does not have all of it. `chunks`
+    /// is the split of the object when it has one, a whole copy is preferred.
+    pub async fn size(&self, oid: &str, chunks: &[ChunkRef]) -> Option<u64> {
+        if let Some(size) = file_size(&self.path(oid)).await {
+            return Some(size);
+        }
+        if chunks.is_empty() {
+            return None;
+        }
+        for chunk in chunks {
+            if file_size(&self.chunk_path(&chunk.oid)).await != Some(chunk.size) {
+                return None;
+            }
+        }
+        chunks.last().map(|x| x.offset + x.size)
+    }
+    /// Streams `len` bytes of the object from `start` on, reassembling it from `chunks`
+    /// when there is no whole copy.
+    pub async fn read(
+        &self,
+        oid: &str,
+        chunks: &[ChunkRef],
+        start: u64,
+        len: u64,
+    ) -> io::Result<LfsBody> {
+        if file_size(&self.path(oid)).await.is_some() {
+            let file = open_at(&self.path(oid), start).await?;
+            return Ok(Box::pin(ReaderStream::with_capacity(
+                file.take(len),
+                READ_SIZE,
+            )));
+        }
+        let end = start + len;
+        let parts = chunks
+            .iter()
+            .filter(|x| x.offset < end && x.offset + x.size > start)
+            .map(|x| {
+                let skip = start.saturating_sub(x.offset);
+                let take = (x.offset + x.size).min(end) - x.offset - skip;
+                (self.chunk_path(&x.oid), skip, take)
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::pin(stream! {
+            for (path, skip, take) in parts {
+                let file = match open_at(&path, skip).await {
+                    Ok(file) => file,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+                let mut part = ReaderStream::with_capacity(file.take(take), READ_SIZE);
+                while let Some(bytes) = part.next().await {
+                    yield bytes;
+                }
+            }
+        }))
+    }
+    /// Streams an upload into the store. The content has to hash to `oid` and be exactly
+    /// `size` bytes, otherwise nothing is kept and `InvalidData` is returned. With `split`
+    /// the object is cut into chunks, which are returned in order; chunks already on the
+    /// storage are not written again.
+    pub async fn write<R: AsyncRead + Unpin>(
+        &self,
+        oid: &str,
+        size: u64,
+        split: bool,
+        input: &mut R,
+    ) -> io::Result<Vec<ChunkRef>> {
+        let tmp_dir = self.root.join("tmp");
+        tokio::fs::create_dir_all(&tmp_dir).await?;
+        if split {
+            return self.write_chunks(oid, size, input).await;
+        }
+        let tmp = tmp_dir.join(Uuid::new_v4().to_string());
+        let result = self.write_tmp(&tmp, oid, size, input).await;
+        if result.is_err() {
+            tokio::fs::remove_file(&tmp).await.ok();
+        }
+        result.map(|_| vec![])
+    }
+    async fn write_tmp<R: AsyncRead + Unpin>(
+        &self,
+        tmp: &Path,
+        oid: &str,
+        size: u64,
+        input: &mut R,
+    ) -> io::Result<()> {
+        let mut file = tokio::fs::File::create(tmp).await?;
+        let mut hasher = Sha256::new();
+        let mut written = 0u64;
+        let mut buf = vec![0u8; READ_SIZE];
+        loop {
+            let read = input.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            written += read as u64;
+            if written > size {
+                return Err(too_large());
+            }
+            hasher.update(&buf[..read]);
+            file.write_all(&buf[..read]).await?;
+        }
+        file.sync_all().await?;
+        drop(file);
+        check_object(oid, size, written, hasher)?;
+        let path = self.path(oid);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // the same content may already be there from another repository
+        tokio::fs::rename(tmp, &path).await
+    }
+    /// Chunks are staged in a directory of their own and only moved into the store once
+    /// the object checks out, an upload that fails leaves nothing behind.
+    async fn write_chunks<R: AsyncRead + Unpin>(
+        &self,
+        oid: &str,
+        size: u64,
+        input: &mut R,
+    ) -> io::Result<Vec<ChunkRef>> {
+        let staging = self.root.join("tmp").join(Uuid::new_v4().to_string());
+        tokio::fs::create_dir_all(&staging).await?;
+        let result = match self.stage_chunks(&staging, oid, size, input).await {
+            Ok(chunks) => self.keep_chunks(&staging, &chunks).await.map(|_| chunks),
+            Err(e) => Err(e),
+        };
+        tokio::fs::remove_dir_all(&staging).await.ok();
+        result
+    }
+    async fn stage_chunks<R: AsyncRead + Unpin>(
+        &self,
+        staging: &Path,
+        oid: &str,
+        size: u64,
+        input: &mut R,
+    ) -> io::Result<Vec<ChunkRef>> {
+        let chunker = Chunker::default();
+        let mut hasher = Sha256::new();
+        let mut pending = Vec::with_capacity(chunker.max() + READ_SIZE);
+        let mut buf = vec![0u8; READ_SIZE];
+        let mut chunks = vec![];
+        let (mut written, mut offset, mut eof) = (0u64, 0u64, false);
+        loop {
+            while !eof && pending.len() < chunker.max() {
+                let read = input.read(&mut buf).await?;
+                if read == 0 {
+                    eof = true;
+                    break;
+                }
+                written += read as u64;
+                if written > size {
+                    return Err(too_large());
+                }
+                hasher.update(&buf[..read]);
+                pending.extend_from_slice(&buf[..read]);
+            }
+            if pending.is_empty() {
+                break;
+            }
+            let len = chunker.cut(&pending);
+            let chunk = self.stage_chunk(staging, &pending[..len]).await?;
+            chunks.push(ChunkRef {
+                oid: chunk,
+                offset,
+                size: len as u64,
+            });
+            offset += len as u64;
+            pending.drain(..len);
+        }
+        check_object(oid, size, written, hasher)?;
+        Ok(chunks)
+    }
+    /// Moves the staged chunks into the store, those already there were not staged.
+    async fn keep_chunks(&self, staging: &Path, chunks: &[ChunkRef]) -> io::Result<()> {
+        for chunk in chunks {
+            let staged = staging.join(&chunk.oid);
+            if file_size(&staged).await.is_none() {
+                continue;
+            }
+            let path = self.chunk_path(&chunk.oid);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&staged, &path).await?;
+        }
+        Ok(())
+    }
+    /// Copies the object from another storage as it is stored there, whole or as the
+    /// `chunks` this storage lacks. Returns the bytes copied.
+    pub async fn copy_from(
+        &self,
+        source: &LfsStore,
+        oid: &str,
+        chunks: &[ChunkRef],
+    ) -> io::Result<u64> {
+        tokio::fs::create_dir_all(self.root.join("tmp")).await?;
+        if file_size(&source.path(oid)).await.is_some() {
+            return self.copy_file(&source.path(oid), &self.path(oid)).await;
+        }
+        let mut copied = 0;
+        for chunk in chunks {
+            if file_size(&self.chunk_path(&chunk.oid)).await == Some(chunk.size) {
+                continue;
+            }
+            copied += self
+                .copy_file(&source.chunk_path(&chunk.oid), &self.chunk_path(&chunk.oid))
+                .await?;
+        }
+        Ok(copied)
+    }
+    async fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let tmp = self.root.join("tmp").join(Uuid::new_v4().to_string());
+        let result = async {
+            let copied = tokio::fs::copy(from, &tmp).await?;
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(&tmp, to).await?;
+            Ok(copied)
+        }
+        .await;
+        if result.is_err() {
+            tokio::fs::remove_file(&tmp).await.ok();
+        }
+        result
+    }
+    async fn stage_chunk(&self, staging: &Path, data: &[u8]) -> io::Result<String> {
+        let oid = hex::encode(Sha256::digest(data));
+        let size = Some(data.len() as u64);
+        let staged = staging.join(&oid);
+        if file_size(&self.chunk_path(&oid)).await == size || file_size(&staged).await == size {
+            return Ok(oid);
+        }
+        let mut file = tokio::fs::File::create(&staged).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        Ok(oid)
+    }
+}
+
+async fn file_size(path: &Path) -> Option<u64> {
+    tokio::fs::metadata(path)
+        .await
+        .ok()
+        .filter(|x| x.is_file())
+        .map(|x| x.len())
+}
+
+async fn open_at(path: &Path, offset: u64) -> io::Result<tokio::fs::File> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    Ok(file)
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "object larger than announced")
+}
+
+fn check_object(oid: &str, size: u64, written: u64, hasher: Sha256) -> io::Result<()> {
+    if written != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {} bytes, got {}", size, written),
+        ));
+    }
+    if hex::encode(hasher.finalize()) != oid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "content does not match the object id",
+        ));
+    }
+    Ok(())
+}
+
+impl GitServer {
+    /// Fails for a storage whose LFS objects have nowhere to go, checked at startup rather
+    /// than on the first upload.
+    pub fn check_lfs_storages(&self) -> Result<(), AppError> {
+        for storage in &self.config.git.storage {
+            LfsStore::new(storage, &self.config.git.lfs)?;
+        }
+        Ok(())
+    }
+}
+
+/// LFS object ids are lowercase hex sha256, which also keeps them safe as file names.
+pub fn valid_oid(oid: &str) -> bool {
+    oid.len() == 64 && oid.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[tokio::test]
+async fn test_lfs_store() {
+    let root = crate::testing::TempDir::new("lfs");
+    let store = LfsStore {
+        root: root.to_path_buf(),
+        chunk_threshold: 0,
+    };
+    let data = b"hello lfs\n";
+    let oid = sha256::digest(&data[..]);
+    assert!(valid_oid(&oid));
+    assert!(!valid_oid("../../etc/passwd"));
+    assert!(store.write(&oid, 3, false, &mut &data[..]).await.is_err());
+    let other = sha256::digest(b"other");
+    assert!(
+        store
+            .write(&other, 10, false, &mut &data[..])
+            .await
+            .is_err()
+    );
+    assert_eq!(store.size(&oid, &[]).await, None);
+    store.write(&oid, 10, false, &mut &data[..]).await.unwrap();
+    assert_eq!(store.size(&oid, &[]).await, Some(10));
+    assert_eq!(std::fs::read_dir(root.join("tmp")).unwrap().count(), 0);
+
+    // two versions of a large object differing in one byte share all but one chunk
+    let mut state = 7u64;
+    let mut large = (0..20 * 1024 * 1024)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as u8
+        })
+        .collect::<Vec<_>>();
+    let large_oid = sha256::digest(&large[..]);
+    let chunks = store
+        .write(&large_oid, large.len() as u64, true, &mut &large[..])
+        .await
+        .unwrap();
+    assert!(chunks.len() > 2);
+    assert_eq!(store.size(&large_oid, &[]).await, None);
+    assert_eq!(
+        store.size(&large_oid, &chunks).await,
+        Some(large.len() as u64)
+    );
+    let mut body = store
+        .read(&large_oid, &chunks, 1000, 5_000_000)
+        .await
+        .unwrap();
+    let mut range = vec![];
+    while let Some(bytes) = body.next().await {
+        range.extend_from_slice(&bytes.unwrap());
+    }
+    assert_eq!(range, &large[1000..5_001_000]);
+    large[10 * 1024 * 1024] ^= 1;
+    let edited_oid = sha256::digest(&large[..]);
+    let edited = store
+        .write(&edited_oid, large.len() as u64, true, &mut &large[..])
+        .await
+        .unwrap();
+    let shared = edited.iter().filter(|x| chunks.contains(x)).count();
+    assert!(shared + 2 >= chunks.len());
+
+    // an upload that does not check out keeps none of its chunks
+    fn files(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .map(|x| {
+                x.flatten()
+                    .map(|x| match x.path().is_dir() {
+                        true => files(&x.path()),
+                        false => 1,
+                    })
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+    let stored = files(&root.join("chunks"));
+    large[15 * 1024 * 1024] ^= 1;
+    assert!(
+        store
+            .write(&edited_oid, large.len() as u64, true, &mut &large[..])
+            .await
+            .is_err()
+    );
+    assert_eq!(files(&root.join("chunks")), stored);
+    assert_eq!(files(&root.join("tmp")), 0);
+    large[15 * 1024 * 1024] ^= 1;
+
+    // another storage gets the objects as they are stored here
+    let other = LfsStore {
+        root: root.join("other"),
+        chunk_threshold: 0,
+    };
+    assert_eq!(other.copy_from(&store, &oid, &[]).await.unwrap(), 10);
+    assert_eq!(other.size(&oid, &[]).await, Some(10));
+    let copied = other.copy_from(&store, &large_oid, &chunks).await.unwrap();
+    assert_eq!(copied, large.len() as u64);
+    assert_eq!(
+        other.size(&large_oid, &chunks).await,
+        Some(large.len() as u64)
+    );
+    let copied = other.copy_from(&store, &edited_oid, &edited).await.unwrap();
+    assert!(copied < large.len() as u64);
+}
+
+#[test]
+fn test_lfs_store_root() {
+    let lfs = AppGitLfs::default();
+    let storage = |storage_type, lfs_path: Option<&str>| AppGitStorage {
+        name: "node".to_string(),
+        path: PathBuf::from("/srv/repo"),
+        storage_type,
+        address: Some("10.0.0.2:7170".to_string()),
+        secret: String::new(),
+        lfs_path: lfs_path.map(PathBuf::from),
+        weight: 1,
+    };
+    let root = |storage| LfsStore::new(&storage, &lfs).map(|x| x.root);
+    assert_eq!(
+        root(storage(None, None)).unwrap(),
+        PathBuf::from("/srv/repo/lfs")
+    );
+    let remote = Some(GitStorageType::Remote);
+    assert!(root(storage(remote.clone(), None)).is_err());
+    assert!(root(storage(remote.clone(), Some("/srv/repo/lfs"))).is_err());
+    assert_eq!(
+        root(storage(remote, Some("/mnt/shared/lfs"))).unwrap(),
+        PathBuf::from("/mnt/shared/lfs")
+    );
+}
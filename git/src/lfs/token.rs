@@ -0,0 +1,128 @@
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use anyhow::anyhow;
+use database::entity::{git_repo, users};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Tokens handed out by `git-lfs-authenticate`, told apart from access keys by the prefix.
+pub const LFS_TOKEN_PREFIX: &str = "_gtl";
+/// Seconds an LFS token stays valid, git-lfs asks for a new one once it ran out.
+pub const LFS_TOKEN_TTL: u64 = 600;
+
+/// Reply of `git-lfs-authenticate`: where the HTTP LFS API of the repository is and the
+/// header that gets the client in.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LfsAuthenticate {
+    pub href: String,
+    pub header: HashMap<String, String>,
+    pub expires_in: u64,
+}
+
+fn token_key(token: &str) -> String {
+    format!("git:lfs:token:{}", sha256::digest(token))
+}
+
+/// `<user>:<repo>:<access>` as stored under the token.
+fn parse_grant(grant: &str) -> Option<(Uuid, Uuid, i32)> {
+    let mut parts = grant.splitn(3, ':');
+    let user = Uuid::parse_str(parts.next()?).ok()?;
+    let repo = Uuid::parse_str(parts.next()?).ok()?;
+    let access = parts.next()?.parse().ok()?;
+    Some((user, repo, access))
+}
+
+impl GitServer {
+    /// Base URL of the HTTP server as clients reach it.
+    pub fn http_url(&self) -> String {
+        match self.config.git.lfs.http_url.trim_end_matches('/') {
+            "" => format!("http://{}:{}", self.config.api.host, self.config.api.port),
+            url => url.to_string(),
+        }
+    }
+    /// Issues a token for the LFS API of `repo` only, good for `access` and for
+    /// `LFS_TOKEN_TTL` seconds. Redis keeps its hash, never the token itself.
+    pub async fn lfs_token_issue(
+        &self,
+        user: &users::Model,
+        repo: &git_repo::Model,
+        access: RepoAccess,
+    ) -> Result<LfsAuthenticate, AppError> {
+        let token = format!(
+            "{}{}{}",
+            LFS_TOKEN_PREFIX,
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        let mut conn = self
+            .redis
+            .get()
+            .await
+            .map_err(|e| AppError::from(anyhow!("redis unavailable: {}", e)))?;
+        redis::cmd("SET")
+            .arg(token_key(&token))
+            .arg(format!("{}:{}:{}", user.uid, repo.uid, access as i32))
+            .arg("EX")
+            .arg(LFS_TOKEN_TTL)
+            .query_async::<()>(&mut *conn)
+            .await
+            .map_err(|e| AppError::from(anyhow!("storing lfs token failed: {}", e)))?;
+        let mut header = HashMap::new();
+        header.insert("Authorization".to_string(), format!("Bearer {}", token));
+        Ok(LfsAuthenticate {
+            href: format!(
+                "{}/{}/{}.git/info/lfs",
+                self.http_url(),
+                repo.namespace,
+                repo.repo_name
+            ),
+            header,
+            expires_in: LFS_TOKEN_TTL,
+        })
+    }
+    /// The user an LFS token was issued to, if it is still valid for `need` on `repo`.
+    pub async fn lfs_token_owner(
+        &self,
+        token: &str,
+        repo: &git_repo::Model,
+        need: RepoAccess,
+    ) -> Result<users::Model, AppError> {
+        let mut conn = self
+            .redis
+            .get()
+            .await
+            .map_err(|e| AppError::from(anyhow!("redis unavailable: {}", e)))?;
+        let grant = redis::cmd("GET")
+            .arg(token_key(token))
+            .query_async::<Option<String>>(&mut *conn)
+            .await
+            .map_err(|e| AppError::from(anyhow!("reading lfs token failed: {}", e)))?
+            .ok_or(AppError::from(anyhow!("LFS token not found or expired")))?;
+        let (user_uid, repo_uid, access) =
+            parse_grant(&grant).ok_or(AppError::from(anyhow!("LFS token is malformed")))?;
+        if repo_uid != repo.uid || access < need as i32 {
+            return Err(AppError::from(anyhow!(
+                "The LFS token does not grant this operation"
+            )));
+        }
+        users::Entity::find()
+            .filter(users::Column::Uid.eq(user_uid))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::from(anyhow!("LFS token owner not found")))
+    }
+}
+
+#[test]
+fn test_parse_grant() {
+    let (user, repo) = (Uuid::new_v4(), Uuid::new_v4());
+    assert_eq!(
+        parse_grant(&format!("{}:{}:2", user, repo)),
+        Some((user, repo, 2))
+    );
+    assert_eq!(parse_grant("nonsense"), None);
+    assert_eq!(token_key("_gtl1").len(), "git:lfs:token:".len() + 64);
+}
@@ -1,17 +1,32 @@
+use crate::service::quota;
+use crate::storage::rpc::{GitOp, ObjectCall, StorageCall};
+use crate::storage::{RemoteRepo, StorageAddress};
+use crate::transport::backend::{PackChild, PackIo};
 use anyhow::anyhow;
-use config::git::AppGitConfig;
+use config::git::{AppGitConfig, GitStorageType};
 use database::entity::git_repo::Model;
 use error::AppError;
 use git2::Repository;
+use std::io;
 use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
 
+pub mod lfs;
 pub mod object;
 pub mod service;
+pub mod storage;
 pub mod transport;
 
+#[cfg(test)]
+mod testing;
+
+/// A repository. `path_dir` is where it lives on the disk of whoever serves it, this host
+/// or the storage node in `remote`.
 #[derive(Clone)]
 pub struct GitContext {
     pub path_dir: PathBuf,
+    pub remote: Option<RemoteRepo>,
 }
 
 impl TryFrom<(Model, AppGitConfig)> for GitContext {
@@ -21,7 +36,22 @@ impl TryFrom<(Model, AppGitConfig)> for GitContext {
         let repo_storage_name = model.storage.clone();
         if let Some(storage) = config.storage.iter().find(|x| x.name == repo_storage_name) {
             let path_dir = storage.path.join(model.uid.to_string());
-            Ok(Self { path_dir })
+            let remote = match storage.storage_type {
+                Some(GitStorageType::Remote) => {
+                    let address = storage
+                        .address
+                        .as_deref()
+                        .and_then(StorageAddress::parse)
+                        .ok_or_else(|| anyhow!("storage {} has no address", storage.name))?;
+                    Some(RemoteRepo {
+                        address,
+                        name: model.uid.to_string(),
+                        secret: storage.secret.clone(),
+                    })
+                }
+                _ => None,
+            };
+            Ok(Self { path_dir, remote })
         } else {
             Err(AppError::from(anyhow!("storage not found")))
         }
@@ -30,9 +60,87 @@ impl TryFrom<(Model, AppGitConfig)> for GitContext {
 
 impl GitContext {
     pub fn repo(&self) -> Result<Repository, AppError> {
+        if self.remote.is_some() {
+            return Err(AppError::from(anyhow!("repository is on a storage node")));
+        }
         Repository::open_bare(self.path_dir.as_path()).map_err(|e| AppError::from(anyhow!(e)))
     }
-    pub fn init(&self) -> Result<Repository, AppError> {
-        Repository::init_bare(self.path_dir.as_path()).map_err(|e| AppError::from(anyhow!(e)))
+    pub fn init(&self) -> Result<(), AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::Init);
+        }
+        Repository::init_bare(self.path_dir.as_path()).map_err(|e| AppError::from(anyhow!(e)))?;
+        Ok(())
+    }
+    pub fn exists(&self) -> Result<bool, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::Exists);
+        }
+        Ok(self.path_dir.exists())
+    }
+    /// Deletes the repository from disk, for one that moved to another storage.
+    pub fn remove(&self) -> Result<(), AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::Remove);
+        }
+        std::fs::remove_dir_all(&self.path_dir)?;
+        Ok(())
+    }
+    /// Bytes of everything under `objects`.
+    pub fn objects_size(&self) -> io::Result<u64> {
+        if let Some(remote) = &self.remote {
+            return remote
+                .object(ObjectCall::Size)
+                .map_err(|e| io::Error::other(e.msg));
+        }
+        quota::objects_size(&self.path_dir)
+    }
+    /// Runs `f` on the blocking pool. The methods here block on libgit2 or on a storage node,
+    /// async code calls them through this.
+    pub async fn blocking<T, F>(&self, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce(&GitContext) -> Result<T, AppError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let git = self.clone();
+        tokio::task::spawn_blocking(move || f(&git))
+            .await
+            .map_err(|e| AppError::from(anyhow!(e)))?
+    }
+    /// `objects_size` on the blocking pool, the walk takes a while on a large repository.
+    pub async fn measure_objects(&self) -> io::Result<u64> {
+        let git = self.clone();
+        tokio::task::spawn_blocking(move || git.objects_size())
+            .await
+            .map_err(io::Error::other)?
+    }
+    /// Runs a git command in the repository, wherever it lives.
+    pub fn git(&self, op: GitOp) -> io::Result<PackIo> {
+        if let Some(remote) = &self.remote {
+            return Ok(remote.spawn(StorageCall::Git {
+                repo: remote.name.clone(),
+                op,
+            }));
+        }
+        let mut child = Command::new("git")
+            .args(op.args()?)
+            .current_dir(&self.path_dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(io::Error::other("git process pipes unavailable"));
+        };
+        Ok(PackIo {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            child: PackChild::process(child),
+        })
     }
 }
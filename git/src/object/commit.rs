@@ -1,7 +1,9 @@
 use crate::GitContext;
+use crate::storage::rpc::ObjectCall;
 use error::AppError;
-use git2::Oid;
+use git2::{BranchType, Oid};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
 pub struct CommitPaginator {
@@ -28,8 +30,32 @@ pub struct Signature {
     pub email: String,
 }
 
+impl From<&git2::Commit<'_>> for CommitItem {
+    fn from(commit: &git2::Commit) -> Self {
+        CommitItem {
+            tree_oid: commit.tree_id().to_string(),
+            commit_oid: commit.id().to_string(),
+            author: Signature {
+                name: commit.author().name().unwrap_or("nil").to_string(),
+                email: commit.author().email().unwrap_or("nil").to_string(),
+            },
+            committer: Signature {
+                name: commit.committer().name().unwrap_or("nil").to_string(),
+                email: commit.committer().email().unwrap_or("nil").to_string(),
+            },
+            message: commit.message().unwrap_or("nil").to_string(),
+            parents: commit.parents().map(|x| x.id().to_string()).collect(),
+            time: commit.time().seconds(),
+            offset_date: commit.time().offset_minutes(),
+        }
+    }
+}
+
 impl GitContext {
     pub fn commit_list(&self, param: CommitPaginator) -> Result<Vec<CommitItem>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::CommitList { param });
+        }
         let repo = self.repo()?;
         let refs = match param.refs {
             None => repo.head()?,
@@ -45,35 +71,8 @@ impl GitContext {
         }
         let mut result = vec![];
         while let Ok(parent) = commit.parent(0) {
-            let tree_oid = commit.tree_id().to_string();
             let commit_oid = commit.id().to_string();
-            let message = commit.message().unwrap_or("nil").to_string();
-            let author_email = commit.author().email().unwrap_or("nil").to_string();
-            let author_name = commit.author().name().unwrap_or("nil").to_string();
-            let commiter_email = commit.committer().email().unwrap_or("nil").to_string();
-            let commiter_name = commit.committer().name().unwrap_or("nil").to_string();
-            let parents = commit
-                .parents()
-                .map(|x| x.id().to_string())
-                .collect::<Vec<_>>();
-            let time = commit.time().seconds();
-            let offset_date = commit.time().offset_minutes();
-            result.push(CommitItem {
-                tree_oid,
-                commit_oid: commit_oid.clone(),
-                author: Signature {
-                    name: author_name,
-                    email: author_email,
-                },
-                committer: Signature {
-                    name: commiter_name,
-                    email: commiter_email,
-                },
-                message,
-                parents,
-                time,
-                offset_date,
-            });
+            result.push(CommitItem::from(&commit));
             if let Some(start) = param.start_oid.clone() {
                 if commit_oid == start {
                     break;
@@ -86,6 +85,53 @@ impl GitContext {
         }
         Ok(result)
     }
+    /// Commits reachable from `tip` but from none of `hide`, newest first. Hidden commits
+    /// the repository no longer has are skipped.
+    pub fn commit_range(&self, tip: Oid, hide: &[Oid]) -> Result<Vec<CommitItem>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::CommitRange {
+                tip: tip.to_string(),
+                hide: hide.iter().map(|x| x.to_string()).collect(),
+            });
+        }
+        let repo = self.repo()?;
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        walk.push(tip)?;
+        for oid in hide {
+            walk.hide(*oid).ok();
+        }
+        let mut result = vec![];
+        for oid in walk {
+            result.push(CommitItem::from(&repo.find_commit(oid?)?));
+        }
+        Ok(result)
+    }
+    pub fn is_descendant(&self, commit: Oid, ancestor: Oid) -> Result<bool, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::IsDescendant {
+                commit: commit.to_string(),
+                ancestor: ancestor.to_string(),
+            });
+        }
+        Ok(self.repo()?.graph_descendant_of(commit, ancestor)?)
+    }
+    /// Ids of every commit a branch reaches.
+    pub fn reachable_commits(&self) -> Result<HashSet<String>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::ReachableCommits);
+        }
+        let repo = self.repo()?;
+        let mut walk = repo.revwalk()?;
+        for branch in repo.branches(Some(BranchType::Local))? {
+            if let Some(tip) = branch?.0.get().target() {
+                walk.push(tip)?;
+            }
+        }
+        Ok(walk
+            .map(|x| x.map(|x| x.to_string()))
+            .collect::<Result<_, _>>()?)
+    }
 }
 
 #[test]
@@ -93,6 +139,7 @@ fn test_commit() {
     use std::path::PathBuf;
     let ctx = GitContext {
         path_dir: PathBuf::from("E:\\Code\\acl-anthology.git"),
+        remote: None,
     };
     let r = ctx
         .commit_list(CommitPaginator {
@@ -1,6 +1,8 @@
 use crate::GitContext;
+use crate::storage::rpc::ObjectCall;
 use anyhow::anyhow;
 use error::AppError;
+use git2::Oid;
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -13,6 +15,9 @@ pub struct RefsItem {
 
 impl GitContext {
     pub fn refs_list(&self) -> Result<Vec<RefsItem>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::RefsList);
+        }
         let repo = self.repo()?;
         let mut branches = repo
             .branches(None)
@@ -44,7 +49,15 @@ impl GitContext {
         }
         Ok(result)
     }
+    /// Moves a branch, callers hold the repository lock like for every ref change outside a
+    /// push.
     pub fn refs_rename(&self, old_name: &str, new_name: &str) -> Result<(), AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::RefsRename {
+                old: old_name.to_string(),
+                new: new_name.to_string(),
+            });
+        }
         let repo = self.repo()?;
         let mut branch = repo.find_branch(old_name, git2::BranchType::Local)?;
         if repo.find_branch(new_name, git2::BranchType::Local).is_ok() {
@@ -54,12 +67,22 @@ impl GitContext {
         Ok(())
     }
     pub fn refs_delete(&self, name: &str) -> Result<(), AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::RefsDelete {
+                name: name.to_string(),
+            });
+        }
         let repo = self.repo()?;
         let mut branch = repo.find_branch(name, git2::BranchType::Local)?;
         branch.delete()?;
         Ok(())
     }
     pub fn refs_exchange_head(&self, name: &str) -> Result<(), AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::RefsExchangeHead {
+                name: name.to_string(),
+            });
+        }
         let mut name = name.to_string();
         let repo = self.repo()?;
         if !name.starts_with("refs/heads/") {
@@ -68,4 +91,20 @@ impl GitContext {
         repo.set_head(&name)?;
         Ok(())
     }
+    /// The commits every ref points at, tags peeled.
+    pub fn ref_tips(&self) -> Result<Vec<Oid>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote
+                .object::<Vec<String>>(ObjectCall::RefTips)?
+                .iter()
+                .map(|x| Oid::from_str(x).map_err(AppError::from))
+                .collect();
+        }
+        Ok(self
+            .repo()?
+            .references()?
+            .flatten()
+            .filter_map(|x| x.peel_to_commit().ok().map(|x| x.id()))
+            .collect())
+    }
 }
@@ -1,5 +1,6 @@
 use crate::GitContext;
 use crate::object::commit::Signature;
+use crate::storage::rpc::ObjectCall;
 use error::AppError;
 use git2::ObjectType;
 use serde::{Deserialize, Serialize};
@@ -14,6 +15,9 @@ pub struct TagItem {
 
 impl GitContext {
     pub fn tag_list(&self) -> Result<Vec<TagItem>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::TagList);
+        }
         let repo = self.repo()?;
         let tags = repo.tag_names(None)?;
         let mut result = vec![];
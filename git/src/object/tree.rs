@@ -1,4 +1,5 @@
 use crate::GitContext;
+use crate::storage::rpc::ObjectCall;
 use error::AppError;
 use git2::{ObjectType, TreeWalkResult};
 use serde::{Deserialize, Serialize};
@@ -37,6 +38,9 @@ pub struct TreeItemLastCommit {
 
 impl GitContext {
     pub fn tree(&self, param: TreeParam) -> Result<Vec<TreeItem>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::Tree { param });
+        }
         let repo = self.repo()?;
         let refs = match param.refs {
             None => repo.head()?,
@@ -94,6 +98,9 @@ impl GitContext {
         &self,
         param: Vec<TreeItem>,
     ) -> Result<Vec<TreeItemLastCommit>, AppError> {
+        if let Some(remote) = &self.remote {
+            return remote.object(ObjectCall::TreeItemLastCommit { items: param });
+        }
         let repo = self.repo()?;
         let mut result = Vec::new();
 
@@ -172,6 +179,7 @@ impl GitContext {
 fn test_tree() {
     let ctx = GitContext {
         path_dir: PathBuf::from("E:\\Code\\acl-anthology.git"),
+        remote: None,
     };
     let res = ctx
         .tree(TreeParam {
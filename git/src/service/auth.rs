@@ -0,0 +1,135 @@
+use crate::lfs::token::LFS_TOKEN_PREFIX;
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use anyhow::anyhow;
+use database::entity::{git_repo, user_access_keys, users};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::sqlx::types::chrono::{NaiveDate, Utc};
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, EntityTrait, QueryFilter, Set};
+use sha256::Sha256Digest;
+
+pub const ACCESS_KEY_PREFIX: &str = "_gta";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GitCredential {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+impl GitCredential {
+    /// The token, when this is one `git-lfs-authenticate` handed out.
+    pub fn lfs_token(&self) -> Option<&str> {
+        let token = match self {
+            GitCredential::Basic { password, .. } => password,
+            GitCredential::Bearer(token) => token,
+        };
+        token
+            .starts_with(LFS_TOKEN_PREFIX)
+            .then_some(token.as_str())
+    }
+}
+
+impl GitServer {
+    pub async fn find_password_owner(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<users::Model, AppError> {
+        // same rule as `AppCore::auth_user_login`
+        users::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(users::Column::Username.eq(username.to_lowercase()))
+                    .add(users::Column::Email.eq(username.to_lowercase())),
+            )
+            .filter(users::Column::PasswordHash.eq(password.to_lowercase().digest()))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::from(anyhow!("username or password is incorrect")))
+    }
+    /// Resolves the user behind a git credential. Access keys are limited by their
+    /// `repo_access` level, so `need` is checked here; passwords carry no such limit.
+    /// LFS tokens from `git-lfs-authenticate` are only taken for the LFS API, `lfs` set,
+    /// of the repository they were issued for.
+    pub async fn authenticate(
+        &self,
+        credential: &GitCredential,
+        repo: &git_repo::Model,
+        need: RepoAccess,
+        lfs: bool,
+    ) -> Result<users::Model, AppError> {
+        if let Some(token) = credential.lfs_token() {
+            if !lfs {
+                return Err(AppError::from(anyhow!("LFS tokens only open the LFS API")));
+            }
+            return self.lfs_token_owner(token, repo, need).await;
+        }
+        match credential {
+            GitCredential::Basic { password, .. } if password.starts_with(ACCESS_KEY_PREFIX) => {
+                self.find_token_owner(password, need as i32).await
+            }
+            GitCredential::Basic { username, password } => {
+                self.find_password_owner(username, password).await
+            }
+            GitCredential::Bearer(token) => self.find_token_owner(token, need as i32).await,
+        }
+    }
+    /// Creates an access key limited to repositories, as `setting_access_key_new` does for
+    /// the web settings. The token is only ever returned here.
+    pub async fn access_key_issue(
+        &self,
+        user: &users::Model,
+        title: &str,
+        repo_access: RepoAccess,
+        expiration: NaiveDate,
+    ) -> Result<String, AppError> {
+        if title.is_empty() || title.len() > 50 {
+            return Err(AppError::from(anyhow!(
+                "Title length must be between 1 and 50 characters"
+            )));
+        }
+        if user_access_keys::Entity::find()
+            .filter(user_access_keys::Column::Title.eq(title))
+            .filter(user_access_keys::Column::ResourceOwnerUid.eq(user.uid))
+            .one(&self.db)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::from(anyhow!("Access key already exists")));
+        }
+        let token = format!(
+            "{}{}",
+            ACCESS_KEY_PREFIX,
+            Uuid::new_v4().to_string().digest()
+        );
+        let fingerprint = format!("SHA256:{}", sha256::digest(token.clone()));
+        let active = user_access_keys::ActiveModel {
+            uid: Set(Uuid::now_v7()),
+            title: Set(title.to_string()),
+            description: Set(Some("Created over SSH".to_string())),
+            token: Set(token.clone()),
+            use_history: Set(vec![]),
+            resource_owner: Set(user.username.clone()),
+            resource_owner_uid: Set(user.uid),
+            expiration: Set(expiration.format("%Y-%m-%d").to_string()),
+            fingerprint: Set(fingerprint),
+            repo_access: Set(repo_access as i32),
+            email_access: Set(0),
+            event_access: Set(0),
+            follow_access: Set(0),
+            gpg_access: Set(0),
+            ssh_access: Set(0),
+            webhook_access: Set(0),
+            wiki_access: Set(0),
+            project_access: Set(0),
+            issue_access: Set(0),
+            comment_access: Set(0),
+            profile_access: Set(0),
+            created_at: Set(Utc::now().naive_utc()),
+            updated_at: Set(Utc::now().naive_utc()),
+        };
+        active.insert(&self.db).await?;
+        Ok(token)
+    }
+}
@@ -0,0 +1,80 @@
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use anyhow::anyhow;
+use database::entity::{deploy_keys, git_repo, users};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::sea_query::Expr;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+
+impl GitServer {
+    /// Deploy keys with this fingerprint, one per repository the key was added to.
+    pub async fn find_deploy_keys(
+        &self,
+        fingerprint: &str,
+    ) -> Result<Vec<deploy_keys::Model>, AppError> {
+        Ok(deploy_keys::Entity::find()
+            .filter(deploy_keys::Column::Fingerprint.eq(fingerprint))
+            .all(&self.db)
+            .await?)
+    }
+    /// What a deploy key may do on `repo`: nothing on other repositories, and never more
+    /// than the user who added it can still do.
+    pub async fn deploy_key_access(
+        &self,
+        key: &deploy_keys::Model,
+        repo: &git_repo::Model,
+    ) -> Result<(RepoAccess, users::Model), AppError> {
+        let creator = users::Entity::find()
+            .filter(users::Column::Uid.eq(key.created_by))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::from(anyhow!("Deploy key creator not found")))?;
+        if key.repo_uid != repo.uid {
+            return Ok((RepoAccess::None, creator));
+        }
+        let granted = if key.read_only {
+            RepoAccess::Read
+        } else {
+            RepoAccess::Write
+        };
+        let access = granted.min(self.repo_access(repo, Some(&creator)).await);
+        Ok((access, creator))
+    }
+    /// The repositories a set of deploy keys was added to, with the access each key grants.
+    pub async fn deploy_key_repos(
+        &self,
+        keys: &[deploy_keys::Model],
+    ) -> Result<Vec<(git_repo::Model, RepoAccess)>, AppError> {
+        let repos = git_repo::Entity::find()
+            .filter(git_repo::Column::Uid.is_in(keys.iter().map(|x| x.repo_uid)))
+            .order_by_asc(git_repo::Column::Namespace)
+            .order_by_asc(git_repo::Column::RepoName)
+            .all(&self.db)
+            .await?;
+        Ok(repos
+            .into_iter()
+            .filter_map(|repo| {
+                let key = keys.iter().find(|x| x.repo_uid == repo.uid)?;
+                let access = if key.read_only {
+                    RepoAccess::Read
+                } else {
+                    RepoAccess::Write
+                };
+                Some((repo, access))
+            })
+            .collect())
+    }
+    pub async fn deploy_key_used(&self, key_uid: Uuid) -> Result<(), AppError> {
+        deploy_keys::Entity::update_many()
+            .col_expr(
+                deploy_keys::Column::LastUsedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(deploy_keys::Column::Uid.eq(key_uid))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+}
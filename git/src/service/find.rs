@@ -5,6 +5,8 @@ use error::AppError;
 use sea_orm::ColumnTrait;
 use sea_orm::QueryFilter;
 use sea_orm::prelude::Uuid;
+use sea_orm::sea_query::Expr;
+use sea_orm::sqlx::types::chrono::Utc;
 use sea_orm::{Condition, EntityTrait};
 
 impl GitServer {
@@ -40,18 +42,38 @@ impl GitServer {
             .ok_or(AppError::from(anyhow!("Repo not found")))?;
         Ok(repo)
     }
-    pub async fn find_ssh_key_owner(&self, ssh_key: &str) -> Result<users::Model, AppError> {
-        let owner = ssh_keys::Entity::find()
-            .filter(Condition::all().add(ssh_keys::Column::Content.eq(ssh_key)))
+    /// The key with this fingerprint and its owner, unless the key expired.
+    pub async fn find_ssh_key_owner(
+        &self,
+        fingerprint: &str,
+    ) -> Result<(ssh_keys::Model, users::Model), AppError> {
+        let key = ssh_keys::Entity::find()
+            .filter(ssh_keys::Column::Fingerprint.eq(fingerprint))
+            .filter(
+                Condition::any()
+                    .add(ssh_keys::Column::ExpiresAt.is_null())
+                    .add(ssh_keys::Column::ExpiresAt.gt(Utc::now().naive_utc())),
+            )
             .one(&self.db)
             .await?
             .ok_or(AppError::from(anyhow!("SSH key not found")))?;
         let users = users::Entity::find()
-            .filter(Condition::all().add(users::Column::Uid.eq(owner.user_id)))
+            .filter(Condition::all().add(users::Column::Uid.eq(key.user_id)))
             .one(&self.db)
             .await?
             .ok_or(AppError::from(anyhow!("SSH key owner not found")))?;
-        Ok(users)
+        Ok((key, users))
+    }
+    pub async fn ssh_key_used(&self, key_uid: Uuid) -> Result<(), AppError> {
+        ssh_keys::Entity::update_many()
+            .col_expr(
+                ssh_keys::Column::LastUsedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(ssh_keys::Column::Uid.eq(key_uid))
+            .exec(&self.db)
+            .await?;
+        Ok(())
     }
     pub async fn find_token_owner(
         &self,
@@ -0,0 +1,411 @@
+use crate::GitContext;
+use crate::service::GitServer;
+use crate::storage::rpc::GitOp;
+use database::entity::{git_commit, git_refs, git_repo, repo_integrity_reports};
+use error::AppError;
+use sea_orm::prelude::{Expr, Uuid};
+use sea_orm::sqlx::types::chrono;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io;
+use std::time::Duration;
+
+pub const TRIGGER_SCHEDULE: &str = "schedule";
+pub const TRIGGER_MANUAL: &str = "manual";
+pub const TRIGGER_REPAIR: &str = "repair";
+
+/// Reports of a repository the status API lists.
+const HISTORY_SIZE: u64 = 20;
+/// Lines of `git fsck` output a report keeps.
+const MAX_FSCK_FINDINGS: usize = 100;
+/// Commits a finding names, the rest are only counted.
+const SAMPLE_SIZE: usize = 10;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    /// The repository directory cannot be opened as a repository.
+    Unreadable,
+    /// Reported by `git fsck`, a corrupt or missing object or a broken link.
+    Fsck,
+    /// A branch without a `git_refs` row.
+    RefMissing,
+    /// A `git_refs` row whose branch is gone.
+    RefStale,
+    /// A `git_refs` row pointing elsewhere than its branch.
+    RefMismatch,
+    /// Commits a branch reaches without a `git_commit` row.
+    CommitMissing,
+    /// `git_commit` rows of commits no branch reaches.
+    CommitStale,
+}
+
+impl FindingKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FindingKind::Unreadable => "unreadable",
+            FindingKind::Fsck => "fsck",
+            FindingKind::RefMissing => "ref_missing",
+            FindingKind::RefStale => "ref_stale",
+            FindingKind::RefMismatch => "ref_mismatch",
+            FindingKind::CommitMissing => "commit_missing",
+            FindingKind::CommitStale => "commit_stale",
+        }
+    }
+    /// Whether resyncing the database from the repository fixes it, the others are damage
+    /// to the repository itself.
+    pub fn repairable(&self) -> bool {
+        !matches!(self, FindingKind::Unreadable | FindingKind::Fsck)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct IntegrityFinding {
+    pub kind: FindingKind,
+    pub detail: String,
+}
+
+impl IntegrityFinding {
+    fn new(kind: FindingKind, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Problems `git fsck` reports for the repository, empty when it passes.
+pub async fn fsck(git: &GitContext) -> io::Result<Vec<String>> {
+    let output = git.git(GitOp::Fsck)?.output().await?;
+    if output.code == 0 {
+        return Ok(vec![]);
+    }
+    let mut problems = [output.stdout, output.stderr]
+        .iter()
+        .flat_map(|x| {
+            String::from_utf8_lossy(x)
+                .lines()
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    if problems.is_empty() {
+        problems.push(format!("git fsck exited with {}", output.code));
+    }
+    Ok(problems)
+}
+
+/// Compares the ref and commit rows of a repository with its branches and the commits they
+/// reach.
+pub fn index_findings(
+    git: &GitContext,
+    refs: &[git_refs::Model],
+    commits: &HashSet<String>,
+) -> Vec<IntegrityFinding> {
+    let mut findings = vec![];
+    let branches = match git.refs_list() {
+        Ok(branches) => branches
+            .into_iter()
+            .map(|x| (x.name, x.hash))
+            .collect::<HashMap<_, _>>(),
+        Err(e) => {
+            return vec![IntegrityFinding::new(
+                FindingKind::Fsck,
+                format!("branches unreadable: {}", e.msg),
+            )];
+        }
+    };
+    for (name, tip) in &branches {
+        match refs.iter().find(|x| &x.ref_name == name) {
+            None => findings.push(IntegrityFinding::new(
+                FindingKind::RefMissing,
+                format!("{} at {} has no row", name, tip),
+            )),
+            Some(row) if &row.ref_git_id != tip => findings.push(IntegrityFinding::new(
+                FindingKind::RefMismatch,
+                format!("{} is at {}, its row at {}", name, tip, row.ref_git_id),
+            )),
+            Some(_) => {}
+        }
+    }
+    for row in refs.iter().filter(|x| !branches.contains_key(&x.ref_name)) {
+        findings.push(IntegrityFinding::new(
+            FindingKind::RefStale,
+            format!("{} at {} is not a branch", row.ref_name, row.ref_git_id),
+        ));
+    }
+
+    let reachable = match git.reachable_commits() {
+        Ok(reachable) => reachable,
+        Err(e) => {
+            findings.push(IntegrityFinding::new(
+                FindingKind::Fsck,
+                format!("history unreadable: {}", e.msg),
+            ));
+            return findings;
+        }
+    };
+    let missing = reachable.difference(commits).collect::<BTreeSet<_>>();
+    if !missing.is_empty() {
+        findings.push(IntegrityFinding::new(
+            FindingKind::CommitMissing,
+            sample("commits have no row", &missing),
+        ));
+    }
+    let stale = commits.difference(&reachable).collect::<BTreeSet<_>>();
+    if !stale.is_empty() {
+        findings.push(IntegrityFinding::new(
+            FindingKind::CommitStale,
+            sample("rows are of unreachable commits", &stale),
+        ));
+    }
+    findings
+}
+
+fn sample(what: &str, commits: &BTreeSet<&String>) -> String {
+    let names = commits
+        .iter()
+        .take(SAMPLE_SIZE)
+        .map(|x| x.as_str())
+        .collect::<Vec<_>>();
+    let more = commits.len().saturating_sub(SAMPLE_SIZE);
+    let more = if more > 0 {
+        format!(" and {} more", more)
+    } else {
+        String::new()
+    };
+    format!("{} {}: {}{}", commits.len(), what, names.join(", "), more)
+}
+
+/// When a repository was last verified and its latest reports, newest first.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct IntegrityStatus {
+    pub verified_at: Option<chrono::NaiveDateTime>,
+    pub reports: Vec<repo_integrity_reports::Model>,
+}
+
+impl IntegrityStatus {
+    pub async fn load(db: &DatabaseConnection, repo: &git_repo::Model) -> Result<Self, AppError> {
+        let reports = repo_integrity_reports::Entity::find()
+            .filter(repo_integrity_reports::Column::RepoUid.eq(repo.uid))
+            .order_by_desc(repo_integrity_reports::Column::CreatedAt)
+            .limit(HISTORY_SIZE)
+            .all(db)
+            .await?;
+        Ok(Self {
+            verified_at: repo.verified_at,
+            reports,
+        })
+    }
+}
+
+impl GitServer {
+    /// Runs `git fsck` on the repository and checks it and its database rows against each
+    /// other, and stores what was found. Only the comparison holds the repository lock, so
+    /// a push in flight is not taken for drift, a long fsck does not hold off pushes.
+    pub async fn verify_repo(
+        &self,
+        repo: &git_repo::Model,
+        trigger: &str,
+    ) -> Result<repo_integrity_reports::Model, AppError> {
+        let findings = self.inspect_repo(repo).await?;
+        let now = Utc::now().naive_utc();
+        let report = repo_integrity_reports::ActiveModel {
+            uid: Set(Uuid::now_v7()),
+            repo_uid: Set(repo.uid),
+            trigger: Set(trigger.to_string()),
+            healthy: Set(findings.is_empty()),
+            findings: Set(serde_json::to_value(&findings)?),
+            created_at: Set(now),
+        }
+        .insert(&self.db)
+        .await?;
+        git_repo::Entity::update_many()
+            .col_expr(git_repo::Column::VerifiedAt, Expr::value(now))
+            .filter(git_repo::Column::Uid.eq(repo.uid))
+            .exec(&self.db)
+            .await?;
+        Ok(report)
+    }
+    /// Rebuilds the database rows of the repository from disk, then verifies it again.
+    pub async fn repair_repo(
+        &self,
+        repo: &git_repo::Model,
+    ) -> Result<repo_integrity_reports::Model, AppError> {
+        self.resync_repo(repo.uid).await?;
+        self.verify_repo(repo, TRIGGER_REPAIR).await
+    }
+    async fn inspect_repo(
+        &self,
+        repo: &git_repo::Model,
+    ) -> Result<Vec<IntegrityFinding>, AppError> {
+        let config = &self.config.git.integrity;
+        let git = match GitContext::try_from((repo.clone(), self.config.git.clone())) {
+            Ok(git) => git,
+            Err(e) => return Ok(vec![IntegrityFinding::new(FindingKind::Unreadable, e.msg)]),
+        };
+        match git.blocking(|git| git.exists()).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Ok(vec![IntegrityFinding::new(
+                    FindingKind::Unreadable,
+                    "repository directory missing",
+                )]);
+            }
+            // an unreachable storage node says nothing about the repository
+            Err(e) => return Err(e),
+        }
+        let timeout = Duration::from_secs(config.timeout);
+        let mut findings = match tokio::time::timeout(timeout, fsck(&git)).await {
+            Ok(problems) => problems?
+                .into_iter()
+                .take(MAX_FSCK_FINDINGS)
+                .map(|x| IntegrityFinding::new(FindingKind::Fsck, x))
+                .collect(),
+            Err(_) => vec![IntegrityFinding::new(
+                FindingKind::Fsck,
+                "git fsck timed out",
+            )],
+        };
+        let _lock = self.lock_repo_on(repo).await?;
+        let refs = git_refs::Entity::find()
+            .filter(git_refs::Column::RepoUid.eq(repo.uid))
+            .all(&self.db)
+            .await?;
+        let commits = git_commit::Entity::find()
+            .select_only()
+            .column(git_commit::Column::CommitId)
+            .filter(git_commit::Column::RepoUid.eq(repo.uid))
+            .into_tuple::<String>()
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        findings.extend(
+            tokio::task::spawn_blocking(move || index_findings(&git, &refs, &commits))
+                .await
+                .map_err(io::Error::other)?,
+        );
+        Ok(findings)
+    }
+    /// Takes a repository on a configured storage that was not verified for
+    /// `git.integrity.interval` seconds, marking it verified so no other worker takes it too.
+    pub async fn claim_verification(&self) -> Result<Option<git_repo::Model>, AppError> {
+        let config = &self.config.git.integrity;
+        let storages = self
+            .config
+            .git
+            .storage
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>();
+        let cutoff = Utc::now().naive_utc() - chrono::Duration::seconds(config.interval as i64);
+        let due = || {
+            Condition::any()
+                .add(git_repo::Column::VerifiedAt.is_null())
+                .add(git_repo::Column::VerifiedAt.lt(cutoff))
+        };
+        loop {
+            let Some(repo) = git_repo::Entity::find()
+                .filter(git_repo::Column::Storage.is_in(storages.clone()))
+                .filter(due())
+                .order_by_asc(git_repo::Column::VerifiedAt)
+                .one(&self.db)
+                .await?
+            else {
+                return Ok(None);
+            };
+            let claimed = git_repo::Entity::update_many()
+                .col_expr(
+                    git_repo::Column::VerifiedAt,
+                    Expr::value(Utc::now().naive_utc()),
+                )
+                .filter(git_repo::Column::Uid.eq(repo.uid))
+                .filter(due())
+                .exec(&self.db)
+                .await?;
+            if claimed.rows_affected == 1 {
+                return Ok(Some(repo));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_verify() {
+    use crate::testing::{self, TempDir};
+    use git2::{Oid, Repository};
+
+    let dir = TempDir::new("integrity");
+    let repo = Repository::init_bare(&*dir).unwrap();
+    let mut commits = vec![];
+    let mut blobs = vec![];
+    for i in 0..3 {
+        let row = format!("row {}\n", i);
+        let parents = commits.last().copied().into_iter().collect::<Vec<_>>();
+        commits.push(testing::commit(
+            &repo,
+            "refs/heads/main",
+            &[("data.csv", row.as_bytes())],
+            &parents,
+        ));
+        blobs.push(repo.blob(row.as_bytes()).unwrap());
+    }
+    let row = |name: &str, oid: Oid| git_refs::Model {
+        uid: Uuid::now_v7(),
+        repo_uid: Uuid::nil(),
+        ref_name: name.to_string(),
+        ref_git_id: oid.to_string(),
+        default_branch: true,
+        created_at: Utc::now().naive_utc(),
+        updated_at: Utc::now().naive_utc(),
+    };
+    let recorded = commits
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<HashSet<_>>();
+
+    let git = GitContext {
+        path_dir: dir.to_path_buf(),
+        remote: None,
+    };
+    assert!(fsck(&git).await.unwrap().is_empty());
+    assert!(index_findings(&git, &[row("main", commits[2])], &recorded).is_empty());
+
+    let mut drifted = recorded.clone();
+    drifted.remove(&commits[0].to_string());
+    drifted.insert("0123456789012345678901234567890123456789".to_string());
+    let kinds =
+        |findings: Vec<IntegrityFinding>| findings.into_iter().map(|x| x.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds(index_findings(
+            &git,
+            &[row("main", commits[1]), row("gone", commits[0])],
+            &drifted
+        )),
+        [
+            FindingKind::RefMismatch,
+            FindingKind::RefStale,
+            FindingKind::CommitMissing,
+            FindingKind::CommitStale
+        ]
+    );
+    assert_eq!(
+        kinds(index_findings(&git, &[], &recorded)),
+        [FindingKind::RefMissing]
+    );
+
+    // a lost object is damage only git fsck sees
+    let blob = blobs[1].to_string();
+    std::fs::remove_file(dir.join("objects").join(&blob[..2]).join(&blob[2..])).unwrap();
+    let problems = fsck(&git).await.unwrap();
+    assert!(problems.iter().any(|x| x.contains(&blob)));
+    assert!(!FindingKind::Fsck.repairable());
+    assert!(FindingKind::CommitMissing.repairable());
+}
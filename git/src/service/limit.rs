@@ -0,0 +1,291 @@
+use crate::service::GitServer;
+use config::git::AppGitLimits;
+use sea_orm::prelude::Uuid;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Window the anonymous rate limit counts sessions in.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Addresses tracked before expired windows are swept.
+const RATE_SWEEP: usize = 10_000;
+/// Requests one counted session covers, a stateless fetch posts once per negotiation round
+/// after a single ref advertisement.
+const SESSION_ROUNDS: u32 = 64;
+
+/// Who a pack session counts against. The address of an anonymous client may be unknown,
+/// such a client is only held to the repository and global caps.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PackClient {
+    User(Uuid),
+    Anonymous(Option<IpAddr>),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum SlotKey {
+    Global,
+    Client(PackClient),
+    Repo(Uuid),
+}
+
+/// Slots of the sessions running in this process, HTTP and SSH alike.
+static SLOTS: LazyLock<Mutex<HashMap<SlotKey, Arc<Semaphore>>>> = LazyLock::new(Default::default);
+
+/// The current window of an anonymous address.
+struct Rate {
+    start: Instant,
+    sessions: u32,
+    /// Requests left of the sessions counted so far.
+    rounds: u32,
+}
+
+static RATES: LazyLock<Mutex<HashMap<IpAddr, Rate>>> = LazyLock::new(Default::default);
+
+/// Why a pack session was turned away.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PackLimited {
+    /// Too many anonymous sessions from one address.
+    RateLimited { retry_after: u64 },
+    /// No slot freed up within the queue timeout.
+    Busy { scope: &'static str },
+}
+
+impl PackLimited {
+    /// Seconds a client should wait before trying again.
+    pub fn retry_after(&self) -> u64 {
+        match self {
+            PackLimited::RateLimited { retry_after } => *retry_after,
+            PackLimited::Busy { .. } => 10,
+        }
+    }
+}
+
+impl fmt::Display for PackLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackLimited::RateLimited { retry_after } => write!(
+                f,
+                "too many anonymous requests from your address, retry in {} seconds or sign in",
+                retry_after
+            ),
+            PackLimited::Busy { scope } => write!(
+                f,
+                "too many git operations running for this {}, try again later",
+                scope
+            ),
+        }
+    }
+}
+
+/// Slots held by a running pack session, released on drop.
+pub struct PackPermit {
+    keys: Vec<SlotKey>,
+    permits: Vec<OwnedSemaphorePermit>,
+}
+
+fn slot(key: SlotKey, size: usize) -> Arc<Semaphore> {
+    let mut slots = SLOTS.lock().unwrap_or_else(|x| x.into_inner());
+    slots
+        .entry(key)
+        .or_insert_with(|| Arc::new(Semaphore::new(size)))
+        .clone()
+}
+
+/// Counts a session from `ip` against the anonymous rate limit, or for a `round` one more
+/// request of the sessions already counted, a new session once those are used up.
+fn check_rate(
+    config: &AppGitLimits,
+    ip: IpAddr,
+    now: Instant,
+    round: bool,
+) -> Result<(), PackLimited> {
+    if config.anonymous_per_minute == 0 {
+        return Ok(());
+    }
+    let mut rates = RATES.lock().unwrap_or_else(|x| x.into_inner());
+    if rates.len() > RATE_SWEEP {
+        rates.retain(|_, rate| now.duration_since(rate.start) < RATE_WINDOW);
+    }
+    let rate = rates.entry(ip).or_insert(Rate {
+        start: now,
+        sessions: 0,
+        rounds: 0,
+    });
+    if now.duration_since(rate.start) >= RATE_WINDOW {
+        *rate = Rate {
+            start: now,
+            sessions: 0,
+            rounds: 0,
+        };
+    }
+    if round && rate.rounds > 0 {
+        rate.rounds -= 1;
+        return Ok(());
+    }
+    if rate.sessions >= config.anonymous_per_minute {
+        let retry_after = RATE_WINDOW.saturating_sub(now.duration_since(rate.start));
+        return Err(PackLimited::RateLimited {
+            retry_after: retry_after.as_secs().max(1),
+        });
+    }
+    rate.sessions += 1;
+    rate.rounds = SESSION_ROUNDS - u32::from(round);
+    Ok(())
+}
+
+impl PackPermit {
+    /// Waits for a slot under every cap that applies, the client's first so a busy client
+    /// does not hold repository or global slots while it queues.
+    pub async fn acquire(
+        config: &AppGitLimits,
+        client: PackClient,
+        repo_uid: Uuid,
+    ) -> Result<Self, PackLimited> {
+        let deadline = Instant::now() + Duration::from_secs(config.queue_timeout);
+        let mut permit = PackPermit {
+            keys: vec![],
+            permits: vec![],
+        };
+        let caps = [
+            (SlotKey::Client(client), config.per_user, "user"),
+            (SlotKey::Repo(repo_uid), config.per_repo, "repository"),
+            (SlotKey::Global, config.global, "server"),
+        ];
+        for (key, size, scope) in caps {
+            if size == 0 || key == SlotKey::Client(PackClient::Anonymous(None)) {
+                continue;
+            }
+            let semaphore = slot(key, size);
+            permit.keys.push(key);
+            let acquired = match semaphore.clone().try_acquire_owned() {
+                Ok(acquired) => Some(acquired),
+                Err(_) => tokio::time::timeout_at(deadline.into(), semaphore.acquire_owned())
+                    .await
+                    .ok()
+                    .and_then(|x| x.ok()),
+            };
+            let Some(acquired) = acquired else {
+                warn!(
+                    "pack session turned away, {} cap of {} reached",
+                    scope, size
+                );
+                return Err(PackLimited::Busy { scope });
+            };
+            permit.permits.push(acquired);
+        }
+        Ok(permit)
+    }
+}
+
+impl Drop for PackPermit {
+    fn drop(&mut self) {
+        self.permits.clear();
+        let mut slots = SLOTS.lock().unwrap_or_else(|x| x.into_inner());
+        for key in &self.keys {
+            if slots.get(key).is_some_and(|x| Arc::strong_count(x) == 1) {
+                slots.remove(key);
+            }
+        }
+    }
+}
+
+impl GitServer {
+    pub async fn pack_permit(
+        &self,
+        client: PackClient,
+        repo_uid: Uuid,
+    ) -> Result<PackPermit, PackLimited> {
+        PackPermit::acquire(&self.config.git.limits, client, repo_uid).await
+    }
+    /// Counts a clone or fetch of `client` against the anonymous rate limit. Called once
+    /// per session where it starts, at the ref advertisement, not for every round of it.
+    pub fn count_pack_session(&self, client: PackClient) -> Result<(), PackLimited> {
+        match client {
+            PackClient::Anonymous(Some(ip)) => {
+                check_rate(&self.config.git.limits, ip, Instant::now(), false)
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Counts an upload-pack request of `client`. The rounds of a session that started
+    /// with an advertisement are free up to a point, a client skipping the advertisement
+    /// still pays for every session's worth of requests.
+    pub fn count_pack_round(&self, client: PackClient) -> Result<(), PackLimited> {
+        match client {
+            PackClient::Anonymous(Some(ip)) => {
+                check_rate(&self.config.git.limits, ip, Instant::now(), true)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_pack_permit() {
+    let config = AppGitLimits {
+        global: 0,
+        per_user: 1,
+        per_repo: 2,
+        queue_timeout: 0,
+        anonymous_per_minute: 0,
+        trusted_proxies: vec![],
+    };
+    let (alice, bob, repo) = (
+        PackClient::User(Uuid::new_v4()),
+        PackClient::User(Uuid::new_v4()),
+        Uuid::new_v4(),
+    );
+    let first = PackPermit::acquire(&config, alice, repo).await.unwrap();
+    assert_eq!(
+        PackPermit::acquire(&config, alice, repo).await.err(),
+        Some(PackLimited::Busy { scope: "user" })
+    );
+    let second = PackPermit::acquire(&config, bob, repo).await.unwrap();
+    let anonymous = PackClient::Anonymous(None);
+    assert_eq!(
+        PackPermit::acquire(&config, anonymous, repo).await.err(),
+        Some(PackLimited::Busy {
+            scope: "repository"
+        })
+    );
+    drop(first);
+    assert!(PackPermit::acquire(&config, anonymous, repo).await.is_ok());
+    drop(second);
+    let slots = SLOTS.lock().unwrap();
+    assert!(!slots.contains_key(&SlotKey::Repo(repo)));
+    assert!(!slots.contains_key(&SlotKey::Client(alice)));
+}
+
+#[test]
+fn test_check_rate() {
+    let config = AppGitLimits {
+        anonymous_per_minute: 2,
+        ..Default::default()
+    };
+    let ip = IpAddr::from([192, 0, 2, 1]);
+    let now = Instant::now();
+    assert!(check_rate(&config, ip, now, false).is_ok());
+    assert!(check_rate(&config, ip, now, false).is_ok());
+    assert_eq!(
+        check_rate(&config, ip, now + Duration::from_secs(15), false),
+        Err(PackLimited::RateLimited { retry_after: 45 })
+    );
+    // the rounds of the last session counted are free, one more is not
+    for _ in 0..SESSION_ROUNDS {
+        assert!(check_rate(&config, ip, now, true).is_ok());
+    }
+    assert!(check_rate(&config, ip, now, true).is_err());
+    assert!(check_rate(&config, IpAddr::from([192, 0, 2, 2]), now, false).is_ok());
+    assert!(check_rate(&config, ip, now + RATE_WINDOW, false).is_ok());
+
+    // requests without an advertisement are counted as sessions of their own
+    let direct = IpAddr::from([192, 0, 2, 3]);
+    for _ in 0..2 * SESSION_ROUNDS {
+        assert!(check_rate(&config, direct, now, true).is_ok());
+    }
+    assert!(check_rate(&config, direct, now, true).is_err());
+}
@@ -0,0 +1,330 @@
+use crate::service::GitServer;
+use anyhow::anyhow;
+use bb8::Pool;
+use bb8_redis::RedisConnectionManager;
+use config::git::AppGitLock;
+use database::entity::git_repo;
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::{EntityTrait, QuerySelect};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedMutexGuard;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// How long to wait for a Redis connection before holding the lock in-process only.
+const REDIS_WAIT: Duration = Duration::from_secs(1);
+
+const RENEW: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+const RELEASE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Holders in this process. Taken before the Redis lock, so local contention never reaches
+/// Redis, and all there is when Redis is down.
+static LOCAL: LazyLock<Mutex<HashMap<Uuid, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+static STATS: LockCounters = LockCounters {
+    acquired: AtomicU64::new(0),
+    contended: AtomicU64::new(0),
+    timeouts: AtomicU64::new(0),
+    local_only: AtomicU64::new(0),
+    lost_leases: AtomicU64::new(0),
+    wait_ms_total: AtomicU64::new(0),
+    wait_ms_max: AtomicU64::new(0),
+};
+
+struct LockCounters {
+    acquired: AtomicU64,
+    contended: AtomicU64,
+    timeouts: AtomicU64,
+    local_only: AtomicU64,
+    lost_leases: AtomicU64,
+    wait_ms_total: AtomicU64,
+    wait_ms_max: AtomicU64,
+}
+
+/// Lock wait figures of this process since start.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LockStats {
+    pub acquired: u64,
+    /// Acquisitions that had to wait for another holder.
+    pub contended: u64,
+    pub timeouts: u64,
+    /// Acquisitions that fell back to the in-process lock because Redis was unreachable.
+    pub local_only: u64,
+    pub lost_leases: u64,
+    pub wait_ms_total: u64,
+    pub wait_ms_max: u64,
+}
+
+/// Exclusive access to a repository across all jzfs processes, for anything that moves
+/// refs or rewrites the database view of them: pushes with their sync, branch changes and
+/// maintenance, and the end of a storage migration. Released on drop.
+pub struct RepoLock {
+    pub repo_uid: Uuid,
+    local: Option<OwnedMutexGuard<()>>,
+    remote: Option<RemoteLease>,
+}
+
+struct RemoteLease {
+    redis: Pool<RedisConnectionManager>,
+    key: String,
+    token: String,
+    renew: JoinHandle<()>,
+    lost: Arc<AtomicBool>,
+}
+
+impl RepoLock {
+    pub async fn acquire(
+        redis: &Pool<RedisConnectionManager>,
+        config: &AppGitLock,
+        repo_uid: Uuid,
+    ) -> Result<Self, AppError> {
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(config.timeout);
+        let mutex = LOCAL
+            .lock()
+            .map_err(|_| AppError::from(anyhow!("repository lock poisoned")))?
+            .entry(repo_uid)
+            .or_default()
+            .clone();
+        let mut contended = false;
+        let local = match mutex.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                contended = true;
+                match tokio::time::timeout_at(deadline.into(), mutex.lock_owned()).await {
+                    Ok(guard) => guard,
+                    Err(_) => return Err(Self::timed_out(repo_uid)),
+                }
+            }
+        };
+        let mut lock = Self {
+            repo_uid,
+            local: Some(local),
+            remote: None,
+        };
+        match RemoteLease::acquire(redis, config, repo_uid, deadline, &mut contended).await {
+            Ok(Some(remote)) => lock.remote = Some(remote),
+            Ok(None) => {
+                STATS.local_only.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "redis unreachable, repository {} locked in-process only",
+                    repo_uid
+                );
+            }
+            Err(e) => return Err(e),
+        }
+        let waited = start.elapsed().as_millis() as u64;
+        STATS.acquired.fetch_add(1, Ordering::Relaxed);
+        STATS.wait_ms_total.fetch_add(waited, Ordering::Relaxed);
+        STATS.wait_ms_max.fetch_max(waited, Ordering::Relaxed);
+        if contended {
+            STATS.contended.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(lock)
+    }
+    fn timed_out(repo_uid: Uuid) -> AppError {
+        STATS.timeouts.fetch_add(1, Ordering::Relaxed);
+        warn!("timed out waiting for the lock of repository {}", repo_uid);
+        AppError::from(anyhow!("repository is busy, try again later"))
+    }
+    /// Whether the Redis lease ran out while held, another process may have taken over.
+    pub fn is_lost(&self) -> bool {
+        self.remote
+            .as_ref()
+            .is_some_and(|x| x.lost.load(Ordering::Relaxed))
+    }
+    /// Fails once the lease was lost, checked right before a write that must not land
+    /// while another process may hold the repository.
+    pub fn ensure_held(&self) -> Result<(), AppError> {
+        if self.is_lost() {
+            return Err(AppError::from(anyhow!(
+                "lost the lock of repository {}",
+                self.repo_uid
+            )));
+        }
+        Ok(())
+    }
+    pub fn stats() -> LockStats {
+        LockStats {
+            acquired: STATS.acquired.load(Ordering::Relaxed),
+            contended: STATS.contended.load(Ordering::Relaxed),
+            timeouts: STATS.timeouts.load(Ordering::Relaxed),
+            local_only: STATS.local_only.load(Ordering::Relaxed),
+            lost_leases: STATS.lost_leases.load(Ordering::Relaxed),
+            wait_ms_total: STATS.wait_ms_total.load(Ordering::Relaxed),
+            wait_ms_max: STATS.wait_ms_max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if let Some(remote) = self.remote.take() {
+            remote.release();
+        }
+        drop(self.local.take());
+        if let Ok(mut local) = LOCAL.lock()
+            && local
+                .get(&self.repo_uid)
+                .is_some_and(|x| Arc::strong_count(x) == 1)
+        {
+            local.remove(&self.repo_uid);
+        }
+    }
+}
+
+impl RemoteLease {
+    /// `None` when Redis cannot be reached.
+    async fn acquire(
+        redis: &Pool<RedisConnectionManager>,
+        config: &AppGitLock,
+        repo_uid: Uuid,
+        deadline: Instant,
+        contended: &mut bool,
+    ) -> Result<Option<Self>, AppError> {
+        let key = format!("git:repo:{}:lock", repo_uid);
+        let token = Uuid::new_v4().to_string();
+        let lease = Duration::from_secs(config.lease.max(1));
+        let mut backoff = Duration::from_millis(20);
+        loop {
+            let Ok(Ok(mut conn)) = tokio::time::timeout(REDIS_WAIT, redis.get()).await else {
+                return Ok(None);
+            };
+            let set = redis::cmd("SET")
+                .arg(&key)
+                .arg(&token)
+                .arg("NX")
+                .arg("PX")
+                .arg(lease.as_millis() as u64)
+                .query_async::<Option<String>>(&mut *conn)
+                .await;
+            match set {
+                Ok(Some(_)) => break,
+                Ok(None) => {}
+                Err(_) => return Ok(None),
+            }
+            drop(conn);
+            *contended = true;
+            if Instant::now() + backoff > deadline {
+                return Err(RepoLock::timed_out(repo_uid));
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_millis(500));
+        }
+        let lost = Arc::new(AtomicBool::new(false));
+        let renew = tokio::spawn({
+            let (redis, key, token, lost) =
+                (redis.clone(), key.clone(), token.clone(), lost.clone());
+            async move {
+                loop {
+                    tokio::time::sleep(lease / 3).await;
+                    let Ok(mut conn) = redis.get().await else {
+                        continue;
+                    };
+                    let renewed = redis::Script::new(RENEW)
+                        .key(&key)
+                        .arg(&token)
+                        .arg(lease.as_millis() as u64)
+                        .invoke_async::<i32>(&mut *conn)
+                        .await;
+                    if let Ok(0) = renewed {
+                        STATS.lost_leases.fetch_add(1, Ordering::Relaxed);
+                        lost.store(true, Ordering::Relaxed);
+                        warn!("lost the lock lease of {}", key);
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Some(Self {
+            redis: redis.clone(),
+            key,
+            token,
+            renew,
+            lost,
+        }))
+    }
+    fn release(self) {
+        self.renew.abort();
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            // the lease runs out on its own
+            return;
+        };
+        runtime.spawn(async move {
+            if let Ok(mut conn) = self.redis.get().await {
+                redis::Script::new(RELEASE)
+                    .key(&self.key)
+                    .arg(&self.token)
+                    .invoke_async::<i32>(&mut *conn)
+                    .await
+                    .ok();
+            }
+        });
+    }
+}
+
+impl GitServer {
+    pub async fn lock_repo(&self, repo_uid: Uuid) -> Result<RepoLock, AppError> {
+        RepoLock::acquire(&self.redis, &self.config.git.lock, repo_uid).await
+    }
+    /// Locks the repository and checks it is still on the storage `repo` was read from, a
+    /// migration may have moved it while the lock was awaited.
+    pub async fn lock_repo_on(&self, repo: &git_repo::Model) -> Result<RepoLock, AppError> {
+        let lock = self.lock_repo(repo.uid).await?;
+        let storage = git_repo::Entity::find_by_id(repo.uid)
+            .select_only()
+            .column(git_repo::Column::Storage)
+            .into_tuple::<String>()
+            .one(&self.db)
+            .await?;
+        if storage.as_deref() != Some(repo.storage.as_str()) {
+            return Err(AppError::from(anyhow!(
+                "Repository moved to another storage, try again"
+            )));
+        }
+        Ok(lock)
+    }
+}
+
+#[tokio::test]
+async fn test_repo_lock_local() {
+    let redis = Pool::builder()
+        .connection_timeout(Duration::from_millis(50))
+        .build_unchecked(RedisConnectionManager::new("redis://127.0.0.1:1").unwrap());
+    let config = AppGitLock {
+        timeout: 1,
+        lease: 1,
+    };
+    let repo_uid = Uuid::new_v4();
+    let lock = RepoLock::acquire(&redis, &config, repo_uid).await.unwrap();
+    // a lock held in-process only has no lease to lose
+    assert!(lock.ensure_held().is_ok());
+    assert!(RepoLock::acquire(&redis, &config, repo_uid).await.is_err());
+    assert!(
+        RepoLock::acquire(&redis, &config, Uuid::new_v4())
+            .await
+            .is_ok()
+    );
+    drop(lock);
+    assert!(RepoLock::acquire(&redis, &config, repo_uid).await.is_ok());
+    assert!(!LOCAL.lock().unwrap().contains_key(&repo_uid));
+    let stats = RepoLock::stats();
+    assert!(stats.timeouts >= 1 && stats.local_only >= 3);
+}
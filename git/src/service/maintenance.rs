@@ -0,0 +1,511 @@
+use crate::GitContext;
+use crate::service::GitServer;
+use crate::service::lock::RepoLock;
+use crate::storage::rpc::GitOp;
+use anyhow::anyhow;
+use config::git::AppGitMaintenance;
+use database::entity::{git_repo, repo_maintenance_jobs};
+use error::AppError;
+use sea_orm::prelude::{Expr, Uuid};
+use sea_orm::sqlx::types::chrono;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub const JOB_QUEUED: &str = "queued";
+pub const JOB_RUNNING: &str = "running";
+pub const JOB_SUCCEEDED: &str = "succeeded";
+pub const JOB_FAILED: &str = "failed";
+
+pub const TRIGGER_PUSHES: &str = "pushes";
+pub const TRIGGER_AGE: &str = "age";
+pub const TRIGGER_MANUAL: &str = "manual";
+/// Queued by the migration for repositories whose size was never measured.
+pub const TRIGGER_BACKFILL: &str = "backfill";
+
+/// Jobs of a repository the status API lists.
+const HISTORY_SIZE: u64 = 50;
+
+/// One git command of repository housekeeping.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaintenanceTask {
+    /// Packs loose objects into a new pack, leaving existing packs alone.
+    LooseObjects,
+    /// Repacks everything into one pack with a bitmap, prunes, then packs refs.
+    Gc,
+    CommitGraph,
+    MultiPackIndex,
+    /// Repacks everything into one pack with a reachability bitmap, without the rest of gc.
+    Bitmap,
+    Prune,
+}
+
+impl MaintenanceTask {
+    /// After a burst of pushes, cheap and proportional to what was pushed.
+    pub const INCREMENTAL: &[Self] = &[Self::LooseObjects, Self::CommitGraph, Self::MultiPackIndex];
+    /// Once in a while, rewrites the whole repository.
+    pub const FULL: &[Self] = &[Self::Gc, Self::CommitGraph];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaintenanceTask::LooseObjects => "loose-objects",
+            MaintenanceTask::Gc => "gc",
+            MaintenanceTask::CommitGraph => "commit-graph",
+            MaintenanceTask::MultiPackIndex => "multi-pack-index",
+            MaintenanceTask::Bitmap => "bitmap",
+            MaintenanceTask::Prune => "prune",
+        }
+    }
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            Self::LooseObjects,
+            Self::Gc,
+            Self::CommitGraph,
+            Self::MultiPackIndex,
+            Self::Bitmap,
+            Self::Prune,
+        ]
+        .into_iter()
+        .find(|x| x.name() == name)
+    }
+    pub(crate) fn args(&self, prune_expire: &str) -> Vec<String> {
+        let prune = format!("--prune={}", prune_expire);
+        let expire = format!("--expire={}", prune_expire);
+        let args = match self {
+            MaintenanceTask::LooseObjects => vec!["repack", "-d", "-q"],
+            MaintenanceTask::Gc => vec![
+                "-c",
+                "gc.packRefs=false",
+                "-c",
+                "repack.writeBitmaps=true",
+                "gc",
+                "--quiet",
+                &prune,
+            ],
+            MaintenanceTask::CommitGraph => vec!["commit-graph", "write", "--reachable", "--split"],
+            MaintenanceTask::MultiPackIndex => vec!["multi-pack-index", "write"],
+            MaintenanceTask::Bitmap => vec!["repack", "-a", "-d", "-q", "--write-bitmap-index"],
+            MaintenanceTask::Prune => vec!["prune", &expire],
+        };
+        args.into_iter().map(|x| x.to_string()).collect()
+    }
+    /// Whether the task packs refs, which is run apart from the rest under the repository
+    /// lock.
+    fn packs_refs(&self) -> bool {
+        *self == MaintenanceTask::Gc
+    }
+}
+
+/// Tasks as a job stores them, comma separated.
+pub fn encode_tasks(tasks: &[MaintenanceTask]) -> String {
+    tasks.iter().map(|x| x.name()).collect::<Vec<_>>().join(",")
+}
+
+pub fn decode_tasks(tasks: &str) -> Vec<MaintenanceTask> {
+    tasks
+        .split(',')
+        .filter_map(MaintenanceTask::parse)
+        .collect()
+}
+
+/// Runs `tasks` one after the other in the repository, stopping at the first that fails.
+/// Objects are repacked and pruned alongside pushes, as git itself does in the background;
+/// only the steps that rewrite refs take the lock `lock` returns.
+pub async fn run_tasks<L, F>(
+    git: &GitContext,
+    tasks: &[MaintenanceTask],
+    config: &AppGitMaintenance,
+    lock: L,
+) -> io::Result<()>
+where
+    L: Fn() -> F,
+    F: Future<Output = Result<RepoLock, AppError>>,
+{
+    for task in tasks {
+        let op = GitOp::Maintenance {
+            task: *task,
+            prune_expire: config.prune_expire.clone(),
+        };
+        run_task(git, op).await?;
+        if task.packs_refs() {
+            let _lock = lock().await.map_err(|e| io::Error::other(e.msg))?;
+            run_task(git, GitOp::PackRefs).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_task(git: &GitContext, op: GitOp) -> io::Result<()> {
+    let name = op.name();
+    let output = git.git(op)?.output().await?;
+    if output.code != 0 {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Queues a job unless the repository has one queued or running already.
+pub async fn enqueue_maintenance(
+    db: &DatabaseConnection,
+    repo_uid: Uuid,
+    tasks: &[MaintenanceTask],
+    trigger: &str,
+) -> Result<repo_maintenance_jobs::Model, AppError> {
+    let active = repo_maintenance_jobs::Entity::find()
+        .filter(repo_maintenance_jobs::Column::RepoUid.eq(repo_uid))
+        .filter(repo_maintenance_jobs::Column::Status.is_in([JOB_QUEUED, JOB_RUNNING]))
+        .one(db)
+        .await?;
+    if active.is_some() {
+        return Err(AppError::from(anyhow!("Maintenance already queued")));
+    }
+    if tasks.is_empty() {
+        return Err(AppError::from(anyhow!("No maintenance tasks")));
+    }
+    Ok(repo_maintenance_jobs::ActiveModel {
+        uid: Set(Uuid::now_v7()),
+        repo_uid: Set(repo_uid),
+        tasks: Set(encode_tasks(tasks)),
+        trigger: Set(trigger.to_string()),
+        status: Set(JOB_QUEUED.to_string()),
+        error: Set(None),
+        created_at: Set(Utc::now().naive_utc()),
+        started_at: Set(None),
+        finished_at: Set(None),
+    }
+    .insert(db)
+    .await?)
+}
+
+/// Where a repository stands with its housekeeping, the latest jobs first.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MaintenanceStatus {
+    pub pushes_since_maintenance: i32,
+    pub maintained_at: Option<chrono::NaiveDateTime>,
+    pub jobs: Vec<repo_maintenance_jobs::Model>,
+}
+
+impl MaintenanceStatus {
+    pub async fn load(db: &DatabaseConnection, repo: &git_repo::Model) -> Result<Self, AppError> {
+        let jobs = repo_maintenance_jobs::Entity::find()
+            .filter(repo_maintenance_jobs::Column::RepoUid.eq(repo.uid))
+            .order_by_desc(repo_maintenance_jobs::Column::CreatedAt)
+            .limit(HISTORY_SIZE)
+            .all(db)
+            .await?;
+        Ok(Self {
+            pushes_since_maintenance: repo.pushes_since_maintenance,
+            maintained_at: repo.maintained_at,
+            jobs,
+        })
+    }
+}
+
+impl GitServer {
+    /// Queues jobs for repositories due for maintenance, returns how many.
+    pub async fn schedule_maintenance(&self) -> Result<usize, AppError> {
+        let config = &self.config.git.maintenance;
+        let cutoff = Utc::now().naive_utc() - chrono::Duration::seconds(config.max_age as i64);
+        let aged = Condition::all()
+            .add(git_repo::Column::PushesSinceMaintenance.gt(0))
+            .add(
+                Condition::any()
+                    .add(git_repo::Column::MaintainedAt.lt(cutoff))
+                    .add(
+                        Condition::all()
+                            .add(git_repo::Column::MaintainedAt.is_null())
+                            .add(git_repo::Column::CreatedAt.lt(cutoff)),
+                    ),
+            );
+        let repos = git_repo::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(git_repo::Column::PushesSinceMaintenance.gte(config.push_threshold))
+                    .add(aged),
+            )
+            .all(&self.db)
+            .await?;
+        let active = repo_maintenance_jobs::Entity::find()
+            .filter(repo_maintenance_jobs::Column::Status.is_in([JOB_QUEUED, JOB_RUNNING]))
+            .filter(repo_maintenance_jobs::Column::RepoUid.is_in(repos.iter().map(|x| x.uid)))
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|x| x.repo_uid)
+            .collect::<HashSet<_>>();
+        let mut queued = 0;
+        for repo in repos.iter().filter(|x| !active.contains(&x.uid)) {
+            let aged = repo.maintained_at.unwrap_or(repo.created_at) < cutoff;
+            let (tasks, trigger) = if aged {
+                (MaintenanceTask::FULL, TRIGGER_AGE)
+            } else {
+                (MaintenanceTask::INCREMENTAL, TRIGGER_PUSHES)
+            };
+            enqueue_maintenance(&self.db, repo.uid, tasks, trigger).await?;
+            queued += 1;
+        }
+        Ok(queued)
+    }
+    /// Takes the oldest queued job. Workers of several processes may race for it, only the
+    /// one whose update finds it still queued gets it.
+    pub async fn claim_maintenance(
+        &self,
+    ) -> Result<Option<repo_maintenance_jobs::Model>, AppError> {
+        loop {
+            let Some(job) = repo_maintenance_jobs::Entity::find()
+                .filter(repo_maintenance_jobs::Column::Status.eq(JOB_QUEUED))
+                .order_by_asc(repo_maintenance_jobs::Column::CreatedAt)
+                .one(&self.db)
+                .await?
+            else {
+                return Ok(None);
+            };
+            let now = Utc::now().naive_utc();
+            let claimed = repo_maintenance_jobs::Entity::update_many()
+                .col_expr(
+                    repo_maintenance_jobs::Column::Status,
+                    Expr::value(JOB_RUNNING),
+                )
+                .col_expr(repo_maintenance_jobs::Column::StartedAt, Expr::value(now))
+                .filter(repo_maintenance_jobs::Column::Uid.eq(job.uid))
+                .filter(repo_maintenance_jobs::Column::Status.eq(JOB_QUEUED))
+                .exec(&self.db)
+                .await?;
+            if claimed.rows_affected == 1 {
+                return Ok(Some(repo_maintenance_jobs::Model {
+                    status: JOB_RUNNING.to_string(),
+                    started_at: Some(now),
+                    ..job
+                }));
+            }
+        }
+    }
+    /// Runs a claimed job, see `run_tasks` for what of it holds the repository lock. However
+    /// it ends, the job is closed and the repository counts as maintained, a failing
+    /// repository is not retried before it is due again. Pushes during the job count
+    /// towards the next one, and their size stands over the one measured here.
+    pub async fn run_maintenance(&self, job: repo_maintenance_jobs::Model) -> Result<(), AppError> {
+        let config = &self.config.git.maintenance;
+        // a repository that cannot be resolved fails the job rather than leave it running
+        let target = self.find_repo_by_id(job.repo_uid).await.and_then(|repo| {
+            let git = GitContext::try_from((repo.clone(), self.config.git.clone()))?;
+            Ok((repo, git))
+        });
+        let pushes = target
+            .as_ref()
+            .map(|(repo, _)| repo.pushes_since_maintenance)
+            .unwrap_or(0);
+        let result = match target {
+            Ok((repo, git)) => {
+                let tasks = decode_tasks(&job.tasks);
+                let timeout = Duration::from_secs(config.timeout);
+                let lock = || self.lock_repo_on(&repo);
+                match tokio::time::timeout(timeout, run_tasks(&git, &tasks, config, lock)).await {
+                    Ok(Ok(())) => Ok(git.measure_objects().await.ok()),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("timed out".to_string()),
+                }
+            }
+            Err(e) => Err(e.msg),
+        };
+        let now = Utc::now().naive_utc();
+        let mut active = repo_maintenance_jobs::ActiveModel {
+            uid: Set(job.uid),
+            finished_at: Set(Some(now)),
+            ..Default::default()
+        };
+        match result {
+            Ok(size) => {
+                info!("maintenance of {} done: {}", job.repo_uid, job.tasks);
+                active.status = Set(JOB_SUCCEEDED.to_string());
+                // repacking changes what the repository takes against its quota, unless a
+                // push measured it again since
+                if let Some(size) = size {
+                    git_repo::Entity::update_many()
+                        .col_expr(git_repo::Column::Size, Expr::value(size as i64))
+                        .filter(git_repo::Column::Uid.eq(job.repo_uid))
+                        .filter(git_repo::Column::PushesSinceMaintenance.eq(pushes))
+                        .exec(&self.db)
+                        .await?;
+                }
+            }
+            Err(e) => {
+                error!("maintenance of {} failed: {}", job.repo_uid, e);
+                active.status = Set(JOB_FAILED.to_string());
+                active.error = Set(Some(e));
+            }
+        }
+        active.update(&self.db).await?;
+        git_repo::Entity::update_many()
+            .col_expr(
+                git_repo::Column::PushesSinceMaintenance,
+                Expr::col(git_repo::Column::PushesSinceMaintenance).sub(pushes),
+            )
+            .col_expr(git_repo::Column::MaintainedAt, Expr::value(now))
+            .filter(git_repo::Column::Uid.eq(job.repo_uid))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+    /// Jobs left running by a process that died are failed once they ran longer than any
+    /// job may.
+    async fn expire_maintenance(&self) -> Result<(), AppError> {
+        let config = &self.config.git.maintenance;
+        let cutoff = Utc::now().naive_utc()
+            - chrono::Duration::seconds((config.timeout + config.interval) as i64);
+        repo_maintenance_jobs::Entity::update_many()
+            .col_expr(
+                repo_maintenance_jobs::Column::Status,
+                Expr::value(JOB_FAILED),
+            )
+            .col_expr(
+                repo_maintenance_jobs::Column::Error,
+                Expr::value("interrupted"),
+            )
+            .col_expr(
+                repo_maintenance_jobs::Column::FinishedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(repo_maintenance_jobs::Column::Status.eq(JOB_RUNNING))
+            .filter(repo_maintenance_jobs::Column::StartedAt.lt(cutoff))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+    /// The housekeeping loop of a process: queue what is due, then work the queue off,
+    /// every `git.maintenance.interval` seconds. Returns at once when maintenance is off.
+    pub async fn maintenance_worker(self) {
+        let config = self.config.git.maintenance.clone();
+        if !config.enabled {
+            return;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.expire_maintenance().await {
+                error!("maintenance expiry failed: {}", e.msg);
+            }
+            if let Err(e) = self.schedule_maintenance().await {
+                error!("maintenance scheduling failed: {}", e.msg);
+            }
+            loop {
+                match self.claim_maintenance().await {
+                    Ok(Some(job)) => {
+                        if let Err(e) = self.run_maintenance(job).await {
+                            error!("maintenance failed: {}", e.msg);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("maintenance claim failed: {}", e.msg);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_maintenance_tasks() {
+    let tasks = encode_tasks(MaintenanceTask::INCREMENTAL);
+    assert_eq!(tasks, "loose-objects,commit-graph,multi-pack-index");
+    assert_eq!(decode_tasks(&tasks), MaintenanceTask::INCREMENTAL);
+    assert_eq!(
+        decode_tasks("gc,unknown,prune"),
+        [MaintenanceTask::Gc, MaintenanceTask::Prune]
+    );
+    assert_eq!(
+        serde_json::to_string(&MaintenanceTask::MultiPackIndex).unwrap(),
+        r#""multi-pack-index""#
+    );
+}
+
+#[tokio::test]
+async fn test_run_tasks() {
+    use crate::testing::{self, TempDir};
+    use config::AppConfig;
+    use git2::Repository;
+    use std::path::Path;
+
+    let dir = TempDir::new("maintenance");
+    let repo = Repository::init_bare(&*dir).unwrap();
+    let mut parents = vec![];
+    for i in 0..3 {
+        let row = format!("row {}\n", i);
+        let commit = testing::commit(
+            &repo,
+            "refs/heads/main",
+            &[("data.csv", row.as_bytes())],
+            &parents,
+        );
+        parents = vec![commit];
+    }
+    let loose = |dir: &Path| {
+        std::fs::read_dir(dir.join("objects"))
+            .unwrap()
+            .flatten()
+            .filter(|x| x.file_name().len() == 2)
+            .map(|x| std::fs::read_dir(x.path()).unwrap().count())
+            .sum::<usize>()
+    };
+    let files = |dir: &Path, suffix: &str| {
+        std::fs::read_dir(dir.join("objects/pack"))
+            .unwrap()
+            .flatten()
+            .filter(|x| x.file_name().to_string_lossy().ends_with(suffix))
+            .count()
+    };
+    assert!(loose(&dir) > 0);
+    let git = GitContext {
+        path_dir: dir.to_path_buf(),
+        remote: None,
+    };
+    let config = AppGitMaintenance::default();
+    let mut server = AppConfig::default();
+    server.git.lock.timeout = 1;
+    let server = testing::git_server(server);
+    let lock = || server.lock_repo(Uuid::nil());
+    run_tasks(&git, MaintenanceTask::INCREMENTAL, &config, lock)
+        .await
+        .unwrap();
+    assert_eq!(loose(&dir), 0);
+    assert_eq!(files(&dir, ".pack"), 1);
+    assert!(dir.join("objects/pack/multi-pack-index").exists());
+    assert!(dir.join("objects/info/commit-graphs").exists());
+    run_tasks(&git, MaintenanceTask::FULL, &config, lock)
+        .await
+        .unwrap();
+    assert_eq!(files(&dir, ".bitmap"), 1);
+    assert!(dir.join("packed-refs").exists());
+    assert!(!dir.join("refs/heads/main").exists());
+    // refs are only packed once the lock is had
+    let busy = RepoLock::acquire(&server.redis, &server.config.git.lock, Uuid::nil())
+        .await
+        .unwrap();
+    let gc = run_tasks(&git, &[MaintenanceTask::Gc], &config, lock).await;
+    assert!(gc.is_err());
+    drop(busy);
+    // a task git refuses fails the run
+    let config = AppGitMaintenance {
+        prune_expire: "someday".to_string(),
+        ..config
+    };
+    let Err(e) = run_tasks(&git, &[MaintenanceTask::Prune], &config, lock).await else {
+        panic!("pruned with a malformed expiry");
+    };
+    assert!(e.to_string().starts_with("prune failed: "));
+}
@@ -14,8 +14,21 @@ pub struct GitServer {
     pub redis: Pool<RedisConnectionManager>,
 }
 
+pub mod auth;
+pub mod deploy_key;
 pub mod find;
+pub mod integrity;
+pub mod limit;
+pub mod lock;
+pub mod maintenance;
+pub mod partial_clone;
 pub mod permissions;
+pub mod placement;
+pub mod protection;
+pub mod quota;
+pub mod ssh_ca;
+pub mod stats;
+pub mod storage_migration;
 pub mod sync;
 
 impl GitServer {
@@ -0,0 +1,180 @@
+use crate::service::GitServer;
+use database::entity::git_repo;
+use serde::{Deserialize, Serialize};
+
+/// Filter kinds git upload-pack can be told to allow one by one.
+pub const FILTER_KINDS: &[&str] = &[
+    "blob:none",
+    "blob:limit",
+    "tree",
+    "sparse:oid",
+    "object:type",
+    "combine",
+];
+
+/// The partial clone filters upload-pack accepts for a repository. Empty when the repository
+/// did not turn partial clone on or the site does not allow it, git then refuses `filter`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct FilterPolicy {
+    pub filters: Vec<String>,
+    pub tree_max_depth: Option<u64>,
+}
+
+impl FilterPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+    /// `-c` settings for git upload-pack. Promisor remotes fetch missing blobs lazily by
+    /// asking for them by id, which v0 only allows with `allowAnySHA1InWant`.
+    pub fn git_config(&self) -> Vec<String> {
+        if self.is_empty() {
+            return vec![];
+        }
+        let mut config = vec![
+            "uploadpack.allowFilter=true".to_string(),
+            "uploadpack.allowAnySHA1InWant=true".to_string(),
+            "uploadpackfilter.allow=false".to_string(),
+        ];
+        for filter in &self.filters {
+            config.push(format!("uploadpackfilter.{}.allow=true", filter));
+        }
+        if let Some(depth) = self.tree_max_depth {
+            config.push(format!("uploadpackfilter.tree.maxDepth={}", depth));
+        }
+        config
+    }
+}
+
+impl GitServer {
+    /// What `repo` allows, limited by the filters the site allows.
+    pub fn filter_policy(&self, repo: &git_repo::Model) -> FilterPolicy {
+        let config = &self.config.git.partial_clone;
+        if !config.enabled || !repo.partial_clone {
+            return FilterPolicy::default();
+        }
+        FilterPolicy {
+            filters: config
+                .filters
+                .iter()
+                .filter(|x| FILTER_KINDS.contains(&x.as_str()))
+                .cloned()
+                .collect(),
+            tree_max_depth: config.tree_max_depth,
+        }
+    }
+}
+
+#[test]
+fn test_filter_policy_config() {
+    assert!(FilterPolicy::default().git_config().is_empty());
+    let policy = FilterPolicy {
+        filters: vec!["blob:none".to_string(), "tree".to_string()],
+        tree_max_depth: Some(0),
+    };
+    assert_eq!(
+        policy.git_config(),
+        vec![
+            "uploadpack.allowFilter=true",
+            "uploadpack.allowAnySHA1InWant=true",
+            "uploadpackfilter.allow=false",
+            "uploadpackfilter.blob:none.allow=true",
+            "uploadpackfilter.tree.allow=true",
+            "uploadpackfilter.tree.maxDepth=0",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_filtered_fetch() {
+    use crate::service::protection::BranchProtection;
+    use crate::testing::{self, TempDir};
+    use crate::transport::GitService;
+    use crate::transport::backend::{GitPack, PackIo, PackRequest, SubprocessPack};
+    use crate::transport::pkt::{DELIM_PKT, FLUSH_PKT, encode};
+    use crate::transport::protocol::GitProtocol;
+    use git2::Repository;
+    use std::path::{Path, PathBuf};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = TempDir::new("filter");
+    let repo = Repository::init_bare(&*dir).unwrap();
+    let data: &[u8] = b"id,value\n1,2\n";
+    let commit = testing::commit(&repo, "refs/heads/main", &[("data.csv", data)], &[]);
+    let blob = repo.blob(data).unwrap();
+
+    // objects in the pack of a response, `None` when there is none
+    async fn fetch(
+        dir: &Path,
+        filter: &FilterPolicy,
+        protocol: Option<GitProtocol>,
+        stateless: bool,
+        input: Vec<u8>,
+    ) -> Option<u32> {
+        let PackIo {
+            mut stdin,
+            mut stdout,
+            stderr: _stderr,
+            mut child,
+        } = SubprocessPack
+            .spawn(PackRequest {
+                service: GitService::UploadPack,
+                path: PathBuf::from(dir),
+                protocol,
+                stateless,
+                advertise_refs: false,
+                protection: BranchProtection::default(),
+                filter: filter.clone(),
+                max_input_size: None,
+            })
+            .unwrap();
+        stdin.write_all(&input).await.ok();
+        stdin.shutdown().await.ok();
+        let mut out = vec![];
+        stdout.read_to_end(&mut out).await.unwrap();
+        child.wait().await.unwrap();
+        let start = out.windows(4).position(|x| x == b"PACK")?;
+        let count = out.get(start + 8..start + 12)?;
+        Some(u32::from_be_bytes(count.try_into().unwrap()))
+    }
+
+    let policy = FilterPolicy {
+        filters: vec!["blob:none".to_string()],
+        tree_max_depth: None,
+    };
+    // a stateless round, as over HTTP
+    let v0 = |want: String, filter: &str| {
+        let mut input = encode(want.as_bytes());
+        if !filter.is_empty() {
+            input.extend(encode(format!("filter {}\n", filter).as_bytes()));
+        }
+        [input, FLUSH_PKT.to_vec(), encode(b"done\n")].concat()
+    };
+    let filtered = v0(format!("want {} filter\n", commit), "blob:none");
+    assert_eq!(
+        fetch(&dir, &policy, None, true, filtered.clone()).await,
+        Some(2)
+    );
+    let full = v0(format!("want {}\n", commit), "");
+    assert_eq!(fetch(&dir, &policy, None, true, full).await, Some(3));
+    assert_eq!(
+        fetch(&dir, &FilterPolicy::default(), None, true, filtered).await,
+        None
+    );
+    // the lazy fetch of a promisor remote asks for the blob alone
+    let lazy = v0(format!("want {}\n", blob), "");
+    assert_eq!(fetch(&dir, &policy, None, true, lazy).await, Some(1));
+
+    // a whole v2 session, as over SSH
+    let session = [
+        encode(b"command=fetch\n"),
+        DELIM_PKT.to_vec(),
+        encode(format!("want {}\n", commit).as_bytes()),
+        encode(b"filter blob:none\n"),
+        encode(b"done\n"),
+        FLUSH_PKT.to_vec(),
+        FLUSH_PKT.to_vec(),
+    ]
+    .concat();
+    let v2 = GitProtocol::parse("version=2", true);
+    assert_eq!(fetch(&dir, &policy, v2, false, session).await, Some(2));
+}
@@ -1,11 +1,37 @@
 use crate::service::GitServer;
 use database::entity::{git_repo, user_repo, users};
+use error::AppError;
 use sea_orm::ColumnTrait;
 use sea_orm::EntityTrait;
 use sea_orm::QueryFilter;
+use sea_orm::{Condition, QueryOrder};
+use serde::{Deserialize, Serialize};
+
+// access 0 no 1 read 2 read and write, same scale as `user_access_keys.repo_access`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+pub enum RepoAccess {
+    None = 0,
+    Read = 1,
+    Write = 2,
+}
 
 impl GitServer {
-    pub async fn ssh_permissions(&self, repo: git_repo::Model, user: users::Model) -> bool {
+    pub async fn repo_access(
+        &self,
+        repo: &git_repo::Model,
+        user: Option<&users::Model>,
+    ) -> RepoAccess {
+        let public = if repo.is_private {
+            RepoAccess::None
+        } else {
+            RepoAccess::Read
+        };
+        let Some(user) = user else {
+            return public;
+        };
+        if user.username == repo.namespace {
+            return RepoAccess::Write;
+        }
         if let Ok(list) = user_repo::Entity::find()
             .filter(user_repo::Column::UserUid.eq(user.uid))
             .all(&self.db)
@@ -13,10 +39,39 @@ impl GitServer {
         {
             for item in list {
                 if item.repo_uid == repo.uid {
-                    return true;
+                    return RepoAccess::Write;
                 }
             }
         }
-        false
+        public
+    }
+    /// Repositories `user` owns or is a member of, public ones of others are left out.
+    pub async fn accessible_repos(
+        &self,
+        user: &users::Model,
+    ) -> Result<Vec<(git_repo::Model, RepoAccess)>, AppError> {
+        let members = user_repo::Entity::find()
+            .filter(user_repo::Column::UserUid.eq(user.uid))
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|x| x.repo_uid)
+            .collect::<Vec<_>>();
+        let repos = git_repo::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(git_repo::Column::Namespace.eq(user.username.as_str()))
+                    .add(git_repo::Column::Uid.is_in(members)),
+            )
+            .order_by_asc(git_repo::Column::Namespace)
+            .order_by_asc(git_repo::Column::RepoName)
+            .all(&self.db)
+            .await?;
+        Ok(repos.into_iter().map(|x| (x, RepoAccess::Write)).collect())
+    }
+    /// Owners may override what members do, such as force unlocking their LFS locks.
+    pub fn repo_admin(&self, repo: &git_repo::Model, user: &users::Model) -> bool {
+        // TODO team
+        user.username == repo.namespace
     }
 }
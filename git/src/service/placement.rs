@@ -0,0 +1,137 @@
+use crate::service::GitServer;
+use anyhow::anyhow;
+use config::git::{AppGitConfig, PlacementPolicy};
+use database::entity::git_repo;
+use error::AppError;
+use sea_orm::{EntityTrait, QuerySelect};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What a storage holds, as far as the database knows.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct StorageUsage {
+    pub name: String,
+    pub repos: u64,
+    pub size: u64,
+}
+
+/// The storage a new repository of `owner` goes to, see `AppGitPlacement`.
+pub fn place(
+    config: &AppGitConfig,
+    owner: &str,
+    usage: &[StorageUsage],
+) -> Result<String, AppError> {
+    if let Some(pin) = config.placement.pins.iter().find(|x| x.owner == owner) {
+        if !config.storage.iter().any(|x| x.name == pin.storage) {
+            return Err(AppError::from(anyhow!(
+                "storage {} pinned for {} is not configured",
+                pin.storage,
+                owner
+            )));
+        }
+        return Ok(pin.storage.clone());
+    }
+    let used = |name: &str| {
+        usage
+            .iter()
+            .find(|x| x.name == name)
+            .cloned()
+            .unwrap_or_default()
+    };
+    let candidates = config
+        .storage
+        .iter()
+        .filter(|x| x.weight > 0)
+        .map(|x| (x, used(&x.name)));
+    let chosen = match config.placement.policy {
+        PlacementPolicy::LeastUsed => candidates.min_by_key(|(_, used)| used.size),
+        // the storage furthest below its share once it took the repository
+        PlacementPolicy::Weighted => candidates.min_by(|(a, a_used), (b, b_used)| {
+            ((a_used.repos + 1) as u128 * b.weight as u128)
+                .cmp(&((b_used.repos + 1) as u128 * a.weight as u128))
+        }),
+    };
+    chosen
+        .map(|(storage, _)| storage.name.clone())
+        .ok_or(AppError::from(anyhow!("No storage takes new repositories")))
+}
+
+impl GitServer {
+    /// Repositories and their bytes per storage name.
+    pub async fn storage_usage(&self) -> Result<Vec<StorageUsage>, AppError> {
+        let repos = git_repo::Entity::find()
+            .select_only()
+            .column(git_repo::Column::Storage)
+            .column(git_repo::Column::Size)
+            .into_tuple::<(String, i64)>()
+            .all(&self.db)
+            .await?;
+        let mut usage = HashMap::<String, StorageUsage>::new();
+        for (storage, size) in repos {
+            let entry = usage.entry(storage.clone()).or_insert(StorageUsage {
+                name: storage,
+                ..Default::default()
+            });
+            entry.repos += 1;
+            entry.size += size.max(0) as u64;
+        }
+        let mut usage = usage.into_values().collect::<Vec<_>>();
+        usage.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(usage)
+    }
+    /// Picks the storage for a new repository of `owner`.
+    pub async fn place_repo(&self, owner: &str) -> Result<String, AppError> {
+        let usage = self.storage_usage().await?;
+        place(&self.config.git, owner, &usage)
+    }
+}
+
+#[test]
+fn test_place() {
+    use config::git::{AppGitPin, AppGitStorage};
+    use std::path::PathBuf;
+
+    let storage = |name: &str, weight| AppGitStorage {
+        name: name.to_string(),
+        path: PathBuf::from(name),
+        storage_type: None,
+        address: None,
+        secret: String::new(),
+        lfs_path: None,
+        weight,
+    };
+    let usage = |name: &str, repos, size| StorageUsage {
+        name: name.to_string(),
+        repos,
+        size,
+    };
+    let mut config = AppGitConfig {
+        storage: vec![storage("a", 1), storage("b", 3), storage("c", 0)],
+        ..AppGitConfig::default()
+    };
+    let usage = [usage("a", 2, 100), usage("b", 5, 500), usage("c", 0, 0)];
+    // c takes no new repositories, however empty it is
+    assert_eq!(place(&config, "alice", &usage).unwrap(), "a");
+    assert_eq!(place(&config, "alice", &[]).unwrap(), "a");
+
+    // b has three times the share of a: 6/3 is less than 3/1
+    config.placement.policy = PlacementPolicy::Weighted;
+    assert_eq!(place(&config, "alice", &usage).unwrap(), "b");
+
+    config.placement.pins = vec![
+        AppGitPin {
+            owner: "alice".to_string(),
+            storage: "c".to_string(),
+        },
+        AppGitPin {
+            owner: "bob".to_string(),
+            storage: "missing".to_string(),
+        },
+    ];
+    assert_eq!(place(&config, "alice", &usage).unwrap(), "c");
+    assert!(place(&config, "bob", &usage).is_err());
+    assert_eq!(place(&config, "carol", &usage).unwrap(), "b");
+
+    config.storage = vec![storage("c", 0)];
+    assert!(place(&config, "carol", &usage).is_err());
+}
@@ -0,0 +1,342 @@
+use crate::service::GitServer;
+use crate::transport::push::RefCommand;
+use database::entity::{branch_protection, branch_protection_pusher, git_repo};
+use error::AppError;
+use git2::{Oid, Repository};
+use sea_orm::prelude::Uuid;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+/// The ref status git shows for a push turned down by a rule.
+pub const PROTECTED_REASON: &str = "protected branch hook declined";
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BranchRule {
+    pub pattern: String,
+    pub block_force_push: bool,
+    pub block_deletion: bool,
+    pub require_linear_history: bool,
+    pub restrict_push: bool,
+    pub pushers: Vec<Uuid>,
+}
+
+/// The branch protection rules of a repository. Patterns are matched against the branch
+/// name without `refs/heads/`, `*` matches any run of characters including `/` and `?` a
+/// single one, the same as a shell `case` so the pre-receive hook agrees with us.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BranchProtection {
+    pub rules: Vec<BranchRule>,
+    /// Files locked through LFS by someone other than the pusher, filled in when the
+    /// repository enforces locks. No commit a push adds to a branch may touch them.
+    pub locked_paths: Vec<String>,
+    /// Who pushes, for the checks receive-pack repeats before it moves a ref.
+    pub pusher: Option<Uuid>,
+}
+
+impl BranchProtection {
+    pub async fn load(db: &DatabaseConnection, repo_uid: Uuid) -> Result<Self, AppError> {
+        let rules = branch_protection::Entity::find()
+            .filter(branch_protection::Column::RepoUid.eq(repo_uid))
+            .all(db)
+            .await?;
+        if rules.is_empty() {
+            return Ok(Self::default());
+        }
+        let pushers = branch_protection_pusher::Entity::find()
+            .filter(branch_protection_pusher::Column::RuleUid.is_in(rules.iter().map(|x| x.uid)))
+            .all(db)
+            .await?;
+        let rules = rules
+            .into_iter()
+            .map(|rule| BranchRule {
+                pushers: pushers
+                    .iter()
+                    .filter(|x| x.rule_uid == rule.uid)
+                    .map(|x| x.user_uid)
+                    .collect(),
+                pattern: rule.pattern,
+                block_force_push: rule.block_force_push,
+                block_deletion: rule.block_deletion,
+                require_linear_history: rule.require_linear_history,
+                restrict_push: rule.restrict_push,
+            })
+            .collect();
+        Ok(Self {
+            rules,
+            locked_paths: vec![],
+            pusher: None,
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.locked_paths.is_empty()
+    }
+    pub fn matching<'a>(&'a self, branch: &'a str) -> impl Iterator<Item = &'a BranchRule> {
+        self.rules
+            .iter()
+            .filter(move |rule| glob_match(&rule.pattern, branch))
+    }
+    pub fn check_delete(&self, branch: &str) -> Result<(), String> {
+        if self.matching(branch).any(|rule| rule.block_deletion) {
+            return Err(format!(
+                "deleting protected branch '{}' is not allowed",
+                branch
+            ));
+        }
+        Ok(())
+    }
+    /// Every matching rule that restricts pushes has to list the pusher.
+    pub fn check_pusher(&self, branch: &str, pusher: Option<Uuid>) -> Result<(), String> {
+        let allowed = self
+            .matching(branch)
+            .filter(|rule| rule.restrict_push)
+            .all(|rule| pusher.is_some_and(|user| rule.pushers.contains(&user)));
+        if !allowed {
+            return Err(format!(
+                "you are not allowed to push to protected branch '{}'",
+                branch
+            ));
+        }
+        Ok(())
+    }
+    /// Checks that only need the command list.
+    pub fn check_command(&self, command: &RefCommand, pusher: Option<Uuid>) -> Result<(), String> {
+        let Some(branch) = command.branch() else {
+            return Ok(());
+        };
+        if command.is_delete() {
+            self.check_delete(branch)?;
+        }
+        self.check_pusher(branch, pusher)
+    }
+    pub fn check_commands(&self, commands: &[RefCommand], pusher: Option<Uuid>) -> Vec<String> {
+        commands
+            .iter()
+            .filter_map(|command| self.check_command(command, pusher).err())
+            .collect()
+    }
+    /// Checks that need the pushed objects, run once the pack is in but before any ref
+    /// moves.
+    pub fn check_history(&self, repo: &Repository, command: &RefCommand) -> Result<(), String> {
+        let Some(branch) = command.branch() else {
+            return Ok(());
+        };
+        if command.is_delete() {
+            return Ok(());
+        }
+        if !self.locked_paths.is_empty()
+            && let Some(path) = first_locked(repo, command, &self.locked_paths)
+                .map_err(|e| e.message().to_string())?
+        {
+            return Err(format!("'{}' is locked by another user", path));
+        }
+        let rules = self.matching(branch).collect::<Vec<_>>();
+        if !command.is_create()
+            && rules.iter().any(|rule| rule.block_force_push)
+            && command.old != command.new
+            && !repo
+                .graph_descendant_of(command.new, command.old)
+                .unwrap_or(false)
+        {
+            return Err(format!(
+                "force pushing to protected branch '{}' is not allowed",
+                branch
+            ));
+        }
+        if rules.iter().any(|rule| rule.require_linear_history)
+            && let Some(merge) = first_merge(repo, command).map_err(|e| e.message().to_string())?
+        {
+            return Err(format!(
+                "protected branch '{}' requires linear history, merge commit {} is not allowed",
+                branch, merge
+            ));
+        }
+        Ok(())
+    }
+    pub fn deletion_patterns(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.block_deletion)
+            .map(|rule| rule.pattern.as_str())
+            .collect()
+    }
+    /// Patterns of the rules that restrict pushes and do not list the pusher.
+    pub fn restricted_patterns(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.restrict_push)
+            .filter(|rule| !self.pusher.is_some_and(|user| rule.pushers.contains(&user)))
+            .map(|rule| rule.pattern.as_str())
+            .collect()
+    }
+    pub fn force_push_patterns(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.block_force_push)
+            .map(|rule| rule.pattern.as_str())
+            .collect()
+    }
+    pub fn linear_history_patterns(&self) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.require_linear_history)
+            .map(|rule| rule.pattern.as_str())
+            .collect()
+    }
+}
+
+/// A merge commit among those the push adds to the branch.
+fn first_merge(repo: &Repository, command: &RefCommand) -> Result<Option<Oid>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.push(command.new)?;
+    if command.is_create() {
+        walk.hide_glob("refs/*")?;
+    } else {
+        walk.hide(command.old)?;
+    }
+    for oid in walk {
+        let oid = oid?;
+        if repo.find_commit(oid)?.parent_count() > 1 {
+            return Ok(Some(oid));
+        }
+    }
+    Ok(None)
+}
+
+/// A locked path touched by one of the commits the push adds. Merges are skipped, as
+/// `git log --name-only` does in the hook.
+fn first_locked(
+    repo: &Repository,
+    command: &RefCommand,
+    locked: &[String],
+) -> Result<Option<String>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.push(command.new)?;
+    if command.is_create() {
+        walk.hide_glob("refs/*")?;
+    } else {
+        walk.hide(command.old)?;
+    }
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let parent = match commit.parent_count() {
+            0 => None,
+            1 => Some(commit.parent(0)?.tree()?),
+            _ => continue,
+        };
+        let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+        for delta in diff.deltas() {
+            for file in [delta.old_file(), delta.new_file()] {
+                if let Some(path) = file.path().and_then(|x| x.to_str())
+                    && locked.iter().any(|x| x == path)
+                {
+                    return Ok(Some(path.to_string()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Patterns are limited to ref name characters plus `*` and `?`, which keeps them safe to
+/// hand to the hook through the environment.
+pub fn valid_pattern(pattern: &str) -> bool {
+    !pattern.is_empty()
+        && pattern.len() <= 255
+        && !pattern.starts_with('/')
+        && !pattern.contains("..")
+        && pattern
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._/-*?".contains(c))
+}
+
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+impl GitServer {
+    /// The rules a push by `pusher` to `repo` is checked against.
+    pub async fn branch_protection(
+        &self,
+        repo: &git_repo::Model,
+        pusher: Option<Uuid>,
+    ) -> Result<BranchProtection, AppError> {
+        let mut protection = BranchProtection::load(&self.db, repo.uid).await?;
+        protection.pusher = pusher;
+        if repo.lfs_lock_enforced {
+            protection.locked_paths = self.lfs_locked_paths(repo.uid, pusher).await?;
+        }
+        Ok(protection)
+    }
+}
+
+#[test]
+fn test_branch_protection() {
+    assert!(glob_match("main", "main"));
+    assert!(!glob_match("main", "main2"));
+    assert!(glob_match("release/*", "release/1.0"));
+    assert!(glob_match("release/*", "release/1.0/hotfix"));
+    assert!(glob_match("v?.*", "v1.2"));
+    assert!(glob_match("*", "anything"));
+    assert!(!glob_match("release/*", "feature/release"));
+    assert!(valid_pattern("release/*"));
+    assert!(!valid_pattern("main branch"));
+    assert!(!valid_pattern("$(reboot)"));
+
+    let owner = Uuid::new_v4();
+    let protection = BranchProtection {
+        rules: vec![BranchRule {
+            pattern: "main".to_string(),
+            block_force_push: true,
+            block_deletion: true,
+            require_linear_history: false,
+            restrict_push: true,
+            pushers: vec![owner],
+        }],
+        locked_paths: vec![],
+        pusher: Some(owner),
+    };
+    let delete = RefCommand {
+        old: Oid::from_str("1111111111111111111111111111111111111111").unwrap(),
+        new: Oid::zero(),
+        name: "refs/heads/main".to_string(),
+    };
+    assert!(protection.check_command(&delete, Some(owner)).is_err());
+    let update = RefCommand {
+        new: delete.old,
+        old: delete.old,
+        name: delete.name.clone(),
+    };
+    assert!(protection.check_command(&update, Some(owner)).is_ok());
+    assert!(protection.check_command(&update, Some(Uuid::new_v4())).is_err());
+    assert!(protection.check_command(&update, None).is_err());
+    let other = RefCommand {
+        name: "refs/heads/dev".to_string(),
+        ..delete
+    };
+    assert!(protection.check_command(&other, None).is_ok());
+    assert_eq!(protection.deletion_patterns(), vec!["main"]);
+    assert!(protection.restricted_patterns().is_empty());
+    let stranger = BranchProtection {
+        pusher: None,
+        ..protection.clone()
+    };
+    assert_eq!(stranger.restricted_patterns(), vec!["main"]);
+}
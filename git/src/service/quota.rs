@@ -0,0 +1,185 @@
+use crate::service::GitServer;
+use crate::transport::push::RefCommand;
+use config::git::AppGitQuota;
+use database::entity::{git_repo, lfs_objects, lfs_repo_objects};
+use error::AppError;
+use sea_orm::prelude::Uuid;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// The `ng` reason of refs in a push turned down for its size.
+pub const QUOTA_REASON: &str = "quota exceeded";
+
+/// Storage taken by a repository or by all repositories of a user against the quota,
+/// `limit` is `None` without one.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct QuotaUsage {
+    pub git_size: u64,
+    pub lfs_size: u64,
+    pub limit: Option<u64>,
+}
+
+impl QuotaUsage {
+    pub fn used(&self) -> u64 {
+        self.git_size + self.lfs_size
+    }
+    /// Bytes left before the quota is used up.
+    pub fn remaining(&self) -> Option<u64> {
+        self.limit.map(|x| x.saturating_sub(self.used()))
+    }
+    pub async fn repo(
+        db: &DatabaseConnection,
+        quota: &AppGitQuota,
+        repo: &git_repo::Model,
+    ) -> Result<Self, AppError> {
+        Ok(Self {
+            git_size: repo.size.max(0) as u64,
+            lfs_size: lfs_size(db, vec![repo.uid]).await?,
+            limit: (quota.per_repo > 0).then_some(quota.per_repo),
+        })
+    }
+    /// Usage of the repositories in the namespace of `username`.
+    pub async fn user(
+        db: &DatabaseConnection,
+        quota: &AppGitQuota,
+        username: &str,
+    ) -> Result<Self, AppError> {
+        let repos = git_repo::Entity::find()
+            .filter(git_repo::Column::Namespace.eq(username))
+            .all(db)
+            .await?;
+        Ok(Self {
+            git_size: repos.iter().map(|x| x.size.max(0) as u64).sum(),
+            lfs_size: lfs_size(db, repos.iter().map(|x| x.uid).collect()).await?,
+            limit: (quota.per_user > 0).then_some(quota.per_user),
+        })
+    }
+}
+
+/// LFS objects linked to the repositories, an object linked to several counts for each.
+async fn lfs_size(db: &DatabaseConnection, repos: Vec<Uuid>) -> Result<u64, AppError> {
+    let links = lfs_repo_objects::Entity::find()
+        .filter(lfs_repo_objects::Column::RepoUid.is_in(repos))
+        .all(db)
+        .await?;
+    let mut oids = links.iter().map(|x| x.oid.clone()).collect::<Vec<_>>();
+    oids.sort();
+    oids.dedup();
+    let mut sizes = HashMap::new();
+    for chunk in oids.chunks(1000) {
+        sizes.extend(
+            lfs_objects::Entity::find()
+                .filter(lfs_objects::Column::Oid.is_in(chunk.to_vec()))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| (x.oid, x.size.max(0) as u64)),
+        );
+    }
+    Ok(links.iter().filter_map(|x| sizes.get(&x.oid)).sum())
+}
+
+/// Bytes of everything under `objects`, loose objects and packs alike.
+pub fn objects_size(repo: &Path) -> io::Result<u64> {
+    fn walk(dir: &Path) -> io::Result<u64> {
+        let mut size = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                size += walk(&entry.path())?;
+            } else {
+                size += meta.len();
+            }
+        }
+        Ok(size)
+    }
+    walk(&repo.join("objects"))
+}
+
+/// What a push may still add, the least of what its repository and the repository owner
+/// have left. `None` when neither has a quota.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PushQuota {
+    pub remaining: Option<u64>,
+}
+
+impl PushQuota {
+    /// Why a push of `commands` is turned down before receive-pack accepts any objects:
+    /// nothing is left, or the request is known to be larger than what is left. Pushes that
+    /// only delete refs send no objects and always go through.
+    pub fn check(&self, commands: &[RefCommand], size: Option<u64>) -> Option<String> {
+        let remaining = self.remaining?;
+        if commands.iter().all(|x| x.is_delete()) {
+            return None;
+        }
+        if remaining == 0 {
+            return Some("storage quota exceeded, delete data to push again".to_string());
+        }
+        match size {
+            Some(size) if size > remaining => Some(format!(
+                "storage quota exceeded, the push takes {} bytes but only {} are left",
+                size, remaining
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl GitServer {
+    pub async fn push_quota(&self, repo: &git_repo::Model) -> Result<PushQuota, AppError> {
+        let quota = &self.config.git.quota;
+        let mut remaining = QuotaUsage::repo(&self.db, quota, repo).await?.remaining();
+        if quota.per_user > 0 {
+            let user = QuotaUsage::user(&self.db, quota, &repo.namespace).await?;
+            remaining = match (remaining, user.remaining()) {
+                (Some(repo), Some(user)) => Some(repo.min(user)),
+                (repo, user) => repo.or(user),
+            };
+        }
+        Ok(PushQuota { remaining })
+    }
+}
+
+#[test]
+fn test_push_quota() {
+    use git2::Oid;
+
+    let head = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+    let update = RefCommand {
+        old: Oid::zero(),
+        new: head,
+        name: "refs/heads/main".to_string(),
+    };
+    let delete = RefCommand {
+        old: head,
+        new: Oid::zero(),
+        name: "refs/heads/dev".to_string(),
+    };
+    let unlimited = PushQuota { remaining: None };
+    assert_eq!(unlimited.check(&[update.clone()], Some(u64::MAX)), None);
+    let full = PushQuota { remaining: Some(0) };
+    assert!(full.check(&[update.clone()], None).is_some());
+    assert!(
+        full.check(&[delete.clone(), update.clone()], None)
+            .is_some()
+    );
+    assert_eq!(full.check(&[delete], None), None);
+    let some = PushQuota {
+        remaining: Some(100),
+    };
+    assert_eq!(some.check(&[update.clone()], None), None);
+    assert_eq!(some.check(&[update.clone()], Some(100)), None);
+    assert!(some.check(&[update], Some(101)).is_some());
+
+    let usage = QuotaUsage {
+        git_size: 60,
+        lfs_size: 50,
+        limit: Some(100),
+    };
+    assert_eq!(usage.used(), 110);
+    assert_eq!(usage.remaining(), Some(0));
+}
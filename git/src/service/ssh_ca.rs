@@ -0,0 +1,77 @@
+use crate::service::GitServer;
+use crate::transport::ssh::key::{key_fingerprint, parse_public_key};
+use anyhow::anyhow;
+use database::entity::{ssh_cas, users};
+use error::AppError;
+use russh::keys::ssh_key::Certificate;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+/// Whether one of the configured site-wide CA keys has this fingerprint.
+fn site_ca(configured: &[String], fingerprint: &str) -> bool {
+    configured
+        .iter()
+        .any(|x| parse_public_key(x).is_ok_and(|x| x.fingerprint == fingerprint))
+}
+
+impl GitServer {
+    /// The user a certificate signs in as, by its principals. A site-wide CA may vouch for
+    /// any user, a CA a user added only for that user. The certificate itself is checked
+    /// by `check_certificate`.
+    pub async fn find_certificate_owner(
+        &self,
+        cert: &Certificate,
+    ) -> Result<users::Model, AppError> {
+        let fingerprint = key_fingerprint(cert.signature_key());
+        let principals = cert.valid_principals();
+        if principals.is_empty() {
+            return Err(AppError::from(anyhow!("Certificate names no principals")));
+        }
+        if site_ca(&self.config.ssh.trusted_user_ca_keys, &fingerprint) {
+            let mut users = users::Entity::find()
+                .filter(users::Column::Username.is_in(principals.iter().map(|x| x.as_str())))
+                .all(&self.db)
+                .await?;
+            if users.len() > 1 {
+                return Err(AppError::from(anyhow!(
+                    "Certificate principals name more than one user"
+                )));
+            }
+            return users.pop().ok_or(AppError::from(anyhow!(
+                "No user for certificate principals"
+            )));
+        }
+        let owners = ssh_cas::Entity::find()
+            .filter(ssh_cas::Column::Fingerprint.eq(fingerprint.as_str()))
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|x| x.user_uid)
+            .collect::<Vec<_>>();
+        if owners.is_empty() {
+            return Err(AppError::from(anyhow!("Certificate CA is not trusted")));
+        }
+        users::Entity::find()
+            .filter(users::Column::Uid.is_in(owners))
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .find(|user| principals.contains(&user.username))
+            .ok_or(AppError::from(anyhow!(
+                "Certificate CA is not trusted for its principals"
+            )))
+    }
+}
+
+#[test]
+fn test_site_ca() {
+    let configured = vec![
+        "not a key".to_string(),
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPKIm+4ETeD/s9F87oxoEAoqfscA0QIBY5OXPUSlf/Mj ca@example.com"
+            .to_string(),
+    ];
+    assert!(site_ca(
+        &configured,
+        "SHA256:Ye/F8JiayLU2FitwVaY2/qF3BA2TSHu5tHJ0BBmDgm0"
+    ));
+    assert!(!site_ca(&configured, "SHA256:unknown"));
+}
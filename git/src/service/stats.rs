@@ -0,0 +1,62 @@
+use crate::service::GitServer;
+use crate::service::lock::{LockStats, RepoLock};
+use crate::transport::pack_cache::{PackCache, PackCacheStats};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::info;
+
+/// How often the counters of this process are logged.
+const REPORT_EVERY: Duration = Duration::from_secs(300);
+
+/// Counters of the git service in this process since start.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GitStats {
+    pub lock: LockStats,
+    pub pack_cache: PackCacheStats,
+}
+
+impl GitStats {
+    pub fn current() -> Self {
+        Self {
+            lock: RepoLock::stats(),
+            pack_cache: PackCache::stats(),
+        }
+    }
+}
+
+impl GitServer {
+    /// Logs `GitStats` every few minutes while anything changed.
+    pub async fn stats_worker(self) {
+        let mut interval = tokio::time::interval(REPORT_EVERY);
+        let mut last = GitStats::default();
+        loop {
+            interval.tick().await;
+            let stats = GitStats::current();
+            if stats == last {
+                continue;
+            }
+            let lock = &stats.lock;
+            info!(
+                acquired = lock.acquired,
+                contended = lock.contended,
+                timeouts = lock.timeouts,
+                local_only = lock.local_only,
+                lost_leases = lock.lost_leases,
+                wait_ms_total = lock.wait_ms_total,
+                wait_ms_max = lock.wait_ms_max,
+                "repository locks"
+            );
+            let cache = &stats.pack_cache;
+            info!(
+                hits = cache.hits,
+                misses = cache.misses,
+                bypassed = cache.bypassed,
+                stored = cache.stored,
+                evicted = cache.evicted,
+                bytes_served = cache.bytes_served,
+                "pack cache"
+            );
+            last = stats;
+        }
+    }
+}
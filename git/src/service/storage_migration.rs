@@ -0,0 +1,673 @@
+use crate::GitContext;
+use crate::lfs::store::LfsStore;
+use crate::service::GitServer;
+use crate::service::integrity::fsck;
+use crate::storage::rpc::GitOp;
+use crate::transport::backend::PackIo;
+use anyhow::anyhow;
+use database::entity::{git_repo, lfs_objects, lfs_repo_objects, repo_storage_migrations};
+use error::AppError;
+use sea_orm::prelude::{Expr, Uuid};
+use sea_orm::sqlx::types::chrono;
+use sea_orm::sqlx::types::chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+pub const MIGRATION_RUNNING: &str = "running";
+pub const MIGRATION_SUCCEEDED: &str = "succeeded";
+pub const MIGRATION_FAILED: &str = "failed";
+
+/// Copies objects, refs and LFS objects while the repository takes pushes.
+pub const PHASE_COPY: &str = "copy";
+/// Runs `git fsck` on the copy.
+pub const PHASE_VERIFY: &str = "verify";
+/// Copies what was pushed meanwhile, with pushes held off until the switch.
+pub const PHASE_CATCH_UP: &str = "catch-up";
+pub const PHASE_SWITCH: &str = "switch";
+/// Deletes the repository from the old storage.
+pub const PHASE_CLEANUP: &str = "cleanup";
+pub const PHASE_DONE: &str = "done";
+
+/// A running migration not updated for this long belongs to a process that died.
+const STALE: Duration = Duration::from_secs(3600);
+/// Least time between two progress updates of the migration row.
+const REPORT_EVERY: Duration = Duration::from_secs(1);
+const COPY_BUFFER: usize = 64 * 1024;
+/// Time sessions opened before the switch get to finish before the old copy is deleted.
+const DRAIN: Duration = Duration::from_secs(600);
+/// Attempts at copying the LFS objects uploaded to the old storage during the switch.
+const LFS_ATTEMPTS: u32 = 3;
+const LFS_RETRY: Duration = Duration::from_secs(30);
+/// Migrations of a repository the status API lists.
+const HISTORY_SIZE: u64 = 20;
+
+/// Where a repository is stored and how it got there, the latest migrations first.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct StorageStatus {
+    pub storage: String,
+    pub migrations: Vec<repo_storage_migrations::Model>,
+}
+
+impl StorageStatus {
+    pub async fn load(db: &DatabaseConnection, repo: &git_repo::Model) -> Result<Self, AppError> {
+        let migrations = repo_storage_migrations::Entity::find()
+            .filter(repo_storage_migrations::Column::RepoUid.eq(repo.uid))
+            .order_by_desc(repo_storage_migrations::Column::CreatedAt)
+            .limit(HISTORY_SIZE)
+            .all(db)
+            .await?;
+        Ok(Self {
+            storage: repo.storage.clone(),
+            migrations,
+        })
+    }
+}
+
+/// The refs of a repository by name with the objects they point at, and the ref HEAD
+/// points at.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct RefState {
+    head: String,
+    refs: BTreeMap<String, String>,
+}
+
+impl RefState {
+    async fn read(git: &GitContext) -> io::Result<Self> {
+        let refs = run_git(git, GitOp::ForEachRef, b"").await?;
+        let head = run_git(git, GitOp::SymbolicRef { target: None }, b"").await?;
+        Ok(Self {
+            head: String::from_utf8_lossy(&head).trim().to_string(),
+            refs: String::from_utf8_lossy(&refs)
+                .lines()
+                .filter_map(|x| x.split_once(' '))
+                .map(|(oid, name)| (name.to_string(), oid.to_string()))
+                .collect(),
+        })
+    }
+    /// `rev-list` arguments for what `self` reaches beyond `other`.
+    fn revs_beyond(&self, other: &RefState) -> Option<String> {
+        let known = other.refs.values().collect::<HashSet<_>>();
+        let tips = self
+            .refs
+            .values()
+            .filter(|x| !known.contains(x))
+            .collect::<HashSet<_>>();
+        if tips.is_empty() {
+            return None;
+        }
+        let mut revs = tips.iter().map(|x| format!("{}\n", x)).collect::<String>();
+        revs.extend(known.iter().map(|x| format!("^{}\n", x)));
+        Some(revs)
+    }
+    /// `update-ref --stdin` commands turning the refs of `other` into these.
+    fn updates_from(&self, other: &RefState) -> String {
+        let mut commands = String::new();
+        for (name, oid) in &self.refs {
+            if other.refs.get(name) != Some(oid) {
+                commands.push_str(&format!("update {} {}\n", name, oid));
+            }
+        }
+        for name in other.refs.keys() {
+            if !self.refs.contains_key(name) {
+                commands.push_str(&format!("delete {}\n", name));
+            }
+        }
+        commands
+    }
+}
+
+/// Runs git with `input` on stdin and returns its output, failing unless it exits cleanly.
+async fn run_git(git: &GitContext, op: GitOp, input: &[u8]) -> io::Result<Vec<u8>> {
+    let name = op.name();
+    let mut session = git.git(op)?;
+    session.stdin.write_all(input).await?;
+    let output = session.output().await?;
+    if output.code != 0 {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+fn drain(mut reader: Box<dyn AsyncRead + Send + Unpin>) -> JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        let mut data = vec![];
+        reader.read_to_end(&mut data).await.ok();
+        data
+    })
+}
+
+/// Streams the objects `revs` selects from `source` into a new pack of `target`, and
+/// checks `target` has everything they reach afterwards.
+async fn copy_objects(
+    source: &GitContext,
+    target: &GitContext,
+    revs: &str,
+    progress: &mut Progress<'_>,
+) -> Result<(), AppError> {
+    let PackIo {
+        mut stdin,
+        mut stdout,
+        stderr,
+        mut child,
+    } = source.git(GitOp::PackObjects)?;
+    stdin.write_all(revs.as_bytes()).await?;
+    drop(stdin);
+    let PackIo {
+        stdin: mut sink,
+        stdout: indexed,
+        stderr: index_stderr,
+        child: mut index,
+    } = target.git(GitOp::IndexPack)?;
+    let (stderr, indexed, index_stderr) = (drain(stderr), drain(indexed), drain(index_stderr));
+    let mut buf = vec![0; COPY_BUFFER];
+    loop {
+        let read = stdout.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        sink.write_all(&buf[..read]).await?;
+        progress.copied(read as u64).await?;
+    }
+    sink.shutdown().await?;
+    drop(sink);
+    let failed = |command: &str, stderr: Vec<u8>| {
+        AppError::from(anyhow!(
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&stderr).trim()
+        ))
+    };
+    if child.wait().await? != 0 {
+        return Err(failed("pack-objects", stderr.await.unwrap_or_default()));
+    }
+    if index.wait().await? != 0 {
+        return Err(failed("index-pack", index_stderr.await.unwrap_or_default()));
+    }
+    indexed.await.ok();
+    run_git(target, GitOp::RevList, revs.as_bytes()).await?;
+    Ok(())
+}
+
+/// Brings the refs of `target`, currently `current`, to `state`, copying the objects they
+/// need first.
+async fn copy_refs(
+    source: &GitContext,
+    target: &GitContext,
+    state: &RefState,
+    current: &RefState,
+    progress: &mut Progress<'_>,
+) -> Result<(), AppError> {
+    if let Some(revs) = state.revs_beyond(current) {
+        copy_objects(source, target, &revs, progress).await?;
+    }
+    let updates = state.updates_from(current);
+    if !updates.is_empty() {
+        run_git(target, GitOp::UpdateRef, updates.as_bytes()).await?;
+    }
+    if state.head != current.head {
+        let head = GitOp::SymbolicRef {
+            target: Some(state.head.clone()),
+        };
+        run_git(target, head, b"").await?;
+    }
+    Ok(())
+}
+
+/// Keeps the migration row up to date, the copied bytes at most once per `REPORT_EVERY`.
+struct Progress<'a> {
+    db: &'a DatabaseConnection,
+    uid: Uuid,
+    copied: u64,
+    reported: Instant,
+}
+
+impl<'a> Progress<'a> {
+    fn new(db: &'a DatabaseConnection, uid: Uuid) -> Self {
+        Self {
+            db,
+            uid,
+            copied: 0,
+            reported: Instant::now(),
+        }
+    }
+    async fn total(&mut self, total: u64) -> Result<(), AppError> {
+        repo_storage_migrations::Entity::update_many()
+            .col_expr(
+                repo_storage_migrations::Column::BytesTotal,
+                Expr::value(total as i64),
+            )
+            .filter(repo_storage_migrations::Column::Uid.eq(self.uid))
+            .exec(self.db)
+            .await?;
+        Ok(())
+    }
+    async fn phase(&mut self, phase: &str) -> Result<(), AppError> {
+        info!("storage migration {}: {}", self.uid, phase);
+        self.report(Some(phase)).await
+    }
+    async fn copied(&mut self, bytes: u64) -> Result<(), AppError> {
+        self.copied += bytes;
+        if self.reported.elapsed() >= REPORT_EVERY {
+            self.report(None).await?;
+        }
+        Ok(())
+    }
+    async fn report(&mut self, phase: Option<&str>) -> Result<(), AppError> {
+        let mut update = repo_storage_migrations::Entity::update_many()
+            .col_expr(
+                repo_storage_migrations::Column::BytesCopied,
+                Expr::value(self.copied as i64),
+            )
+            .col_expr(
+                repo_storage_migrations::Column::UpdatedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(repo_storage_migrations::Column::Uid.eq(self.uid));
+        if let Some(phase) = phase {
+            update = update.col_expr(repo_storage_migrations::Column::Phase, Expr::value(phase));
+        }
+        update.exec(self.db).await?;
+        self.reported = Instant::now();
+        Ok(())
+    }
+}
+
+/// Both sides of a migration.
+struct Sides {
+    source: GitContext,
+    target: GitContext,
+    source_lfs: LfsStore,
+    target_lfs: LfsStore,
+}
+
+impl GitServer {
+    /// Records a move of the repository to the storage `target`, carried out by
+    /// `run_storage_migration`.
+    pub async fn start_storage_migration(
+        &self,
+        repo_uid: Uuid,
+        target: &str,
+    ) -> Result<repo_storage_migrations::Model, AppError> {
+        let repo = self.find_repo_by_id(repo_uid).await?;
+        if repo.storage == target {
+            return Err(AppError::from(anyhow!(
+                "Repository is on storage {} already",
+                target
+            )));
+        }
+        if !self.config.git.storage.iter().any(|x| x.name == target) {
+            return Err(AppError::from(anyhow!(
+                "storage {} is not configured",
+                target
+            )));
+        }
+        let now = Utc::now().naive_utc();
+        let stale = now - chrono::Duration::seconds(STALE.as_secs() as i64);
+        repo_storage_migrations::Entity::update_many()
+            .col_expr(
+                repo_storage_migrations::Column::Status,
+                Expr::value(MIGRATION_FAILED),
+            )
+            .col_expr(
+                repo_storage_migrations::Column::Error,
+                Expr::value("interrupted"),
+            )
+            .col_expr(
+                repo_storage_migrations::Column::FinishedAt,
+                Expr::value(now),
+            )
+            .filter(repo_storage_migrations::Column::RepoUid.eq(repo_uid))
+            .filter(repo_storage_migrations::Column::Status.eq(MIGRATION_RUNNING))
+            .filter(repo_storage_migrations::Column::UpdatedAt.lt(stale))
+            .exec(&self.db)
+            .await?;
+        let active = repo_storage_migrations::Entity::find()
+            .filter(repo_storage_migrations::Column::RepoUid.eq(repo_uid))
+            .filter(repo_storage_migrations::Column::Status.eq(MIGRATION_RUNNING))
+            .one(&self.db)
+            .await?;
+        if active.is_some() {
+            return Err(AppError::from(anyhow!(
+                "Repository is being migrated already"
+            )));
+        }
+        Ok(repo_storage_migrations::ActiveModel {
+            uid: Set(Uuid::now_v7()),
+            repo_uid: Set(repo_uid),
+            source: Set(repo.storage),
+            target: Set(target.to_string()),
+            status: Set(MIGRATION_RUNNING.to_string()),
+            phase: Set(PHASE_COPY.to_string()),
+            bytes_total: Set(0),
+            bytes_copied: Set(0),
+            error: Set(None),
+            created_at: Set(now),
+            updated_at: Set(now),
+            finished_at: Set(None),
+        }
+        .insert(&self.db)
+        .await?)
+    }
+    pub async fn find_storage_migration(
+        &self,
+        uid: Uuid,
+    ) -> Result<repo_storage_migrations::Model, AppError> {
+        repo_storage_migrations::Entity::find_by_id(uid)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::from(anyhow!("Storage migration not found")))
+    }
+    /// Moves the repository while it stays online: everything is copied while pushes go
+    /// on, then pushes are held off for the last of it, the copy is compared with the
+    /// original and `git_repo.storage` switched. The original is deleted once the
+    /// repository is served from the new storage and sessions opened before had time to
+    /// finish. A migration that fails before the switch leaves the repository where it was
+    /// and removes the copy, one failing in the cleanup phase has moved it but lacks LFS
+    /// objects uploaded during the switch.
+    pub async fn run_storage_migration(
+        &self,
+        job: repo_storage_migrations::Model,
+    ) -> Result<repo_storage_migrations::Model, AppError> {
+        let result = self.migrate(&job).await;
+        let now = Utc::now().naive_utc();
+        let mut active = repo_storage_migrations::ActiveModel {
+            uid: Set(job.uid),
+            updated_at: Set(now),
+            finished_at: Set(Some(now)),
+            ..Default::default()
+        };
+        match result {
+            Ok(warning) => {
+                info!(
+                    "repository {} moved from {} to {}",
+                    job.repo_uid, job.source, job.target
+                );
+                active.status = Set(MIGRATION_SUCCEEDED.to_string());
+                active.phase = Set(PHASE_DONE.to_string());
+                active.error = Set(warning);
+            }
+            Err(e) => {
+                warn!("storage migration of {} failed: {}", job.repo_uid, e.msg);
+                active.status = Set(MIGRATION_FAILED.to_string());
+                active.error = Set(Some(e.msg));
+            }
+        }
+        Ok(active.update(&self.db).await?)
+    }
+    /// Returns what went wrong deleting the old copy, which no longer fails the migration.
+    async fn migrate(
+        &self,
+        job: &repo_storage_migrations::Model,
+    ) -> Result<Option<String>, AppError> {
+        let config = &self.config.git;
+        let repo = self.find_repo_by_id(job.repo_uid).await?;
+        if repo.storage != job.source {
+            return Err(AppError::from(anyhow!(
+                "Repository moved to {} meanwhile",
+                repo.storage
+            )));
+        }
+        let moved = git_repo::Model {
+            storage: job.target.clone(),
+            ..repo.clone()
+        };
+        let sides = Sides {
+            source: GitContext::try_from((repo.clone(), config.clone()))?,
+            target: GitContext::try_from((moved.clone(), config.clone()))?,
+            source_lfs: LfsStore::try_from((repo, config.clone()))?,
+            target_lfs: LfsStore::try_from((moved, config.clone()))?,
+        };
+        let mut progress = Progress::new(&self.db, job.uid);
+        if sides.target.blocking(|git| git.exists()).await? {
+            // only the storage in git_repo holds the repository, this is a leftover
+            warn!(
+                "removing a stale copy of {} on {}",
+                job.repo_uid, job.target
+            );
+            sides.target.blocking(|git| git.remove()).await?;
+        }
+        if let Err(e) = self.copy_repo(job, &sides, &mut progress).await {
+            if let Err(e) = sides.target.blocking(|git| git.remove()).await {
+                warn!("copy of {} not removed: {}", job.repo_uid, e.msg);
+            }
+            return Err(e);
+        }
+        // sessions that resolved the repository before the switch still read the old copy
+        tokio::time::sleep(DRAIN).await;
+        // LFS uploads do not wait for the repository lock, those that went to the old
+        // storage during the switch follow
+        let mut lfs = Ok(());
+        for attempt in 0..LFS_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(LFS_RETRY).await;
+            }
+            lfs = self
+                .copy_lfs(
+                    job.repo_uid,
+                    &sides.source_lfs,
+                    &sides.target_lfs,
+                    &mut progress,
+                )
+                .await;
+            if lfs.is_ok() {
+                break;
+            }
+        }
+        // LFS objects stay, other repositories on the old storage may share them
+        let warning = match sides.source.blocking(|git| git.remove()).await {
+            Ok(()) => None,
+            Err(e) => {
+                warn!("old copy of {} not removed: {}", job.repo_uid, e.msg);
+                Some(format!("old copy not removed: {}", e.msg))
+            }
+        };
+        if let Err(e) = lfs {
+            return Err(AppError::from(anyhow!(
+                "moved to {}, but LFS objects not copied: {}",
+                job.target,
+                e.msg
+            )));
+        }
+        Ok(warning)
+    }
+    async fn copy_repo(
+        &self,
+        job: &repo_storage_migrations::Model,
+        sides: &Sides,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), AppError> {
+        let Sides {
+            source,
+            target,
+            source_lfs,
+            target_lfs,
+        } = sides;
+        let total = source.measure_objects().await? + self.lfs_size(job.repo_uid).await?;
+        progress.total(total).await?;
+        target.blocking(|git| git.init()).await?;
+        let state = RefState::read(source).await?;
+        copy_refs(
+            source,
+            target,
+            &state,
+            &RefState::read(target).await?,
+            progress,
+        )
+        .await?;
+        self.copy_lfs(job.repo_uid, source_lfs, target_lfs, progress)
+            .await?;
+
+        progress.phase(PHASE_VERIFY).await?;
+        let problems = fsck(target).await?;
+        if let Some(problem) = problems.first() {
+            return Err(AppError::from(anyhow!("copy failed fsck: {}", problem)));
+        }
+
+        progress.phase(PHASE_CATCH_UP).await?;
+        let lock = self.lock_repo(job.repo_uid).await?;
+        let state = RefState::read(source).await?;
+        copy_refs(
+            source,
+            target,
+            &state,
+            &RefState::read(target).await?,
+            progress,
+        )
+        .await?;
+        self.copy_lfs(job.repo_uid, source_lfs, target_lfs, progress)
+            .await?;
+        if RefState::read(target).await? != state {
+            return Err(AppError::from(anyhow!("copy differs from the repository")));
+        }
+
+        progress.phase(PHASE_SWITCH).await?;
+        let txn = self.db.begin().await?;
+        let switched = git_repo::Entity::update_many()
+            .col_expr(git_repo::Column::Storage, Expr::value(job.target.clone()))
+            .filter(git_repo::Column::Uid.eq(job.repo_uid))
+            .filter(git_repo::Column::Storage.eq(job.source.clone()))
+            .exec(&txn)
+            .await?;
+        if switched.rows_affected != 1 {
+            return Err(AppError::from(anyhow!("Repository moved meanwhile")));
+        }
+        repo_storage_migrations::Entity::update_many()
+            .col_expr(
+                repo_storage_migrations::Column::Phase,
+                Expr::value(PHASE_CLEANUP),
+            )
+            .col_expr(
+                repo_storage_migrations::Column::UpdatedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(repo_storage_migrations::Column::Uid.eq(job.uid))
+            .exec(&txn)
+            .await?;
+        lock.ensure_held()?;
+        txn.commit().await?;
+        drop(lock);
+        Ok(())
+    }
+    /// Copies the LFS objects of the repository `target` lacks.
+    async fn copy_lfs(
+        &self,
+        repo_uid: Uuid,
+        source: &LfsStore,
+        target: &LfsStore,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), AppError> {
+        let objects = lfs_repo_objects::Entity::find()
+            .filter(lfs_repo_objects::Column::RepoUid.eq(repo_uid))
+            .all(&self.db)
+            .await?;
+        for object in objects {
+            let chunks = self.lfs_chunks(&object.oid).await?;
+            if target.size(&object.oid, &chunks).await.is_some() {
+                continue;
+            }
+            if source.size(&object.oid, &chunks).await.is_none() {
+                warn!("LFS object {} of {} is missing", object.oid, repo_uid);
+                continue;
+            }
+            let copied = target.copy_from(source, &object.oid, &chunks).await?;
+            progress.copied(copied).await?;
+        }
+        Ok(())
+    }
+    async fn lfs_size(&self, repo_uid: Uuid) -> Result<u64, AppError> {
+        let oids = lfs_repo_objects::Entity::find()
+            .select_only()
+            .column(lfs_repo_objects::Column::Oid)
+            .filter(lfs_repo_objects::Column::RepoUid.eq(repo_uid))
+            .into_tuple::<String>()
+            .all(&self.db)
+            .await?;
+        let sizes = lfs_objects::Entity::find()
+            .select_only()
+            .column(lfs_objects::Column::Size)
+            .filter(lfs_objects::Column::Oid.is_in(oids))
+            .into_tuple::<i64>()
+            .all(&self.db)
+            .await?;
+        Ok(sizes.into_iter().map(|x| x.max(0) as u64).sum())
+    }
+}
+
+#[tokio::test]
+async fn test_copy_refs() {
+    use crate::testing::{self, TempDir};
+    use git2::{Oid, Repository};
+
+    let root = TempDir::new("storage-migration");
+    let source = GitContext {
+        path_dir: root.join("source"),
+        remote: None,
+    };
+    let target = GitContext {
+        path_dir: root.join("target"),
+        remote: None,
+    };
+    source.init().unwrap();
+    target.init().unwrap();
+    let repo = Repository::open_bare(&source.path_dir).unwrap();
+    let commit = |refs: &str, content: &[u8], parents: &[Oid]| {
+        testing::commit(&repo, refs, &[("data.csv", content)], parents)
+    };
+    let first = commit("refs/heads/main", b"1\n", &[]);
+    commit("refs/heads/old", b"old\n", &[]);
+    source.refs_exchange_head("refs/heads/main").unwrap();
+
+    // there is no database, the progress is never due to be reported
+    let db = DatabaseConnection::Disconnected;
+    let mut progress = Progress {
+        reported: Instant::now() + STALE,
+        ..Progress::new(&db, Uuid::now_v7())
+    };
+    let state = RefState::read(&source).await.unwrap();
+    let empty = RefState::read(&target).await.unwrap();
+    assert!(empty.refs.is_empty());
+    copy_refs(&source, &target, &state, &empty, &mut progress)
+        .await
+        .unwrap();
+    assert_eq!(RefState::read(&target).await.unwrap(), state);
+    let copied = progress.copied;
+    assert!(copied > 0);
+
+    // pushes during the copy: a new commit, a deleted branch and a new one
+    let second = commit("refs/heads/main", b"2\n", &[first]);
+    repo.find_reference("refs/heads/old")
+        .unwrap()
+        .delete()
+        .unwrap();
+    repo.reference("refs/heads/dev", first, false, "branch")
+        .unwrap();
+    let caught_up = RefState::read(&source).await.unwrap();
+    assert_eq!(
+        caught_up.updates_from(&state),
+        format!(
+            "update refs/heads/dev {}\nupdate refs/heads/main {}\ndelete refs/heads/old\n",
+            first, second
+        )
+    );
+    copy_refs(&source, &target, &caught_up, &state, &mut progress)
+        .await
+        .unwrap();
+    assert_eq!(RefState::read(&target).await.unwrap(), caught_up);
+    // only the new commit was sent
+    assert!(progress.copied - copied < copied);
+    assert!(caught_up.revs_beyond(&caught_up).is_none());
+    assert!(fsck(&target).await.unwrap().is_empty());
+}
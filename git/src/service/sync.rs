@@ -1,242 +1,507 @@
 use crate::GitContext;
-use crate::object::commit::CommitPaginator;
+use crate::object::commit::{CommitItem, CommitPaginator};
 use crate::service::GitServer;
+use crate::service::lock::RepoLock;
+use crate::transport::push::RefCommand;
 use anyhow::anyhow;
 use database::entity::{
     git_commit, git_refs, git_repo, git_tag, user_repo_active, user_repo_tagger, users,
 };
 use error::AppError;
-use redis::AsyncCommands;
-use sea_orm::prelude::Uuid;
+use git2::Oid;
+use sea_orm::prelude::{Expr, Uuid};
 use sea_orm::sqlx::types::chrono::Utc;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, EntityTrait, IntoActiveModel, QueryFilter, Set,
-    TransactionTrait, TryIntoModel,
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseTransaction, EntityTrait, IntoActiveModel,
+    QueryFilter, Set, TransactionTrait, TryIntoModel,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 impl GitServer {
-    pub async fn sync_repo(&self, repo_uid: Uuid) -> Result<(), AppError> {
-        let mut redis_client = self
-            .redis
-            .get()
-            .await
-            .map_err(|_| AppError::from(anyhow!("Redis error")))?;
-        redis_client
-            .set_ex::<String, i32, ()>(
-                format!("git:repo:{}:sync", repo_uid.to_string()),
-                1,
-                60 * 10,
-            )
+    /// Rebuilds the ref, commit and tag rows of a repository from what is on disk, for an
+    /// index that drifted from the repository. Runs in one transaction, readers see either
+    /// the old rows or the new ones.
+    pub async fn resync_repo(&self, repo_uid: Uuid) -> Result<(), AppError> {
+        let lock = self.lock_repo(repo_uid).await?;
+        let repo = git_repo::Entity::find_by_id(repo_uid)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::from(anyhow!("Repo not found")))?;
+        let git = GitContext::try_from((repo, self.config.git.clone()))?;
+        // nothing is dropped for a repository whose branches cannot even be read
+        git.blocking(|git| git.refs_list()).await?;
+        let size = git.measure_objects().await?;
+        let txn = self.db.begin().await?;
+        user_repo_active::Entity::delete_many()
+            .filter(user_repo_active::Column::RepoUid.eq(repo_uid))
+            .exec(&txn)
             .await?;
+        git_commit::Entity::delete_many()
+            .filter(git_commit::Column::RepoUid.eq(repo_uid))
+            .exec(&txn)
+            .await?;
+        git_refs::Entity::delete_many()
+            .filter(git_refs::Column::RepoUid.eq(repo_uid))
+            .exec(&txn)
+            .await?;
+        git_tag::Entity::delete_many()
+            .filter(git_tag::Column::RepoUid.eq(repo_uid))
+            .exec(&txn)
+            .await?;
+        sync_refs(&txn, &git, repo_uid).await?;
+        sync_tags(&txn, &git, repo_uid).await?;
+        git_repo::Entity::update_many()
+            .col_expr(git_repo::Column::Size, Expr::value(size as i64))
+            .filter(git_repo::Column::Uid.eq(repo_uid))
+            .exec(&txn)
+            .await?;
+        lock.ensure_held()?;
+        txn.commit().await?;
+        Ok(())
+    }
+    /// Brings the database in line with a finished push. Only the commits a ref update
+    /// added are walked; commits a force push or deletion left unreachable are dropped.
+    /// Commands receive-pack turned down are recognised by the ref not holding their new
+    /// value and skipped, which only works while the lock taken for the push is still held.
+    pub async fn sync_push(
+        &self,
+        lock: &RepoLock,
+        commands: &[RefCommand],
+    ) -> Result<(), AppError> {
+        let repo_uid = lock.repo_uid;
         let repo = git_repo::Entity::find_by_id(repo_uid)
             .one(&self.db)
             .await?
             .ok_or(AppError::from(anyhow!("Repo not found")))?;
         let git = GitContext::try_from((repo, self.config.git.clone()))?;
-        let refs = git.refs_list()?;
+        let refs = git.blocking(|git| git.refs_list()).await?;
+        let tips = refs
+            .iter()
+            .filter_map(|x| Some((x.name.as_str(), Oid::from_str(&x.hash).ok()?)))
+            .collect::<HashMap<_, _>>();
+        // the branches as they were before the push; commits reachable from the new tip of
+        // another branch pushed alongside are not recorded yet
+        let mut before = tips.clone();
+        for command in commands {
+            let Some(branch) = command.branch() else {
+                continue;
+            };
+            if command.is_create() {
+                before.remove(branch);
+            } else {
+                before.insert(branch, command.old);
+            }
+        }
+        // the next push is checked against the quota with what this one left on disk
+        let size = git.measure_objects().await?;
         let txn = self.db.begin().await?;
         let db_refs = git_refs::Entity::find()
             .filter(Condition::all().add(git_refs::Column::RepoUid.eq(repo_uid)))
             .all(&txn)
             .await?;
-
-        for ref_item in refs {
-            let ref_item =
-                if let Some(db_ref) = db_refs.iter().find(|x| x.ref_name == ref_item.name) {
-                    if db_ref.ref_git_id != ref_item.hash {
-                        let mut ref_active = db_ref.clone().into_active_model();
-                        ref_active.ref_git_id = Set(ref_item.hash);
-                        ref_active.updated_at = Set(Utc::now().naive_utc());
-                        ref_active.update(&txn).await?
-                    } else {
-                        continue;
-                    }
-                } else {
+        let mut dropped = vec![];
+        let mut deleted_refs = vec![];
+        let mut tags = false;
+        for command in commands {
+            if let Some(tag) = command.name.strip_prefix("refs/tags/") {
+                if command.is_delete() {
+                    git_tag::Entity::delete_many()
+                        .filter(git_tag::Column::RepoUid.eq(repo_uid))
+                        .filter(git_tag::Column::TagName.eq(tag))
+                        .exec(&txn)
+                        .await?;
+                }
+                tags = true;
+                continue;
+            }
+            let Some(branch) = command.branch() else {
+                continue;
+            };
+            let applied = match tips.get(branch) {
+                Some(tip) => !command.is_delete() && *tip == command.new,
+                None => command.is_delete(),
+            };
+            if !applied {
+                continue;
+            }
+            let db_ref = db_refs.iter().find(|x| x.ref_name == branch);
+            if command.is_delete() {
+                if let Some(db_ref) = db_ref {
+                    git_refs::Entity::delete_by_id(db_ref.uid)
+                        .exec(&txn)
+                        .await?;
+                    deleted_refs.push(db_ref.uid);
+                }
+                dropped.push(command.old);
+                continue;
+            }
+            let ref_item = match db_ref {
+                Some(db_ref) => {
+                    let mut ref_active = db_ref.clone().into_active_model();
+                    ref_active.ref_git_id = Set(command.new.to_string());
+                    ref_active.updated_at = Set(Utc::now().naive_utc());
+                    ref_active.update(&txn).await?
+                }
+                None => {
                     let ref_active = git_refs::ActiveModel {
                         uid: Set(Uuid::now_v7()),
-                        repo_uid: Set(repo_uid.clone()),
-                        ref_name: Set(ref_item.name),
-                        ref_git_id: Set(ref_item.hash),
-                        default_branch: Set(ref_item.is_head),
+                        repo_uid: Set(repo_uid),
+                        ref_name: Set(branch.to_string()),
+                        ref_git_id: Set(command.new.to_string()),
+                        default_branch: Set(refs.iter().any(|x| x.name == branch && x.is_head)),
                         created_at: Set(Utc::now().naive_utc()),
                         updated_at: Set(Utc::now().naive_utc()),
                     };
                     ref_active.insert(&txn).await?
-                };
-            let commit = git.commit_list(CommitPaginator {
-                start_oid: None,
-                end_oid: None,
-                refs: Some(ref_item.ref_name),
-            })?;
-            let commits_hash = commit
-                .clone()
-                .iter()
-                .map(|x| x.commit_oid.clone())
-                .collect::<Vec<_>>();
-            let mut need_commit_hash = HashSet::new();
-            let db_hash = git_commit::Entity::find()
-                .filter(
-                    Condition::all()
-                        .add(git_commit::Column::RepoUid.eq(repo_uid))
-                        .add(git_commit::Column::CommitId.is_in(commits_hash)),
-                )
-                .all(&txn)
-                .await?
-                .iter()
-                .map(|x| x.commit_id.clone())
-                .collect::<Vec<_>>();
-            for commit_item in commit {
-                if !db_hash.contains(&commit_item.commit_oid) {
-                    need_commit_hash.insert(commit_item);
                 }
+            };
+            // whatever a branch reached before the push is already recorded
+            let hide = before.values().copied().collect::<Vec<_>>();
+            let (new, old, create) = (command.new, command.old, command.is_create());
+            let (commits, descends) = git
+                .blocking(move |git| {
+                    let descends = create || git.is_descendant(new, old).unwrap_or(false);
+                    Ok((git.commit_range(new, &hide)?, descends))
+                })
+                .await?;
+            let known = known_commits(
+                &txn,
+                repo_uid,
+                commits.iter().map(|x| x.commit_oid.clone()).collect(),
+            )
+            .await?;
+            let commits = commits
+                .into_iter()
+                .filter(|x| !known.contains(&x.commit_oid));
+            insert_commits(&txn, repo_uid, ref_item.uid, commits).await?;
+            // a force push leaves the old tip behind
+            if !descends {
+                dropped.push(command.old);
             }
-            for commit_item in need_commit_hash {
-                let commiter = commit_item.committer;
-                let authors = commit_item.author;
-                let mut commit_active = git_commit::ActiveModel {
-                    uid: Set(Uuid::now_v7()),
-                    repo_uid: Set(repo_uid.clone()),
-                    refs_uid: Set(ref_item.uid),
-                    commit_id: Set(commit_item.commit_oid),
-                    tree: Set(commit_item.tree_oid),
-                    parents_id: Set(serde_json::to_value(commit_item.parents)?),
-                    author: Set(None),
-                    committer: Set(None),
-                    content: Set(commit_item.message),
-                    time: Set(commit_item.time),
-                    offset: Set(commit_item.offset_date),
-                };
-                let commit = commit_active.clone().try_into_model()?;
-                let (author_uid, commiter_uid) = if commiter == authors {
-                    let user_uid = users::Entity::find()
-                        .filter(
-                            Condition::all().add(users::Column::Email.eq(authors.email.clone())),
-                        )
-                        .one(&txn)
-                        .await?
-                        .map(|x| x.uid);
-                    let authors = user_repo_active::ActiveModel {
-                        uid: Set(Uuid::now_v7()),
-                        name: Set(authors.name.clone()),
-                        email: Set(authors.email),
-                        user_uid: Set(user_uid),
-                        commit: Set(commit.uid.clone()),
-                        repo_uid: Set(repo_uid.clone()),
-                        time: Set(commit.time),
-                        offset: Set(commit.offset),
-                    };
-                    let au = authors.insert(&txn).await?;
-                    (au.uid, au.uid)
-                } else {
-                    let user_uid = users::Entity::find()
-                        .filter(
-                            Condition::all().add(users::Column::Email.eq(authors.email.clone())),
-                        )
-                        .one(&txn)
-                        .await?
-                        .map(|x| x.uid);
-                    let authors = user_repo_active::ActiveModel {
-                        uid: Set(Uuid::now_v7()),
-                        name: Set(authors.name.clone()),
-                        email: Set(authors.email),
-                        user_uid: Set(user_uid),
-                        commit: Set(commit.uid.clone()),
-                        repo_uid: Set(repo_uid.clone()),
-                        time: Set(commit.time),
-                        offset: Set(commit.offset),
-                    };
-                    let au = authors.insert(&txn).await?;
-                    let user_uid = users::Entity::find()
-                        .filter(
-                            Condition::all().add(users::Column::Email.eq(commiter.email.clone())),
-                        )
-                        .one(&txn)
-                        .await?
-                        .map(|x| x.uid);
-                    let commiter = user_repo_active::ActiveModel {
-                        uid: Set(Uuid::now_v7()),
-                        name: Set(commiter.name),
-                        email: Set(commiter.email),
-                        user_uid: Set(user_uid),
-                        commit: Set(commit.uid.clone()),
-                        repo_uid: Set(repo_uid.clone()),
-                        time: Set(commit.time),
-                        offset: Set(commit.offset),
-                    };
-                    let cm = commiter.insert(&txn).await?;
-                    (au.uid, cm.uid)
-                };
-                commit_active.committer = Set(Some(commiter_uid));
-                commit_active.author = Set(Some(author_uid));
-                commit_active.insert(&txn).await?;
-            }
         }
-        let tags = git.tag_list()?;
-        for tag in tags {
-            if git_tag::Entity::find()
-                .filter(
-                    Condition::all()
-                        .add(git_tag::Column::RepoUid.eq(repo_uid.clone()))
-                        .add(git_tag::Column::TagName.eq(tag.tag_name.clone()))
-                        .add(git_tag::Column::TagId.eq(tag.tag_id.clone())),
-                )
-                .one(&txn)
+        if !dropped.is_empty() {
+            let gone = git
+                .blocking(move |git| {
+                    let reachable = git.ref_tips()?;
+                    let mut gone = vec![];
+                    for old in dropped {
+                        gone.extend(
+                            git.commit_range(old, &reachable)?
+                                .into_iter()
+                                .map(|x| x.commit_oid),
+                        );
+                    }
+                    Ok(gone)
+                })
+                .await?;
+            prune_commits(&txn, repo_uid, gone).await?;
+        }
+        if !deleted_refs.is_empty() {
+            // commits of a deleted branch that are still reachable move to the default one
+            if let Some(default) = git_refs::Entity::find()
+                .filter(git_refs::Column::RepoUid.eq(repo_uid))
+                .filter(git_refs::Column::Uid.is_not_in(deleted_refs.clone()))
+                .all(&txn)
                 .await?
-                .is_some()
+                .into_iter()
+                .max_by_key(|x| x.default_branch)
             {
-                continue;
+                git_commit::Entity::update_many()
+                    .col_expr(git_commit::Column::RefsUid, Expr::value(default.uid))
+                    .filter(git_commit::Column::RepoUid.eq(repo_uid))
+                    .filter(git_commit::Column::RefsUid.is_in(deleted_refs))
+                    .exec(&txn)
+                    .await?;
+            }
+        }
+        if tags {
+            sync_tags(&txn, &git, repo_uid).await?;
+        }
+        // the push counts towards the next maintenance
+        git_repo::Entity::update_many()
+            .col_expr(git_repo::Column::Size, Expr::value(size as i64))
+            .col_expr(
+                git_repo::Column::PushesSinceMaintenance,
+                Expr::col(git_repo::Column::PushesSinceMaintenance).add(1),
+            )
+            .filter(git_repo::Column::Uid.eq(repo_uid))
+            .exec(&txn)
+            .await?;
+        lock.ensure_held()?;
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+/// Records the branches of the repository and the commits they reach that are not recorded
+/// yet.
+async fn sync_refs(
+    txn: &DatabaseTransaction,
+    git: &GitContext,
+    repo_uid: Uuid,
+) -> Result<(), AppError> {
+    let refs = git.blocking(|git| git.refs_list()).await?;
+    let db_refs = git_refs::Entity::find()
+        .filter(Condition::all().add(git_refs::Column::RepoUid.eq(repo_uid)))
+        .all(txn)
+        .await?;
+
+    for ref_item in refs {
+        let ref_item = if let Some(db_ref) = db_refs.iter().find(|x| x.ref_name == ref_item.name) {
+            if db_ref.ref_git_id != ref_item.hash {
+                let mut ref_active = db_ref.clone().into_active_model();
+                ref_active.ref_git_id = Set(ref_item.hash);
+                ref_active.updated_at = Set(Utc::now().naive_utc());
+                ref_active.update(txn).await?
             } else {
-                let tagger = tag.tag_tagger;
-                let tagger = if let Some(tagger) = tagger {
-                    let users = users::Entity::find()
-                        .filter(Condition::all().add(users::Column::Email.eq(tagger.email.clone())))
-                        .one(&txn)
-                        .await?
-                        .map(|x| x.uid);
-                    if let Some(users) = users {
-                        let tagger = user_repo_tagger::Entity::find()
-                            .filter(
-                                Condition::all()
-                                    .add(user_repo_tagger::Column::UserUid.eq(users.clone()))
-                                    .add(user_repo_tagger::Column::RepoUid.eq(repo_uid.clone())),
-                            )
-                            .one(&txn)
-                            .await?;
-                        if let Some(tagger) = tagger {
-                            Some(tagger)
-                        } else {
-                            let tagger = user_repo_tagger::ActiveModel {
-                                uid: Set(Uuid::now_v7()),
-                                user_uid: Set(Option::from(users)),
-                                repo_uid: Set(repo_uid.clone()),
-                                name: Set(tag.tag_name.clone()),
-                                email: Set(tag.tag_msg.clone()),
-                            };
-                            let model = tagger.insert(&txn).await?;
-                            Some(model)
-                        }
+                continue;
+            }
+        } else {
+            let ref_active = git_refs::ActiveModel {
+                uid: Set(Uuid::now_v7()),
+                repo_uid: Set(repo_uid.clone()),
+                ref_name: Set(ref_item.name),
+                ref_git_id: Set(ref_item.hash),
+                default_branch: Set(ref_item.is_head),
+                created_at: Set(Utc::now().naive_utc()),
+                updated_at: Set(Utc::now().naive_utc()),
+            };
+            ref_active.insert(txn).await?
+        };
+        let refs = Some(ref_item.ref_name);
+        let commit = git
+            .blocking(move |git| {
+                git.commit_list(CommitPaginator {
+                    start_oid: None,
+                    end_oid: None,
+                    refs,
+                })
+            })
+            .await?;
+        let commits_hash = commit
+            .clone()
+            .iter()
+            .map(|x| x.commit_oid.clone())
+            .collect::<Vec<_>>();
+        let mut need_commit_hash = HashSet::new();
+        let db_hash = git_commit::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(git_commit::Column::RepoUid.eq(repo_uid))
+                    .add(git_commit::Column::CommitId.is_in(commits_hash)),
+            )
+            .all(txn)
+            .await?
+            .iter()
+            .map(|x| x.commit_id.clone())
+            .collect::<Vec<_>>();
+        for commit_item in commit {
+            if !db_hash.contains(&commit_item.commit_oid) {
+                need_commit_hash.insert(commit_item);
+            }
+        }
+        insert_commits(txn, repo_uid, ref_item.uid, need_commit_hash).await?;
+    }
+    Ok(())
+}
+
+/// The subset of `commit_ids` already recorded for the repository.
+async fn known_commits(
+    txn: &DatabaseTransaction,
+    repo_uid: Uuid,
+    commit_ids: Vec<String>,
+) -> Result<HashSet<String>, AppError> {
+    let mut known = HashSet::new();
+    for chunk in commit_ids.chunks(1000) {
+        known.extend(
+            git_commit::Entity::find()
+                .filter(git_commit::Column::RepoUid.eq(repo_uid))
+                .filter(git_commit::Column::CommitId.is_in(chunk.to_vec()))
+                .all(txn)
+                .await?
+                .into_iter()
+                .map(|x| x.commit_id),
+        );
+    }
+    Ok(known)
+}
+
+/// Removes commit rows, together with their author and committer rows.
+async fn prune_commits(
+    txn: &DatabaseTransaction,
+    repo_uid: Uuid,
+    commit_ids: Vec<String>,
+) -> Result<(), AppError> {
+    for chunk in commit_ids.chunks(1000) {
+        let commits = git_commit::Entity::find()
+            .filter(git_commit::Column::RepoUid.eq(repo_uid))
+            .filter(git_commit::Column::CommitId.is_in(chunk.to_vec()))
+            .all(txn)
+            .await?;
+        if commits.is_empty() {
+            continue;
+        }
+        let uids = commits.iter().map(|x| x.uid).collect::<Vec<_>>();
+        user_repo_active::Entity::delete_many()
+            .filter(user_repo_active::Column::Commit.is_in(uids.clone()))
+            .exec(txn)
+            .await?;
+        git_commit::Entity::delete_many()
+            .filter(git_commit::Column::Uid.is_in(uids))
+            .exec(txn)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn insert_commits(
+    txn: &DatabaseTransaction,
+    repo_uid: Uuid,
+    refs_uid: Uuid,
+    commits: impl IntoIterator<Item = CommitItem>,
+) -> Result<(), AppError> {
+    for commit_item in commits {
+        let commiter = commit_item.committer;
+        let authors = commit_item.author;
+        let mut commit_active = git_commit::ActiveModel {
+            uid: Set(Uuid::now_v7()),
+            repo_uid: Set(repo_uid.clone()),
+            refs_uid: Set(refs_uid),
+            commit_id: Set(commit_item.commit_oid),
+            tree: Set(commit_item.tree_oid),
+            parents_id: Set(serde_json::to_value(commit_item.parents)?),
+            author: Set(None),
+            committer: Set(None),
+            content: Set(commit_item.message),
+            time: Set(commit_item.time),
+            offset: Set(commit_item.offset_date),
+        };
+        let commit = commit_active.clone().try_into_model()?;
+        let (author_uid, commiter_uid) = if commiter == authors {
+            let user_uid = users::Entity::find()
+                .filter(Condition::all().add(users::Column::Email.eq(authors.email.clone())))
+                .one(txn)
+                .await?
+                .map(|x| x.uid);
+            let authors = user_repo_active::ActiveModel {
+                uid: Set(Uuid::now_v7()),
+                name: Set(authors.name.clone()),
+                email: Set(authors.email),
+                user_uid: Set(user_uid),
+                commit: Set(commit.uid.clone()),
+                repo_uid: Set(repo_uid.clone()),
+                time: Set(commit.time),
+                offset: Set(commit.offset),
+            };
+            let au = authors.insert(txn).await?;
+            (au.uid, au.uid)
+        } else {
+            let user_uid = users::Entity::find()
+                .filter(Condition::all().add(users::Column::Email.eq(authors.email.clone())))
+                .one(txn)
+                .await?
+                .map(|x| x.uid);
+            let authors = user_repo_active::ActiveModel {
+                uid: Set(Uuid::now_v7()),
+                name: Set(authors.name.clone()),
+                email: Set(authors.email),
+                user_uid: Set(user_uid),
+                commit: Set(commit.uid.clone()),
+                repo_uid: Set(repo_uid.clone()),
+                time: Set(commit.time),
+                offset: Set(commit.offset),
+            };
+            let au = authors.insert(txn).await?;
+            let user_uid = users::Entity::find()
+                .filter(Condition::all().add(users::Column::Email.eq(commiter.email.clone())))
+                .one(txn)
+                .await?
+                .map(|x| x.uid);
+            let commiter = user_repo_active::ActiveModel {
+                uid: Set(Uuid::now_v7()),
+                name: Set(commiter.name),
+                email: Set(commiter.email),
+                user_uid: Set(user_uid),
+                commit: Set(commit.uid.clone()),
+                repo_uid: Set(repo_uid.clone()),
+                time: Set(commit.time),
+                offset: Set(commit.offset),
+            };
+            let cm = commiter.insert(txn).await?;
+            (au.uid, cm.uid)
+        };
+        commit_active.committer = Set(Some(commiter_uid));
+        commit_active.author = Set(Some(author_uid));
+        commit_active.insert(txn).await?;
+    }
+    Ok(())
+}
+
+async fn sync_tags(
+    txn: &DatabaseTransaction,
+    git: &GitContext,
+    repo_uid: Uuid,
+) -> Result<(), AppError> {
+    let tags = git.blocking(|git| git.tag_list()).await?;
+    for tag in tags {
+        if git_tag::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(git_tag::Column::RepoUid.eq(repo_uid.clone()))
+                    .add(git_tag::Column::TagName.eq(tag.tag_name.clone()))
+                    .add(git_tag::Column::TagId.eq(tag.tag_id.clone())),
+            )
+            .one(txn)
+            .await?
+            .is_some()
+        {
+            continue;
+        } else {
+            let tagger = tag.tag_tagger;
+            let tagger = if let Some(tagger) = tagger {
+                let users = users::Entity::find()
+                    .filter(Condition::all().add(users::Column::Email.eq(tagger.email.clone())))
+                    .one(txn)
+                    .await?
+                    .map(|x| x.uid);
+                if let Some(users) = users {
+                    let tagger = user_repo_tagger::Entity::find()
+                        .filter(
+                            Condition::all()
+                                .add(user_repo_tagger::Column::UserUid.eq(users.clone()))
+                                .add(user_repo_tagger::Column::RepoUid.eq(repo_uid.clone())),
+                        )
+                        .one(txn)
+                        .await?;
+                    if let Some(tagger) = tagger {
+                        Some(tagger)
                     } else {
-                        None
+                        let tagger = user_repo_tagger::ActiveModel {
+                            uid: Set(Uuid::now_v7()),
+                            user_uid: Set(Option::from(users)),
+                            repo_uid: Set(repo_uid.clone()),
+                            name: Set(tag.tag_name.clone()),
+                            email: Set(tag.tag_msg.clone()),
+                        };
+                        let model = tagger.insert(txn).await?;
+                        Some(model)
                     }
                 } else {
                     None
-                };
-                let tag = git_tag::ActiveModel {
-                    uid: Set(Uuid::now_v7()),
-                    repo_uid: Set(repo_uid.clone()),
-                    tag_id: Set(tag.tag_id),
-                    tag_name: Set(tag.tag_name),
-                    tagger: Set(tagger.map(|x| x.uid)),
-                    message: Set(tag.tag_msg),
-                };
-                tag.insert(&txn).await?;
-            }
+                }
+            } else {
+                None
+            };
+            let tag = git_tag::ActiveModel {
+                uid: Set(Uuid::now_v7()),
+                repo_uid: Set(repo_uid.clone()),
+                tag_id: Set(tag.tag_id),
+                tag_name: Set(tag.tag_name),
+                tagger: Set(tagger.map(|x| x.uid)),
+                message: Set(tag.tag_msg),
+            };
+            tag.insert(txn).await?;
         }
-        txn.commit().await?;
-        redis_client
-            .del::<String, ()>(format!("git:repo:{}:sync", repo_uid))
-            .await?;
-        Ok(())
     }
+    Ok(())
 }
@@ -0,0 +1,250 @@
+use crate::storage::rpc::{
+    FrameKind, ObjectCall, StorageCall, read_frame, read_frame_blocking, write_frame,
+    write_frame_blocking,
+};
+use crate::transport::backend::{PackChild, PackIo};
+use anyhow::anyhow;
+use error::AppError;
+use serde::de::DeserializeOwned;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tracing::warn;
+
+pub mod node;
+pub mod rpc;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an object call waits on the node to take its request or answer it.
+const CALL_TIMEOUT: Duration = Duration::from_secs(300);
+/// Bytes buffered between a remote session and its reader or writer.
+const PIPE_SIZE: usize = 64 * 1024;
+
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+pub trait BlockingConnection: io::Read + io::Write + Send {}
+
+impl<T: io::Read + io::Write + Send> BlockingConnection for T {}
+
+/// Where a storage node listens, `host:port` or `unix:/path/to/socket`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StorageAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl StorageAddress {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.strip_prefix("unix:") {
+            Some("") => None,
+            Some(path) => Some(Self::Unix(PathBuf::from(path))),
+            None if value.is_empty() => None,
+            None => Some(Self::Tcp(value.to_string())),
+        }
+    }
+    pub async fn connect(&self) -> io::Result<Box<dyn Connection>> {
+        let connect = async {
+            let conn: Box<dyn Connection> = match self {
+                StorageAddress::Tcp(addr) => Box::new(tokio::net::TcpStream::connect(addr).await?),
+                #[cfg(unix)]
+                StorageAddress::Unix(path) => {
+                    Box::new(tokio::net::UnixStream::connect(path).await?)
+                }
+                #[cfg(not(unix))]
+                StorageAddress::Unix(_) => return Err(io::Error::from(io::ErrorKind::Unsupported)),
+            };
+            Ok::<_, io::Error>(conn)
+        };
+        tokio::time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    }
+    /// A connection whose reads and writes fail once they waited `timeout`.
+    pub fn connect_blocking(&self, timeout: Duration) -> io::Result<Box<dyn BlockingConnection>> {
+        match self {
+            StorageAddress::Tcp(addr) => {
+                let mut last = io::Error::from(io::ErrorKind::AddrNotAvailable);
+                for addr in addr.to_socket_addrs()? {
+                    match std::net::TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                        Ok(stream) => {
+                            stream.set_read_timeout(Some(timeout))?;
+                            stream.set_write_timeout(Some(timeout))?;
+                            return Ok(Box::new(stream));
+                        }
+                        Err(e) => last = e,
+                    }
+                }
+                Err(last)
+            }
+            #[cfg(unix)]
+            StorageAddress::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(not(unix))]
+            StorageAddress::Unix(_) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+}
+
+impl Display for StorageAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageAddress::Tcp(addr) => write!(f, "{}", addr),
+            StorageAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A repository on a storage node, `name` is its directory below the node's root. `secret`
+/// is what the node takes its clients by.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemoteRepo {
+    pub address: StorageAddress,
+    pub name: String,
+    pub secret: String,
+}
+
+impl RemoteRepo {
+    /// Runs an object call on the node and waits for the reply. Blocks like the libgit2
+    /// calls it stands in for, async callers go through `GitContext::blocking`.
+    pub fn object<T: DeserializeOwned>(&self, object: ObjectCall) -> Result<T, AppError> {
+        let call = StorageCall::Object {
+            repo: self.name.clone(),
+            object,
+        };
+        let mut conn = self.address.connect_blocking(CALL_TIMEOUT)?;
+        write_frame_blocking(&mut conn, FrameKind::Auth, self.secret.as_bytes())?;
+        write_frame_blocking(&mut conn, FrameKind::Call, &serde_json::to_vec(&call)?)?;
+        match read_frame_blocking(&mut conn)? {
+            Some((FrameKind::Reply, data)) => Ok(serde_json::from_slice(&data)?),
+            Some((FrameKind::Error, data)) => Err(serde_json::from_slice::<AppError>(&data)?),
+            _ => Err(AppError::from(anyhow!(
+                "storage node {} sent no reply",
+                self.address
+            ))),
+        }
+    }
+    /// Runs a `Pack` or `Git` call on the node, shaped like a local process. A node that
+    /// cannot be reached shows as a session failing with exit code 128.
+    pub fn spawn(&self, call: StorageCall) -> PackIo {
+        let (stdin, input) = tokio::io::duplex(PIPE_SIZE);
+        let (mut output, stdout) = tokio::io::duplex(PIPE_SIZE);
+        let (mut errput, stderr) = tokio::io::duplex(PIPE_SIZE);
+        let remote = self.clone();
+        let handle = tokio::spawn(async move {
+            match session(&remote, call, input, &mut output, &mut errput).await {
+                Ok(code) => code,
+                Err(e) => {
+                    warn!("storage node {}: {}", remote.address, e);
+                    errput
+                        .write_all(b"fatal: repository storage unavailable\n")
+                        .await
+                        .ok();
+                    128
+                }
+            }
+        });
+        PackIo {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            child: PackChild::remote(handle),
+        }
+    }
+}
+
+async fn session(
+    remote: &RemoteRepo,
+    call: StorageCall,
+    mut input: DuplexStream,
+    output: &mut DuplexStream,
+    errput: &mut DuplexStream,
+) -> io::Result<i32> {
+    let conn = remote.address.connect().await?;
+    let (mut reader, mut writer) = tokio::io::split(conn);
+    write_frame(&mut writer, FrameKind::Auth, remote.secret.as_bytes()).await?;
+    write_frame(&mut writer, FrameKind::Call, &serde_json::to_vec(&call)?).await?;
+    let pump = tokio::spawn(async move {
+        let mut buf = vec![0; PIPE_SIZE];
+        loop {
+            let n = input.read(&mut buf).await?;
+            // the empty frame is the end of stdin
+            write_frame(&mut writer, FrameKind::Stdin, &buf[..n]).await?;
+            if n == 0 {
+                return Ok::<_, io::Error>(());
+            }
+        }
+    });
+    let result = async {
+        loop {
+            match read_frame(&mut reader).await? {
+                // output nobody reads any more is dropped, the session still runs to its end
+                Some((FrameKind::Stdout, data)) => {
+                    output.write_all(&data).await.ok();
+                }
+                Some((FrameKind::Stderr, data)) => {
+                    errput.write_all(&data).await.ok();
+                }
+                Some((FrameKind::Exit, data)) => {
+                    let code = <[u8; 4]>::try_from(data.as_slice()).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "bad exit frame")
+                    })?;
+                    return Ok(i32::from_be_bytes(code));
+                }
+                Some((FrameKind::Error, data)) => {
+                    let e = serde_json::from_slice::<AppError>(&data)?;
+                    return Err(io::Error::other(e.msg));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the session exited",
+                    ));
+                }
+            }
+        }
+    }
+    .await;
+    pump.abort();
+    result
+}
+
+#[test]
+fn test_storage_address() {
+    assert_eq!(
+        StorageAddress::parse("10.0.0.2:7170"),
+        Some(StorageAddress::Tcp("10.0.0.2:7170".to_string()))
+    );
+    let unix = StorageAddress::parse("unix:/run/jzfs/storage.sock").unwrap();
+    assert_eq!(
+        unix,
+        StorageAddress::Unix(PathBuf::from("/run/jzfs/storage.sock"))
+    );
+    assert_eq!(unix.to_string(), "unix:/run/jzfs/storage.sock");
+    assert_eq!(StorageAddress::parse("unix:"), None);
+    assert_eq!(StorageAddress::parse(""), None);
+}
+
+#[test]
+fn test_blocking_call_timeout() {
+    // a node that takes the connection but never answers
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = StorageAddress::Tcp(listener.local_addr().unwrap().to_string());
+    let mut conn = address
+        .connect_blocking(Duration::from_millis(100))
+        .unwrap();
+    write_frame_blocking(&mut conn, FrameKind::Call, b"{}").unwrap();
+    let e = read_frame_blocking(&mut conn).unwrap_err();
+    assert!(matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    ));
+}
@@ -0,0 +1,434 @@
+use crate::GitContext;
+use crate::storage::StorageAddress;
+use crate::storage::rpc::{FrameKind, ObjectCall, PackSpec, StorageCall, read_frame, write_frame};
+use crate::transport::backend::{PackIo, allow_v2, local_backend};
+use anyhow::anyhow;
+use config::git::AppGitConfig;
+use error::AppError;
+use git2::Oid;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+/// Bytes of git output a frame carries at most.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A git storage service. Serves the repositories below `root` to the API and SSH frontends
+/// whose storage entries point at it, running git and libgit2 next to the data. Only clients
+/// presenting `git.node.secret` are served.
+#[derive(Clone)]
+pub struct StorageNode {
+    root: PathBuf,
+    config: AppGitConfig,
+}
+
+impl StorageNode {
+    /// `config` decides the pack backend and whether protocol v2 is allowed, like it does
+    /// for a frontend serving local storage.
+    pub fn new(root: PathBuf, config: AppGitConfig) -> Self {
+        Self { root, config }
+    }
+    pub async fn serve(self, listen: &str) -> io::Result<()> {
+        if self.config.node.secret.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "git.node.secret is not set",
+            ));
+        }
+        let address = StorageAddress::parse(listen).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid listen address {}", listen),
+            )
+        })?;
+        info!(
+            "storage node serving {} on {}",
+            self.root.display(),
+            address
+        );
+        match address {
+            StorageAddress::Tcp(addr) => self.serve_tcp(TcpListener::bind(addr).await?).await,
+            #[cfg(unix)]
+            StorageAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                // a socket left behind by an earlier run
+                if std::fs::symlink_metadata(&path).is_ok_and(|x| x.file_type().is_socket()) {
+                    std::fs::remove_file(&path)?;
+                }
+                let listener = tokio::net::UnixListener::bind(&path)?;
+                loop {
+                    let (stream, _) = listener.accept().await?;
+                    tokio::spawn(self.clone().connection(stream));
+                }
+            }
+            #[cfg(not(unix))]
+            StorageAddress::Unix(_) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+    pub async fn serve_tcp(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            stream.set_nodelay(true).ok();
+            tokio::spawn(self.clone().connection(stream));
+        }
+    }
+    /// Starts the node on a free localhost port, on a runtime of its own so clients blocking
+    /// on object calls cannot starve it. For tests and trying remote storage on one machine.
+    pub fn spawn_local(self) -> io::Result<StorageAddress> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = StorageAddress::Tcp(listener.local_addr()?.to_string());
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let listener = TcpListener::from_std(listener)?;
+                self.serve_tcp(listener).await
+            })
+        });
+        Ok(address)
+    }
+    async fn connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(self, stream: S) {
+        if let Err(e) = self.call(stream).await {
+            warn!("storage call failed: {}", e);
+        }
+    }
+    async fn call<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+        &self,
+        stream: S,
+    ) -> io::Result<()> {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let secret = match read_frame(&mut reader).await? {
+            Some((FrameKind::Auth, data)) => data,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected the secret",
+                ));
+            }
+            None => return Ok(()),
+        };
+        if !self.authorized(&secret) {
+            let e = AppError::from(anyhow!("storage node refused the secret"));
+            write_frame(&mut writer, FrameKind::Error, &serde_json::to_vec(&e)?).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "client with a wrong secret",
+            ));
+        }
+        let call = match read_frame(&mut reader).await? {
+            Some((FrameKind::Call, data)) => serde_json::from_slice::<StorageCall>(&data)?,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected a call",
+                ));
+            }
+            None => return Ok(()),
+        };
+        let spawned = match call {
+            StorageCall::Ping => return reply(&mut writer, Ok(Value::Null)).await,
+            StorageCall::Object { repo, object } => {
+                let result = match self.context(&repo) {
+                    Ok(git) => tokio::task::spawn_blocking(move || object.run(&git))
+                        .await
+                        .map_err(io::Error::other)?,
+                    Err(e) => Err(e),
+                };
+                return reply(&mut writer, result).await;
+            }
+            StorageCall::Pack { repo, pack } => {
+                self.context(&repo).and_then(|git| self.pack(&git, pack))
+            }
+            StorageCall::Git { repo, op } => self
+                .context(&repo)
+                .and_then(|git| git.git(op).map_err(AppError::from)),
+        };
+        match spawned {
+            Ok(io) => relay(io, reader, writer).await,
+            Err(e) => write_frame(&mut writer, FrameKind::Error, &serde_json::to_vec(&e)?).await,
+        }
+    }
+    /// Digests are compared, how long it takes tells nothing of how much of a guess was right.
+    fn authorized(&self, secret: &[u8]) -> bool {
+        let expected = self.config.node.secret.as_bytes();
+        !expected.is_empty() && Sha256::digest(secret) == Sha256::digest(expected)
+    }
+    /// The repository `repo` names, a single directory below the root.
+    fn context(&self, repo: &str) -> Result<GitContext, AppError> {
+        let valid = !repo.is_empty()
+            && !repo.starts_with('.')
+            && repo
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if !valid {
+            return Err(AppError::from(anyhow!("invalid repository name")));
+        }
+        Ok(GitContext {
+            path_dir: self.root.join(repo),
+            remote: None,
+        })
+    }
+    fn pack(&self, git: &GitContext, pack: PackSpec) -> Result<PackIo, AppError> {
+        if !git.exists()? {
+            return Err(AppError::from(anyhow!("Repository not found")));
+        }
+        let backend = local_backend(&self.config, pack.service, &pack.filter);
+        let allow_v2 = allow_v2(&self.config, pack.service, backend.as_ref());
+        Ok(backend.spawn(pack.request(git.path_dir.clone(), allow_v2))?)
+    }
+}
+
+async fn reply<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    result: Result<Value, AppError>,
+) -> io::Result<()> {
+    match result {
+        Ok(value) => write_frame(writer, FrameKind::Reply, &serde_json::to_vec(&value)?).await,
+        Err(e) => write_frame(writer, FrameKind::Error, &serde_json::to_vec(&e)?).await,
+    }
+}
+
+/// Feeds the client's `Stdin` frames to the session and sends its output back until it
+/// exits. A client that goes away kills the session.
+async fn relay<R, W>(io: PackIo, mut reader: R, mut writer: W) -> io::Result<()>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let PackIo {
+        stdin,
+        mut stdout,
+        mut stderr,
+        mut child,
+    } = io;
+    let (gone_tx, mut gone) = oneshot::channel::<()>();
+    // its own task, so git blocking on a full stdout never holds up its stdin
+    let input = tokio::spawn(async move {
+        let mut stdin = Some(stdin);
+        loop {
+            match read_frame(&mut reader).await {
+                Ok(Some((FrameKind::Stdin, data))) if data.is_empty() => {
+                    if let Some(mut pipe) = stdin.take() {
+                        pipe.shutdown().await.ok();
+                    }
+                }
+                Ok(Some((FrameKind::Stdin, data))) => {
+                    if let Some(pipe) = stdin.as_mut()
+                        && pipe.write_all(&data).await.is_err()
+                    {
+                        // git stopped reading, the rest of the input goes nowhere
+                        stdin = None;
+                    }
+                }
+                _ => {
+                    gone_tx.send(()).ok();
+                    return;
+                }
+            }
+        }
+    });
+    let result = async {
+        let (mut out, mut err) = (vec![0; CHUNK_SIZE], vec![0; CHUNK_SIZE]);
+        let (mut out_open, mut err_open) = (true, true);
+        while out_open || err_open {
+            tokio::select! {
+                n = stdout.read(&mut out), if out_open => match n? {
+                    0 => out_open = false,
+                    n => write_frame(&mut writer, FrameKind::Stdout, &out[..n]).await?,
+                },
+                n = stderr.read(&mut err), if err_open => match n? {
+                    0 => err_open = false,
+                    n => write_frame(&mut writer, FrameKind::Stderr, &err[..n]).await?,
+                },
+                _ = &mut gone => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "client went away",
+                    ));
+                }
+            }
+        }
+        let code = child.wait().await?;
+        write_frame(&mut writer, FrameKind::Exit, &code.to_be_bytes()).await
+    }
+    .await;
+    input.abort();
+    if result.is_err() {
+        child.kill().await.ok();
+    }
+    result
+}
+
+impl ObjectCall {
+    /// Runs the call against the repository on this node's disk.
+    fn run(self, git: &GitContext) -> Result<Value, AppError> {
+        let oid = |x: &str| Oid::from_str(x).map_err(AppError::from);
+        Ok(match self {
+            ObjectCall::Init => {
+                git.init()?;
+                Value::Null
+            }
+            ObjectCall::Exists => serde_json::to_value(git.exists()?)?,
+            ObjectCall::Remove => {
+                git.remove()?;
+                Value::Null
+            }
+            ObjectCall::Size => serde_json::to_value(git.objects_size()?)?,
+            ObjectCall::CommitList { param } => serde_json::to_value(git.commit_list(param)?)?,
+            ObjectCall::CommitRange { tip, hide } => {
+                let hide = hide.iter().map(|x| oid(x)).collect::<Result<Vec<_>, _>>()?;
+                serde_json::to_value(git.commit_range(oid(&tip)?, &hide)?)?
+            }
+            ObjectCall::IsDescendant { commit, ancestor } => {
+                serde_json::to_value(git.is_descendant(oid(&commit)?, oid(&ancestor)?)?)?
+            }
+            ObjectCall::RefTips => serde_json::to_value(
+                git.ref_tips()?
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>(),
+            )?,
+            ObjectCall::ReachableCommits => serde_json::to_value(git.reachable_commits()?)?,
+            ObjectCall::RefsList => serde_json::to_value(git.refs_list()?)?,
+            ObjectCall::RefsRename { old, new } => {
+                git.refs_rename(&old, &new)?;
+                Value::Null
+            }
+            ObjectCall::RefsDelete { name } => {
+                git.refs_delete(&name)?;
+                Value::Null
+            }
+            ObjectCall::RefsExchangeHead { name } => {
+                git.refs_exchange_head(&name)?;
+                Value::Null
+            }
+            ObjectCall::TagList => serde_json::to_value(git.tag_list()?)?,
+            ObjectCall::Tree { param } => serde_json::to_value(git.tree(param)?)?,
+            ObjectCall::TreeItemLastCommit { items } => {
+                serde_json::to_value(git.tree_item_last_commit(items)?)?
+            }
+            ObjectCall::ArchiveTarget { refs, path } => {
+                // not found travels as a reply so the frontend can tell it apart
+                let target = match git.archive_target(&refs, path.as_deref()) {
+                    Ok((commit, tree)) => Ok((commit.to_string(), tree.to_string())),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Err(e.to_string()),
+                    Err(e) => return Err(AppError::from(e)),
+                };
+                serde_json::to_value(target)?
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_storage_node() {
+    use crate::service::partial_clone::FilterPolicy;
+    use crate::service::protection::BranchProtection;
+    use crate::storage::RemoteRepo;
+    use crate::storage::rpc::GitOp;
+    use crate::testing::{self, TempDir};
+    use crate::transport::GitService;
+    use crate::transport::backend::{GitPack, PackRequest, RemotePack};
+    use git2::Repository;
+
+    let root = TempDir::new("storage-node");
+    let mut config = AppGitConfig::default();
+    config.node.secret = "secret".to_string();
+    let address = StorageNode::new(root.to_path_buf(), config)
+        .spawn_local()
+        .unwrap();
+    let remote_with = |name: &str, secret: &str| GitContext {
+        path_dir: root.join(name),
+        remote: Some(RemoteRepo {
+            address: address.clone(),
+            name: name.to_string(),
+            secret: secret.to_string(),
+        }),
+    };
+    let remote = |name: &str| remote_with(name, "secret");
+    let git = remote("repo");
+    assert!(!git.exists().unwrap());
+    git.init().unwrap();
+    assert!(git.exists().unwrap());
+    assert!(remote("../repo").exists().is_err());
+    assert!(remote_with("repo", "guess").exists().is_err());
+    assert!(remote_with("repo", "").exists().is_err());
+
+    // a push, straight to the node's disk
+    let repo = Repository::open_bare(root.join("repo")).unwrap();
+    let files: &[(&str, &[u8])] = &[("data.csv", b"1,2\n")];
+    let first = testing::commit(&repo, "refs/heads/main", files, &[]);
+    let second = testing::commit(&repo, "refs/heads/main", files, &[first]);
+    let tree = repo.find_commit(second).unwrap().tree_id();
+    git.refs_exchange_head("main").unwrap();
+
+    let refs = git.refs_list().unwrap();
+    assert_eq!(refs.len(), 1);
+    assert_eq!(refs[0].hash, second.to_string());
+    assert_eq!(git.ref_tips().unwrap(), [second]);
+    assert!(git.is_descendant(second, first).unwrap());
+    assert_eq!(git.commit_range(second, &[first]).unwrap().len(), 1);
+    assert_eq!(git.reachable_commits().unwrap().len(), 2);
+    assert!(git.objects_size().unwrap() > 0);
+    assert_eq!(git.archive_target("main", None).unwrap(), (second, tree));
+    let missing = git.archive_target("missing", None).unwrap_err();
+    assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+
+    let output = git
+        .git(GitOp::SymbolicRef { target: None })
+        .unwrap()
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.code, 0);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "refs/heads/main"
+    );
+    let refused = remote_with("repo", "guess")
+        .git(GitOp::Fsck)
+        .unwrap()
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(refused.code, 128);
+
+    let request = PackRequest {
+        service: GitService::UploadPack,
+        path: git.path_dir.clone(),
+        protocol: None,
+        stateless: true,
+        advertise_refs: true,
+        protection: BranchProtection::default(),
+        filter: FilterPolicy::default(),
+        max_input_size: None,
+    };
+    let backend = RemotePack(git.remote.clone().unwrap());
+    let output = backend.spawn(request).unwrap().output().await.unwrap();
+    assert_eq!(output.code, 0);
+    let advertised = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(advertised.contains(&format!("{} refs/heads/main", second)));
+    git.remove().unwrap();
+    assert!(!git.exists().unwrap());
+
+    // a node that is not there fails like git would
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let gone = RemoteRepo {
+        address: StorageAddress::Tcp(port.to_string()),
+        name: "repo".to_string(),
+        secret: "secret".to_string(),
+    };
+    let output = gone.spawn(StorageCall::Ping).output().await.unwrap();
+    assert_eq!(output.code, 128);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("storage unavailable"));
+}
@@ -0,0 +1,353 @@
+use crate::object::commit::CommitPaginator;
+use crate::object::tree::{TreeItem, TreeParam};
+use crate::service::maintenance::MaintenanceTask;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::protection::BranchProtection;
+use crate::transport::GitService;
+use crate::transport::backend::PackRequest;
+use crate::transport::protocol::GitProtocol;
+use git2::{Oid, Reference};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest payload a frame may carry, a reply listing a whole tree included.
+const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+/// A connection carries a single call. The client opens it with `Auth`, the secret the node
+/// shares with its frontends, and then `Call`; a wrong secret is answered with `Error`.
+/// Object calls are answered with one `Reply`, or `Error` holding an `AppError`. `Pack` and
+/// `Git` run a process on the node: the client sends its input as `Stdin` frames ended by an
+/// empty one, the node sends the output as `Stdout` and `Stderr` frames and finally `Exit`
+/// with the exit code, a big endian `i32`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FrameKind {
+    Call = 1,
+    Reply = 2,
+    Error = 3,
+    Stdin = 4,
+    Stdout = 5,
+    Stderr = 6,
+    Exit = 7,
+    Auth = 8,
+}
+
+impl FrameKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        Some(match kind {
+            1 => Self::Call,
+            2 => Self::Reply,
+            3 => Self::Error,
+            4 => Self::Stdin,
+            5 => Self::Stdout,
+            6 => Self::Stderr,
+            7 => Self::Exit,
+            8 => Self::Auth,
+            _ => return None,
+        })
+    }
+}
+
+pub type Frame = (FrameKind, Vec<u8>);
+
+fn header(kind: FrameKind, data: &[u8]) -> io::Result<[u8; 5]> {
+    if data.len() > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut header = [kind as u8, 0, 0, 0, 0];
+    header[1..].copy_from_slice(&(data.len() as u32).to_be_bytes());
+    Ok(header)
+}
+
+fn parse_header(header: [u8; 5]) -> io::Result<(FrameKind, usize)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let kind = FrameKind::from_u8(header[0]).ok_or_else(|| invalid("unknown frame kind"))?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME {
+        return Err(invalid("frame too large"));
+    }
+    Ok((kind, len))
+}
+
+/// Reads the next frame, `None` when the peer closed the connection.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Frame>> {
+    let mut head = [0; 5];
+    match reader.read_exact(&mut head).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let (kind, len) = parse_header(head)?;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).await?;
+    Ok(Some((kind, data)))
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    kind: FrameKind,
+    data: &[u8],
+) -> io::Result<()> {
+    writer.write_all(&header(kind, data)?).await?;
+    writer.write_all(data).await?;
+    writer.flush().await
+}
+
+pub fn read_frame_blocking<R: Read>(reader: &mut R) -> io::Result<Option<Frame>> {
+    let mut head = [0; 5];
+    match reader.read_exact(&mut head) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let (kind, len) = parse_header(head)?;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    Ok(Some((kind, data)))
+}
+
+pub fn write_frame_blocking<W: Write>(
+    writer: &mut W,
+    kind: FrameKind,
+    data: &[u8],
+) -> io::Result<()> {
+    writer.write_all(&header(kind, data)?)?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// What a client asks of a storage node, the payload of the `Call` frame. `repo` is the
+/// directory name of the repository below the node's root.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "call", rename_all = "snake_case")]
+pub enum StorageCall {
+    Ping,
+    Object { repo: String, object: ObjectCall },
+    Pack { repo: String, pack: PackSpec },
+    Git { repo: String, op: GitOp },
+}
+
+/// Repository reads and writes done with libgit2 on the node, one per `GitContext` method.
+/// Object ids travel as hex strings.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ObjectCall {
+    Init,
+    Exists,
+    Remove,
+    Size,
+    CommitList { param: CommitPaginator },
+    CommitRange { tip: String, hide: Vec<String> },
+    IsDescendant { commit: String, ancestor: String },
+    RefTips,
+    ReachableCommits,
+    RefsList,
+    RefsRename { old: String, new: String },
+    RefsDelete { name: String },
+    RefsExchangeHead { name: String },
+    TagList,
+    Tree { param: TreeParam },
+    TreeItemLastCommit { items: Vec<TreeItem> },
+    ArchiveTarget { refs: String, path: Option<String> },
+}
+
+/// The git commands run in a repository, one per use. The command line is built from these
+/// where git runs, a node never takes one from its client.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum GitOp {
+    /// The object id and name of every ref, a line each.
+    ForEachRef,
+    /// Where `HEAD` points, or points it at `target`.
+    SymbolicRef {
+        target: Option<String>,
+    },
+    /// Applies the `update-ref` commands on stdin.
+    UpdateRef,
+    /// A pack of the objects the revisions on stdin select, on stdout.
+    PackObjects,
+    /// Stores the pack on stdin.
+    IndexPack,
+    /// Fails unless every object the revisions on stdin reach is there.
+    RevList,
+    Fsck,
+    /// A tar, or zip, of `commit` with every file under `prefix`, only those below `path`
+    /// when it is set.
+    Archive {
+        zip: bool,
+        prefix: String,
+        commit: String,
+        path: Option<String>,
+    },
+    Maintenance {
+        task: MaintenanceTask,
+        prune_expire: String,
+    },
+    PackRefs,
+}
+
+impl GitOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GitOp::ForEachRef => "for-each-ref",
+            GitOp::SymbolicRef { .. } => "symbolic-ref",
+            GitOp::UpdateRef => "update-ref",
+            GitOp::PackObjects => "pack-objects",
+            GitOp::IndexPack => "index-pack",
+            GitOp::RevList => "rev-list",
+            GitOp::Fsck => "fsck",
+            GitOp::Archive { .. } => "archive",
+            GitOp::Maintenance { task, .. } => task.name(),
+            GitOp::PackRefs => "pack-refs",
+        }
+    }
+    /// The arguments to git. Values that end up as arguments of their own are checked, so
+    /// none of them passes for an option.
+    pub fn args(&self) -> io::Result<Vec<String>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+        let args = match self {
+            GitOp::ForEachRef => vec!["for-each-ref", "--format=%(objectname) %(refname)"],
+            GitOp::SymbolicRef { target: None } => vec!["symbolic-ref", "HEAD"],
+            GitOp::SymbolicRef {
+                target: Some(target),
+            } => {
+                if !target.starts_with("refs/") || !Reference::is_valid_name(target) {
+                    return Err(invalid("invalid ref name"));
+                }
+                vec!["symbolic-ref", "HEAD", target]
+            }
+            GitOp::UpdateRef => vec!["update-ref", "--stdin"],
+            GitOp::PackObjects => vec!["pack-objects", "--revs", "--stdout", "-q"],
+            GitOp::IndexPack => vec!["index-pack", "--stdin"],
+            GitOp::RevList => vec!["rev-list", "--objects", "--quiet", "--stdin"],
+            GitOp::Fsck => vec!["fsck", "--no-dangling", "--no-progress"],
+            GitOp::Archive {
+                zip,
+                prefix,
+                commit,
+                path,
+            } => {
+                if Oid::from_str(commit).is_err() {
+                    return Err(invalid("invalid commit"));
+                }
+                let mut args = vec![
+                    "--literal-pathspecs".to_string(),
+                    "archive".to_string(),
+                    format!("--format={}", if *zip { "zip" } else { "tar" }),
+                    format!("--prefix={}/", prefix),
+                    commit.clone(),
+                ];
+                if let Some(path) = path {
+                    args.extend(["--".to_string(), path.clone()]);
+                }
+                return Ok(args);
+            }
+            GitOp::Maintenance { task, prune_expire } => return Ok(task.args(prune_expire)),
+            GitOp::PackRefs => vec!["pack-refs", "--all", "--prune"],
+        };
+        Ok(args.into_iter().map(|x| x.to_string()).collect())
+    }
+}
+
+/// A `PackRequest` without the repository path, which the node resolves itself.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PackSpec {
+    pub service: GitService,
+    pub protocol: Option<String>,
+    pub stateless: bool,
+    pub advertise_refs: bool,
+    pub protection: BranchProtection,
+    pub filter: FilterPolicy,
+    pub max_input_size: Option<u64>,
+}
+
+impl From<&PackRequest> for PackSpec {
+    fn from(request: &PackRequest) -> Self {
+        Self {
+            service: request.service,
+            protocol: request.protocol.as_ref().map(|x| x.value()),
+            stateless: request.stateless,
+            advertise_refs: request.advertise_refs,
+            protection: request.protection.clone(),
+            filter: request.filter.clone(),
+            max_input_size: request.max_input_size,
+        }
+    }
+}
+
+impl PackSpec {
+    /// The protocol is parsed again, the node decides on its own whether v2 is allowed.
+    pub fn request(self, path: PathBuf, allow_v2: bool) -> PackRequest {
+        PackRequest {
+            service: self.service,
+            path,
+            protocol: self.protocol.and_then(|x| GitProtocol::parse(&x, allow_v2)),
+            stateless: self.stateless,
+            advertise_refs: self.advertise_refs,
+            protection: self.protection,
+            filter: self.filter,
+            max_input_size: self.max_input_size,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_frame() {
+    let mut buf = vec![];
+    write_frame(&mut buf, FrameKind::Stdout, b"hello")
+        .await
+        .unwrap();
+    write_frame_blocking(&mut buf, FrameKind::Stdin, b"").unwrap();
+    let mut reader = buf.as_slice();
+    assert_eq!(
+        read_frame(&mut reader).await.unwrap(),
+        Some((FrameKind::Stdout, b"hello".to_vec()))
+    );
+    assert_eq!(
+        read_frame_blocking(&mut reader).unwrap(),
+        Some((FrameKind::Stdin, vec![]))
+    );
+    assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+    let mut bad = [9, 0, 0, 0, 0].as_slice();
+    assert!(read_frame(&mut bad).await.is_err());
+}
+
+#[test]
+fn test_git_op() {
+    let head = |target: &str| GitOp::SymbolicRef {
+        target: Some(target.to_string()),
+    };
+    assert_eq!(
+        head("refs/heads/main").args().unwrap(),
+        ["symbolic-ref", "HEAD", "refs/heads/main"]
+    );
+    assert!(head("--upload-pack=touch").args().is_err());
+    assert!(head("HEAD").args().is_err());
+    let archive = |commit: &str, path: Option<&str>| GitOp::Archive {
+        zip: false,
+        prefix: "demo".to_string(),
+        commit: commit.to_string(),
+        path: path.map(|x| x.to_string()),
+    };
+    let commit = "1111111111111111111111111111111111111111";
+    assert_eq!(
+        archive(commit, Some("--output=x")).args().unwrap(),
+        [
+            "--literal-pathspecs",
+            "archive",
+            "--format=tar",
+            "--prefix=demo/",
+            commit,
+            "--",
+            "--output=x"
+        ]
+    );
+    assert!(archive("--remote=x", None).args().is_err());
+    // a client names an operation, never a command line
+    let call = r#"{"call":"git","repo":"repo","args":["config","core.sshCommand","x"]}"#;
+    assert!(serde_json::from_str::<StorageCall>(call).is_err());
+}
@@ -0,0 +1,149 @@
+use crate::service::GitServer;
+use bb8::Pool;
+use bb8_redis::RedisConnectionManager;
+use config::AppConfig;
+use git2::{Index, IndexEntry, IndexTime, Oid, Repository, Signature};
+use sea_orm::DatabaseConnection;
+use sea_orm::prelude::Uuid;
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// A fresh directory under the system temp dir, removed with everything in it on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("jzfs-{}-{}", name, Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Runs git in `dir` as the test author with `input` on stdin, returns its stdout.
+pub fn git_with_input(dir: &Path, args: &[&str], input: &[u8]) -> Vec<u8> {
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "jzfs")
+        .env("GIT_AUTHOR_EMAIL", "jzfs@example.com")
+        .env("GIT_COMMITTER_NAME", "jzfs")
+        .env("GIT_COMMITTER_EMAIL", "jzfs@example.com")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap().stdout
+}
+
+/// Runs git in `dir` as the test author, returns its stdout trimmed.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    String::from_utf8_lossy(&git_with_input(dir, args, b""))
+        .trim()
+        .to_string()
+}
+
+/// Commits a tree of `files`, paths with `/` making subtrees, on top of `parents` and
+/// points `refs` at it.
+pub fn commit(repo: &Repository, refs: &str, files: &[(&str, &[u8])], parents: &[Oid]) -> Oid {
+    let mut index = Index::new().unwrap();
+    for (path, content) in files {
+        index
+            .add(&IndexEntry {
+                ctime: IndexTime::new(0, 0),
+                mtime: IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: content.len() as u32,
+                id: repo.blob(content).unwrap(),
+                flags: path.len().min(0xfff) as u16,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            })
+            .unwrap();
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo).unwrap()).unwrap();
+    let parents = parents
+        .iter()
+        .map(|x| repo.find_commit(*x).unwrap())
+        .collect::<Vec<_>>();
+    let sig = Signature::now("jzfs", "jzfs@example.com").unwrap();
+    repo.commit(
+        Some(refs),
+        &sig,
+        &sig,
+        "update",
+        &tree,
+        &parents.iter().collect::<Vec<_>>(),
+    )
+    .unwrap()
+}
+
+/// A `GitServer` on `config` with neither a database nor Redis behind it.
+pub fn git_server(config: AppConfig) -> GitServer {
+    let redis = Pool::builder()
+        .connection_timeout(Duration::from_millis(50))
+        .build_unchecked(RedisConnectionManager::new("redis://127.0.0.1:1").unwrap());
+    GitServer {
+        db: DatabaseConnection::Disconnected,
+        config,
+        redis,
+    }
+}
+
+/// Clones `url` with `--filter=blob:none` over protocol `version`, checks the clone is a
+/// promisor pack missing its blobs and that checking out `file` fetches it lazily.
+pub fn check_partial_clone(url: &str, version: &str, file: &str, content: &[u8]) {
+    let dir = TempDir::new("partial-clone");
+    let protocol = format!("protocol.version={}", version);
+    git(
+        &dir,
+        &[
+            "-c",
+            &protocol,
+            "clone",
+            "-q",
+            "--filter=blob:none",
+            "--no-checkout",
+            url,
+            "clone",
+        ],
+    );
+    let clone = dir.join("clone");
+    let packs = std::fs::read_dir(clone.join(".git/objects/pack"))
+        .unwrap()
+        .flatten()
+        .map(|x| x.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert!(packs.iter().any(|x| x.ends_with(".promisor")));
+    let missing = || {
+        let args = ["rev-list", "--objects", "--all", "--missing=print"];
+        let objects = git(&clone, &args);
+        objects.lines().filter(|x| x.starts_with('?')).count()
+    };
+    assert_eq!(missing(), 1);
+    // lazy fetches take the protocol from the clone's own config
+    git(&clone, &["config", "protocol.version", version]);
+    git(&clone, &["checkout", "-q", "HEAD", "--", "."]);
+    assert_eq!(std::fs::read(clone.join(file)).unwrap(), content);
+    assert_eq!(missing(), 0);
+}
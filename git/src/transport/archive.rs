@@ -0,0 +1,323 @@
+use crate::GitContext;
+use crate::service::GitServer;
+use crate::storage::rpc::{GitOp, ObjectCall};
+use crate::transport::backend::PackIo;
+use crate::transport::disk_cache::DiskCache;
+use async_compression::tokio::bufread::{GzipEncoder, ZstdEncoder};
+use async_stream::stream;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use git2::{ObjectType, Oid, Repository};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+const ARCHIVE_CHUNK_SIZE: usize = 64 * 1024;
+
+pub type ArchiveBody = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Splits a file name such as `main.tar.gz` into the ref and the format.
+    pub fn split(file: &str) -> Option<(&str, Self)> {
+        [Self::TarGz, Self::TarZst, Self::Zip]
+            .into_iter()
+            .find_map(|format| {
+                let refs = file.strip_suffix(format.extension())?;
+                let refs = refs.strip_suffix('.')?;
+                (!refs.is_empty()).then_some((refs, format))
+            })
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::TarZst => "application/zstd",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+}
+
+/// A snapshot of the commit `refs` names, only the files below `path` when it is set. Every
+/// file sits under the directory `prefix` in the archive.
+pub struct ArchiveRequest {
+    pub git: GitContext,
+    pub refs: String,
+    pub path: Option<String>,
+    pub format: ArchiveFormat,
+    pub prefix: String,
+}
+
+impl GitContext {
+    /// The commit to archive and the tree its files come from. A ref or path that does not
+    /// exist is `NotFound`.
+    pub fn archive_target(&self, refs: &str, path: Option<&str>) -> io::Result<(Oid, Oid)> {
+        let not_found = |msg: &str| io::Error::new(io::ErrorKind::NotFound, msg.to_string());
+        if let Some(remote) = &self.remote {
+            let target = remote
+                .object::<Result<(String, String), String>>(ObjectCall::ArchiveTarget {
+                    refs: refs.to_string(),
+                    path: path.map(|x| x.to_string()),
+                })
+                .map_err(|e| io::Error::other(e.msg))?
+                .map_err(|e| not_found(&e))?;
+            let oid = |x: &str| Oid::from_str(x).map_err(io::Error::other);
+            return Ok((oid(&target.0)?, oid(&target.1)?));
+        }
+        let repo = Repository::open_bare(&self.path_dir).map_err(io::Error::other)?;
+        let commit = repo
+            .revparse_single(refs)
+            .and_then(|x| x.peel_to_commit())
+            .map_err(|_| not_found("ref not found"))?;
+        let tree = commit.tree().map_err(io::Error::other)?;
+        let Some(path) = path else {
+            return Ok((commit.id(), tree.id()));
+        };
+        let entry = tree
+            .get_path(Path::new(path))
+            .map_err(|_| not_found("path not found"))?;
+        if entry.kind() != Some(ObjectType::Tree) {
+            return Err(not_found("path is not a directory"));
+        }
+        Ok((commit.id(), entry.id()))
+    }
+}
+
+/// The commit is part of the key rather than just the tree, git stamps its id and time into
+/// the archive.
+fn archive_key(request: &ArchiveRequest, commit: Oid) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(request.format.extension().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(request.prefix.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(request.path.as_deref().unwrap_or_default().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(commit.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Streams the archive while `git archive` writes it, from the cache when the same commit was
+/// archived the same way before. The archive is cached once git exited cleanly.
+pub async fn archive(cache: Option<DiskCache>, request: ArchiveRequest) -> io::Result<ArchiveBody> {
+    let (git, refs, path) = (
+        request.git.clone(),
+        request.refs.clone(),
+        request.path.clone(),
+    );
+    let (commit, _) =
+        tokio::task::spawn_blocking(move || git.archive_target(&refs, path.as_deref()))
+            .await
+            .map_err(io::Error::other)??;
+    let key = archive_key(&request, commit);
+    if let Some(cache) = &cache
+        && let Some(file) = cache.open(&key).await
+    {
+        return Ok(Box::pin(ReaderStream::with_capacity(
+            file,
+            ARCHIVE_CHUNK_SIZE,
+        )));
+    }
+
+    let PackIo {
+        stdout,
+        stderr,
+        mut child,
+        ..
+    } = request.git.git(GitOp::Archive {
+        zip: request.format == ArchiveFormat::Zip,
+        prefix: request.prefix.clone(),
+        commit: commit.to_string(),
+        path: request.path.clone(),
+    })?;
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            warn!("archive: {}", line);
+        }
+    });
+    let stdout: Box<dyn AsyncRead + Send + Unpin> = match request.format {
+        ArchiveFormat::TarGz => Box::new(GzipEncoder::new(BufReader::new(stdout))),
+        ArchiveFormat::TarZst => Box::new(ZstdEncoder::new(BufReader::new(stdout))),
+        ArchiveFormat::Zip => Box::new(stdout),
+    };
+    let mut writer = match &cache {
+        Some(cache) => match cache.create(&key).await {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                warn!("archive cache entry not created: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let body = stream! {
+        let mut stdout = ReaderStream::with_capacity(stdout, ARCHIVE_CHUNK_SIZE);
+        while let Some(chunk) = stdout.next().await {
+            match &chunk {
+                Ok(data) => {
+                    if let Some(file) = writer.as_mut() {
+                        if let Err(e) = file.write(data).await {
+                            warn!("archive cache entry dropped: {}", e);
+                            writer = None;
+                        }
+                    }
+                }
+                Err(_) => writer = None,
+            }
+            yield chunk;
+        }
+        let status = child.wait().await;
+        if let (Ok(status), Some(writer)) = (status, writer) {
+            if status == 0 {
+                if let Err(e) = writer.commit().await {
+                    warn!("archive cache entry not stored: {}", e);
+                }
+            }
+        }
+    };
+    Ok(Box::pin(body))
+}
+
+impl GitServer {
+    /// The cache of generated archives, unless `git.archive.cache` is off.
+    pub fn archive_cache(&self) -> Option<DiskCache> {
+        let config = &self.config.git.archive;
+        config
+            .cache
+            .then(|| DiskCache::new(config.path.clone(), config.max_size))
+    }
+}
+
+#[test]
+fn test_archive_format() {
+    assert_eq!(
+        ArchiveFormat::split("main.tar.gz"),
+        Some(("main", ArchiveFormat::TarGz))
+    );
+    assert_eq!(
+        ArchiveFormat::split("release/v1.0.tar.zst"),
+        Some(("release/v1.0", ArchiveFormat::TarZst))
+    );
+    assert_eq!(
+        ArchiveFormat::split("v1.0.zip"),
+        Some(("v1.0", ArchiveFormat::Zip))
+    );
+    assert_eq!(ArchiveFormat::split(".zip"), None);
+    assert_eq!(ArchiveFormat::split("main.tar"), None);
+    assert_eq!(ArchiveFormat::split("main.targz"), None);
+}
+
+#[tokio::test]
+async fn test_archive() {
+    use crate::testing::{self, TempDir};
+    use async_compression::tokio::bufread::GzipDecoder;
+    use tokio::io::AsyncReadExt;
+
+    let root = TempDir::new("archive");
+    let dir = root.join("repo");
+    let repo = Repository::init_bare(&dir).unwrap();
+    let commit = testing::commit(
+        &repo,
+        "refs/heads/main",
+        &[("data/rows.csv", b"1,2\n"), ("README.md", b"# demo\n")],
+        &[],
+    );
+    async fn collect(body: ArchiveBody) -> Vec<u8> {
+        body.map(|x| x.unwrap()).collect::<Vec<_>>().await.concat()
+    }
+    let cache = DiskCache::new(root.join("cache"), 1024 * 1024);
+    let git = GitContext {
+        path_dir: dir.clone(),
+        remote: None,
+    };
+    let request = |format, path: Option<&str>| ArchiveRequest {
+        git: git.clone(),
+        refs: "main".to_string(),
+        path: path.map(|x| x.to_string()),
+        format,
+        prefix: "demo-main".to_string(),
+    };
+    let first = archive(
+        Some(cache.clone()),
+        request(ArchiveFormat::TarGz, Some("data")),
+    )
+    .await
+    .unwrap();
+    let first = collect(first).await;
+    let mut tar = vec![];
+    GzipDecoder::new(first.as_slice())
+        .read_to_end(&mut tar)
+        .await
+        .unwrap();
+    let contains = |name: &[u8]| tar.windows(name.len()).any(|x| x == name);
+    assert!(contains(b"demo-main/data/rows.csv"));
+    assert!(!contains(b"README.md"));
+    // the second time comes from the cache
+    let key = archive_key(&request(ArchiveFormat::TarGz, Some("data")), commit);
+    assert!(cache.entry(&key).exists());
+    let second = archive(
+        Some(cache.clone()),
+        request(ArchiveFormat::TarGz, Some("data")),
+    )
+    .await
+    .unwrap();
+    assert_eq!(collect(second).await, first);
+    // a new commit of the same tree is archived anew, with its own id in the header
+    let next = testing::commit(
+        &repo,
+        "refs/heads/main",
+        &[("data/rows.csv", b"1,2\n"), ("README.md", b"# demo\n")],
+        &[commit],
+    );
+    let third = archive(
+        Some(cache.clone()),
+        request(ArchiveFormat::TarGz, Some("data")),
+    )
+    .await
+    .unwrap();
+    let mut tar = vec![];
+    GzipDecoder::new(collect(third).await.as_slice())
+        .read_to_end(&mut tar)
+        .await
+        .unwrap();
+    let next = next.to_string();
+    assert!(tar.windows(next.len()).any(|x| x == next.as_bytes()));
+
+    let zip = collect(
+        archive(None, request(ArchiveFormat::Zip, None))
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert!(zip.starts_with(b"PK"));
+    let zst = collect(
+        archive(None, request(ArchiveFormat::TarZst, None))
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert!(zst.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+    for path in ["missing", "README.md"] {
+        let Err(e) = archive(None, request(ArchiveFormat::Zip, Some(path))).await else {
+            panic!("archived {}", path);
+        };
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+}
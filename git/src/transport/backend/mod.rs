@@ -0,0 +1,206 @@
+use crate::GitContext;
+use crate::service::GitServer;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::protection::BranchProtection;
+use crate::transport::GitService;
+use crate::transport::protocol::GitProtocol;
+use config::git::{AppGitConfig, GitPackBackend};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::process::Child;
+use tokio::task::JoinHandle;
+
+pub mod native;
+pub mod remote;
+pub mod subprocess;
+
+pub use native::NativePack;
+pub use remote::RemotePack;
+pub use subprocess::SubprocessPack;
+
+pub struct PackRequest {
+    pub service: GitService,
+    pub path: PathBuf,
+    pub protocol: Option<GitProtocol>,
+    /// One request/response round per HTTP call, as `--stateless-rpc`.
+    pub stateless: bool,
+    /// Only write the ref advertisement, as `--advertise-refs`.
+    pub advertise_refs: bool,
+    /// Rules checked against the pushed history before receive-pack moves any ref.
+    pub protection: BranchProtection,
+    /// Partial clone filters upload-pack accepts.
+    pub filter: FilterPolicy,
+    /// Pack bytes receive-pack accepts, what is left of the storage quota.
+    pub max_input_size: Option<u64>,
+}
+
+/// The pipes of a running pack session, shaped like a child process so transports do not
+/// care whether git runs in a subprocess or in-process.
+pub struct PackIo {
+    pub stdin: Box<dyn AsyncWrite + Send + Unpin>,
+    pub stdout: Box<dyn AsyncRead + Send + Unpin>,
+    pub stderr: Box<dyn AsyncRead + Send + Unpin>,
+    pub child: PackChild,
+}
+
+/// What a session left behind once it exited.
+pub struct PackOutput {
+    pub code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl PackIo {
+    /// Closes stdin and collects the output until the session exits.
+    pub async fn output(self) -> io::Result<PackOutput> {
+        let PackIo {
+            stdin,
+            mut stdout,
+            mut stderr,
+            mut child,
+        } = self;
+        drop(stdin);
+        let (mut out, mut err) = (vec![], vec![]);
+        tokio::try_join!(stdout.read_to_end(&mut out), stderr.read_to_end(&mut err))?;
+        Ok(PackOutput {
+            code: child.wait().await?,
+            stdout: out,
+            stderr: err,
+        })
+    }
+}
+
+pub struct PackChild {
+    inner: ChildInner,
+}
+
+enum ChildInner {
+    Process(Child),
+    Task {
+        handle: JoinHandle<i32>,
+        code: Option<i32>,
+        cancel: Arc<AtomicBool>,
+    },
+    /// A session on a storage node, ended by dropping its connection.
+    Remote {
+        handle: JoinHandle<i32>,
+        code: Option<i32>,
+    },
+}
+
+impl PackChild {
+    pub fn process(child: Child) -> Self {
+        Self {
+            inner: ChildInner::Process(child),
+        }
+    }
+    pub fn task(handle: JoinHandle<i32>, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            inner: ChildInner::Task {
+                handle,
+                code: None,
+                cancel,
+            },
+        }
+    }
+    pub fn remote(handle: JoinHandle<i32>) -> Self {
+        Self {
+            inner: ChildInner::Remote { handle, code: None },
+        }
+    }
+    /// Waits for the session to finish and returns its exit code. Cancel safe, so it can
+    /// be polled from a `select!` loop.
+    pub async fn wait(&mut self) -> io::Result<i32> {
+        match &mut self.inner {
+            ChildInner::Process(child) => Ok(child.wait().await?.code().unwrap_or(128)),
+            ChildInner::Task { handle, code, .. } | ChildInner::Remote { handle, code } => {
+                if let Some(code) = code {
+                    return Ok(*code);
+                }
+                let result = handle.await.map_err(io::Error::other)?;
+                *code = Some(result);
+                Ok(result)
+            }
+        }
+    }
+    pub async fn kill(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            ChildInner::Process(child) => child.kill().await,
+            ChildInner::Task { cancel, .. } => {
+                // the blocking task notices at its next read or write
+                cancel.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            ChildInner::Remote { handle, .. } => {
+                handle.abort();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for PackChild {
+    fn drop(&mut self) {
+        // like `kill_on_drop` for a process, the node kills git when the connection goes
+        if let ChildInner::Remote { handle, .. } = &self.inner {
+            handle.abort();
+        }
+    }
+}
+
+/// A smart protocol implementation. `spawn` is called from within the tokio runtime and
+/// must not block.
+pub trait GitPack: Send + Sync {
+    fn supports(&self, service: GitService) -> bool;
+    fn supports_v2(&self) -> bool;
+    fn supports_filter(&self) -> bool;
+    fn spawn(&self, request: PackRequest) -> io::Result<PackIo>;
+}
+
+/// The backend `config` selects for a repository on local disk, falling back to the git
+/// subprocess for services the native backend does not implement and for repositories that
+/// allow partial clone filters.
+pub fn local_backend(
+    config: &AppGitConfig,
+    service: GitService,
+    filter: &FilterPolicy,
+) -> Box<dyn GitPack> {
+    match config.backend {
+        GitPackBackend::Native
+            if NativePack.supports(service)
+                && (service != GitService::UploadPack
+                    || filter.is_empty()
+                    || NativePack.supports_filter()) =>
+        {
+            Box::new(NativePack)
+        }
+        _ => Box::new(SubprocessPack),
+    }
+}
+
+/// Whether a client's `version=2` may be passed on to `backend`.
+pub fn allow_v2(config: &AppGitConfig, service: GitService, backend: &dyn GitPack) -> bool {
+    config.protocol_v2 && service == GitService::UploadPack && backend.supports_v2()
+}
+
+impl GitServer {
+    /// The backend configured in `git.backend` for `git`, or its storage node when the
+    /// repository lives on one.
+    pub fn pack_backend(
+        &self,
+        git: &GitContext,
+        service: GitService,
+        filter: &FilterPolicy,
+    ) -> Box<dyn GitPack> {
+        match &git.remote {
+            Some(remote) => Box::new(RemotePack(remote.clone())),
+            None => local_backend(&self.config.git, service, filter),
+        }
+    }
+    pub fn allow_v2(&self, service: GitService, backend: &dyn GitPack) -> bool {
+        allow_v2(&self.config.git, service, backend)
+    }
+}
@@ -0,0 +1,824 @@
+use crate::service::protection::PROTECTED_REASON;
+use crate::transport::GitService;
+use crate::transport::backend::{GitPack, PackChild, PackIo, PackRequest};
+use crate::transport::pkt::{FLUSH_PKT, Pkt, PktReader, Sideband, encode, write_flush, write_pkt};
+use crate::transport::push::RefCommand;
+use anyhow::{anyhow, bail};
+use git2::{ObjectType, Oid, PackBuilder, Reference, Repository};
+use std::collections::HashSet;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::duplex;
+use tokio_util::io::SyncIoBridge;
+
+const PIPE_SIZE: usize = 64 * 1024;
+const AGENT: &str = concat!("agent=jzfs/", env!("CARGO_PKG_VERSION"));
+const UPLOAD_CAPS: &str =
+    "multi_ack multi_ack_detailed no-done side-band side-band-64k ofs-delta no-progress";
+// thin packs would need their bases resolved against the odb, ask for full packs instead
+const RECEIVE_CAPS: &str = "report-status delete-refs side-band-64k quiet ofs-delta no-thin";
+
+/// In-process upload-pack/receive-pack on top of libgit2, speaking protocol v0/v1 only.
+/// Each session runs on the blocking pool and talks to the transport through in-memory
+/// pipes, so a fetch costs a thread instead of a process.
+pub struct NativePack;
+
+impl GitPack for NativePack {
+    fn supports(&self, service: GitService) -> bool {
+        matches!(service, GitService::UploadPack | GitService::ReceivePack)
+    }
+    fn supports_v2(&self) -> bool {
+        false
+    }
+    fn supports_filter(&self) -> bool {
+        false
+    }
+    fn spawn(&self, request: PackRequest) -> io::Result<PackIo> {
+        if !self.supports(request.service) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is not implemented natively", request.service.name()),
+            ));
+        }
+        let (stdin, stdin_rx) = duplex(PIPE_SIZE);
+        let (stdout_tx, stdout) = duplex(PIPE_SIZE);
+        let (stderr_tx, stderr) = duplex(PIPE_SIZE);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let input = Cancellable::new(SyncIoBridge::new(stdin_rx), cancel.clone());
+        let output = Cancellable::new(SyncIoBridge::new(stdout_tx), cancel.clone());
+        let mut stderr_tx = SyncIoBridge::new(stderr_tx);
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut output = BufWriter::new(output);
+            let result = Repository::open(&request.path)
+                .map_err(anyhow::Error::from)
+                .and_then(|repo| serve(&repo, &request, BufReader::new(input), &mut output))
+                .and_then(|_| output.flush().map_err(anyhow::Error::from));
+            match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    writeln!(stderr_tx, "fatal: {}", e).ok();
+                    128
+                }
+            }
+        });
+        Ok(PackIo {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            child: PackChild::task(handle, cancel),
+        })
+    }
+}
+
+struct Cancellable<T> {
+    inner: T,
+    cancel: Arc<AtomicBool>,
+}
+
+impl<T> Cancellable<T> {
+    fn new(inner: T, cancel: Arc<AtomicBool>) -> Self {
+        Self { inner, cancel }
+    }
+    fn check(&self) -> io::Result<()> {
+        if self.cancel.load(Ordering::Relaxed) {
+            Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "cancelled",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<T: Read> Read for Cancellable<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for Cancellable<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.check()?;
+        self.inner.flush()
+    }
+}
+
+struct AdvertisedRef {
+    name: String,
+    oid: Oid,
+    peeled: Option<Oid>,
+}
+
+struct Refs {
+    /// symbolic target and value of HEAD, if it is born
+    head: Option<(String, Oid)>,
+    refs: Vec<AdvertisedRef>,
+}
+
+impl Refs {
+    fn load(repo: &Repository) -> Result<Self, git2::Error> {
+        let mut refs = vec![];
+        for reference in repo.references()? {
+            let reference = reference?;
+            let (Some(name), Some(oid)) = (reference.name(), reference.target()) else {
+                continue;
+            };
+            let peeled = match repo.find_object(oid, None) {
+                Ok(object) if object.kind() == Some(ObjectType::Tag) => {
+                    object.peel(ObjectType::Any).ok().map(|x| x.id())
+                }
+                _ => None,
+            };
+            refs.push(AdvertisedRef {
+                name: name.to_string(),
+                oid,
+                peeled,
+            });
+        }
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        let head = repo.find_reference("HEAD").ok().and_then(|head| {
+            let target = head.symbolic_target()?.to_string();
+            let oid = head.resolve().ok()?.target()?;
+            Some((target, oid))
+        });
+        Ok(Self { head, refs })
+    }
+    fn tips(&self) -> HashSet<Oid> {
+        self.refs
+            .iter()
+            .flat_map(|x| [Some(x.oid), x.peeled])
+            .flatten()
+            .collect()
+    }
+    fn advertise<W: Write>(&self, service: GitService, version: u8, out: &mut W) -> io::Result<()> {
+        if version == 1 {
+            write_pkt(out, b"version 1\n")?;
+        }
+        let mut lines = vec![];
+        let mut caps = match service {
+            GitService::UploadPack => UPLOAD_CAPS.to_string(),
+            _ => RECEIVE_CAPS.to_string(),
+        };
+        if service == GitService::UploadPack
+            && let Some((target, oid)) = &self.head
+        {
+            lines.push((*oid, "HEAD".to_string()));
+            caps.push_str(&format!(" symref=HEAD:{}", target));
+        }
+        for item in &self.refs {
+            lines.push((item.oid, item.name.clone()));
+            if service == GitService::UploadPack
+                && let Some(peeled) = item.peeled
+            {
+                lines.push((peeled, format!("{}^{{}}", item.name)));
+            }
+        }
+        if lines.is_empty() {
+            if service == GitService::UploadPack {
+                return write_flush(out);
+            }
+            lines.push((Oid::zero(), "capabilities^{}".to_string()));
+        }
+        caps.push_str(" object-format=sha1 ");
+        caps.push_str(AGENT);
+        for (idx, (oid, name)) in lines.iter().enumerate() {
+            let line = if idx == 0 {
+                format!("{} {}\0{}\n", oid, name, caps)
+            } else {
+                format!("{} {}\n", oid, name)
+            };
+            write_pkt(out, line.as_bytes())?;
+        }
+        write_flush(out)
+    }
+}
+
+fn serve<R: BufRead, W: Write>(
+    repo: &Repository,
+    request: &PackRequest,
+    input: R,
+    out: &mut W,
+) -> anyhow::Result<()> {
+    let refs = Refs::load(repo)?;
+    let version = request.protocol.as_ref().map(|x| x.version()).unwrap_or(0);
+    if request.advertise_refs || !request.stateless {
+        refs.advertise(request.service, version, out)?;
+        out.flush()?;
+        if request.advertise_refs {
+            return Ok(());
+        }
+    }
+    match request.service {
+        GitService::UploadPack => upload_pack(repo, &refs, request.stateless, input, out),
+        GitService::ReceivePack => receive_pack(repo, request, input, out),
+        GitService::UploadArchive => bail!("upload-archive is not implemented natively"),
+    }
+}
+
+/// Sends an `ERR` packet, which git prints as `remote error: ...`.
+fn refuse<W: Write>(out: &mut W, msg: String) -> anyhow::Error {
+    write_pkt(out, format!("ERR {}\n", msg).as_bytes()).ok();
+    out.flush().ok();
+    anyhow!(msg)
+}
+
+fn write_line<W: Write>(out: &mut W, line: String) -> io::Result<()> {
+    write_pkt(out, line.as_bytes())
+}
+
+fn parse_caps(list: &str, caps: &mut HashSet<String>) {
+    caps.extend(
+        list.split(' ')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string()),
+    );
+}
+
+struct Negotiation<'r> {
+    repo: &'r Repository,
+    /// wants peeled to commits, `None` for trees and blobs
+    wants: Vec<Option<Oid>>,
+    satisfied: Vec<bool>,
+    haves: Vec<Oid>,
+    checked: usize,
+}
+
+impl<'r> Negotiation<'r> {
+    fn new(repo: &'r Repository, wants: &[Oid]) -> Self {
+        let wants = wants
+            .iter()
+            .map(|want| {
+                repo.find_object(*want, None)
+                    .and_then(|x| x.peel_to_commit())
+                    .map(|x| x.id())
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        Self {
+            repo,
+            satisfied: vec![false; wants.len()],
+            wants,
+            haves: vec![],
+            checked: 0,
+        }
+    }
+    /// Whether every want has a common commit in its history, as git's `ok_to_give_up`.
+    fn ok_to_give_up(&mut self) -> bool {
+        let haves = &self.haves[self.checked..];
+        for (want, satisfied) in self.wants.iter().zip(self.satisfied.iter_mut()) {
+            if let Some(want) = want
+                && !*satisfied
+            {
+                *satisfied = haves.iter().any(|have| {
+                    have == want || self.repo.graph_descendant_of(*want, *have).unwrap_or(false)
+                });
+            }
+        }
+        self.checked = self.haves.len();
+        self.satisfied.iter().all(|x| *x)
+    }
+}
+
+/// Mirrors `upload-pack.c`: want lines, then have/ACK rounds until `done`, then the pack.
+fn upload_pack<R: BufRead, W: Write>(
+    repo: &Repository,
+    refs: &Refs,
+    stateless: bool,
+    input: R,
+    out: &mut W,
+) -> anyhow::Result<()> {
+    let tips = refs.tips();
+    let mut reader = PktReader::new(input);
+    let mut wants = vec![];
+    let mut caps = HashSet::new();
+    loop {
+        let Some(pkt) = reader.read_pkt()? else {
+            return Ok(());
+        };
+        if pkt == Pkt::Flush {
+            break;
+        }
+        let line = pkt
+            .text()
+            .ok_or_else(|| anyhow!("protocol error: bad line"))?;
+        let Some(want) = line.strip_prefix("want ") else {
+            return Err(refuse(
+                out,
+                format!("upload-pack: '{}' is not supported", line),
+            ));
+        };
+        let (oid, list) = want.split_once(' ').unwrap_or((want, ""));
+        if wants.is_empty() {
+            parse_caps(list, &mut caps);
+        }
+        let oid = Oid::from_str(oid)?;
+        if !tips.contains(&oid) {
+            return Err(refuse(out, format!("upload-pack: not our ref {}", oid)));
+        }
+        wants.push(oid);
+    }
+    if wants.is_empty() {
+        return Ok(());
+    }
+    let multi_ack = if caps.contains("multi_ack_detailed") {
+        2
+    } else if caps.contains("multi_ack") {
+        1
+    } else {
+        0
+    };
+    let no_done = caps.contains("no-done");
+    let odb = repo.odb()?;
+    let mut negotiation = Negotiation::new(repo, &wants);
+    let mut last = Oid::zero();
+    let mut got_common = false;
+    let mut got_other = false;
+    let mut sent_ready = false;
+    loop {
+        let Some(pkt) = reader.read_pkt()? else {
+            return Ok(());
+        };
+        if pkt == Pkt::Flush {
+            if multi_ack == 2 && got_common && !got_other && negotiation.ok_to_give_up() {
+                sent_ready = true;
+                write_line(out, format!("ACK {} ready\n", last))?;
+            }
+            if negotiation.haves.is_empty() || multi_ack > 0 {
+                write_line(out, "NAK\n".to_string())?;
+            }
+            if no_done && sent_ready {
+                write_line(out, format!("ACK {}\n", last))?;
+                break;
+            }
+            out.flush()?;
+            if stateless {
+                return Ok(());
+            }
+            got_common = false;
+            got_other = false;
+            continue;
+        }
+        let line = pkt
+            .text()
+            .ok_or_else(|| anyhow!("protocol error: bad line"))?;
+        if let Some(have) = line.strip_prefix("have ") {
+            let oid = Oid::from_str(have)?;
+            if !odb.exists(oid) {
+                got_other = true;
+                if multi_ack > 0 && negotiation.ok_to_give_up() {
+                    if multi_ack == 2 {
+                        sent_ready = true;
+                        write_line(out, format!("ACK {} ready\n", oid))?;
+                    } else {
+                        write_line(out, format!("ACK {} continue\n", oid))?;
+                    }
+                }
+                continue;
+            }
+            got_common = true;
+            last = oid;
+            if !negotiation.haves.contains(&oid) {
+                negotiation.haves.push(oid);
+            }
+            match multi_ack {
+                2 => write_line(out, format!("ACK {} common\n", oid))?,
+                1 => write_line(out, format!("ACK {} continue\n", oid))?,
+                _ if negotiation.haves.len() == 1 => write_line(out, format!("ACK {}\n", oid))?,
+                _ => {}
+            }
+        } else if line == "done" {
+            if negotiation.haves.is_empty() {
+                write_line(out, "NAK\n".to_string())?;
+            } else if multi_ack > 0 {
+                write_line(out, format!("ACK {}\n", last))?;
+            }
+            break;
+        } else {
+            bail!("git upload-pack: expected SHA1 list, got '{}'", line);
+        }
+    }
+    send_pack(repo, &wants, &negotiation.haves, &caps, out)
+}
+
+fn send_pack<W: Write>(
+    repo: &Repository,
+    wants: &[Oid],
+    haves: &[Oid],
+    caps: &HashSet<String>,
+    out: &mut W,
+) -> anyhow::Result<()> {
+    let mut builder = repo.packbuilder()?;
+    let mut walk = repo.revwalk()?;
+    for want in wants {
+        let object = repo.find_object(*want, None)?;
+        match object.kind() {
+            Some(ObjectType::Commit) => walk.push(*want)?,
+            Some(ObjectType::Tag) => {
+                builder.insert_object(*want, None)?;
+                let target = object.peel(ObjectType::Any)?;
+                if target.kind() == Some(ObjectType::Commit) {
+                    walk.push(target.id())?;
+                } else {
+                    builder.insert_recursive(target.id(), None)?;
+                }
+            }
+            _ => builder.insert_recursive(*want, None)?,
+        }
+    }
+    for have in haves {
+        if repo.find_commit(*have).is_ok() {
+            walk.hide(*have)?;
+        }
+    }
+    builder.insert_walk(&mut walk)?;
+
+    let large = caps.contains("side-band-64k");
+    if large || caps.contains("side-band") {
+        if !caps.contains("no-progress") {
+            let progress = format!("Enumerating objects: {}, done.\n", builder.object_count());
+            Sideband::new(&mut *out, 2, large).write_all(progress.as_bytes())?;
+        }
+        write_pack(&mut builder, &mut Sideband::new(&mut *out, 1, large))?;
+        write_flush(out)?;
+    } else {
+        write_pack(&mut builder, out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_pack<W: Write>(builder: &mut PackBuilder<'_>, out: &mut W) -> anyhow::Result<()> {
+    let mut error = None;
+    let result = builder.foreach(|chunk| match out.write_all(chunk) {
+        Ok(()) => true,
+        Err(e) => {
+            error = Some(e);
+            false
+        }
+    });
+    if let Some(e) = error {
+        return Err(e.into());
+    }
+    result?;
+    Ok(())
+}
+
+/// Mirrors `receive-pack.c`: the command list, the packfile up to EOF, then ref updates
+/// and a `report-status` when asked for. Branch protection takes the place of the
+/// pre-receive hook, a single violation turns down the whole push.
+fn receive_pack<R: BufRead, W: Write>(
+    repo: &Repository,
+    request: &PackRequest,
+    input: R,
+    out: &mut W,
+) -> anyhow::Result<()> {
+    let mut reader = PktReader::new(input);
+    let mut commands = vec![];
+    let mut caps = HashSet::new();
+    loop {
+        let Some(pkt) = reader.read_pkt()? else {
+            return Ok(());
+        };
+        if pkt == Pkt::Flush {
+            break;
+        }
+        let line = pkt
+            .text()
+            .ok_or_else(|| anyhow!("protocol error: bad line"))?;
+        let (line, list) = line.split_once('\0').unwrap_or((line, ""));
+        if commands.is_empty() {
+            parse_caps(list, &mut caps);
+        }
+        let command =
+            RefCommand::parse(line).ok_or_else(|| anyhow!("protocol error: '{}'", line))?;
+        commands.push(command);
+    }
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let unpack = if commands.iter().all(|x| x.is_delete()) {
+        Ok(())
+    } else {
+        unpack(repo, reader.into_inner(), request.max_input_size)
+    };
+    let denied = match &unpack {
+        Ok(()) => commands
+            .iter()
+            .filter_map(|command| {
+                let protection = &request.protection;
+                protection
+                    .check_command(command, protection.pusher)
+                    .and_then(|_| protection.check_history(repo, command))
+                    .err()
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => vec![],
+    };
+    let results = commands
+        .iter()
+        .map(|command| match &unpack {
+            Ok(()) if denied.is_empty() => update_ref(repo, command),
+            Ok(()) => Err(PROTECTED_REASON.to_string()),
+            Err(_) => Err("unpacker error".to_string()),
+        })
+        .collect::<Vec<_>>();
+    if caps.contains("side-band-64k") {
+        for error in &denied {
+            Sideband::new(&mut *out, 2, true).write_all(format!("error: {}\n", error).as_bytes())?;
+        }
+    }
+
+    if caps.contains("report-status") {
+        let mut report = match &unpack {
+            Ok(()) => encode(b"unpack ok\n"),
+            Err(e) => encode(
+                format!("unpack {}\n", e)
+                    .replace('\n', " ")
+                    .trim_end()
+                    .as_bytes(),
+            ),
+        };
+        for (command, result) in commands.iter().zip(results) {
+            let line = match result {
+                Ok(()) => format!("ok {}\n", command.name),
+                Err(reason) => format!("ng {} {}\n", command.name, reason),
+            };
+            report.extend(encode(line.as_bytes()));
+        }
+        report.extend_from_slice(FLUSH_PKT);
+        if caps.contains("side-band-64k") {
+            Sideband::new(&mut *out, 1, true).write_all(&report)?;
+            write_flush(out)?;
+        } else {
+            out.write_all(&report)?;
+        }
+    }
+    out.flush()?;
+    unpack
+}
+
+/// Dropping the pack writer before `commit` leaves nothing behind, so a pack over
+/// `max_size` is read no further than the limit and discarded.
+fn unpack<R: Read>(repo: &Repository, input: R, max_size: Option<u64>) -> anyhow::Result<()> {
+    let odb = repo.odb()?;
+    let mut writer = odb.packwriter()?;
+    let limit = max_size.map_or(u64::MAX, |x| x.saturating_add(1));
+    let read = io::copy(&mut input.take(limit), &mut writer)?;
+    if max_size.is_some_and(|x| read > x) {
+        bail!("pack exceeds the storage quota");
+    }
+    writer.commit()?;
+    Ok(())
+}
+
+fn update_ref(repo: &Repository, command: &RefCommand) -> Result<(), String> {
+    if !command.name.starts_with("refs/") || !Reference::is_valid_name(&command.name) {
+        return Err("funny refname".to_string());
+    }
+    let current = repo.refname_to_id(&command.name).ok();
+    if command.is_delete() {
+        let Some(current) = current else {
+            return Ok(());
+        };
+        if !command.old.is_zero() && current != command.old {
+            return Err("failed to lock".to_string());
+        }
+        return repo
+            .find_reference(&command.name)
+            .and_then(|mut x| x.delete())
+            .map_err(|_| "failed to delete".to_string());
+    }
+    if repo.find_object(command.new, None).is_err() {
+        return Err("missing necessary objects".to_string());
+    }
+    if command.name.starts_with("refs/heads/") && repo.find_commit(command.new).is_err() {
+        return Err("non-commit object on a branch".to_string());
+    }
+    let result = match current {
+        None if command.old.is_zero() => repo.reference(&command.name, command.new, false, "push"),
+        Some(current) if current == command.old => {
+            repo.reference_matching(&command.name, command.new, true, current, "push")
+        }
+        _ => return Err("failed to lock".to_string()),
+    };
+    result.map(|_| ()).map_err(|e| e.message().to_string())
+}
+
+#[test]
+fn test_native_parity() {
+    use crate::service::protection::{BranchProtection, BranchRule};
+    use crate::testing::{TempDir, git, git_with_input};
+    use crate::transport::backend::SubprocessPack;
+    use std::path::Path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let root = TempDir::new("native");
+    // negotiation lines and the side-band 1 payload of a response
+    let demux = |data: &[u8]| {
+        let mut reader = PktReader::new(io::Cursor::new(data));
+        let (mut lines, mut band) = (vec![], vec![]);
+        while let Ok(Some(pkt)) = reader.read_pkt() {
+            match pkt {
+                Pkt::Data(data) if data[0] == 1 => band.extend_from_slice(&data[1..]),
+                Pkt::Data(data) if data[0] == 2 => {}
+                Pkt::Data(data) => lines.push(String::from_utf8_lossy(&data).to_string()),
+                _ => {}
+            }
+        }
+        (lines, band)
+    };
+    let src = root.join("src");
+    std::fs::create_dir_all(&src).unwrap();
+    git(&src, &["init", "-q", "."]);
+    std::fs::write(src.join("a.txt"), "a").unwrap();
+    git(&src, &["add", "."]);
+    git(&src, &["commit", "-q", "-m", "first"]);
+    let first = git(&src, &["rev-parse", "HEAD"]);
+    std::fs::create_dir_all(src.join("dir")).unwrap();
+    std::fs::write(src.join("dir/b.txt"), "b").unwrap();
+    git(&src, &["add", "."]);
+    git(&src, &["commit", "-q", "-m", "second"]);
+    git(&src, &["tag", "-a", "v1", "-m", "v1"]);
+    let head = git(&src, &["rev-parse", "HEAD"]);
+    let tag = git(&src, &["rev-parse", "v1"]);
+    let pack = git_with_input(
+        &src,
+        &["pack-objects", "--stdout", "--revs", "-q"],
+        format!("{}\n", head).as_bytes(),
+    );
+
+    async fn run(backend: &dyn GitPack, request: PackRequest, input: Vec<u8>) -> (Vec<u8>, i32) {
+        let PackIo {
+            mut stdin,
+            mut stdout,
+            mut stderr,
+            mut child,
+        } = backend.spawn(request).unwrap();
+        let write = async move {
+            stdin.write_all(&input).await.ok();
+            stdin.shutdown().await.ok();
+        };
+        let (mut out, mut err) = (vec![], vec![]);
+        let (_, _, _, code) = tokio::join!(
+            write,
+            stdout.read_to_end(&mut out),
+            stderr.read_to_end(&mut err),
+            child.wait()
+        );
+        (out, code.unwrap())
+    }
+    let request = |service, path: &Path, advertise_refs| PackRequest {
+        service,
+        path: path.to_path_buf(),
+        protocol: None,
+        stateless: true,
+        advertise_refs,
+        protection: crate::service::protection::BranchProtection::default(),
+        filter: crate::service::partial_clone::FilterPolicy::default(),
+        max_input_size: None,
+    };
+    let clone = [
+        encode(format!("want {} multi_ack_detailed side-band-64k ofs-delta\n", head).as_bytes()),
+        encode(format!("want {}\n", tag).as_bytes()),
+        FLUSH_PKT.to_vec(),
+        encode(b"done\n"),
+    ]
+    .concat();
+    let fetch = [
+        encode(format!("want {} multi_ack_detailed side-band-64k ofs-delta\n", head).as_bytes()),
+        FLUSH_PKT.to_vec(),
+        encode(format!("have {}\n", first).as_bytes()),
+        encode(b"done\n"),
+    ]
+    .concat();
+    let push = [
+        encode(
+            format!(
+                "{} {} refs/heads/main\0report-status side-band-64k\n",
+                Oid::zero(),
+                head
+            )
+            .as_bytes(),
+        ),
+        FLUSH_PKT.to_vec(),
+        pack,
+    ]
+    .concat();
+    let delete = [
+        encode(format!("{} {} refs/heads/main\0report-status\n", head, Oid::zero()).as_bytes()),
+        FLUSH_PKT.to_vec(),
+    ]
+    .concat();
+
+    let backends: [(&str, &dyn GitPack); 2] =
+        [("subprocess", &SubprocessPack), ("native", &NativePack)];
+    let results = actix_web::rt::System::new().block_on(async {
+        let mut results = vec![];
+        for (name, backend) in backends {
+            let mut result = vec![];
+            for service in [GitService::UploadPack, GitService::ReceivePack] {
+                let (refs, code) = run(backend, request(service, &src, true), vec![]).await;
+                assert_eq!(code, 0);
+                let (lines, _) = demux(&refs);
+                // capabilities and agents differ, the refs must not
+                result.push(
+                    lines
+                        .iter()
+                        .map(|x| x.split('\0').next().unwrap().to_string())
+                        .collect::<Vec<_>>(),
+                );
+            }
+            for input in [&clone, &fetch] {
+                let (out, code) = run(
+                    backend,
+                    request(GitService::UploadPack, &src, false),
+                    input.clone(),
+                )
+                .await;
+                assert_eq!(code, 0);
+                let (lines, band) = demux(&out);
+                assert_eq!(&band[..4], b"PACK");
+                let objects = u32::from_be_bytes(band[8..12].try_into().unwrap());
+                result.push(lines);
+                result.push(vec![objects.to_string()]);
+                if input == &clone {
+                    let dst = root.join(format!("clone-{}", name));
+                    std::fs::create_dir_all(&dst).unwrap();
+                    git(&dst, &["init", "-q", "--bare", "."]);
+                    git_with_input(&dst, &["index-pack", "--stdin"], &band);
+                    result.push(vec![git(&dst, &["rev-list", "--objects", "--all", &head])]);
+                }
+            }
+            let dst = root.join(format!("push-{}", name));
+            std::fs::create_dir_all(&dst).unwrap();
+            git(&dst, &["init", "-q", "--bare", "."]);
+            // a pack over what is left of the quota moves no ref
+            let mut limited = request(GitService::ReceivePack, &dst, false);
+            limited.max_input_size = Some(16);
+            run(backend, limited, push.clone()).await;
+            assert_eq!(git(&dst, &["for-each-ref"]), "");
+            let (out, code) = run(
+                backend,
+                request(GitService::ReceivePack, &dst, false),
+                push.clone(),
+            )
+            .await;
+            assert_eq!(code, 0);
+            let (_, band) = demux(&out);
+            result.push(demux(&band).0);
+            assert_eq!(git(&dst, &["rev-parse", "refs/heads/main"]), head);
+            let (out, code) = run(
+                backend,
+                request(GitService::ReceivePack, &dst, false),
+                delete.clone(),
+            )
+            .await;
+            assert_eq!(code, 0);
+            result.push(demux(&out).0);
+            assert_eq!(git(&dst, &["for-each-ref"]), "");
+            // the command checks are repeated before a ref moves, a push that got past
+            // those of the transport still leaves protected branches alone
+            git(&dst, &["update-ref", "refs/heads/main", &head]);
+            let open = BranchRule {
+                pattern: "main".to_string(),
+                block_force_push: false,
+                block_deletion: false,
+                require_linear_history: false,
+                restrict_push: false,
+                pushers: vec![],
+            };
+            let rules = [
+                BranchRule {
+                    block_deletion: true,
+                    ..open.clone()
+                },
+                BranchRule {
+                    restrict_push: true,
+                    ..open
+                },
+            ];
+            for rule in rules {
+                let mut protected = request(GitService::ReceivePack, &dst, false);
+                protected.protection = BranchProtection {
+                    rules: vec![rule],
+                    locked_paths: vec![],
+                    pusher: None,
+                };
+                run(backend, protected, delete.clone()).await;
+                assert_eq!(git(&dst, &["rev-parse", "refs/heads/main"]), head);
+            }
+            results.push(result);
+        }
+        results
+    });
+    assert_eq!(results[0], results[1]);
+}
@@ -0,0 +1,27 @@
+use crate::storage::RemoteRepo;
+use crate::storage::rpc::{PackSpec, StorageCall};
+use crate::transport::GitService;
+use crate::transport::backend::{GitPack, PackIo, PackRequest};
+use std::io;
+
+/// Runs the session on the storage node holding the repository. The node picks its own
+/// backend and checks v2 and filters against its own config.
+pub struct RemotePack(pub RemoteRepo);
+
+impl GitPack for RemotePack {
+    fn supports(&self, _: GitService) -> bool {
+        true
+    }
+    fn supports_v2(&self) -> bool {
+        true
+    }
+    fn supports_filter(&self) -> bool {
+        true
+    }
+    fn spawn(&self, request: PackRequest) -> io::Result<PackIo> {
+        Ok(self.0.spawn(StorageCall::Pack {
+            repo: self.0.name.clone(),
+            pack: PackSpec::from(&request),
+        }))
+    }
+}
@@ -0,0 +1,197 @@
+use crate::transport::GitService;
+use crate::transport::backend::{GitPack, PackChild, PackIo, PackRequest};
+use sea_orm::prelude::Uuid;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+pub const FORCE_PUSH_ENV: &str = "JZFS_PROTECT_FORCE_PUSH";
+pub const LINEAR_HISTORY_ENV: &str = "JZFS_PROTECT_LINEAR_HISTORY";
+pub const LOCKED_PATHS_ENV: &str = "JZFS_LOCKED_PATHS";
+pub const DELETION_ENV: &str = "JZFS_PROTECT_DELETION";
+pub const RESTRICTED_ENV: &str = "JZFS_PROTECT_RESTRICTED";
+
+/// `BranchProtection::check_command` and `check_history` for git receive-pack, with the
+/// same messages. The command checks already ran before receive-pack started, repeating
+/// them here keeps a push that got past those from moving a ref. The
+/// patterns come in space separated through the environment, `set -f` keeps the shell from
+/// expanding them against the repository directory. Locked paths come one per line, which
+/// is also how `grep -e` takes a list of patterns.
+const PRE_RECEIVE: &str = r#"#!/bin/sh
+# installed by jzfs, enforces branch protection rules
+set -f
+zero=0000000000000000000000000000000000000000
+matches() {
+    for pattern in $1; do
+        case "$2" in
+        $pattern) return 0 ;;
+        esac
+    done
+    return 1
+}
+status=0
+while read -r old new ref; do
+    branch=${ref#refs/heads/}
+    if [ "$branch" = "$ref" ]; then
+        continue
+    fi
+    if [ "$new" = "$zero" ]; then
+        if matches "$JZFS_PROTECT_DELETION" "$branch"; then
+            echo "error: deleting protected branch '$branch' is not allowed" >&2
+            status=1
+        elif matches "$JZFS_PROTECT_RESTRICTED" "$branch"; then
+            echo "error: you are not allowed to push to protected branch '$branch'" >&2
+            status=1
+        fi
+        continue
+    fi
+    if matches "$JZFS_PROTECT_RESTRICTED" "$branch"; then
+        echo "error: you are not allowed to push to protected branch '$branch'" >&2
+        status=1
+        continue
+    fi
+    if [ -n "$JZFS_LOCKED_PATHS" ]; then
+        if [ "$old" = "$zero" ]; then
+            range="$new --not --all"
+        else
+            range="$old..$new"
+        fi
+        path=$(git -c core.quotePath=false log --format= --name-only --no-renames $range \
+            | grep -Fx -e "$JZFS_LOCKED_PATHS" | head -n 1)
+        if [ -n "$path" ]; then
+            echo "error: '$path' is locked by another user" >&2
+            status=1
+            continue
+        fi
+    fi
+    if [ "$old" != "$zero" ] && [ "$old" != "$new" ] \
+        && matches "$JZFS_PROTECT_FORCE_PUSH" "$branch" \
+        && ! git merge-base --is-ancestor "$old" "$new" 2>/dev/null; then
+        echo "error: force pushing to protected branch '$branch' is not allowed" >&2
+        status=1
+        continue
+    fi
+    if matches "$JZFS_PROTECT_LINEAR_HISTORY" "$branch"; then
+        if [ "$old" = "$zero" ]; then
+            merge=$(git rev-list --merges -n 1 "$new" --not --all)
+        else
+            merge=$(git rev-list --merges -n 1 "$old..$new")
+        fi
+        if [ -n "$merge" ]; then
+            echo "error: protected branch '$branch' requires linear history, merge commit $merge is not allowed" >&2
+            status=1
+        fi
+    fi
+done
+exit $status
+"#;
+
+/// Keeps the pre-receive hook of a repository current. Repositories are only ever written
+/// through jzfs, so the hook slot is ours.
+fn install_hook(path: &Path) -> io::Result<()> {
+    let repo = git2::Repository::open(path).map_err(io::Error::other)?;
+    let hooks = repo.path().join("hooks");
+    let hook = hooks.join("pre-receive");
+    if std::fs::read(&hook).is_ok_and(|x| x == PRE_RECEIVE.as_bytes()) {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&hooks)?;
+    let tmp = hooks.join(format!("pre-receive.{}", Uuid::new_v4()));
+    std::fs::write(&tmp, PRE_RECEIVE)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&tmp, &hook)
+}
+
+/// Forks the system `git` binary, the reference implementation the native backend is
+/// checked against.
+pub struct SubprocessPack;
+
+impl SubprocessPack {
+    pub fn command(request: &PackRequest) -> Command {
+        let mut cmd = Command::new("git");
+        if let Some(protocol) = &request.protocol {
+            protocol.apply(&mut cmd);
+        }
+        if request.service == GitService::UploadPack {
+            for config in request.filter.git_config() {
+                cmd.arg("-c").arg(config);
+            }
+        }
+        if request.service == GitService::ReceivePack
+            && let Some(max) = request.max_input_size
+        {
+            // git reads 0 as no limit
+            cmd.arg("-c")
+                .arg(format!("receive.maxInputSize={}", max.max(1)));
+        }
+        cmd.arg(request.service.name());
+        if request.stateless {
+            cmd.arg("--stateless-rpc");
+        }
+        if request.advertise_refs {
+            cmd.arg("--advertise-refs");
+        }
+        cmd.arg(".")
+            .current_dir(&request.path)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_NO_REPLACE_OBJECTS", "1");
+        cmd
+    }
+}
+
+impl GitPack for SubprocessPack {
+    fn supports(&self, _: GitService) -> bool {
+        true
+    }
+    fn supports_v2(&self) -> bool {
+        true
+    }
+    fn supports_filter(&self) -> bool {
+        true
+    }
+    fn spawn(&self, request: PackRequest) -> io::Result<PackIo> {
+        let mut cmd = Self::command(&request);
+        if request.service == GitService::ReceivePack && !request.protection.is_empty() {
+            install_hook(&request.path)?;
+            cmd.env(
+                FORCE_PUSH_ENV,
+                request.protection.force_push_patterns().join(" "),
+            )
+            .env(
+                LINEAR_HISTORY_ENV,
+                request.protection.linear_history_patterns().join(" "),
+            )
+            .env(LOCKED_PATHS_ENV, request.protection.locked_paths.join("\n"))
+            .env(
+                DELETION_ENV,
+                request.protection.deletion_patterns().join(" "),
+            )
+            .env(
+                RESTRICTED_ENV,
+                request.protection.restricted_patterns().join(" "),
+            );
+        }
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(io::Error::other("git process pipes unavailable"));
+        };
+        Ok(PackIo {
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            child: PackChild::process(child),
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use sea_orm::prelude::Uuid;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+
+/// Files on local disk named by a key, the least recently used deleted once they take more
+/// than `max_size` bytes together. Shared by the caches of generated responses.
+#[derive(Clone, Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+    /// Where the entry of a hex `key` lives, spread over directories by its first byte.
+    pub fn entry(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(key)
+    }
+    /// The entry under `key`, marked as just used.
+    pub async fn open(&self, key: &str) -> Option<tokio::fs::File> {
+        let file = tokio::fs::File::open(self.entry(key)).await.ok()?;
+        // the modification time orders eviction
+        let file = file.into_std().await;
+        file.set_modified(SystemTime::now()).ok();
+        Some(tokio::fs::File::from_std(file))
+    }
+    pub async fn create(&self, key: &str) -> io::Result<CacheWriter> {
+        let path = self.entry(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+        let file = tokio::fs::File::create(&tmp).await?;
+        Ok(CacheWriter {
+            cache: self.clone(),
+            file,
+            tmp,
+            path,
+            size: 0,
+        })
+    }
+    /// Deletes the least recently used entries until the cache fits `max_size` again and
+    /// returns how many went.
+    fn evict(&self) -> io::Result<u64> {
+        let mut entries = vec![];
+        let mut total = 0;
+        for shard in std::fs::read_dir(&self.dir)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(shard.path())? {
+                let entry = entry?;
+                let meta = entry.metadata()?;
+                if !meta.is_file() || entry.file_name().to_string_lossy().ends_with(".tmp") {
+                    continue;
+                }
+                total += meta.len();
+                entries.push((meta.modified()?, meta.len(), entry.path()));
+            }
+        }
+        entries.sort();
+        let mut evicted = 0;
+        for (_, len, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                evicted += 1;
+                total -= len;
+            }
+        }
+        Ok(evicted)
+    }
+}
+
+/// An entry being written next to its final name, moved in place on commit and removed
+/// when dropped before.
+pub struct CacheWriter {
+    cache: DiskCache,
+    file: tokio::fs::File,
+    tmp: PathBuf,
+    path: PathBuf,
+    size: u64,
+}
+
+impl CacheWriter {
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.size += chunk.len() as u64;
+        if self.size > self.cache.max_size {
+            return Err(io::Error::other("entry larger than the cache"));
+        }
+        self.file.write_all(chunk).await
+    }
+    /// Makes the entry visible and evicts what no longer fits, returning how many entries
+    /// were evicted.
+    pub async fn commit(mut self) -> io::Result<u64> {
+        self.file.flush().await?;
+        tokio::fs::rename(&self.tmp, &self.path).await?;
+        let cache = self.cache.clone();
+        tokio::task::spawn_blocking(move || cache.evict())
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // a no-op once committed, the file has moved
+        std::fs::remove_file(&self.tmp).ok();
+    }
+}
+
+#[tokio::test]
+async fn test_disk_cache_eviction() {
+    let dir = crate::testing::TempDir::new("disk-cache");
+    let cache = DiskCache::new(dir.to_path_buf(), 10);
+    let key = |x: char| x.to_string().repeat(64);
+    for x in ['a', 'b'] {
+        let mut writer = cache.create(&key(x)).await.unwrap();
+        writer.write(b"12345").await.unwrap();
+        assert_eq!(writer.commit().await.unwrap(), 0);
+    }
+    // `a` is used again, so `b` is the least recently used
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    assert!(cache.open(&key('a')).await.is_some());
+    let mut writer = cache.create(&key('c')).await.unwrap();
+    writer.write(b"12345").await.unwrap();
+    assert_eq!(writer.commit().await.unwrap(), 1);
+    assert!(cache.entry(&key('a')).exists());
+    assert!(!cache.entry(&key('b')).exists());
+    // too large entries and abandoned ones leave nothing behind
+    let mut writer = cache.create(&key('d')).await.unwrap();
+    assert!(writer.write(&[0; 11]).await.is_err());
+    drop(writer);
+    assert_eq!(std::fs::read_dir(dir.join("dd")).unwrap().count(), 0);
+}
@@ -0,0 +1,89 @@
+use crate::GitContext;
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use crate::transport::archive::{ArchiveFormat, ArchiveRequest, archive};
+use crate::transport::http::auth::git_authorize;
+use crate::transport::http::pack::{PackBody, hold_permit, pack_client, pack_limited};
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentEncoding;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use database::entity::users;
+use std::io;
+use tracing::error;
+
+/// Serves `file`, a ref followed by the archive extension, of a repository. A signed in
+/// `user` needs read access, otherwise the request's own git credentials are checked.
+pub async fn git_archive(
+    request: &HttpRequest,
+    status: &GitServer,
+    owner: &str,
+    repo: &str,
+    file: &str,
+    path: Option<String>,
+    user: Option<users::Model>,
+) -> HttpResponse {
+    let not_found = || HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("Not Found");
+    let Ok(repo) = status.find_repo(owner, repo).await else {
+        return not_found();
+    };
+    let Some((refs, format)) = ArchiveFormat::split(file) else {
+        return not_found();
+    };
+    if refs.starts_with('-') {
+        return not_found();
+    }
+    let user = match user {
+        Some(user) => {
+            if status.repo_access(&repo, Some(&user)).await < RepoAccess::Read {
+                return not_found();
+            }
+            Some(user)
+        }
+        None => match git_authorize(request, status, &repo, RepoAccess::Read).await {
+            Ok(user) => user,
+            Err(response) => return response,
+        },
+    };
+    let client = pack_client(request, &status.config.git.limits, user.as_ref());
+    if let Err(limited) = status.count_pack_session(client) {
+        return pack_limited(&limited);
+    }
+    let permit = match status.pack_permit(client, repo.uid).await {
+        Ok(permit) => permit,
+        Err(limited) => return pack_limited(&limited),
+    };
+    let Ok(git) = GitContext::try_from((repo.clone(), status.config.git.clone())) else {
+        return HttpResponse::InternalServerError().finish();
+    };
+    let path = path
+        .map(|x| x.trim_matches('/').to_string())
+        .filter(|x| !x.is_empty());
+    let prefix = format!("{}-{}", repo.repo_name, refs.replace('/', "-"));
+    let body = archive(
+        status.archive_cache(),
+        ArchiveRequest {
+            git,
+            refs: refs.to_string(),
+            path,
+            format,
+            prefix: prefix.clone(),
+        },
+    )
+    .await;
+    let body = match body {
+        Ok(body) => body,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return not_found(),
+        Err(e) => {
+            error!("archive {}/{}: {}", repo.namespace, repo.repo_name, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentEncoding::Identity)
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", prefix, format.extension()),
+        ))
+        .streaming(hold_permit(body as PackBody, permit))
+}
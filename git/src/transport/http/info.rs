@@ -1,12 +1,18 @@
 use crate::GitContext;
 use crate::service::GitServer;
-use crate::transport::GitPack;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::protection::BranchProtection;
+use crate::service::permissions::RepoAccess;
+use crate::transport::GitService;
+use crate::transport::backend::{PackIo, PackRequest};
+use crate::transport::http::auth::git_authorize;
+use crate::transport::http::pack::{pack_client, pack_limited};
+use crate::transport::protocol::GitProtocol;
 use actix_web::http::StatusCode;
 use actix_web::web::{Data, Path};
-use actix_web::{HttpRequest, HttpResponseBuilder, Responder};
-use std::process::Stdio;
-use tokio::process::Command;
-use tracing::info;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
+use tokio::io::AsyncReadExt;
+use tracing::{info, warn};
 
 pub async fn git_refs(
     request: HttpRequest,
@@ -14,15 +20,6 @@ pub async fn git_refs(
     status: Data<GitServer>,
 ) -> impl Responder {
     let (owner, repo) = path.into_inner();
-    let version = request
-        .headers()
-        .get("Git-Protocol")
-        .and_then(|x| x.to_str().ok());
-    let mut response = HttpResponseBuilder::new(StatusCode::OK);
-    response
-        .insert_header(("Pragma", "no-cache"))
-        .insert_header(("Cache-Control", "no-cache, max-age=0, must-revalidate"))
-        .insert_header(("Expires", "Fri, 01 Jan 1980 00:00:00 GMT"));
     let url = request
         .uri()
         .to_string()
@@ -31,23 +28,11 @@ pub async fn git_refs(
         .filter(|x| !x.is_empty())
         .collect::<Vec<_>>();
 
-    let mut cmd = Command::new("git");
     info!("request url: {}", url.join("/"));
     let server = if url.iter().any(|x| x.contains("git-upload-pack")) {
-        response.insert_header((
-            "Content-Type",
-            "application/x-git-upload-pack-advertisement",
-        ));
-        cmd.arg("upload-pack");
-        GitPack::UploadPack
+        GitService::UploadPack
     } else if url.iter().any(|x| x.contains("git-receive-pack")) {
-        response.insert_header((
-            "Content-Type",
-            "application/x-git-receive-pack-advertisement",
-        ));
-        cmd.arg("receive-pack");
-
-        GitPack::ReceivePack
+        GitService::ReceivePack
     } else {
         return HttpResponseBuilder::new(StatusCode::BAD_REQUEST).body("Protoc Not Support");
     };
@@ -56,48 +41,120 @@ pub async fn git_refs(
     let Ok(repo) = status.find_repo(&owner, &repo).await else {
         return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("Repository Not Found");
     };
-    let Ok(path) = GitContext::try_from((repo, status.config.git.clone())).map(|x| x.path_dir)
-    else {
+    let need = match server {
+        GitService::UploadPack => RepoAccess::Read,
+        _ => RepoAccess::Write,
+    };
+    let user = match git_authorize(&request, &status, &repo, need).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let client = pack_client(&request, &status.config.git.limits, user.as_ref());
+    if let Err(limited) = status.count_pack_session(client) {
+        return pack_limited(&limited);
+    }
+    let _permit = match status.pack_permit(client, repo.uid).await {
+        Ok(permit) => permit,
+        Err(limited) => return pack_limited(&limited),
+    };
+    let filter = status.filter_policy(&repo);
+    let Ok(git) = GitContext::try_from((repo, status.config.git.clone())) else {
         return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
             .body("Internal Server Error");
     };
-    if !path.exists() {
-        return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("repository not found");
-    }
-    cmd.arg("--stateless-rpc");
-    cmd.arg("--advertise-refs");
-    cmd.arg(".");
-    cmd.current_dir(path);
-    cmd.stdin(Stdio::piped());
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
-    if let Some(version) = version {
-        cmd.env("GIT_PROTOCOL", version);
-    }
-
-    let output = match cmd.output().await {
-        Ok(output) => {
-            info!("Command status: {:?}", output.status);
-            output
+    match git.blocking(|git| git.exists()).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("repository not found");
         }
-        Err(e) => {
-            eprintln!("Error running command: {}", e);
-            return HttpResponseBuilder::new(StatusCode::BAD_REQUEST).body(e.to_string());
+        Err(_) => {
+            return HttpResponseBuilder::new(StatusCode::SERVICE_UNAVAILABLE)
+                .body("repository storage unavailable");
         }
-    };
+    }
+    advertise_refs(&request, &status, &git, server, filter).await
+}
 
-    let mut result = String::new();
+/// The ref advertisement of `server` on `git`, `git_refs` past the repository lookup.
+pub(crate) async fn advertise_refs(
+    request: &HttpRequest,
+    status: &GitServer,
+    git: &GitContext,
+    server: GitService,
+    filter: FilterPolicy,
+) -> HttpResponse {
+    let mut response = HttpResponseBuilder::new(StatusCode::OK);
+    response
+        .insert_header(("Pragma", "no-cache"))
+        .insert_header(("Cache-Control", "no-cache, max-age=0, must-revalidate"))
+        .insert_header(("Expires", "Fri, 01 Jan 1980 00:00:00 GMT"));
     match server {
-        GitPack::UploadPack => {
-            result.push_str("001e# service=git-upload-pack\n");
-            result.push_str("0000");
-        }
-        GitPack::ReceivePack => {
-            result.push_str("001f# service=git-receive-pack\n");
-            result.push_str("0000");
+        GitService::UploadPack => response.insert_header((
+            "Content-Type",
+            "application/x-git-upload-pack-advertisement",
+        )),
+        _ => response.insert_header((
+            "Content-Type",
+            "application/x-git-receive-pack-advertisement",
+        )),
+    };
+    let backend = status.pack_backend(git, server, &filter);
+    let protocol = GitProtocol::from_request(request, status.allow_v2(server, backend.as_ref()));
+    let pack = PackRequest {
+        service: server,
+        path: git.path_dir.clone(),
+        protocol: protocol.clone(),
+        stateless: true,
+        advertise_refs: true,
+        protection: BranchProtection::default(),
+        filter,
+        max_input_size: None,
+    };
+    let PackIo {
+        stdin,
+        mut stdout,
+        mut stderr,
+        mut child,
+    } = match backend.spawn(pack) {
+        Ok(io) => io,
+        Err(e) => {
+            warn!("advertise refs failed: {}", e);
+            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Server Error");
         }
     };
+    drop(stdin);
+    let mut refs = vec![];
+    let mut errors = vec![];
+    let (read, _, code) = tokio::join!(
+        stdout.read_to_end(&mut refs),
+        stderr.read_to_end(&mut errors),
+        child.wait()
+    );
+    info!("advertise refs exit: {:?}", code);
+    if read.is_err() || !matches!(code, Ok(0)) {
+        warn!(
+            "advertise refs failed: {}",
+            String::from_utf8_lossy(&errors)
+        );
+        return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Server Error");
+    }
 
-    result.push_str(std::str::from_utf8(&output.stdout).unwrap());
+    // v2 starts with the capability advertisement, there is no service announcement
+    let mut result = Vec::new();
+    if !protocol.as_ref().is_some_and(|x| x.is_v2()) {
+        match server {
+            GitService::UploadPack => {
+                result.extend_from_slice(b"001e# service=git-upload-pack\n");
+                result.extend_from_slice(b"0000");
+            }
+            _ => {
+                result.extend_from_slice(b"001f# service=git-receive-pack\n");
+                result.extend_from_slice(b"0000");
+            }
+        };
+    }
+    result.extend_from_slice(&refs);
     response.body(result)
 }
@@ -0,0 +1,348 @@
+use crate::lfs::batch::{BatchRequest, LFS_HASH_ALGO, LfsLink};
+use crate::lfs::store::{LfsStore, valid_oid};
+use crate::lfs::{LFS_CONTENT_TYPE, LfsError, LfsOperation, LfsPointer};
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use crate::transport::http::auth::lfs_authorize;
+use crate::transport::http::pack::request_body;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, ContentEncoding, RANGE,
+};
+use actix_web::web::{Bytes, Data, Path, Payload};
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use database::entity::git_repo;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use tracing::error;
+
+pub fn lfs_error(code: StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(code)
+        .content_type(LFS_CONTENT_TYPE)
+        .json(LfsError {
+            message: message.to_string(),
+        })
+}
+
+/// Transfer actions go back to this server with the credential of the batch request, so
+/// the client does not have to authenticate again for every object.
+fn lfs_link(request: &HttpRequest, repo: &git_repo::Model) -> LfsLink {
+    let info = request.connection_info();
+    let mut header = HashMap::new();
+    if let Some(value) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+    {
+        header.insert("Authorization".to_string(), value.to_string());
+    }
+    LfsLink {
+        href: format!(
+            "{}://{}/{}/{}.git/info/lfs/objects",
+            info.scheme(),
+            info.host(),
+            repo.namespace,
+            repo.repo_name
+        ),
+        header,
+    }
+}
+
+pub async fn lfs_repo(
+    status: &GitServer,
+    owner: &str,
+    repo: &str,
+) -> Result<(git_repo::Model, LfsStore), HttpResponse> {
+    let repo = repo.replace(".git", "");
+    let Ok(repo) = status.find_repo(owner, &repo).await else {
+        return Err(lfs_error(StatusCode::NOT_FOUND, "Repository Not Found"));
+    };
+    let Ok(store) = LfsStore::try_from((repo.clone(), status.config.git.clone())) else {
+        return Err(lfs_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error",
+        ));
+    };
+    Ok((repo, store))
+}
+
+pub async fn lfs_batch(
+    request: HttpRequest,
+    body: Bytes,
+    path: Path<(String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo) = path.into_inner();
+    let (repo, _) = match lfs_repo(&status, &owner, &repo).await {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+    let batch: BatchRequest = match serde_json::from_slice(&body) {
+        Ok(batch) => batch,
+        Err(e) => return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+    };
+    let need = match batch.operation {
+        LfsOperation::Upload => RepoAccess::Write,
+        LfsOperation::Download => RepoAccess::Read,
+    };
+    if let Err(response) = lfs_authorize(&request, &status, &repo, need).await {
+        return response;
+    }
+    if batch
+        .hash_algo
+        .as_deref()
+        .is_some_and(|x| x != LFS_HASH_ALGO)
+    {
+        return lfs_error(StatusCode::CONFLICT, "Unsupported hash algorithm");
+    }
+    if !batch.supported() {
+        return lfs_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Unsupported transfer adapter",
+        );
+    }
+    let link = lfs_link(&request, &repo);
+    match status.lfs_batch(&repo, batch, &link).await {
+        Ok(response) => HttpResponse::Ok()
+            .content_type(LFS_CONTENT_TYPE)
+            .json(response),
+        Err(e) => {
+            error!("lfs batch failed: {}", e.msg);
+            lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+        }
+    }
+}
+
+pub async fn lfs_download(
+    request: HttpRequest,
+    path: Path<(String, String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo, oid) = path.into_inner();
+    let (repo, store) = match lfs_repo(&status, &owner, &repo).await {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+    if let Err(response) = lfs_authorize(&request, &status, &repo, RepoAccess::Read).await {
+        return response;
+    }
+    if !valid_oid(&oid) {
+        return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid object id");
+    }
+    match status.lfs_linked(repo.uid, &oid).await {
+        Ok(true) => {}
+        Ok(false) => return lfs_error(StatusCode::NOT_FOUND, "Object does not exist"),
+        Err(e) => {
+            error!("lfs download failed: {}", e.msg);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    }
+    let chunks = match status.lfs_chunks(&oid).await {
+        Ok(chunks) => chunks,
+        Err(e) => {
+            error!("lfs download failed: {}", e.msg);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    };
+    let Some(size) = store.size(&oid, &chunks).await else {
+        return lfs_error(StatusCode::NOT_FOUND, "Object does not exist");
+    };
+    let range = match request
+        .headers()
+        .get(RANGE)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| byte_range(x, size))
+    {
+        Some(Ok(range)) => range,
+        Some(Err(())) => {
+            return HttpResponse::RangeNotSatisfiable()
+                .insert_header((CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish();
+        }
+        None => None,
+    };
+    let (start, len) = range
+        .as_ref()
+        .map_or((0, size), |x| (x.start, x.end - x.start));
+    let body = match store.read(&oid, &chunks, start, len).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("lfs download failed: {}", e);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    };
+    let mut response = match range {
+        Some(range) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+            ));
+            response
+        }
+        None => HttpResponse::Ok(),
+    };
+    response
+        .content_type("application/octet-stream")
+        .insert_header(ContentEncoding::Identity)
+        .insert_header((ACCEPT_RANGES, "bytes"))
+        .no_chunking(len)
+        .streaming(body)
+}
+
+/// A single `bytes=` range of a `Range` header. `Ok(None)` for anything else, which is
+/// answered with the whole object, `Err` when the range lies past the end.
+fn byte_range(value: &str, size: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let range = match (first.trim(), last.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Err(()),
+            Ok(suffix) => size.saturating_sub(suffix)..size,
+            Err(_) => return Ok(None),
+        },
+        (first, last) => {
+            let Ok(first) = first.parse::<u64>() else {
+                return Ok(None);
+            };
+            let last = match last {
+                "" => size.saturating_sub(1),
+                last => match last.parse::<u64>() {
+                    Ok(last) if last >= first => last.min(size.saturating_sub(1)),
+                    _ => return Ok(None),
+                },
+            };
+            if first >= size {
+                return Err(());
+            }
+            first..last + 1
+        }
+    };
+    if range.is_empty() {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+pub async fn lfs_upload(
+    request: HttpRequest,
+    payload: Payload,
+    path: Path<(String, String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo, oid) = path.into_inner();
+    let (repo, store) = match lfs_repo(&status, &owner, &repo).await {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+    if let Err(response) = lfs_authorize(&request, &status, &repo, RepoAccess::Write).await {
+        return response;
+    }
+    if !valid_oid(&oid) {
+        return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid object id");
+    }
+    let Some(size) = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok())
+    else {
+        return lfs_error(StatusCode::LENGTH_REQUIRED, "Content-Length required");
+    };
+    match status.push_quota(&repo).await {
+        Ok(quota) if quota.remaining.is_some_and(|x| size > x) => {
+            return lfs_error(StatusCode::INSUFFICIENT_STORAGE, "Storage quota exceeded");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("lfs upload failed: {}", e.msg);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    }
+    let split = match status.lfs_chunks(&oid).await {
+        Ok(chunks) => !chunks.is_empty() || store.splits(size),
+        Err(e) => {
+            error!("lfs upload failed: {}", e.msg);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    };
+    // stored again even when another repository has it, the hash check is what proves the
+    // client owns the content before it gets linked here
+    let mut input = request_body(&request, payload);
+    let chunks = match store.write(&oid, size, split, &mut input).await {
+        Ok(chunks) => chunks,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string());
+        }
+        Err(e) => {
+            error!("lfs upload failed: {}", e);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    };
+    if let Err(e) = status.lfs_link(repo.uid, &oid, size as i64, &chunks).await {
+        error!("lfs upload failed: {}", e.msg);
+        return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+    }
+    HttpResponse::Ok().finish()
+}
+
+pub async fn lfs_verify(
+    request: HttpRequest,
+    body: Bytes,
+    path: Path<(String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo) = path.into_inner();
+    let (repo, store) = match lfs_repo(&status, &owner, &repo).await {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+    if let Err(response) = lfs_authorize(&request, &status, &repo, RepoAccess::Write).await {
+        return response;
+    }
+    let pointer: LfsPointer = match serde_json::from_slice(&body) {
+        Ok(pointer) => pointer,
+        Err(e) => return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+    };
+    if !valid_oid(&pointer.oid) {
+        return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid object id");
+    }
+    let linked = match status.lfs_linked(repo.uid, &pointer.oid).await {
+        Ok(linked) => linked,
+        Err(e) => {
+            error!("lfs verify failed: {}", e.msg);
+            return lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    };
+    if !linked {
+        return lfs_error(StatusCode::NOT_FOUND, "Object does not exist");
+    }
+    match status.lfs_size(&store, &pointer.oid).await {
+        Ok(Some(size)) if size as i64 == pointer.size => HttpResponse::Ok().finish(),
+        Ok(Some(_)) => lfs_error(StatusCode::UNPROCESSABLE_ENTITY, "Object size mismatch"),
+        Ok(None) => lfs_error(StatusCode::NOT_FOUND, "Object does not exist"),
+        Err(e) => {
+            error!("lfs verify failed: {}", e.msg);
+            lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+        }
+    }
+}
+
+#[test]
+fn test_byte_range() {
+    assert_eq!(byte_range("bytes=0-99", 1000), Ok(Some(0..100)));
+    assert_eq!(byte_range("bytes=900-", 1000), Ok(Some(900..1000)));
+    assert_eq!(byte_range("bytes=-100", 1000), Ok(Some(900..1000)));
+    assert_eq!(byte_range("bytes=990-2000", 1000), Ok(Some(990..1000)));
+    assert_eq!(byte_range("bytes=1000-", 1000), Err(()));
+    assert_eq!(byte_range("bytes=0-1,5-9", 1000), Ok(None));
+    assert_eq!(byte_range("items=0-1", 1000), Ok(None));
+}
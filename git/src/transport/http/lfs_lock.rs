@@ -0,0 +1,175 @@
+use crate::lfs::LFS_CONTENT_TYPE;
+use crate::lfs::lock::{CreateLockRequest, LfsLock, LockFilter, UnlockRequest, valid_lock_path};
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use crate::transport::http::auth::{lfs_authorize, unauthorized};
+use crate::transport::http::lfs::{lfs_error, lfs_repo};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data, Path, Query};
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use database::entity::{git_repo, users};
+use serde::Serialize;
+use tracing::error;
+
+#[derive(Serialize)]
+struct LockResponse {
+    lock: LfsLock,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+fn lfs_json<T: Serialize>(code: StatusCode, body: T) -> HttpResponse {
+    HttpResponse::build(code)
+        .content_type(LFS_CONTENT_TYPE)
+        .json(body)
+}
+
+fn internal_error(action: &str, msg: &str) -> HttpResponse {
+    error!("lfs {} failed: {}", action, msg);
+    lfs_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+}
+
+/// Creating, verifying and releasing locks takes push access and a known user, locks are
+/// held in their name.
+async fn lock_user(
+    request: &HttpRequest,
+    status: &GitServer,
+    owner: &str,
+    repo: &str,
+) -> Result<(git_repo::Model, users::Model), HttpResponse> {
+    let (repo, _) = lfs_repo(status, owner, repo).await?;
+    match lfs_authorize(request, status, &repo, RepoAccess::Write).await? {
+        Some(user) => Ok((repo, user)),
+        None => Err(unauthorized("Authentication required")),
+    }
+}
+
+pub async fn lfs_lock_create(
+    request: HttpRequest,
+    body: Bytes,
+    path: Path<(String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo) = path.into_inner();
+    let (repo, user) = match lock_user(&request, &status, &owner, &repo).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let lock: CreateLockRequest = match serde_json::from_slice(&body) {
+        Ok(lock) => lock,
+        Err(e) => return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+    };
+    if !valid_lock_path(&lock.path) {
+        return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid lock path");
+    }
+    match status.lfs_lock_create(repo.uid, &user, lock).await {
+        Ok(Ok(lock)) => lfs_json(
+            StatusCode::CREATED,
+            LockResponse {
+                lock,
+                message: None,
+            },
+        ),
+        Ok(Err(lock)) => lfs_json(
+            StatusCode::CONFLICT,
+            LockResponse {
+                lock,
+                message: Some("already created lock".to_string()),
+            },
+        ),
+        Err(e) => internal_error("lock", &e.msg),
+    }
+}
+
+pub async fn lfs_lock_list(
+    request: HttpRequest,
+    query: Query<LockFilter>,
+    path: Path<(String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo) = path.into_inner();
+    let (repo, _) = match lfs_repo(&status, &owner, &repo).await {
+        Ok(repo) => repo,
+        Err(response) => return response,
+    };
+    if let Err(response) = lfs_authorize(&request, &status, &repo, RepoAccess::Read).await {
+        return response;
+    }
+    match status.lfs_lock_list(repo.uid, &query).await {
+        Ok(list) => lfs_json(StatusCode::OK, list),
+        Err(e) => internal_error("lock list", &e.msg),
+    }
+}
+
+pub async fn lfs_lock_verify(
+    request: HttpRequest,
+    body: Bytes,
+    path: Path<(String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo) = path.into_inner();
+    let (repo, user) = match lock_user(&request, &status, &owner, &repo).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let filter: LockFilter = match serde_json::from_slice(&body) {
+        Ok(filter) => filter,
+        Err(e) => return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+    };
+    let filter = LockFilter {
+        path: None,
+        id: None,
+        ..filter
+    };
+    match status.lfs_lock_verify(repo.uid, user.uid, &filter).await {
+        Ok(list) => lfs_json(StatusCode::OK, list),
+        Err(e) => internal_error("lock verify", &e.msg),
+    }
+}
+
+pub async fn lfs_unlock(
+    request: HttpRequest,
+    body: Bytes,
+    path: Path<(String, String, String)>,
+    status: Data<GitServer>,
+) -> impl Responder {
+    let (owner, repo, id) = path.into_inner();
+    let (repo, user) = match lock_user(&request, &status, &owner, &repo).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let unlock: UnlockRequest = if body.is_empty() {
+        UnlockRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(unlock) => unlock,
+            Err(e) => return lfs_error(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+        }
+    };
+    let lock = match status.lfs_lock_find(repo.uid, &id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => return lfs_error(StatusCode::NOT_FOUND, "Lock not found"),
+        Err(e) => return internal_error("unlock", &e.msg),
+    };
+    if lock.owner_uid != user.uid {
+        if !unlock.force {
+            return lfs_error(StatusCode::FORBIDDEN, "Lock is owned by another user");
+        }
+        if !status.repo_admin(&repo, &user) {
+            return lfs_error(
+                StatusCode::FORBIDDEN,
+                "Only repository admins can force unlock",
+            );
+        }
+    }
+    match status.lfs_unlock(lock).await {
+        Ok(lock) => lfs_json(
+            StatusCode::OK,
+            LockResponse {
+                lock,
+                message: None,
+            },
+        ),
+        Err(e) => internal_error("unlock", &e.msg),
+    }
+}
@@ -1,3 +1,8 @@
+pub mod archive;
+pub mod auth;
 pub mod info;
+pub mod lfs;
+pub mod lfs_lock;
+pub mod pack;
 pub mod receive_pack;
 pub mod upload_pack;
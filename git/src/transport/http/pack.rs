@@ -0,0 +1,240 @@
+use crate::service::limit::{PackClient, PackLimited, PackPermit};
+use crate::transport::backend::{GitPack, PackIo, PackRequest};
+use actix_web::error::PayloadError;
+use actix_web::http::header::{CONTENT_ENCODING, RETRY_AFTER, X_FORWARDED_FOR};
+use actix_web::{HttpRequest, HttpResponse};
+use async_compression::tokio::bufread::GzipDecoder;
+use async_stream::stream;
+use bytes::Bytes;
+use config::git::AppGitLimits;
+use database::entity::users;
+use futures_util::{Stream, StreamExt};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{info, warn};
+
+const PACK_CHUNK_SIZE: usize = 64 * 1024;
+
+pub type PackBody = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>>>>;
+
+/// A running stateless upload-pack/receive-pack session wired to an HTTP request. `body`
+/// streams stdout to the client and is only read as fast as the client consumes it; `exit`
+/// resolves to the exit code once the session is over, or to `None` when the client went
+/// away first and the session had to be killed.
+pub struct PackProcess {
+    pub body: PackBody,
+    pub exit: JoinHandle<Option<i32>>,
+}
+
+/// git sends `Content-Encoding: gzip` for large negotiation requests.
+pub fn request_body<S>(request: &HttpRequest, payload: S) -> Pin<Box<dyn AsyncRead>>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + 'static,
+{
+    let reader = StreamReader::new(payload.map(|chunk| chunk.map_err(io::Error::other)));
+    let gzip = request
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.eq_ignore_ascii_case("gzip") || x.eq_ignore_ascii_case("x-gzip"))
+        .unwrap_or(false);
+    if gzip {
+        Box::pin(GzipDecoder::new(reader))
+    } else {
+        Box::pin(reader)
+    }
+}
+
+/// Whom the limits of `pack_permit` count a request against.
+pub fn pack_client(
+    request: &HttpRequest,
+    limits: &AppGitLimits,
+    user: Option<&users::Model>,
+) -> PackClient {
+    match user {
+        Some(user) => PackClient::User(user.uid),
+        None => PackClient::Anonymous(request.peer_addr().map(|peer| {
+            let forwarded = request
+                .headers()
+                .get(X_FORWARDED_FOR)
+                .and_then(|x| x.to_str().ok());
+            client_ip(peer.ip(), forwarded, &limits.trusted_proxies)
+        })),
+    }
+}
+
+/// The peer address, or when the peer is a trusted proxy the last address it was forwarded
+/// from that is not one too. Earlier entries came from the client and prove nothing.
+fn client_ip(peer: IpAddr, forwarded: Option<&str>, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+    let hops = forwarded.unwrap_or_default().rsplit(',').map(|x| x.trim());
+    for hop in hops {
+        let Some(ip) = hop
+            .parse::<SocketAddr>()
+            .map(|x| x.ip())
+            .or_else(|_| hop.trim_matches(['[', ']']).parse::<IpAddr>())
+            .ok()
+        else {
+            break;
+        };
+        if !trusted.contains(&ip) {
+            return ip;
+        }
+    }
+    peer
+}
+
+/// git shows a `text/plain` error body to the user, each line prefixed with `remote:`.
+pub fn pack_limited(limited: &PackLimited) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header((RETRY_AFTER, limited.retry_after().to_string()))
+        .body(format!("{}\n", limited))
+}
+
+/// Keeps the slots of `permit` until the client has read the whole body or went away.
+pub fn hold_permit(body: PackBody, permit: PackPermit) -> PackBody {
+    Box::pin(stream! {
+        let _permit = permit;
+        let mut body = body;
+        while let Some(chunk) = body.next().await {
+            yield chunk;
+        }
+    })
+}
+
+pub fn spawn_pack(
+    backend: &dyn GitPack,
+    request: PackRequest,
+    mut input: Pin<Box<dyn AsyncRead>>,
+    label: String,
+) -> io::Result<PackProcess> {
+    let PackIo {
+        mut stdin,
+        stdout,
+        stderr,
+        mut child,
+    } = backend.spawn(request)?;
+
+    // the request payload is not `Send`, so pump it on the worker's local set
+    let stdin_label = label.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = tokio::io::copy(&mut input, &mut stdin).await {
+            warn!("{}: request body aborted: {}", stdin_label, e);
+        }
+        stdin.shutdown().await.ok();
+    });
+
+    let stderr_label = label.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            warn!("{}: {}", stderr_label, line);
+        }
+    });
+
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let exit = tokio::spawn(async move {
+        tokio::select! {
+            status = child.wait() => status.ok(),
+            done = done_rx => {
+                if done.is_ok() {
+                    child.wait().await.ok()
+                } else {
+                    info!("{}: client disconnected, killing session", label);
+                    child.kill().await.ok();
+                    None
+                }
+            }
+        }
+    });
+
+    let body = stream! {
+        let mut stdout = ReaderStream::with_capacity(stdout, PACK_CHUNK_SIZE);
+        while let Some(chunk) = stdout.next().await {
+            yield chunk;
+        }
+        done_tx.send(()).ok();
+    };
+    Ok(PackProcess {
+        body: Box::pin(body),
+        exit,
+    })
+}
+
+#[test]
+fn test_spawn_pack_gzip() {
+    use crate::testing::{self, TempDir};
+    use actix_web::test::TestRequest;
+    use async_compression::tokio::bufread::GzipEncoder;
+    use tokio::io::AsyncReadExt;
+
+    let dir = TempDir::new("pack");
+    let repo = git2::Repository::init_bare(&*dir).unwrap();
+    let commit = testing::commit(&repo, "refs/heads/main", &[], &[]);
+
+    actix_web::rt::System::new().block_on(async {
+        let want = format!("want {}\n", commit);
+        let plain = format!("{:04x}{}00000009done\n", want.len() + 4, want);
+        let mut gzip = vec![];
+        GzipEncoder::new(plain.as_bytes())
+            .read_to_end(&mut gzip)
+            .await
+            .unwrap();
+        let (request, payload) = TestRequest::default()
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .set_payload(gzip)
+            .to_http_parts();
+        let process = spawn_pack(
+            &crate::transport::backend::SubprocessPack,
+            PackRequest {
+                service: crate::transport::GitService::UploadPack,
+                path: dir.to_path_buf(),
+                protocol: None,
+                stateless: true,
+                advertise_refs: false,
+                protection: crate::service::protection::BranchProtection::default(),
+                filter: crate::service::partial_clone::FilterPolicy::default(),
+                max_input_size: None,
+            },
+            request_body(&request, payload),
+            "test".to_string(),
+        )
+        .unwrap();
+        let body = process
+            .body
+            .map(|x| x.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert!(body.starts_with(b"0008NAK\n"));
+        assert!(body.windows(4).any(|x| x == b"PACK"));
+        assert_eq!(process.exit.await.unwrap(), Some(0));
+    });
+}
+
+#[test]
+fn test_client_ip() {
+    let proxy = IpAddr::from([10, 0, 0, 1]);
+    let client = IpAddr::from([192, 0, 2, 1]);
+    let forged = Some("198.51.100.7, 192.0.2.1");
+    assert_eq!(client_ip(client, forged, &[proxy]), client);
+    assert_eq!(client_ip(proxy, forged, &[proxy]), client);
+    assert_eq!(
+        client_ip(proxy, Some("192.0.2.1, 10.0.0.1"), &[proxy]),
+        client
+    );
+    assert_eq!(
+        client_ip(proxy, Some("[2001:db8::1]:443"), &[proxy]),
+        IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1])
+    );
+    assert_eq!(client_ip(proxy, Some("unknown"), &[proxy]), proxy);
+    assert_eq!(client_ip(proxy, None, &[proxy]), proxy);
+}
@@ -1,18 +1,27 @@
 use crate::GitContext;
 use crate::service::GitServer;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::permissions::RepoAccess;
+use crate::service::protection::PROTECTED_REASON;
+use crate::service::quota::QUOTA_REASON;
+use crate::transport::GitService;
+use crate::transport::backend::PackRequest;
+use crate::transport::http::auth::git_authorize;
+use crate::transport::http::pack::{
+    hold_permit, pack_client, pack_limited, request_body, spawn_pack,
+};
+use crate::transport::push::PushCommands;
 use actix_web::http::StatusCode;
+use actix_web::http::header::{CONTENT_LENGTH, ContentEncoding};
 use actix_web::web::{Data, Path, Payload};
 use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
-use async_stream::stream;
-use bytes::Bytes;
-use futures_util::StreamExt;
-use std::io;
-use std::io::Read;
-use std::process::{Command, Stdio};
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+use tracing::error;
 
 pub async fn git_receive_pack(
-    _request: HttpRequest,
-    mut payload: Payload,
+    request: HttpRequest,
+    payload: Payload,
     path: Path<(String, String)>,
     status: Data<GitServer>,
 ) -> impl Responder {
@@ -21,53 +30,120 @@ pub async fn git_receive_pack(
     let Ok(repo) = status.find_repo(&owner, &repo).await else {
         return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("Repository Not Found");
     };
-    let Ok(path) =
-        GitContext::try_from((repo.clone(), status.config.git.clone())).map(|x| x.path_dir)
+    let pusher = match git_authorize(&request, &status, &repo, RepoAccess::Write).await {
+        Ok(pusher) => pusher,
+        Err(response) => return response,
+    };
+    let Ok(git) = GitContext::try_from((repo.clone(), status.config.git.clone())) else {
+        return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Server Error");
+    };
+    match git.blocking(|git| git.exists()).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("repository not found");
+        }
+        Err(_) => {
+            return HttpResponseBuilder::new(StatusCode::SERVICE_UNAVAILABLE)
+                .body("repository storage unavailable");
+        }
+    }
+    let Ok(protection) = status
+        .branch_protection(&repo, pusher.as_ref().map(|x| x.uid))
+        .await
     else {
         return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
             .body("Internal Server Error");
     };
-    if !path.exists() {
-        return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("repository not found");
+    let Ok(quota) = status.push_quota(&repo).await else {
+        return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Server Error");
+    };
+    let mut input = request_body(&request, payload);
+    // the command list drives both the protection checks, run before receive-pack sees a
+    // single object, and the database sync once it is done
+    let (commands, head) = match PushCommands::read(&mut input).await {
+        Ok(commands) => commands,
+        Err(e) => {
+            return HttpResponseBuilder::new(StatusCode::BAD_REQUEST).body(e.to_string());
+        }
+    };
+    let commands = commands.unwrap_or_default();
+    let mut errors = protection.check_commands(&commands.commands, pusher.as_ref().map(|x| x.uid));
+    let mut reason = PROTECTED_REASON;
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if errors.is_empty()
+        && let Some(error) = quota.check(&commands.commands, length)
+    {
+        errors.push(error);
+        reason = QUOTA_REASON;
     }
-    let mut child = match Command::new("git")
-        .arg("receive-pack")
-        .arg("--stateless-rpc")
-        .arg(path)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
+    if !errors.is_empty() {
+        tokio::io::copy(&mut input, &mut tokio::io::sink())
+            .await
+            .ok();
+        return HttpResponse::Ok()
+            .content_type("application/x-git-receive-pack-result")
+            .insert_header(ContentEncoding::Identity)
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(commands.reject(&errors, reason));
+    }
+    let input = Box::pin(Cursor::new(head).chain(input));
+    let permit = match status
+        .pack_permit(
+            pack_client(&request, &status.config.git.limits, pusher.as_ref()),
+            repo.uid,
+        )
+        .await
     {
-        Ok(c) => c,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+        Ok(permit) => permit,
+        Err(limited) => return pack_limited(&limited),
     };
-    if let Some(mut stdin) = child.stdin.take() {
-        use std::io::Write;
-        while let Some(Ok(bytes)) = payload.next().await {
-            stdin.write_all(&bytes).ok();
+    // held until the database caught up with the refs receive-pack moved
+    let lock = match status.lock_repo_on(&repo).await {
+        Ok(lock) => lock,
+        Err(e) => {
+            return HttpResponseBuilder::new(StatusCode::SERVICE_UNAVAILABLE)
+                .insert_header(("Retry-After", "10"))
+                .body(e.msg);
         }
-    }
-    let mut stdout = child.stdout.unwrap();
-    let body = actix_web::body::BodyStream::new(stream! {
-        let mut buffer = [0; 8192];
-        loop {
-            match stdout.read(&mut buffer) {
-                Ok(0) => {
-                    break;
-                }
-                Ok(n) => {
-                    yield Ok::<_, io::Error>(Bytes::copy_from_slice(&buffer[..n]));
-                }
-                Err(_e) => {
-                    break;
-                }
-            }
+    };
+    let backend = status.pack_backend(&git, GitService::ReceivePack, &FilterPolicy::default());
+    let pack = PackRequest {
+        service: GitService::ReceivePack,
+        path: git.path_dir.clone(),
+        protocol: None,
+        stateless: true,
+        advertise_refs: false,
+        protection,
+        filter: FilterPolicy::default(),
+        max_input_size: quota.remaining,
+    };
+    let label = format!("receive-pack {}/{}", repo.namespace, repo.repo_name);
+    let process = match spawn_pack(backend.as_ref(), pack, input, label) {
+        Ok(process) => process,
+        Err(e) => {
+            error!("Process spawn failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
         }
-    });
+    };
+    let exit = process.exit;
     tokio::spawn(async move {
-        status.sync_repo(repo.uid.clone()).await.ok();
+        // refs are only updated once receive-pack has exited
+        if let Ok(Some(0)) = exit.await
+            && !commands.commands.is_empty()
+            && let Err(e) = status.sync_push(&lock, &commands.commands).await
+        {
+            error!("Sync repo failed: {:?}", e);
+        }
     });
     HttpResponse::Ok()
         .content_type("application/x-git-receive-pack-result")
-        .body(body)
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(hold_permit(process.body, permit))
 }
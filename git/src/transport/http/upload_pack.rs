@@ -1,18 +1,28 @@
 use crate::GitContext;
 use crate::service::GitServer;
+use crate::service::limit::PackPermit;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::protection::BranchProtection;
+use crate::service::permissions::RepoAccess;
+use crate::transport::GitService;
+use crate::transport::backend::PackRequest;
+use crate::transport::http::auth::git_authorize;
+use crate::transport::http::pack::{
+    PackBody, hold_permit, pack_client, pack_limited, request_body, spawn_pack,
+};
+use crate::transport::pack_cache::MAX_CACHED_REQUEST;
+use crate::transport::protocol::GitProtocol;
 use actix_web::http::StatusCode;
+use actix_web::http::header::ContentEncoding;
 use actix_web::web::{Data, Path, Payload};
 use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
-use async_stream::stream;
-use bytes::Bytes;
-use futures_util::StreamExt;
-use std::io;
-use std::io::Read;
-use std::process::{Command, Stdio};
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+use tracing::error;
 
 pub async fn git_upload_pack(
-    _request: HttpRequest,
-    mut payload: Payload,
+    request: HttpRequest,
+    payload: Payload,
     path: Path<(String, String)>,
     status: Data<GitServer>,
 ) -> impl Responder {
@@ -21,67 +31,182 @@ pub async fn git_upload_pack(
     let Ok(repo) = status.find_repo(&owner, &repo).await else {
         return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("Repository Not Found");
     };
-    let Ok(path) =
-        GitContext::try_from((repo.clone(), status.config.git.clone())).map(|x| x.path_dir)
-    else {
+    let user = match git_authorize(&request, &status, &repo, RepoAccess::Read).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let filter = status.filter_policy(&repo);
+    let Ok(git) = GitContext::try_from((repo.clone(), status.config.git.clone())) else {
         return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
             .body("Internal Server Error");
     };
-    if !path.exists() {
-        return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("repository not found");
-    }
-    let mut child = match Command::new("git")
-        .arg("upload-pack")
-        .arg("--stateless-rpc")
-        .arg(path)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-    {
-        Ok(c) => c,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    if let Some(mut stdin) = child.stdin.take() {
-        use std::io::Write;
-        while let Some(Ok(bytes)) = payload.next().await {
-            stdin.write_all(&bytes).ok();
+    match git.blocking(|git| git.exists()).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponseBuilder::new(StatusCode::NOT_FOUND).body("repository not found");
         }
-    }
-    let mut stdout = child.stdout.unwrap();
-    let body = actix_web::body::BodyStream::new(stream! {
-        let mut buffer = [0; 8192];
-        loop {
-            match stdout.read(&mut buffer) {
-                Ok(0) => {
-                    break;
-                }
-                Ok(n) => {
-                    yield Ok::<_, io::Error>(Bytes::copy_from_slice(&buffer[..n]));
-                }
-                Err(_e) => {
-                    break;
-                }
-            }
+        Err(_) => {
+            return HttpResponseBuilder::new(StatusCode::SERVICE_UNAVAILABLE)
+                .body("repository storage unavailable");
         }
-    });
-    let status_copy = status.clone();
-    tokio::spawn(async move {
-        status.sync_repo(repo.uid.clone()).await.ok();
-    });
-    tokio::spawn(async move {
-        let status = status_copy;
-        let repo = repo.clone();
-        match status.find_repo_owner(repo.clone()).await {
-            Ok(owner) => {
+    }
+    let client = pack_client(&request, &status.config.git.limits, user.as_ref());
+    // a client may post without asking for the advertisement first
+    if let Err(limited) = status.count_pack_round(client) {
+        return pack_limited(&limited);
+    }
+    let permit = match status.pack_permit(client, repo.uid).await {
+        Ok(permit) => permit,
+        Err(limited) => return pack_limited(&limited),
+    };
+    let label = format!("upload-pack {}/{}", repo.namespace, repo.repo_name);
+    let response = upload_pack(&request, payload, &status, &git, filter, permit, label).await;
+    if response.status().is_success() {
+        tokio::spawn(async move {
+            let repo = repo.clone();
+            if let Ok(owner) = status.find_repo_owner(repo.clone()).await {
                 status
                     .inner_add_interaction_clone(owner.uid, repo.uid)
                     .await
                     .ok();
             }
-            Err(_) => {}
+        });
+    }
+    response
+}
+
+/// One stateless upload-pack round on `git`, `git_upload_pack` past the repository lookup.
+/// `permit` is held until the response is sent.
+pub(crate) async fn upload_pack(
+    request: &HttpRequest,
+    payload: Payload,
+    status: &GitServer,
+    git: &GitContext,
+    filter: FilterPolicy,
+    permit: PackPermit,
+    label: String,
+) -> HttpResponse {
+    let backend = status.pack_backend(git, GitService::UploadPack, &filter);
+    let allow_v2 = status.allow_v2(GitService::UploadPack, backend.as_ref());
+    let pack = PackRequest {
+        service: GitService::UploadPack,
+        path: git.path_dir.clone(),
+        protocol: GitProtocol::from_request(request, allow_v2),
+        stateless: true,
+        advertise_refs: false,
+        protection: BranchProtection::default(),
+        filter,
+        max_input_size: None,
+    };
+    let mut input = request_body(request, payload);
+    // cache keys come from the refs on local disk
+    let cache = status.pack_cache().filter(|_| git.remote.is_none());
+    let body = match cache {
+        Some(cache) => {
+            let mut buffered = vec![];
+            if let Err(e) = (&mut input)
+                .take(MAX_CACHED_REQUEST as u64 + 1)
+                .read_to_end(&mut buffered)
+                .await
+            {
+                error!("{}: request body aborted: {}", label, e);
+                return HttpResponse::BadRequest().finish();
+            }
+            if buffered.len() > MAX_CACHED_REQUEST {
+                let input = Box::pin(Cursor::new(buffered).chain(input));
+                spawn_pack(backend.as_ref(), pack, input, label).map(|x| x.body)
+            } else {
+                cache
+                    .serve(backend.as_ref(), pack, buffered, label)
+                    .await
+                    .map(|x| x as PackBody)
+            }
         }
-    });
+        None => spawn_pack(backend.as_ref(), pack, input, label).map(|x| x.body),
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Process spawn failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
     HttpResponse::Ok()
         .content_type("application/x-git-upload-pack-result")
-        .body(body)
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(hold_permit(body, permit))
+}
+
+#[test]
+fn test_partial_clone_http() {
+    use crate::service::limit::PackClient;
+    use crate::testing::{self, TempDir};
+    use crate::transport::http::info::advertise_refs;
+    use actix_web::{App, HttpServer, web};
+    use config::AppConfig;
+    use sea_orm::prelude::Uuid;
+
+    let root = TempDir::new("http-partial-clone");
+    let repo = git2::Repository::init_bare(root.join("repo")).unwrap();
+    testing::commit(&repo, "refs/heads/main", &[("data.csv", b"id\n1\n")], &[]);
+    repo.set_head("refs/heads/main").unwrap();
+    let git = GitContext {
+        path_dir: root.join("repo"),
+        remote: None,
+    };
+    let filter = FilterPolicy {
+        filters: vec!["blob:none".to_string()],
+        tree_max_depth: None,
+    };
+    let mut config = AppConfig::default();
+    config.git.protocol_v2 = true;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/repo.git", listener.local_addr().unwrap());
+    actix_web::rt::System::new().block_on(async move {
+        let status = testing::git_server(config);
+        let server = HttpServer::new(move || {
+            let refs = (status.clone(), git.clone(), filter.clone());
+            let pack = refs.clone();
+            App::new()
+                .route(
+                    "/repo.git/info/refs",
+                    web::get().to(move |request: HttpRequest| {
+                        let (status, git, filter) = refs.clone();
+                        async move {
+                            advertise_refs(&request, &status, &git, GitService::UploadPack, filter)
+                                .await
+                        }
+                    }),
+                )
+                .route(
+                    "/repo.git/git-upload-pack",
+                    web::post().to(move |request: HttpRequest, payload: Payload| {
+                        let (status, git, filter) = pack.clone();
+                        async move {
+                            let client = PackClient::Anonymous(None);
+                            let permit = status.pack_permit(client, Uuid::nil()).await.unwrap();
+                            let label = "upload-pack repo".to_string();
+                            upload_pack(&request, payload, &status, &git, filter, permit, label)
+                                .await
+                        }
+                    }),
+                )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        for version in ["0", "2"] {
+            let url = url.clone();
+            actix_web::rt::task::spawn_blocking(move || {
+                testing::check_partial_clone(&url, version, "data.csv", b"id\n1\n")
+            })
+            .await
+            .unwrap();
+        }
+        handle.stop(true).await;
+    });
 }
@@ -1,7 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub mod archive;
+pub mod backend;
+pub mod disk_cache;
 pub mod http;
+pub mod pack_cache;
+pub mod pkt;
+pub mod protocol;
+pub mod push;
 pub mod ssh;
 
-pub enum GitPack {
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitService {
     UploadPack,
     ReceivePack,
+    UploadArchive,
+}
+
+impl GitService {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GitService::UploadPack => "upload-pack",
+            GitService::ReceivePack => "receive-pack",
+            GitService::UploadArchive => "upload-archive",
+        }
+    }
+}
+
+impl FromStr for GitService {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upload-pack" => Ok(Self::UploadPack),
+            "receive-pack" => Ok(Self::ReceivePack),
+            "upload-archive" => Ok(Self::UploadArchive),
+            _ => Err(()),
+        }
+    }
 }
@@ -0,0 +1,368 @@
+use crate::service::GitServer;
+use crate::transport::GitService;
+use crate::transport::backend::{GitPack, PackIo, PackRequest};
+use crate::transport::disk_cache::{CacheWriter, DiskCache};
+use crate::transport::pkt::PktReader;
+use async_stream::stream;
+use bytes::Bytes;
+use config::git::AppGitPackCache;
+use futures_util::{Stream, StreamExt};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+/// Negotiation requests larger than this are passed through without a cache lookup.
+pub const MAX_CACHED_REQUEST: usize = 1024 * 1024;
+
+const CACHE_CHUNK_SIZE: usize = 64 * 1024;
+
+static STATS: CacheCounters = CacheCounters {
+    hits: AtomicU64::new(0),
+    misses: AtomicU64::new(0),
+    bypassed: AtomicU64::new(0),
+    stored: AtomicU64::new(0),
+    evicted: AtomicU64::new(0),
+    bytes_served: AtomicU64::new(0),
+};
+
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    bypassed: AtomicU64,
+    stored: AtomicU64,
+    evicted: AtomicU64,
+    bytes_served: AtomicU64,
+}
+
+/// Pack cache figures of this process since start.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PackCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Rounds that do not end the negotiation and are never cached.
+    pub bypassed: u64,
+    pub stored: u64,
+    pub evicted: u64,
+    /// Bytes answered from the cache.
+    pub bytes_served: u64,
+}
+
+pub type CachedBody = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+/// Upload-pack responses on local disk, one file per response named by its key.
+#[derive(Clone, Debug)]
+pub struct PackCache {
+    cache: DiskCache,
+}
+
+impl PackCache {
+    pub fn new(config: &AppGitPackCache) -> Self {
+        Self {
+            cache: DiskCache::new(config.path.clone(), config.max_size),
+        }
+    }
+    pub fn stats() -> PackCacheStats {
+        PackCacheStats {
+            hits: STATS.hits.load(Ordering::Relaxed),
+            misses: STATS.misses.load(Ordering::Relaxed),
+            bypassed: STATS.bypassed.load(Ordering::Relaxed),
+            stored: STATS.stored.load(Ordering::Relaxed),
+            evicted: STATS.evicted.load(Ordering::Relaxed),
+            bytes_served: STATS.bytes_served.load(Ordering::Relaxed),
+        }
+    }
+    /// Answers one stateless upload-pack round, from the cache when the same request was
+    /// answered for the same refs before. Only the round that ends the negotiation carries
+    /// a pack and is kept; the response is stored once upload-pack exited cleanly.
+    pub async fn serve(
+        &self,
+        backend: &dyn GitPack,
+        request: PackRequest,
+        input: Vec<u8>,
+        label: String,
+    ) -> io::Result<CachedBody> {
+        if request.service != GitService::UploadPack || !ends_negotiation(&input) {
+            STATS.bypassed.fetch_add(1, Ordering::Relaxed);
+            return run_round(backend, request, input, None, label);
+        }
+        let key = match cache_key(&request, &input).await {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("{}: pack cache key failed: {}", label, e);
+                STATS.bypassed.fetch_add(1, Ordering::Relaxed);
+                return run_round(backend, request, input, None, label);
+            }
+        };
+        if let Some(file) = self.cache.open(&key).await {
+            STATS.hits.fetch_add(1, Ordering::Relaxed);
+            let body = ReaderStream::with_capacity(file, CACHE_CHUNK_SIZE).inspect(|chunk| {
+                if let Ok(chunk) = chunk {
+                    STATS
+                        .bytes_served
+                        .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+            });
+            return Ok(Box::pin(body));
+        }
+        STATS.misses.fetch_add(1, Ordering::Relaxed);
+        let writer = match self.cache.create(&key).await {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                warn!("{}: pack cache entry not created: {}", label, e);
+                None
+            }
+        };
+        run_round(backend, request, input, writer, label)
+    }
+}
+
+/// Whether a request ends the negotiation with `done`, so that its response is the pack.
+fn ends_negotiation(input: &[u8]) -> bool {
+    let mut reader = PktReader::new(input);
+    while let Ok(Some(pkt)) = reader.read_pkt() {
+        if pkt.text() == Some("done") {
+            return true;
+        }
+    }
+    false
+}
+
+/// The refs of a repository, hashed, so every ref update starts a fresh set of entries.
+fn refs_state(path: &Path) -> Result<String, git2::Error> {
+    let repo = Repository::open_bare(path)?;
+    let mut refs = vec![];
+    for reference in repo.references()? {
+        let reference = reference?;
+        let target = match reference.target() {
+            Some(oid) => oid.to_string(),
+            None => String::from_utf8_lossy(reference.symbolic_target_bytes().unwrap_or_default())
+                .to_string(),
+        };
+        refs.push((reference.name_bytes().to_vec(), target));
+    }
+    if let Ok(head) = repo.find_reference("HEAD") {
+        let target = head.symbolic_target().unwrap_or_default().to_string();
+        refs.push((b"HEAD".to_vec(), target));
+    }
+    refs.sort();
+    let mut hasher = Sha256::new();
+    for (name, target) in refs {
+        hasher.update(&name);
+        hasher.update(b" ");
+        hasher.update(target.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn cache_key(request: &PackRequest, input: &[u8]) -> io::Result<String> {
+    let path = request.path.clone();
+    let state = tokio::task::spawn_blocking(move || refs_state(&path))
+        .await
+        .map_err(io::Error::other)?
+        .map_err(io::Error::other)?;
+    let protocol = request
+        .protocol
+        .as_ref()
+        .map(|x| x.value())
+        .unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(request.path.to_string_lossy().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(state.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(protocol.as_bytes());
+    hasher.update(b"\0");
+    // a changed filter policy changes what may be answered
+    hasher.update(request.filter.git_config().join("\n").as_bytes());
+    hasher.update(b"\0");
+    hasher.update(input);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Runs one round with `input` as its whole request, bypassing the cache.
+pub fn stateless_round(
+    backend: &dyn GitPack,
+    request: PackRequest,
+    input: Vec<u8>,
+    label: String,
+) -> io::Result<CachedBody> {
+    run_round(backend, request, input, None, label)
+}
+
+/// Runs one round with `input` as its whole request, copying the response into `writer`.
+/// upload-pack is killed when the body is dropped before the end.
+fn run_round(
+    backend: &dyn GitPack,
+    request: PackRequest,
+    input: Vec<u8>,
+    mut writer: Option<CacheWriter>,
+    label: String,
+) -> io::Result<CachedBody> {
+    let PackIo {
+        mut stdin,
+        stdout,
+        stderr,
+        mut child,
+    } = backend.spawn(request)?;
+
+    let stdin_label = label.clone();
+    tokio::spawn(async move {
+        if let Err(e) = stdin.write_all(&input).await {
+            warn!("{}: request aborted: {}", stdin_label, e);
+        }
+        stdin.shutdown().await.ok();
+    });
+
+    let stderr_label = label.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            warn!("{}: {}", stderr_label, line);
+        }
+    });
+
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let exit = tokio::spawn(async move {
+        tokio::select! {
+            status = child.wait() => status.ok(),
+            done = done_rx => {
+                if done.is_ok() {
+                    child.wait().await.ok()
+                } else {
+                    info!("{}: client disconnected, killing session", label);
+                    child.kill().await.ok();
+                    None
+                }
+            }
+        }
+    });
+
+    let body = stream! {
+        let mut stdout = ReaderStream::with_capacity(stdout, CACHE_CHUNK_SIZE);
+        while let Some(chunk) = stdout.next().await {
+            match &chunk {
+                Ok(data) => {
+                    if let Some(file) = writer.as_mut() {
+                        if let Err(e) = file.write(data).await {
+                            warn!("pack cache entry dropped: {}", e);
+                            writer = None;
+                        }
+                    }
+                }
+                Err(_) => writer = None,
+            }
+            yield chunk;
+        }
+        done_tx.send(()).ok();
+        if let (Ok(Some(0)), Some(writer)) = (exit.await, writer) {
+            match writer.commit().await {
+                Ok(evicted) => {
+                    STATS.stored.fetch_add(1, Ordering::Relaxed);
+                    STATS.evicted.fetch_add(evicted, Ordering::Relaxed);
+                }
+                Err(e) => warn!("pack cache entry not stored: {}", e),
+            }
+        }
+    };
+    Ok(Box::pin(body))
+}
+
+impl GitServer {
+    /// The pack cache, when `git.pack_cache.enabled` is set.
+    pub fn pack_cache(&self) -> Option<PackCache> {
+        let config = &self.config.git.pack_cache;
+        config.enabled.then(|| PackCache::new(config))
+    }
+}
+
+#[test]
+fn test_ends_negotiation() {
+    let want = "want 0123456789012345678901234567890123456789\n";
+    let v0 = format!("{:04x}{}00000009done\n", want.len() + 4, want);
+    assert!(ends_negotiation(v0.as_bytes()));
+    assert!(!ends_negotiation(&v0.as_bytes()[..v0.len() - 9]));
+    let v2 = format!(
+        "0012command=fetch\n0001{:04x}{}0032have 0123456789012345678901234567890123456789\n0000",
+        want.len() + 4,
+        want
+    );
+    assert!(!ends_negotiation(v2.as_bytes()));
+    assert!(ends_negotiation(
+        format!("{}0009done\n0000", &v2[..v2.len() - 4]).as_bytes()
+    ));
+}
+
+#[tokio::test]
+async fn test_pack_cache() {
+    use crate::service::partial_clone::FilterPolicy;
+    use crate::service::protection::BranchProtection;
+    use crate::testing::{self, TempDir};
+    use crate::transport::backend::SubprocessPack;
+
+    let root = TempDir::new("pack-cache");
+    let dir = root.join("repo");
+    let repo = Repository::init_bare(&dir).unwrap();
+    let commit = testing::commit(&repo, "refs/heads/main", &[], &[]);
+
+    let cache = PackCache::new(&AppGitPackCache {
+        enabled: true,
+        path: root.join("cache"),
+        max_size: 1024 * 1024,
+    });
+    let want = format!("want {}\n", commit);
+    let input = format!("{:04x}{}00000009done\n", want.len() + 4, want).into_bytes();
+    let request = || PackRequest {
+        service: GitService::UploadPack,
+        path: dir.clone(),
+        protocol: None,
+        stateless: true,
+        advertise_refs: false,
+        protection: BranchProtection::default(),
+        filter: FilterPolicy::default(),
+        max_input_size: None,
+    };
+    async fn collect(body: CachedBody) -> Vec<u8> {
+        body.map(|x| x.unwrap()).collect::<Vec<_>>().await.concat()
+    }
+    let before = PackCache::stats();
+    let first = cache
+        .serve(
+            &SubprocessPack,
+            request(),
+            input.clone(),
+            "test".to_string(),
+        )
+        .await
+        .unwrap();
+    let first = collect(first).await;
+    assert!(first.starts_with(b"0008NAK\n"));
+    let second = cache
+        .serve(
+            &SubprocessPack,
+            request(),
+            input.clone(),
+            "test".to_string(),
+        )
+        .await
+        .unwrap();
+    let second = collect(second).await;
+    assert_eq!(first, second);
+    let after = PackCache::stats();
+    assert!(after.misses > before.misses);
+    assert!(after.hits > before.hits);
+    assert!(after.bytes_served >= before.bytes_served + second.len() as u64);
+
+    // a moved ref is a new key
+    testing::commit(&repo, "refs/heads/main", &[], &[commit]);
+    let key = cache_key(&request(), &input).await.unwrap();
+    assert!(!cache.cache.entry(&key).exists());
+}
@@ -0,0 +1,181 @@
+use std::io;
+use std::io::{BufRead, Write};
+use tokio::io::AsyncRead;
+
+/// Largest pkt-line payload, 65520 minus the 4 byte length prefix.
+pub const MAX_PKT_DATA: usize = 65516;
+
+pub const FLUSH_PKT: &[u8] = b"0000";
+
+pub const DELIM_PKT: &[u8] = b"0001";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Pkt {
+    Flush,
+    Delim,
+    ResponseEnd,
+    Data(Vec<u8>),
+}
+
+impl Pkt {
+    /// The payload of a data line without its trailing newline.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Pkt::Data(data) => {
+                let data = data.strip_suffix(b"\n").unwrap_or(data);
+                std::str::from_utf8(data).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut buf = format!("{:04x}", data.len() + 4).into_bytes();
+    buf.extend_from_slice(data);
+    buf
+}
+
+pub fn write_pkt<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_PKT_DATA {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "pkt-line too long",
+        ));
+    }
+    w.write_all(format!("{:04x}", data.len() + 4).as_bytes())?;
+    w.write_all(data)
+}
+
+pub fn write_flush<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(FLUSH_PKT)
+}
+
+pub struct PktReader<R> {
+    inner: R,
+}
+
+impl<R: BufRead> PktReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+    /// Reads the next packet, `None` once the stream ended cleanly between packets.
+    pub fn read_pkt(&mut self) -> io::Result<Option<Pkt>> {
+        let mut len = [0u8; 4];
+        let mut filled = 0;
+        while filled < len.len() {
+            match self.inner.read(&mut len[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+        match pkt_len(len)? {
+            0 => Ok(Some(Pkt::Flush)),
+            1 => Ok(Some(Pkt::Delim)),
+            2 => Ok(Some(Pkt::ResponseEnd)),
+            len => {
+                let mut data = vec![0u8; len - 4];
+                self.inner.read_exact(&mut data)?;
+                Ok(Some(Pkt::Data(data)))
+            }
+        }
+    }
+    /// Whatever follows the packets, e.g. the packfile of a push.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+fn pkt_len(len: [u8; 4]) -> io::Result<usize> {
+    std::str::from_utf8(&len)
+        .ok()
+        .and_then(|x| usize::from_str_radix(x, 16).ok())
+        .filter(|x| *x != 3)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad pkt-line length"))
+}
+
+/// `PktReader::read_pkt` for protocols served in-process over an async stream.
+pub async fn read_pkt_async<R: AsyncRead + Unpin>(inner: &mut R) -> io::Result<Option<Pkt>> {
+    // scoped, the blocking reader above calls `read` on a type that is not `AsyncRead`
+    use tokio::io::AsyncReadExt;
+
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match inner.read(&mut len[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    match pkt_len(len)? {
+        0 => Ok(Some(Pkt::Flush)),
+        1 => Ok(Some(Pkt::Delim)),
+        2 => Ok(Some(Pkt::ResponseEnd)),
+        len => {
+            let mut data = vec![0u8; len - 4];
+            inner.read_exact(&mut data).await?;
+            Ok(Some(Pkt::Data(data)))
+        }
+    }
+}
+
+/// Multiplexes a stream into `side-band`/`side-band-64k` packets on the given band,
+/// 1 for pack data, 2 for progress and 3 for fatal errors.
+pub struct Sideband<W> {
+    inner: W,
+    band: u8,
+    max: usize,
+}
+
+impl<W: Write> Sideband<W> {
+    pub fn new(inner: W, band: u8, large: bool) -> Self {
+        let max = if large { 65515 } else { 995 };
+        Self { inner, band, max }
+    }
+}
+
+impl<W: Write> Write for Sideband<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.max);
+        if len == 0 {
+            return Ok(0);
+        }
+        self.inner
+            .write_all(format!("{:04x}", len + 5).as_bytes())?;
+        self.inner.write_all(&[self.band])?;
+        self.inner.write_all(&buf[..len])?;
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_pkt_line() {
+    use std::io::Read;
+
+    let mut buf = encode(b"want 1234\n");
+    write_flush(&mut buf).unwrap();
+    Sideband::new(&mut buf, 2, false)
+        .write_all(&[b'x'; 1000])
+        .unwrap();
+    buf.extend_from_slice(b"PACK");
+    let mut reader = PktReader::new(io::Cursor::new(buf));
+    let pkt = reader.read_pkt().unwrap().unwrap();
+    assert_eq!(pkt.text(), Some("want 1234"));
+    assert_eq!(reader.read_pkt().unwrap(), Some(Pkt::Flush));
+    let Some(Pkt::Data(band)) = reader.read_pkt().unwrap() else {
+        panic!("expected sideband data");
+    };
+    assert_eq!((band[0], band.len()), (2, 996));
+    let Some(Pkt::Data(band)) = reader.read_pkt().unwrap() else {
+        panic!("expected sideband data");
+    };
+    assert_eq!(band.len(), 6);
+    let mut rest = vec![];
+    reader.into_inner().read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"PACK");
+}
@@ -0,0 +1,78 @@
+use actix_web::HttpRequest;
+use tokio::process::Command;
+
+pub const GIT_PROTOCOL_HEADER: &str = "Git-Protocol";
+pub const GIT_PROTOCOL_ENV: &str = "GIT_PROTOCOL";
+
+/// Sanitized `Git-Protocol` header / `GIT_PROTOCOL` env value, a colon separated list of
+/// `key` or `key=value` parameters such as `version=2`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitProtocol {
+    params: Vec<String>,
+}
+
+impl GitProtocol {
+    /// Parses a client supplied value. Malformed parameters are dropped, and so is
+    /// `version=2` when v2 is not allowed (disabled in config, or receive-pack, which has
+    /// no v2 flavour), so git falls back to v0/v1 on its own.
+    pub fn parse(value: &str, allow_v2: bool) -> Option<Self> {
+        let params = value
+            .split(':')
+            .filter(|param| {
+                let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+                    && value.chars().all(|c| c.is_ascii_graphic())
+            })
+            .filter(|param| allow_v2 || *param != "version=2")
+            .map(|param| param.to_string())
+            .collect::<Vec<_>>();
+        if params.is_empty() {
+            None
+        } else {
+            Some(Self { params })
+        }
+    }
+    pub fn from_request(request: &HttpRequest, allow_v2: bool) -> Option<Self> {
+        let value = request.headers().get(GIT_PROTOCOL_HEADER)?.to_str().ok()?;
+        Self::parse(value, allow_v2)
+    }
+    pub fn version(&self) -> u8 {
+        self.params
+            .iter()
+            .filter_map(|param| param.strip_prefix("version="))
+            .filter_map(|version| version.parse::<u8>().ok())
+            .next_back()
+            .unwrap_or(0)
+    }
+    pub fn is_v2(&self) -> bool {
+        self.version() == 2
+    }
+    pub fn value(&self) -> String {
+        self.params.join(":")
+    }
+    /// Forwards the protocol to a git subprocess. For v2 the object-info command is
+    /// advertised as well, which newer git versions keep off by default, so this has to be
+    /// applied before the subcommand is added to `cmd`.
+    pub fn apply(&self, cmd: &mut Command) {
+        if self.is_v2() {
+            cmd.arg("-c").arg("transfer.advertiseObjectInfo=true");
+        }
+        cmd.env(GIT_PROTOCOL_ENV, self.value());
+    }
+}
+
+#[test]
+fn test_git_protocol() {
+    let protocol = GitProtocol::parse("version=2", true).unwrap();
+    assert!(protocol.is_v2());
+    assert_eq!(protocol.value(), "version=2");
+    assert_eq!(GitProtocol::parse("version=2", false), None);
+    let protocol = GitProtocol::parse("version=1:version=2:bad key", false).unwrap();
+    assert_eq!(protocol.version(), 1);
+    assert_eq!(protocol.value(), "version=1");
+    let protocol = GitProtocol::parse("version=1:version=2", true).unwrap();
+    assert_eq!(protocol.version(), 2);
+}
@@ -0,0 +1,169 @@
+use crate::transport::pkt::{FLUSH_PKT, Pkt, PktReader, Sideband, encode, write_flush};
+use git2::Oid;
+use std::collections::HashSet;
+use std::io;
+use std::io::Write;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// A command list without its flush has no business being this large.
+pub const MAX_COMMANDS_SIZE: usize = 1024 * 1024;
+
+/// One `<old> <new> <ref>` line of a push.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RefCommand {
+    pub old: Oid,
+    pub new: Oid,
+    pub name: String,
+}
+
+impl RefCommand {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let old = Oid::from_str(parts.next()?).ok()?;
+        let new = Oid::from_str(parts.next()?).ok()?;
+        let name = parts.next()?.to_string();
+        Some(Self { old, new, name })
+    }
+    pub fn is_create(&self) -> bool {
+        self.old.is_zero()
+    }
+    pub fn is_delete(&self) -> bool {
+        self.new.is_zero()
+    }
+    pub fn branch(&self) -> Option<&str> {
+        self.name.strip_prefix("refs/heads/")
+    }
+}
+
+/// The command list a receive-pack request starts with, read before any pack data so a
+/// push can be turned down without accepting objects.
+#[derive(Clone, Debug, Default)]
+pub struct PushCommands {
+    pub commands: Vec<RefCommand>,
+    pub caps: HashSet<String>,
+}
+
+impl PushCommands {
+    /// Parses the command list at the start of `data`, `None` until its flush has arrived.
+    pub fn parse(data: &[u8]) -> io::Result<Option<Self>> {
+        let mut reader = PktReader::new(io::Cursor::new(data));
+        let mut result = Self::default();
+        loop {
+            let pkt = match reader.read_pkt() {
+                Ok(Some(pkt)) => pkt,
+                Ok(None) => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if pkt == Pkt::Flush {
+                return Ok(Some(result));
+            }
+            let line = pkt
+                .text()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad command"))?;
+            let (line, caps) = line.split_once('\0').unwrap_or((line, ""));
+            if result.commands.is_empty() {
+                result.caps.extend(
+                    caps.split(' ')
+                        .filter(|x| !x.is_empty())
+                        .map(|x| x.to_string()),
+                );
+            }
+            // shallow lines of a push from a shallow clone carry no command
+            if line.starts_with("shallow ") {
+                continue;
+            }
+            let command = RefCommand::parse(line)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad command"))?;
+            result.commands.push(command);
+        }
+    }
+    /// Reads from `input` until the command list is complete. Returns it, if the client
+    /// sent one at all, together with everything read so far, which still has to be fed
+    /// to receive-pack.
+    pub async fn read<R: AsyncRead + Unpin>(input: &mut R) -> io::Result<(Option<Self>, Vec<u8>)> {
+        let mut head = vec![];
+        let mut buf = vec![0u8; 8192];
+        loop {
+            if let Some(commands) = Self::parse(&head)? {
+                return Ok((Some(commands), head));
+            }
+            if head.len() > MAX_COMMANDS_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "command list too large",
+                ));
+            }
+            let read = input.read(&mut buf).await?;
+            if read == 0 {
+                return Ok((None, head));
+            }
+            head.extend_from_slice(&buf[..read]);
+        }
+    }
+    /// The response to a push turned down before receive-pack ran: `errors` go out as
+    /// `remote:` lines and every ref is reported as rejected.
+    pub fn reject(&self, errors: &[String], reason: &str) -> Vec<u8> {
+        let sideband = self.caps.contains("side-band-64k");
+        let mut out = vec![];
+        if sideband {
+            for error in errors {
+                Sideband::new(&mut out, 2, true)
+                    .write_all(format!("error: {}\n", error).as_bytes())
+                    .ok();
+            }
+        }
+        // report-status-v2 only adds option lines for accepted refs, rejections read the same
+        if self.caps.contains("report-status") || self.caps.contains("report-status-v2") {
+            let mut report = encode(b"unpack ok\n");
+            for command in &self.commands {
+                report.extend(encode(
+                    format!("ng {} {}\n", command.name, reason).as_bytes(),
+                ));
+            }
+            report.extend_from_slice(FLUSH_PKT);
+            if sideband {
+                Sideband::new(&mut out, 1, true).write_all(&report).ok();
+            } else {
+                out.extend(report);
+            }
+        }
+        if sideband {
+            write_flush(&mut out).ok();
+        }
+        out
+    }
+}
+
+#[test]
+fn test_push_commands() {
+    let zero = Oid::zero();
+    let head = "1111111111111111111111111111111111111111";
+    let mut data = encode(
+        format!("{} {} refs/heads/main\0report-status side-band-64k\n", zero, head).as_bytes(),
+    );
+    assert!(PushCommands::parse(&data).unwrap().is_none());
+    data.extend(encode(format!("{} {} refs/heads/dev\n", head, zero).as_bytes()));
+    data.extend_from_slice(FLUSH_PKT);
+    data.extend_from_slice(b"PACK");
+    let commands = PushCommands::parse(&data).unwrap().unwrap();
+    assert_eq!(commands.commands.len(), 2);
+    assert!(commands.commands[0].is_create());
+    assert!(commands.commands[1].is_delete());
+    assert_eq!(commands.commands[1].branch(), Some("dev"));
+    assert!(commands.caps.contains("report-status"));
+
+    let response = commands.reject(&["nope".to_string()], "declined");
+    let mut reader = PktReader::new(io::Cursor::new(response));
+    let Some(Pkt::Data(message)) = reader.read_pkt().unwrap() else {
+        panic!("expected progress band");
+    };
+    assert_eq!(&message[..], b"\x02error: nope\n");
+    let Some(Pkt::Data(report)) = reader.read_pkt().unwrap() else {
+        panic!("expected report band");
+    };
+    let report = String::from_utf8_lossy(&report[1..]).to_string();
+    assert!(report.contains("ng refs/heads/main declined\n"));
+    assert!(report.contains("ng refs/heads/dev declined\n"));
+    assert_eq!(reader.read_pkt().unwrap(), Some(Pkt::Flush));
+}
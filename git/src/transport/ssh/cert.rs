@@ -0,0 +1,354 @@
+use crate::transport::ssh::key::check_key_policy;
+use russh::keys::ssh_key::certificate::CertType;
+use russh::keys::ssh_key::public::KeyData;
+use russh::keys::ssh_key::{Certificate, HashAlg, PublicKey};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+const KRL_MAGIC: &[u8] = b"SSHKRL\n\0";
+const KRL_SECTION_CERTIFICATES: u8 = 1;
+const KRL_SECTION_EXPLICIT_KEY: u8 = 2;
+const KRL_SECTION_SIGNATURE: u8 = 4;
+const KRL_SECTION_FINGERPRINT_SHA256: u8 = 5;
+const KRL_CERT_SERIAL_LIST: u8 = 0x20;
+const KRL_CERT_SERIAL_RANGE: u8 = 0x21;
+const KRL_CERT_SERIAL_BITMAP: u8 = 0x22;
+const KRL_CERT_KEY_ID: u8 = 0x23;
+
+/// Certificates revoked for one CA, or for any CA without `ca`.
+#[derive(Clone, Debug, Default)]
+struct KrlCerts {
+    ca: Option<Vec<u8>>,
+    serials: Vec<RangeInclusive<u64>>,
+    key_ids: Vec<String>,
+}
+
+/// An OpenSSH key revocation list as `ssh-keygen -k` writes it. SHA1 fingerprint sections
+/// are skipped, `ssh-keygen` only writes them when asked to.
+#[derive(Clone, Debug, Default)]
+pub struct Krl {
+    certs: Vec<KrlCerts>,
+    keys: Vec<Vec<u8>>,
+    sha256: Vec<Vec<u8>>,
+}
+
+struct KrlReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> KrlReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("KRL is truncated".to_string());
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(
+            self.take(4)?.try_into().unwrap_or_default(),
+        ))
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(
+            self.take(8)?.try_into().unwrap_or_default(),
+        ))
+    }
+    fn string(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Krl {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut reader = KrlReader { data };
+        if reader.take(KRL_MAGIC.len())? != KRL_MAGIC {
+            return Err("not an OpenSSH KRL".to_string());
+        }
+        let version = reader.u32()?;
+        if version != 1 {
+            return Err(format!("unsupported KRL format {}", version));
+        }
+        // krl version, generation date and flags
+        reader.take(24)?;
+        reader.string()?;
+        reader.string()?;
+        let mut krl = Krl::default();
+        while !reader.is_empty() {
+            let kind = reader.u8()?;
+            let mut section = KrlReader {
+                data: reader.string()?,
+            };
+            match kind {
+                KRL_SECTION_CERTIFICATES => krl.certs.push(KrlCerts::parse(&mut section)?),
+                KRL_SECTION_EXPLICIT_KEY => {
+                    while !section.is_empty() {
+                        krl.keys.push(section.string()?.to_vec());
+                    }
+                }
+                KRL_SECTION_FINGERPRINT_SHA256 => {
+                    while !section.is_empty() {
+                        krl.sha256.push(section.string()?.to_vec());
+                    }
+                }
+                // signatures come last and are not checked, the file is trusted as configured
+                KRL_SECTION_SIGNATURE => break,
+                _ => {}
+            }
+        }
+        Ok(krl)
+    }
+    /// The configured list, empty without one. A list that cannot be read fails every
+    /// sign-in rather than letting revoked keys back in.
+    pub async fn load(path: Option<&Path>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Krl::default());
+        };
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| format!("reading KRL {}: {}", path.display(), e))?;
+        Krl::parse(&data)
+    }
+    pub fn key_revoked(&self, key: &KeyData) -> bool {
+        let Ok(blob) = PublicKey::from(key.clone()).to_bytes() else {
+            return true;
+        };
+        let hash = Sha256::digest(&blob).to_vec();
+        self.keys.contains(&blob) || self.sha256.contains(&hash)
+    }
+    /// A certificate is revoked by its serial or key id, or when its own key or the CA key
+    /// is.
+    pub fn cert_revoked(&self, cert: &Certificate) -> bool {
+        if self.key_revoked(cert.public_key()) || self.key_revoked(cert.signature_key()) {
+            return true;
+        }
+        let ca = PublicKey::from(cert.signature_key().clone())
+            .to_bytes()
+            .unwrap_or_default();
+        self.certs
+            .iter()
+            .filter(|x| x.ca.as_ref().is_none_or(|x| *x == ca))
+            .any(|x| {
+                x.serials.iter().any(|range| range.contains(&cert.serial()))
+                    || x.key_ids.iter().any(|id| id == cert.key_id())
+            })
+    }
+}
+
+impl KrlCerts {
+    fn parse(section: &mut KrlReader) -> Result<Self, String> {
+        let ca = section.string()?;
+        section.string()?;
+        let mut certs = KrlCerts {
+            ca: (!ca.is_empty()).then(|| ca.to_vec()),
+            ..Default::default()
+        };
+        while !section.is_empty() {
+            let kind = section.u8()?;
+            let mut data = KrlReader {
+                data: section.string()?,
+            };
+            match kind {
+                KRL_CERT_SERIAL_LIST => {
+                    while !data.is_empty() {
+                        let serial = data.u64()?;
+                        certs.serials.push(serial..=serial);
+                    }
+                }
+                KRL_CERT_SERIAL_RANGE => {
+                    let (min, max) = (data.u64()?, data.u64()?);
+                    certs.serials.push(min..=max);
+                }
+                KRL_CERT_SERIAL_BITMAP => {
+                    let offset = data.u64()?;
+                    // an mpint, bit n set revokes serial `offset + n`
+                    let bitmap = data.string()?;
+                    for (index, byte) in bitmap.iter().rev().enumerate() {
+                        for bit in 0..8 {
+                            if byte >> bit & 1 == 1 {
+                                let serial = offset.saturating_add(index as u64 * 8 + bit);
+                                certs.serials.push(serial..=serial);
+                            }
+                        }
+                    }
+                }
+                KRL_CERT_KEY_ID => {
+                    while !data.is_empty() {
+                        certs
+                            .key_ids
+                            .push(String::from_utf8_lossy(data.string()?).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(certs)
+    }
+}
+
+/// Whether `peer` is in a `source-address` list of addresses and CIDR blocks. `None` for a
+/// list that does not parse.
+fn source_address_allowed(list: &str, peer: IpAddr) -> Option<bool> {
+    let peer = peer.to_canonical();
+    for item in list.split(',') {
+        let (addr, prefix) = match item.trim().split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse().ok()?)),
+            None => (item.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let matched = match (addr, peer) {
+            (IpAddr::V4(addr), IpAddr::V4(peer)) => {
+                let prefix: u32 = prefix.unwrap_or(32);
+                if prefix > 32 {
+                    return None;
+                }
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(addr) & mask == u32::from(peer) & mask
+            }
+            (IpAddr::V6(addr), IpAddr::V6(peer)) => {
+                let prefix: u32 = prefix.unwrap_or(128);
+                if prefix > 128 {
+                    return None;
+                }
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                u128::from(addr) & mask == u128::from(peer) & mask
+            }
+            _ => false,
+        };
+        if matched {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// Checks a user certificate against the CA that signed it: type, signature, validity
+/// window at `now` and the critical options. Principals are mapped to users by the caller.
+pub fn check_certificate(
+    cert: &Certificate,
+    now: u64,
+    peer: Option<SocketAddr>,
+    krl: &Krl,
+) -> Result<(), String> {
+    if cert.cert_type() != CertType::User {
+        return Err("not a user certificate".to_string());
+    }
+    check_key_policy(cert.public_key())?;
+    check_key_policy(cert.signature_key())?;
+    let ca = cert.signature_key().fingerprint(HashAlg::Sha256);
+    cert.validate_at(now, [&ca])
+        .map_err(|e| format!("certificate is not valid: {}", e))?;
+    for (name, value) in cert.critical_options().iter() {
+        match name.as_str() {
+            "source-address" => {
+                let allowed = peer.and_then(|peer| source_address_allowed(value, peer.ip()));
+                if allowed != Some(true) {
+                    return Err("certificate is not valid from this address".to_string());
+                }
+            }
+            // force-command and verify-required cannot be honoured by a git server
+            name => return Err(format!("unsupported critical option {}", name)),
+        }
+    }
+    if krl.cert_revoked(cert) {
+        return Err(format!("certificate serial {} is revoked", cert.serial()));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_source_address() {
+    let peer = |x: &str| x.parse::<IpAddr>().unwrap();
+    assert_eq!(
+        source_address_allowed("10.0.0.0/8,192.168.1.7", peer("10.20.30.40")),
+        Some(true)
+    );
+    assert_eq!(
+        source_address_allowed("10.0.0.0/8,192.168.1.7", peer("192.168.1.8")),
+        Some(false)
+    );
+    assert_eq!(
+        source_address_allowed("10.0.0.0/8", peer("::ffff:10.1.1.1")),
+        Some(true)
+    );
+    assert_eq!(
+        source_address_allowed("2001:db8::/32", peer("2001:db8::1")),
+        Some(true)
+    );
+    assert_eq!(
+        source_address_allowed("example.com", peer("10.1.1.1")),
+        None
+    );
+}
+
+#[test]
+fn test_krl() {
+    use rand_core::OsRng;
+    use russh::keys::PrivateKey;
+    use russh::keys::ssh_key::{Algorithm, certificate};
+
+    fn string(data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+    fn section(kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![kind];
+        out.extend(string(data));
+        out
+    }
+    let ca = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+    let user = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+    let cert = |serial: u64| {
+        let mut builder = certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            user.public_key().key_data().clone(),
+            100,
+            200,
+        )
+        .unwrap();
+        builder.serial(serial).unwrap();
+        builder.key_id("ci-runner").unwrap();
+        builder.cert_type(CertType::User).unwrap();
+        builder.valid_principal("alice").unwrap();
+        builder.sign(&ca).unwrap()
+    };
+
+    let mut serials = section(
+        KRL_CERT_SERIAL_RANGE,
+        &[5u64.to_be_bytes(), 9u64.to_be_bytes()].concat(),
+    );
+    // bits 1 and 3 above offset 20
+    serials.extend(section(
+        KRL_CERT_SERIAL_BITMAP,
+        &[20u64.to_be_bytes().to_vec(), string(&[0b1010])].concat(),
+    ));
+    let mut certs = string(&ca.public_key().to_bytes().unwrap());
+    certs.extend(string(b""));
+    certs.extend(serials);
+    let mut data = KRL_MAGIC.to_vec();
+    data.extend(1u32.to_be_bytes());
+    data.extend([0u8; 24]);
+    data.extend(string(b""));
+    data.extend(string(b"test"));
+    data.extend(section(KRL_SECTION_CERTIFICATES, &certs));
+    let krl = Krl::parse(&data).unwrap();
+
+    assert!(check_certificate(&cert(4), 150, None, &krl).is_ok());
+    assert!(check_certificate(&cert(7), 150, None, &krl).is_err());
+    assert!(check_certificate(&cert(21), 150, None, &krl).is_err());
+    assert!(check_certificate(&cert(22), 150, None, &krl).is_ok());
+    assert!(check_certificate(&cert(23), 150, None, &krl).is_err());
+    // outside of the validity window
+    assert!(check_certificate(&cert(4), 250, None, &krl).is_err());
+    assert!(Krl::parse(b"not a krl").is_err());
+}
@@ -0,0 +1,238 @@
+use crate::service::permissions::RepoAccess;
+use crate::transport::ssh::handle::SSHandle;
+use database::entity::git_repo;
+use russh::server::Session;
+use russh::{ChannelId, CryptoVec};
+use sea_orm::sqlx::types::chrono::{Duration, Utc};
+use tracing::error;
+
+/// Days an access token from `personal-access-token` stays valid unless asked otherwise.
+pub const TOKEN_DEFAULT_DAYS: i64 = 30;
+const TOKEN_MAX_DAYS: i64 = 365;
+
+const HELP: &str = "\
+Commands:
+  whoami                 show who this key signs in as
+  info                   list the repositories this key can reach
+  personal-access-token <title> [--read | --write] [--expires-in <days>]
+                         create an access token for git over HTTP
+  help                   show this message
+";
+
+/// Commands the SSH server answers itself, without spawning git.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SshCommand {
+    Help,
+    Whoami,
+    Info,
+    PersonalAccessToken {
+        title: String,
+        access: RepoAccess,
+        days: i64,
+    },
+}
+
+impl SshCommand {
+    /// `None` when the command is not one of these, such as the git commands. Bad
+    /// arguments give the message for the user.
+    pub fn parse(cmd: &str) -> Option<Result<Self, String>> {
+        let mut parts = cmd.split_whitespace();
+        let command = match parts.next()? {
+            "help" => Ok(SshCommand::Help),
+            "whoami" => Ok(SshCommand::Whoami),
+            "info" => Ok(SshCommand::Info),
+            "personal-access-token" => parse_token_args(&mut parts),
+            _ => return None,
+        };
+        if let Some(extra) = parts.next() {
+            return Some(Err(format!("Unexpected argument: {}", extra)));
+        }
+        Some(command)
+    }
+}
+
+fn parse_token_args<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Result<SshCommand, String> {
+    let usage =
+        "Usage: personal-access-token <title> [--read | --write] [--expires-in <days>]".to_string();
+    let mut title = None;
+    let mut access = RepoAccess::Read;
+    let mut days = TOKEN_DEFAULT_DAYS;
+    while let Some(arg) = parts.next() {
+        match arg {
+            "--read" => access = RepoAccess::Read,
+            "--write" => access = RepoAccess::Write,
+            "--expires-in" => {
+                days = match parts.next().and_then(|x| x.parse::<i64>().ok()) {
+                    Some(days) if (1..=TOKEN_MAX_DAYS).contains(&days) => days,
+                    _ => {
+                        return Err(format!(
+                            "--expires-in takes between 1 and {} days",
+                            TOKEN_MAX_DAYS
+                        ));
+                    }
+                }
+            }
+            _ if arg.starts_with('-') || title.is_some() => return Err(usage),
+            _ => title = Some(arg.to_string()),
+        }
+    }
+    let title = title.ok_or(usage)?;
+    Ok(SshCommand::PersonalAccessToken {
+        title,
+        access,
+        days,
+    })
+}
+
+fn access_label(access: RepoAccess) -> &'static str {
+    match access {
+        RepoAccess::Write => "read-write",
+        RepoAccess::Read => "read-only",
+        RepoAccess::None => "none",
+    }
+}
+
+impl SSHandle {
+    /// Who the session signed in as: the username, or the repositories of a deploy key.
+    pub(crate) async fn identity(&self) -> String {
+        if let Some(user) = &self.operator {
+            return user.username.clone();
+        }
+        self.key_repos()
+            .await
+            .iter()
+            .map(|(repo, _)| format!("{}/{}", repo.namespace, repo.repo_name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    async fn key_repos(&self) -> Vec<(git_repo::Model, RepoAccess)> {
+        let repos = match &self.operator {
+            Some(user) => self.app.accessible_repos(user).await,
+            None => self.app.deploy_key_repos(&self.deploy_keys).await,
+        };
+        repos.unwrap_or_else(|e| {
+            error!("Repository listing failed: {}", e.msg);
+            vec![]
+        })
+    }
+    pub(crate) async fn command_exec(
+        &mut self,
+        channel: ChannelId,
+        command: Result<SshCommand, String>,
+        session: &mut Session,
+    ) -> Result<(), russh::Error> {
+        session.channel_success(channel).ok();
+        let output = match command {
+            Ok(command) => self.command_output(command).await,
+            Err(msg) => Err(msg),
+        };
+        let status = match output {
+            Ok(out) => {
+                session
+                    .data(channel, CryptoVec::from(out.into_bytes()))
+                    .ok();
+                0
+            }
+            Err(msg) => {
+                let msg = CryptoVec::from(format!("{}\n", msg).into_bytes());
+                session.extended_data(channel, 1, msg).ok();
+                1
+            }
+        };
+        session.exit_status_request(channel, status).ok();
+        session.eof(channel).ok();
+        session.close(channel).ok();
+        Ok(())
+    }
+    async fn command_output(&self, command: SshCommand) -> Result<String, String> {
+        match command {
+            SshCommand::Help => Ok(HELP.to_string()),
+            SshCommand::Whoami => match &self.operator {
+                Some(user) => Ok(format!("{}\n", user.username)),
+                None => Ok(self
+                    .key_repos()
+                    .await
+                    .iter()
+                    .map(|(repo, access)| {
+                        format!(
+                            "deploy key for {}/{} ({})\n",
+                            repo.namespace,
+                            repo.repo_name,
+                            access_label(*access)
+                        )
+                    })
+                    .collect()),
+            },
+            SshCommand::Info => {
+                let mut out = format!("Hi {}! You can reach:\n\n", self.identity().await);
+                for (repo, access) in self.key_repos().await {
+                    let access = match access {
+                        RepoAccess::Write => "RW",
+                        _ => "R ",
+                    };
+                    out.push_str(&format!(
+                        " {}  {}/{}\n",
+                        access, repo.namespace, repo.repo_name
+                    ));
+                }
+                Ok(out)
+            }
+            SshCommand::PersonalAccessToken {
+                title,
+                access,
+                days,
+            } => {
+                let Some(user) = &self.operator else {
+                    return Err("Deploy keys cannot create access tokens".to_string());
+                };
+                let expiration = Utc::now().date_naive() + Duration::days(days);
+                let token = self
+                    .app
+                    .access_key_issue(user, &title, access, expiration)
+                    .await
+                    .map_err(|e| e.msg)?;
+                Ok(format!(
+                    "{}\n\nThis {} token expires on {} and will not be shown again.\n",
+                    token,
+                    access_label(access),
+                    expiration.format("%Y-%m-%d")
+                ))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse_ssh_command() {
+    assert_eq!(SshCommand::parse("whoami"), Some(Ok(SshCommand::Whoami)));
+    assert_eq!(SshCommand::parse(" info "), Some(Ok(SshCommand::Info)));
+    assert_eq!(
+        SshCommand::parse("personal-access-token ci --write --expires-in 7"),
+        Some(Ok(SshCommand::PersonalAccessToken {
+            title: "ci".to_string(),
+            access: RepoAccess::Write,
+            days: 7
+        }))
+    );
+    assert_eq!(
+        SshCommand::parse("personal-access-token laptop"),
+        Some(Ok(SshCommand::PersonalAccessToken {
+            title: "laptop".to_string(),
+            access: RepoAccess::Read,
+            days: TOKEN_DEFAULT_DAYS
+        }))
+    );
+    assert!(matches!(
+        SshCommand::parse("personal-access-token"),
+        Some(Err(_))
+    ));
+    assert!(matches!(
+        SshCommand::parse("personal-access-token ci --expires-in 9999"),
+        Some(Err(_))
+    ));
+    assert!(matches!(SshCommand::parse("whoami me"), Some(Err(_))));
+    assert_eq!(
+        SshCommand::parse("git-upload-pack 'alice/assets.git'"),
+        None
+    );
+}
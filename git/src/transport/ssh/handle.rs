@@ -1,41 +1,175 @@
 use crate::GitContext;
+use crate::lfs::LfsOperation;
 use crate::service::GitServer;
-use database::entity::{git_repo, users};
+use crate::service::limit::PackClient;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::permissions::RepoAccess;
+use crate::service::protection::{BranchProtection, PROTECTED_REASON};
+use crate::service::quota::{PushQuota, QUOTA_REASON};
+use crate::transport::GitService;
+use crate::transport::backend::{PackIo, PackRequest};
+use crate::transport::pkt::encode;
+use crate::transport::protocol::GIT_PROTOCOL_ENV;
+use crate::transport::push::{MAX_COMMANDS_SIZE, PushCommands, RefCommand};
+use crate::transport::ssh::cert::{Krl, check_certificate};
+use crate::transport::ssh::command::SshCommand;
+use crate::transport::ssh::key::{check_key_policy, key_fingerprint};
+use crate::transport::ssh::lfs::LfsCommand;
+use crate::transport::ssh::upload_pack::ExecPack;
+use database::entity::{deploy_keys, git_repo, users};
 use russh::keys::PublicKey;
+use russh::keys::ssh_key::Certificate;
 use russh::server::{Auth, Handle, Msg, Session};
-use russh::{Channel, ChannelId, CryptoVec, Disconnect, MethodKind, MethodSet};
+use russh::{Channel, ChannelId, CryptoVec, Disconnect, MethodKind, MethodSet, Pty};
+use sea_orm::prelude::Uuid;
 use std::collections::HashMap;
 use std::io;
-use std::path::PathBuf;
-use std::process::{ExitStatus, Stdio};
-use std::str::FromStr;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
-use tokio::process::ChildStdin;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc::Sender;
-use tracing::error;
+use tokio::sync::oneshot;
+use tracing::{error, info};
 
 pub struct SSHandle {
     pub app: GitServer,
-    pub stdin: HashMap<ChannelId, ChildStdin>,
+    pub stdin: HashMap<ChannelId, Box<dyn AsyncWrite + Send + Unpin>>,
     pub eof: HashMap<ChannelId, Sender<bool>>,
+    pub protocol: HashMap<ChannelId, String>,
+    pub push: HashMap<ChannelId, PushGate>,
     pub branch: Option<String>,
     pub repo: Option<git_repo::Model>,
     pub service: Option<GitService>,
     pub operator: Option<users::Model>,
+    /// Set instead of `operator` when the client authenticated with a deploy key.
+    pub deploy_keys: Vec<deploy_keys::Model>,
+    pub peer: Option<SocketAddr>,
 }
 
 impl SSHandle {
-    pub fn new(app: GitServer) -> Self {
+    pub fn new(app: GitServer, peer: Option<SocketAddr>) -> Self {
         Self {
             app,
             stdin: HashMap::new(),
             eof: HashMap::new(),
+            protocol: HashMap::new(),
+            push: HashMap::new(),
             branch: None,
             repo: None,
             service: None,
             operator: None,
+            deploy_keys: vec![],
+            peer,
         }
     }
+    /// The repository an exec request names with the user acting on it and their access,
+    /// once that is known to be at least `need`. The session is disconnected otherwise.
+    pub(crate) async fn exec_repo(
+        &mut self,
+        path: &str,
+        need: RepoAccess,
+        session: &mut Session,
+    ) -> Result<(git_repo::Model, users::Model, RepoAccess), russh::Error> {
+        let (owner, repo) = match parse_repo_path(path) {
+            Some(pair) => pair,
+            None => {
+                let msg = format!("Invalid repository path: {}", path);
+                error!("{}", msg);
+                session
+                    .disconnect(Disconnect::ServiceNotAvailable, &msg, "")
+                    .ok();
+                return Err(russh::Error::Disconnect);
+            }
+        };
+        let repo = repo.replace(".git", "");
+        let repo = match self.app.find_repo(owner, &repo).await {
+            Ok(repo) => repo,
+            Err(e) => {
+                error!("Repository lookup failed: {}", e.msg);
+                session
+                    .disconnect(Disconnect::ByApplication, "Repository not found", "")
+                    .ok();
+                return Err(russh::Error::Disconnect);
+            }
+        };
+        self.repo = Some(repo.clone());
+        // a deploy key acts as the user who added it, limited to its own repository
+        let deploy_key = match &self.operator {
+            Some(_) => None,
+            None => self.deploy_keys.iter().find(|x| x.repo_uid == repo.uid),
+        };
+        let (operator, access) = match (&self.operator, deploy_key) {
+            (Some(user), _) => (user.clone(), self.app.repo_access(&repo, Some(user)).await),
+            (None, Some(key)) => match self.app.deploy_key_access(key, &repo).await {
+                Ok((access, creator)) => (creator, access),
+                Err(e) => {
+                    error!("Deploy key lookup failed: {}", e.msg);
+                    session
+                        .disconnect(Disconnect::ByApplication, "Authentication error", "")
+                        .ok();
+                    return Err(russh::Error::Disconnect);
+                }
+            },
+            (None, None) if !self.deploy_keys.is_empty() => {
+                session
+                    .disconnect(Disconnect::ByApplication, "Access denied", "")
+                    .ok();
+                return Err(russh::Error::Disconnect);
+            }
+            (None, None) => {
+                session
+                    .disconnect(Disconnect::ByApplication, "Authentication error", "")
+                    .ok();
+                return Err(russh::Error::Disconnect);
+            }
+        };
+        if access < need {
+            session
+                .disconnect(Disconnect::ByApplication, "Access denied", "")
+                .ok();
+            return Err(russh::Error::Disconnect);
+        }
+        if let Some(key) = deploy_key
+            && let Err(e) = self.app.deploy_key_used(key.uid).await
+        {
+            error!("Deploy key update failed: {}", e.msg);
+        }
+        Ok((repo, operator, access))
+    }
+    /// Ends a push before receive-pack sees its commands, an empty command list makes it
+    /// exit without touching refs.
+    async fn stop_receive_pack(&mut self, channel: ChannelId) {
+        if let Some(mut stdin) = self.stdin.remove(&channel) {
+            stdin.write_all(b"0000").await.ok();
+            stdin.shutdown().await.ok();
+        }
+    }
+    fn track_clone(&self, repo: git_repo::Model) {
+        let status = self.app.clone();
+        tokio::spawn(async move {
+            let repo = repo.clone();
+            match status.find_repo_owner(repo.clone()).await {
+                Ok(owner) => {
+                    status
+                        .inner_add_interaction_clone(owner.uid, repo.uid)
+                        .await
+                        .ok();
+                }
+                Err(_) => {}
+            }
+        });
+    }
+}
+
+/// Holds back the start of a push until its command list is complete, so protected
+/// branches can be checked before receive-pack accepts any objects. The commands are
+/// handed on for the database sync after receive-pack exits.
+pub struct PushGate {
+    pub head: Vec<u8>,
+    pub protection: BranchProtection,
+    pub pusher: Uuid,
+    pub quota: PushQuota,
+    pub commands: oneshot::Sender<Vec<RefCommand>>,
 }
 
 impl russh::server::Handler for SSHandle {
@@ -67,18 +201,68 @@ impl russh::server::Handler for SSHandle {
         if user != "git" {
             return Err(russh::Error::NotAuthenticated);
         }
-        let public = public_key.to_string();
-        if public.len() < 32 {
+        if let Err(e) = check_key_policy(public_key.key_data()) {
+            info!("Public key rejected: {}", e);
+            return Err(russh::Error::NotAuthenticated);
+        }
+        match Krl::load(self.app.config.ssh.revoked_keys.as_deref()).await {
+            Ok(krl) if !krl.key_revoked(public_key.key_data()) => {}
+            Ok(_) => return Err(russh::Error::NotAuthenticated),
+            Err(e) => {
+                error!("{}", e);
+                return Err(russh::Error::NotAuthenticated);
+            }
+        }
+        let fingerprint = key_fingerprint(public_key.key_data());
+        if let Ok((key, model)) = self.app.find_ssh_key_owner(&fingerprint).await {
+            if let Err(e) = self.app.ssh_key_used(key.uid).await {
+                error!("SSH key update failed: {}", e.msg);
+            }
+            self.operator = Some(model);
+            return Ok(Auth::Accept);
+        }
+        match self.app.find_deploy_keys(&fingerprint).await {
+            Ok(keys) if !keys.is_empty() => {
+                self.deploy_keys = keys;
+                Ok(Auth::Accept)
+            }
+            _ => Err(russh::Error::NotAuthenticated),
+        }
+    }
+
+    async fn auth_openssh_certificate(
+        &mut self,
+        user: &str,
+        certificate: &Certificate,
+    ) -> Result<Auth, Self::Error> {
+        if user != "git" {
             return Err(russh::Error::NotAuthenticated);
         }
-        let model = match self.app.find_ssh_key_owner(&public).await {
-            Ok(model) => model,
-            Err(_) => {
+        let krl = match Krl::load(self.app.config.ssh.revoked_keys.as_deref()).await {
+            Ok(krl) => krl,
+            Err(e) => {
+                error!("{}", e);
                 return Err(russh::Error::NotAuthenticated);
             }
         };
-        self.operator = Some(model);
-        Ok(Auth::Accept)
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        if let Err(e) = check_certificate(certificate, now, self.peer, &krl) {
+            info!("Certificate {} rejected: {}", certificate.key_id(), e);
+            return Err(russh::Error::NotAuthenticated);
+        }
+        match self.app.find_certificate_owner(certificate).await {
+            Ok(model) => {
+                self.operator = Some(model);
+                Ok(Auth::Accept)
+            }
+            Err(e) => {
+                info!("Certificate {} rejected: {}", certificate.key_id(), e.msg);
+                Err(russh::Error::NotAuthenticated)
+            }
+        }
     }
 
     async fn channel_close(
@@ -89,6 +273,8 @@ impl russh::server::Handler for SSHandle {
         if let Some(mut stdin) = self.stdin.remove(&channel) {
             let _ = stdin.shutdown().await;
         }
+        self.protocol.remove(&channel);
+        self.push.remove(&channel);
         Ok(())
     }
     async fn channel_eof(
@@ -113,14 +299,113 @@ impl russh::server::Handler for SSHandle {
         Ok(true)
     }
 
+    async fn env_request(
+        &mut self,
+        channel: ChannelId,
+        variable_name: &str,
+        variable_value: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // git only asks OpenSSH to forward GIT_PROTOCOL, anything else is ignored
+        if variable_name == GIT_PROTOCOL_ENV {
+            self.protocol.insert(channel, variable_value.to_string());
+            session.channel_success(channel).ok();
+        } else {
+            session.channel_failure(channel).ok();
+        }
+        Ok(())
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _: &str,
+        _: u32,
+        _: u32,
+        _: u32,
+        _: u32,
+        _: &[(Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // `ssh -t` asks for one, the shell only prints the greeting into it
+        session.channel_success(channel).ok();
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel).ok();
+        let greeting = format!(
+            "Hi {}! You've successfully authenticated, but GitDataAI does not provide shell access.\r\n",
+            self.identity().await
+        );
+        session
+            .data(channel, CryptoVec::from(greeting.into_bytes()))
+            .ok();
+        session.exit_status_request(channel, 0).ok();
+        session.eof(channel).ok();
+        session.close(channel).ok();
+        Ok(())
+    }
+
     async fn data(
         &mut self,
         channel: ChannelId,
         data: &[u8],
-        _: &mut Session,
+        session: &mut Session,
     ) -> Result<(), Self::Error> {
+        let mut data = data.to_vec();
+        if let Some(gate) = self.push.get_mut(&channel) {
+            gate.head.extend_from_slice(&data);
+            let commands = match PushCommands::parse(&gate.head) {
+                Ok(None) if gate.head.len() <= MAX_COMMANDS_SIZE => return Ok(()),
+                Ok(Some(commands)) => Ok(commands),
+                Ok(None) => Err("command list too large".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            let Some(gate) = self.push.remove(&channel) else {
+                return Ok(());
+            };
+            data = gate.head;
+            // commands that cannot be checked never reach receive-pack, as over HTTP
+            let commands = match commands {
+                Ok(commands) => commands,
+                Err(e) => {
+                    self.stop_receive_pack(channel).await;
+                    session
+                        .extended_data(
+                            channel,
+                            1,
+                            CryptoVec::from(format!("error: {}\n", e).into_bytes()),
+                        )
+                        .ok();
+                    return Ok(());
+                }
+            };
+            let mut errors = gate
+                .protection
+                .check_commands(&commands.commands, Some(gate.pusher));
+            let mut reason = PROTECTED_REASON;
+            if errors.is_empty()
+                && let Some(error) = gate.quota.check(&commands.commands, None)
+            {
+                errors.push(error);
+                reason = QUOTA_REASON;
+            }
+            if !errors.is_empty() {
+                self.stop_receive_pack(channel).await;
+                session
+                    .data(channel, CryptoVec::from(commands.reject(&errors, reason)))
+                    .ok();
+                return Ok(());
+            }
+            gate.commands.send(commands.commands).ok();
+        }
         if let Some(stdin) = self.stdin.get_mut(&channel) {
-            let _ = stdin.write_all(data).await;
+            let _ = stdin.write_all(&data).await;
             stdin.flush().await.ok();
         }
         Ok(())
@@ -142,63 +427,50 @@ impl russh::server::Handler for SSHandle {
                 return Err(russh::Error::Disconnect);
             }
         };
+        if let Some(command) = SshCommand::parse(git_shell_cmd) {
+            return self.command_exec(channel_id, command, session).await;
+        }
+        if let Some((command, path, operation)) = parse_lfs_command(git_shell_cmd) {
+            return self
+                .lfs_exec(channel_id, command, path, operation, session)
+                .await;
+        }
         let (service, path) = match parse_git_command(git_shell_cmd) {
             Some((s, p)) => (s, p),
             None => {
-                let msg = format!("Invalid git command: {}", git_shell_cmd);
-                error!("{}", msg);
-                session
-                    .disconnect(Disconnect::ServiceNotAvailable, &msg, "")
-                    .ok();
-                return Err(russh::Error::Disconnect);
+                let msg = format!(
+                    "Unknown command: {}\nRun `help` for the commands available over SSH.",
+                    git_shell_cmd
+                );
+                return self.command_exec(channel_id, Err(msg), session).await;
             }
         };
         self.service = Some(service);
-        let (owner, repo) = match parse_repo_path(path) {
-            Some(pair) => pair,
-            None => {
-                let msg = format!("Invalid repository path: {}", path);
-                error!("{}", msg);
-                session
-                    .disconnect(Disconnect::ServiceNotAvailable, &msg, "")
-                    .ok();
-                return Err(russh::Error::Disconnect);
-            }
-        };
-        let repo = repo.replace(".git", "");
-        let repo = match self.app.find_repo(&owner, &repo).await {
-            Ok(repo) => repo,
-            Err(e) => {
-                error!("Repository lookup failed: {}", e.msg);
-                session
-                    .disconnect(Disconnect::ByApplication, "Repository not found", "")
-                    .ok();
-                return Err(russh::Error::Disconnect);
-            }
+        let need = match service {
+            GitService::UploadPack | GitService::UploadArchive => RepoAccess::Read,
+            GitService::ReceivePack => RepoAccess::Write,
         };
-        self.repo = Some(repo.clone());
-        let operator = match &self.operator {
-            Some(user) => user,
-            None => {
-                session
-                    .disconnect(Disconnect::ByApplication, "Authentication error", "")
-                    .ok();
-                return Err(russh::Error::Disconnect);
-            }
-        };
-        if !self
+        let (repo, operator, _) = self.exec_repo(path, need, session).await?;
+        let permit = match self
             .app
-            .ssh_permissions(repo.clone(), operator.clone())
+            .pack_permit(PackClient::User(operator.uid), repo.uid)
             .await
         {
-            session
-                .disconnect(Disconnect::ByApplication, "Access denied", "")
-                .ok();
-            return Err(russh::Error::Disconnect);
-        }
+            Ok(permit) => permit,
+            Err(limited) => {
+                // git prints an ERR packet as `fatal: remote error: ...`
+                session.channel_success(channel_id).ok();
+                let msg = encode(format!("ERR {}\n", limited).as_bytes());
+                session.data(channel_id, CryptoVec::from(msg)).ok();
+                session.exit_status_request(channel_id, 1).ok();
+                session.eof(channel_id).ok();
+                session.close(channel_id).ok();
+                return Ok(());
+            }
+        };
 
-        let path = match GitContext::try_from((repo.clone(), self.app.config.git.clone())) {
-            Ok(path) => path.path_dir,
+        let git = match GitContext::try_from((repo.clone(), self.app.config.git.clone())) {
+            Ok(git) => git,
             Err(e) => {
                 error!("Git context build failed: {:?}", e);
                 session
@@ -207,16 +479,89 @@ impl russh::server::Handler for SSHandle {
                 return Err(russh::Error::Disconnect);
             }
         };
-        let mut cmd = build_git_command(service, path);
-        let mut shell = match cmd
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-        {
-            Ok(shell) => {
+        let protection = match service {
+            GitService::ReceivePack => {
+                match self.app.branch_protection(&repo, Some(operator.uid)).await {
+                    Ok(protection) => protection,
+                    Err(e) => {
+                        error!("Branch protection lookup failed: {}", e.msg);
+                        session
+                            .disconnect(Disconnect::ByApplication, "Internal error", "")
+                            .ok();
+                        return Err(russh::Error::Disconnect);
+                    }
+                }
+            }
+            _ => BranchProtection::default(),
+        };
+        let quota = match service {
+            GitService::ReceivePack => match self.app.push_quota(&repo).await {
+                Ok(quota) => quota,
+                Err(e) => {
+                    error!("Quota lookup failed: {}", e.msg);
+                    session
+                        .disconnect(Disconnect::ByApplication, "Internal error", "")
+                        .ok();
+                    return Err(russh::Error::Disconnect);
+                }
+            },
+            _ => PushQuota::default(),
+        };
+        let lock = match service {
+            GitService::ReceivePack => match self.app.lock_repo_on(&repo).await {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    session
+                        .disconnect(Disconnect::ByApplication, &e.msg, "")
+                        .ok();
+                    return Err(russh::Error::Disconnect);
+                }
+            },
+            _ => None,
+        };
+        let (commands_tx, mut commands_rx) = oneshot::channel();
+        if service == GitService::ReceivePack {
+            self.push.insert(
+                channel_id,
+                PushGate {
+                    head: vec![],
+                    protection: protection.clone(),
+                    pusher: operator.uid,
+                    quota,
+                    commands: commands_tx,
+                },
+            );
+        }
+        let filter = match service {
+            GitService::UploadPack => self.app.filter_policy(&repo),
+            _ => FilterPolicy::default(),
+        };
+        let protocol = self.protocol.get(&channel_id).map(|x| x.as_str());
+        let label = format!("{} {}/{}", service.name(), repo.namespace, repo.repo_name);
+        let (backend, pack) = match self.app.exec_pack(&git, service, protocol, filter, label) {
+            ExecPack::Rounds(rounds) => {
+                self.upload_pack_rounds(channel_id, rounds, permit, session);
+                self.track_clone(repo);
+                return Ok(());
+            }
+            ExecPack::Process(backend, pack) => (
+                backend,
+                PackRequest {
+                    protection,
+                    max_input_size: quota.remaining,
+                    ..pack
+                },
+            ),
+        };
+        let PackIo {
+            stdin,
+            stdout: mut shell_stdout,
+            stderr: mut shell_stderr,
+            child: mut shell,
+        } = match backend.spawn(pack) {
+            Ok(io) => {
                 session.channel_success(channel_id).ok();
-                shell
+                io
             }
             Err(e) => {
                 error!("Process spawn failed: {}", e);
@@ -225,15 +570,14 @@ impl russh::server::Handler for SSHandle {
             }
         };
         let session_handle = session.handle();
-        let stdin = shell.stdin.take().unwrap();
         self.stdin.insert(channel_id, stdin);
-        let mut shell_stdout = shell.stdout.take().unwrap();
-        let mut shell_stderr = shell.stderr.take().unwrap();
+        let app = self.app.clone();
 
         let (eof_tx, mut eof_rx) = tokio::sync::mpsc::channel::<bool>(10);
         self.eof.insert(channel_id, eof_tx);
 
         let fut = async move {
+            let _permit = permit;
             async fn forward<'a, R, Fut, Fwd>(
                 session_handle: &'a Handle,
                 chan_id: ChannelId,
@@ -294,7 +638,7 @@ impl russh::server::Handler for SSHandle {
                 enum Pipe {
                     Stdout(Result<(), russh::Error>),
                     Stderr(Result<(), russh::Error>),
-                    Exit(io::Result<ExitStatus>),
+                    Exit(io::Result<i32>),
                 }
 
                 let result = tokio::select! {
@@ -312,13 +656,25 @@ impl russh::server::Handler for SSHandle {
                     }
                     Pipe::Exit(result) => {
                         let status = result?;
+                        // refs are only updated once receive-pack has exited, the commands
+                        // were handed over before receive-pack saw them
+                        if status == 0
+                            && let Some(lock) = lock
+                            && let Ok(commands) = commands_rx.try_recv()
+                        {
+                            tokio::spawn(async move {
+                                if let Err(e) = app.sync_push(&lock, &commands).await {
+                                    error!("Sync repo failed: {:?}", e);
+                                }
+                            });
+                        }
 
                         while let Some(eof) = eof_rx.recv().await {
                             if eof {
                                 break;
                             }
                         }
-                        let status_code = status.code().unwrap_or(128) as u32;
+                        let status_code = status as u32;
                         let _ = session_handle
                             .exit_status_request(channel_id, status_code)
                             .await;
@@ -333,27 +689,7 @@ impl russh::server::Handler for SSHandle {
         };
 
         tokio::spawn(fut);
-        let status = self.app.clone();
-        if let Some(repo) = self.repo.clone() {
-            let app = self.app.clone();
-            tokio::spawn(async move {
-                if let Err(e) = app.sync_repo(repo.uid).await {
-                    error!("Sync repo failed: {:?}", e);
-                }
-            });
-        }
-        tokio::spawn(async move {
-            let repo = repo.clone();
-            match status.find_repo_owner(repo.clone()).await {
-                Ok(owner) => {
-                    status
-                        .inner_add_interaction_clone(owner.uid, repo.uid)
-                        .await
-                        .ok();
-                }
-                Err(_) => {}
-            }
-        });
+        self.track_clone(repo);
         Ok(())
     }
 }
@@ -368,6 +704,23 @@ fn parse_git_command(cmd: &str) -> Option<(GitService, &str)> {
     Some((svc, strip_apostrophes(path)))
 }
 
+/// `git-lfs-authenticate <path> <operation>` and `git-lfs-transfer <path> <operation>`.
+fn parse_lfs_command(cmd: &str) -> Option<(LfsCommand, &str, LfsOperation)> {
+    let mut parts = cmd.split_whitespace();
+    let command = match parts.next()? {
+        "git-lfs-authenticate" => LfsCommand::Authenticate,
+        "git-lfs-transfer" => LfsCommand::Transfer,
+        _ => return None,
+    };
+    let path = strip_apostrophes(parts.next()?);
+    let operation = match parts.next()? {
+        "upload" => LfsOperation::Upload,
+        "download" => LfsOperation::Download,
+        _ => return None,
+    };
+    Some((command, path, operation))
+}
+
 fn parse_repo_path(path: &str) -> Option<(&str, &str)> {
     let path = path.trim_matches('/');
     let mut parts = path.splitn(2, '/');
@@ -377,41 +730,31 @@ fn parse_repo_path(path: &str) -> Option<(&str, &str)> {
     }
 }
 
-fn build_git_command(service: GitService, path: PathBuf) -> tokio::process::Command {
-    let mut cmd = tokio::process::Command::new("git");
-    // cmd.arg("shell").arg("-c").current_dir(path);
-    cmd.current_dir(path);
-    match service {
-        GitService::UploadPack => cmd.arg("upload-pack"),
-        GitService::ReceivePack => cmd.arg("receive-pack"),
-        GitService::UploadArchive => cmd.arg("upload-archive"),
-    };
-    cmd.arg(".")
-        .env("GIT_CONFIG_NOSYSTEM", "1")
-        .env("GIT_NO_REPLACE_OBJECTS", "1");
-    cmd
-}
-
 fn strip_apostrophes(s: &str) -> &str {
     s.trim_matches('\'')
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub enum GitService {
-    UploadPack,
-    ReceivePack,
-    UploadArchive,
-}
-
-impl FromStr for GitService {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "upload-pack" => Ok(Self::UploadPack),
-            "receive-pack" => Ok(Self::ReceivePack),
-            "upload-archive" => Ok(Self::UploadArchive),
-            _ => Err(()),
-        }
-    }
+#[test]
+fn test_parse_lfs_command() {
+    assert_eq!(
+        parse_lfs_command("git-lfs-authenticate 'alice/assets.git' download"),
+        Some((
+            LfsCommand::Authenticate,
+            "alice/assets.git",
+            LfsOperation::Download
+        ))
+    );
+    assert_eq!(
+        parse_lfs_command("git-lfs-transfer alice/assets.git upload"),
+        Some((
+            LfsCommand::Transfer,
+            "alice/assets.git",
+            LfsOperation::Upload
+        ))
+    );
+    assert_eq!(parse_lfs_command("git-lfs-transfer alice/assets.git"), None);
+    assert_eq!(
+        parse_lfs_command("git-upload-pack 'alice/assets.git'"),
+        None
+    );
 }
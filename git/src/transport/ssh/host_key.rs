@@ -0,0 +1,140 @@
+use anyhow::{Context, anyhow};
+use config::ssh::AppSshConfig;
+use rand_core::OsRng;
+use russh::keys::PrivateKey;
+use russh::keys::ssh_key::private::Ed25519Keypair;
+use russh::keys::ssh_key::{Algorithm, EcdsaCurve, HashAlg, LineEnding};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+/// A host key as users see it when verifying the server.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct HostKeyInfo {
+    pub algorithm: String,
+    pub fingerprint: String,
+    pub public_key: String,
+    /// Served to clients negotiating its algorithm. Later keys of the same algorithm are
+    /// only listed, ahead of a rotation.
+    pub active: bool,
+}
+
+/// Algorithm of a host key file that does not exist yet, from its name.
+fn file_algorithm(path: &Path) -> Algorithm {
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if name.contains("rsa") {
+        Algorithm::Rsa { hash: None }
+    } else if name.contains("ecdsa") {
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP256,
+        }
+    } else {
+        Algorithm::Ed25519
+    }
+}
+
+/// Writes a new key to `path`, readable by the owner only, and its public half next to it
+/// like `ssh-keygen` does.
+fn generate_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let key = PrivateKey::random(&mut OsRng, file_algorithm(path))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)?
+        .write_all(key.to_openssh(LineEnding::LF)?.as_bytes())?;
+    let public = format!("{}\n", key.public_key().to_openssh()?);
+    std::fs::write(format!("{}.pub", path.display()), public)?;
+    Ok(key)
+}
+
+fn legacy_key(ed25519_hex: &str) -> anyhow::Result<Option<PrivateKey>> {
+    // "..." was the placeholder of the default config
+    if ed25519_hex.is_empty() || ed25519_hex == "..." {
+        return Ok(None);
+    }
+    let bytes: [u8; 64] = hex::decode(ed25519_hex)?
+        .try_into()
+        .map_err(|_| anyhow!("ed25519_hex must hold 64 bytes"))?;
+    Ok(Some(PrivateKey::from(Ed25519Keypair::from_bytes(&bytes)?)))
+}
+
+/// Host keys of the SSH server in the order they are offered. With `generate`, configured
+/// files that do not exist yet are created, otherwise they are skipped.
+pub fn load_host_keys(config: &AppSshConfig, generate: bool) -> anyhow::Result<Vec<PrivateKey>> {
+    let mut keys = vec![];
+    if let Some(key) = legacy_key(&config.ed25519_hex)? {
+        keys.push(key);
+    }
+    for path in &config.host_keys {
+        if path.exists() {
+            let key = russh::keys::load_secret_key(path, None)
+                .with_context(|| format!("reading host key {}", path.display()))?;
+            keys.push(key);
+        } else if generate {
+            info!("Generating SSH host key {}", path.display());
+            let key = generate_key(path)
+                .with_context(|| format!("generating host key {}", path.display()))?;
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+pub fn host_key_info(keys: &[PrivateKey]) -> Vec<HostKeyInfo> {
+    let mut seen = vec![];
+    keys.iter()
+        .map(|key| {
+            let algorithm = key.algorithm();
+            let active = !seen.contains(&algorithm);
+            seen.push(algorithm.clone());
+            HostKeyInfo {
+                algorithm: algorithm.as_str().to_string(),
+                fingerprint: key.public_key().fingerprint(HashAlg::Sha256).to_string(),
+                public_key: key.public_key().to_openssh().unwrap_or_default(),
+                active,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_host_keys() {
+    let dir = crate::testing::TempDir::new("ssh");
+    let config = AppSshConfig {
+        host_keys: vec![
+            dir.join("ssh_host_ed25519_key"),
+            dir.join("ssh_host_ecdsa_key"),
+            dir.join("ssh_host_ed25519_next_key"),
+        ],
+        ..Default::default()
+    };
+    assert!(load_host_keys(&config, false).unwrap().is_empty());
+    let generated = host_key_info(&load_host_keys(&config, true).unwrap());
+    assert_eq!(
+        generated
+            .iter()
+            .map(|x| (x.algorithm.as_str(), x.active))
+            .collect::<Vec<_>>(),
+        vec![
+            ("ssh-ed25519", true),
+            ("ecdsa-sha2-nistp256", true),
+            ("ssh-ed25519", false)
+        ]
+    );
+    assert!(generated[0].fingerprint.starts_with("SHA256:"));
+    // the second start loads what the first one wrote
+    assert_eq!(
+        host_key_info(&load_host_keys(&config, true).unwrap()),
+        generated
+    );
+    assert!(dir.join("ssh_host_ecdsa_key.pub").exists());
+}
@@ -0,0 +1,87 @@
+use russh::keys::ssh_key::public::KeyData;
+use russh::keys::ssh_key::{Algorithm, HashAlg, PublicKey};
+
+/// RSA keys shorter than this are refused, as OpenSSH does since 9.x for new keys.
+pub const MIN_RSA_BITS: usize = 2048;
+
+/// An OpenSSH public key line as users paste it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SshPublicKey {
+    /// `<type> <base64>`, the comment dropped.
+    pub content: String,
+    /// What `ssh-keygen -l` prints for the key.
+    pub fingerprint: String,
+    pub algorithm: String,
+}
+
+pub fn key_fingerprint(key: &KeyData) -> String {
+    key.fingerprint(HashAlg::Sha256).to_string()
+}
+
+fn rsa_bits(modulus: &[u8]) -> usize {
+    let modulus = match modulus.iter().position(|x| *x != 0) {
+        Some(start) => &modulus[start..],
+        None => return 0,
+    };
+    modulus.len() * 8 - modulus[0].leading_zeros() as usize
+}
+
+/// Refuses DSA and short RSA keys, whether they are being added or signing in.
+pub fn check_key_policy(key: &KeyData) -> Result<(), String> {
+    match key {
+        KeyData::Rsa(rsa) => {
+            let bits = rsa_bits(rsa.n.as_bytes());
+            if bits < MIN_RSA_BITS {
+                return Err(format!(
+                    "RSA keys need at least {} bits, this one has {}",
+                    MIN_RSA_BITS, bits
+                ));
+            }
+            Ok(())
+        }
+        key if key.algorithm() == Algorithm::Dsa => Err("DSA keys are not accepted".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Parses one public key in OpenSSH format, with or without a comment.
+pub fn parse_public_key(raw: &str) -> Result<SshPublicKey, String> {
+    let raw = raw.trim();
+    if raw.lines().count() != 1 {
+        return Err("Expected exactly one SSH public key".to_string());
+    }
+    let mut key =
+        PublicKey::from_openssh(raw).map_err(|e| format!("Invalid SSH public key: {}", e))?;
+    check_key_policy(key.key_data())?;
+    key.set_comment("");
+    Ok(SshPublicKey {
+        content: key
+            .to_openssh()
+            .map_err(|e| format!("Invalid SSH public key: {}", e))?,
+        fingerprint: key_fingerprint(key.key_data()),
+        algorithm: key.algorithm().as_str().to_string(),
+    })
+}
+
+#[test]
+fn test_parse_public_key() {
+    let key = parse_public_key(
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPKIm+4ETeD/s9F87oxoEAoqfscA0QIBY5OXPUSlf/Mj alice@laptop\n",
+    )
+    .unwrap();
+    assert_eq!(
+        key.content,
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPKIm+4ETeD/s9F87oxoEAoqfscA0QIBY5OXPUSlf/Mj"
+    );
+    // as printed by `ssh-keygen -lf`
+    assert_eq!(
+        key.fingerprint,
+        "SHA256:Ye/F8JiayLU2FitwVaY2/qF3BA2TSHu5tHJ0BBmDgm0"
+    );
+    assert_eq!(key.algorithm, "ssh-ed25519");
+    // a 1024 bit key
+    let weak = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQDYVRccYHRp36eg9P88lEPgc6hKH+w8L1JhGdA8BFnDE8dmg7YIDJScrKqMBGqXda1p6JqyXVOKyCKDzb7PYqWZ9L+4VmsJLwsqHlePGfDrsD3tVU9TYe432kgaOpDQfauSIl3m8c3kMXIueYl4k/nPYSUXqyNKQwwPEq9aQnMG+w== root@vm";
+    assert!(parse_public_key(weak).unwrap_err().contains("1024"));
+    assert!(parse_public_key("ssh-ed25519 not-base64").is_err());
+    assert!(parse_public_key("hello world").is_err());
+}
@@ -0,0 +1,654 @@
+use crate::lfs::batch::{BatchRequest, LFS_HASH_ALGO, LFS_TRANSFER, LfsLink, LfsRef};
+use crate::lfs::lock::{CreateLockRequest, LfsLock, LockFilter, valid_lock_path};
+use crate::lfs::store::{LfsStore, valid_oid};
+use crate::lfs::{LfsOperation, LfsPointer};
+use crate::service::GitServer;
+use crate::service::permissions::RepoAccess;
+use crate::transport::pkt::{DELIM_PKT, FLUSH_PKT, MAX_PKT_DATA, Pkt, encode, read_pkt_async};
+use crate::transport::ssh::handle::SSHandle;
+use async_stream::stream;
+use bytes::Bytes;
+use database::entity::{git_repo, users};
+use error::AppError;
+use futures_util::StreamExt;
+use russh::server::{Handle, Session};
+use russh::{ChannelId, CryptoVec, Disconnect};
+use std::collections::HashMap;
+use std::io;
+use tokio::io::AsyncRead;
+use tokio_util::io::StreamReader;
+use tracing::error;
+
+/// Response bytes gathered before they go out on the channel.
+const SEND_SIZE: usize = 64 * 1024;
+
+/// The commands git-lfs runs over SSH: `git-lfs-authenticate` hands out a token for the
+/// HTTP API, `git-lfs-transfer` moves objects over the SSH channel itself.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LfsCommand {
+    Authenticate,
+    Transfer,
+}
+
+impl SSHandle {
+    pub(crate) async fn lfs_exec(
+        &mut self,
+        channel: ChannelId,
+        command: LfsCommand,
+        path: &str,
+        operation: LfsOperation,
+        session: &mut Session,
+    ) -> Result<(), russh::Error> {
+        let need = match operation {
+            LfsOperation::Upload => RepoAccess::Write,
+            LfsOperation::Download => RepoAccess::Read,
+        };
+        let (repo, operator, access) = self.exec_repo(path, need, session).await?;
+        if command == LfsCommand::Authenticate {
+            session.channel_success(channel).ok();
+            let status = match self.app.lfs_token_issue(&operator, &repo, need).await {
+                Ok(reply) => {
+                    let body = serde_json::to_vec(&reply).unwrap_or_default();
+                    session.data(channel, CryptoVec::from(body)).ok();
+                    0
+                }
+                Err(e) => {
+                    error!("lfs authenticate failed: {}", e.msg);
+                    let msg = CryptoVec::from(format!("{}\n", e.msg).into_bytes());
+                    session.extended_data(channel, 1, msg).ok();
+                    1
+                }
+            };
+            session.exit_status_request(channel, status).ok();
+            session.eof(channel).ok();
+            session.close(channel).ok();
+            return Ok(());
+        }
+        let store = match LfsStore::try_from((repo.clone(), self.app.config.git.clone())) {
+            Ok(store) => store,
+            Err(e) => {
+                error!("LFS store lookup failed: {}", e.msg);
+                session
+                    .disconnect(Disconnect::ByApplication, "Internal error", "")
+                    .ok();
+                return Err(russh::Error::Disconnect);
+            }
+        };
+        let (writer, mut reader) = tokio::io::duplex(SEND_SIZE);
+        self.stdin.insert(channel, Box::new(writer));
+        session.channel_success(channel).ok();
+        let handle = session.handle();
+        let mut transfer = LfsTransfer {
+            app: self.app.clone(),
+            repo,
+            store,
+            user: operator,
+            operation,
+            access,
+            out: LfsChannel {
+                handle: handle.clone(),
+                channel,
+                buf: vec![],
+            },
+        };
+        tokio::spawn(async move {
+            let status = match transfer.serve(&mut reader).await {
+                Ok(()) => 0,
+                Err(e) => {
+                    error!("lfs transfer failed: {}", e);
+                    1
+                }
+            };
+            handle.exit_status_request(channel, status).await.ok();
+            handle.eof(channel).await.ok();
+            handle.close(channel).await.ok();
+        });
+        Ok(())
+    }
+}
+
+/// Buffers pkt-lines for the channel of a transfer.
+struct LfsChannel {
+    handle: Handle,
+    channel: ChannelId,
+    buf: Vec<u8>,
+}
+
+impl LfsChannel {
+    fn line(&mut self, line: &str) {
+        self.buf.extend(encode(format!("{}\n", line).as_bytes()));
+    }
+    fn data(&mut self, data: &[u8]) {
+        for part in data.chunks(MAX_PKT_DATA) {
+            self.buf.extend(encode(part));
+        }
+    }
+    /// A full response: status, arguments and, when there are any, the lines after a
+    /// delimiter.
+    fn status(&mut self, code: u16, args: &[String], lines: &[String]) {
+        self.line(&format!("status {}", code));
+        for arg in args {
+            self.line(arg);
+        }
+        if !lines.is_empty() {
+            self.buf.extend_from_slice(DELIM_PKT);
+            for line in lines {
+                self.line(line);
+            }
+        }
+        self.buf.extend_from_slice(FLUSH_PKT);
+    }
+    fn error(&mut self, code: u16, message: &str) {
+        self.status(code, &[], &[message.to_string()]);
+    }
+    async fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let data = CryptoVec::from(std::mem::take(&mut self.buf));
+        self.handle
+            .data(self.channel, data)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// A request: the command line, its `key=value` arguments and whether a body follows
+/// after a delimiter.
+struct LfsRequest {
+    command: String,
+    args: HashMap<String, String>,
+    body: bool,
+}
+
+impl LfsRequest {
+    fn arg(&self, key: &str) -> Option<&str> {
+        self.args.get(key).map(|x| x.as_str())
+    }
+    fn size(&self) -> Option<u64> {
+        self.arg("size").and_then(|x| x.parse().ok())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+async fn read_request<R: AsyncRead + Unpin>(input: &mut R) -> io::Result<Option<LfsRequest>> {
+    let command = match read_pkt_async(input).await? {
+        None => return Ok(None),
+        Some(pkt) => pkt
+            .text()
+            .ok_or_else(|| invalid("expected a command"))?
+            .to_string(),
+    };
+    let mut args = HashMap::new();
+    loop {
+        match read_pkt_async(input).await? {
+            Some(Pkt::Flush) => {
+                return Ok(Some(LfsRequest {
+                    command,
+                    args,
+                    body: false,
+                }));
+            }
+            Some(Pkt::Delim) => {
+                return Ok(Some(LfsRequest {
+                    command,
+                    args,
+                    body: true,
+                }));
+            }
+            Some(pkt) => {
+                let arg = pkt.text().ok_or_else(|| invalid("expected an argument"))?;
+                let (key, value) = arg.split_once('=').unwrap_or((arg, ""));
+                args.insert(key.to_string(), value.to_string());
+            }
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+/// Text lines of a request body up to its flush.
+async fn read_lines<R: AsyncRead + Unpin>(input: &mut R) -> io::Result<Vec<String>> {
+    let mut lines = vec![];
+    loop {
+        match read_pkt_async(input).await? {
+            Some(Pkt::Flush) => return Ok(lines),
+            Some(pkt) => lines.push(
+                pkt.text()
+                    .ok_or_else(|| invalid("expected a line"))?
+                    .to_string(),
+            ),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+/// Reads past a request body that is refused.
+async fn skip_body<R: AsyncRead + Unpin>(input: &mut R) -> io::Result<()> {
+    while !matches!(read_pkt_async(input).await?, Some(Pkt::Flush) | None) {}
+    Ok(())
+}
+
+/// The data packets of a request body as a reader that ends at the flush, `done` is set
+/// once the flush was read.
+fn read_body<'a, R: AsyncRead + Unpin + Send>(
+    input: &'a mut R,
+    done: &'a mut bool,
+) -> impl AsyncRead + Unpin + Send + 'a {
+    StreamReader::new(Box::pin(stream! {
+        loop {
+            match read_pkt_async(input).await {
+                Ok(Some(Pkt::Data(data))) => yield Ok(Bytes::from(data)),
+                Ok(Some(Pkt::Flush)) => {
+                    *done = true;
+                    break;
+                }
+                Ok(Some(_)) => {
+                    yield Err(invalid("unexpected packet in object data"));
+                    break;
+                }
+                Ok(None) => {
+                    yield Err(io::ErrorKind::UnexpectedEof.into());
+                    break;
+                }
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+fn lock_args(lock: &LfsLock) -> Vec<String> {
+    vec![
+        format!("id={}", lock.id),
+        format!("path={}", lock.path),
+        format!("locked-at={}", lock.locked_at),
+        format!("ownername={}", lock.owner.name),
+    ]
+}
+
+/// One `git-lfs-transfer` session, the pure SSH protocol of git-lfs: pkt-line requests
+/// answered in-process with the same objects, links and locks as the HTTP API.
+struct LfsTransfer {
+    app: GitServer,
+    repo: git_repo::Model,
+    store: LfsStore,
+    user: users::Model,
+    operation: LfsOperation,
+    access: RepoAccess,
+    out: LfsChannel,
+}
+
+impl LfsTransfer {
+    async fn serve<R: AsyncRead + Unpin + Send>(&mut self, input: &mut R) -> io::Result<()> {
+        self.out.line("version=1");
+        self.out.buf.extend_from_slice(FLUSH_PKT);
+        self.out.send().await?;
+        let Some(request) = read_request(input).await? else {
+            return Ok(());
+        };
+        if request.command != "version 1" {
+            self.out.error(400, "unsupported protocol version");
+            return self.out.send().await;
+        }
+        self.out.status(200, &[], &[]);
+        self.out.send().await?;
+        while let Some(request) = read_request(input).await? {
+            let (command, arg) = request
+                .command
+                .split_once(' ')
+                .unwrap_or((request.command.as_str(), ""));
+            match command {
+                "batch" => self.batch(&request, input).await?,
+                "put-object" => self.put_object(&request, arg, input).await?,
+                _ if request.body => {
+                    skip_body(input).await?;
+                    self.out.error(400, "unexpected request body");
+                }
+                "quit" => {
+                    self.out.status(200, &[], &[]);
+                    return self.out.send().await;
+                }
+                "verify-object" => self.verify_object(&request, arg).await,
+                "get-object" => self.get_object(arg).await?,
+                "lock" => self.lock(&request).await,
+                "list-lock" => self.list_lock(&request).await,
+                "unlock" => self.unlock(&request, arg).await,
+                _ => self.out.error(400, "unknown command"),
+            }
+            self.out.send().await?;
+        }
+        Ok(())
+    }
+    fn internal(&mut self, action: &str, e: AppError) {
+        error!("lfs {} failed: {}", action, e.msg);
+        self.out.error(500, "Internal Server Error");
+    }
+    async fn batch<R: AsyncRead + Unpin>(
+        &mut self,
+        request: &LfsRequest,
+        input: &mut R,
+    ) -> io::Result<()> {
+        let lines = if request.body {
+            read_lines(input).await?
+        } else {
+            vec![]
+        };
+        if request.arg("hash-algo").is_some_and(|x| x != LFS_HASH_ALGO) {
+            self.out.error(409, "Unsupported hash algorithm");
+            return Ok(());
+        }
+        if request.arg("transfer").is_some_and(|x| x != LFS_TRANSFER) {
+            self.out.error(422, "Unsupported transfer");
+            return Ok(());
+        }
+        let mut objects = vec![];
+        for line in &lines {
+            let mut parts = line.split(' ');
+            let (Some(oid), Some(Ok(size))) = (parts.next(), parts.next().map(str::parse::<i64>))
+            else {
+                self.out.error(400, "Invalid object");
+                return Ok(());
+            };
+            objects.push(LfsPointer {
+                oid: oid.to_string(),
+                size,
+            });
+        }
+        let batch = BatchRequest {
+            operation: self.operation,
+            transfers: vec![],
+            refspec: request.arg("refname").map(|x| LfsRef {
+                name: x.to_string(),
+            }),
+            objects,
+            hash_algo: None,
+        };
+        // there are no hrefs over SSH, only which objects need moving
+        let link = LfsLink {
+            href: String::new(),
+            header: HashMap::new(),
+        };
+        let response = match self.app.lfs_batch(&self.repo, batch, &link).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.internal("batch", e);
+                return Ok(());
+            }
+        };
+        let lines = response
+            .objects
+            .iter()
+            .map(|x| {
+                let action = match &x.actions {
+                    Some(actions) if actions.download.is_some() => "download",
+                    Some(actions) if actions.upload.is_some() => "upload",
+                    _ => "noop",
+                };
+                format!("{} {} {}", x.oid, x.size, action)
+            })
+            .collect::<Vec<_>>();
+        self.out
+            .status(200, &[format!("hash-algo={}", LFS_HASH_ALGO)], &lines);
+        Ok(())
+    }
+    async fn put_object<R: AsyncRead + Unpin + Send>(
+        &mut self,
+        request: &LfsRequest,
+        oid: &str,
+        input: &mut R,
+    ) -> io::Result<()> {
+        if !request.body {
+            self.out.error(400, "Missing object data");
+            return Ok(());
+        }
+        let refuse = if self.operation != LfsOperation::Upload {
+            Some((403, "Not an upload session"))
+        } else if !valid_oid(oid) {
+            Some((400, "Invalid object id"))
+        } else if request.size().is_none() {
+            Some((400, "Missing object size"))
+        } else {
+            None
+        };
+        if let Some((code, message)) = refuse {
+            skip_body(input).await?;
+            self.out.error(code, message);
+            return Ok(());
+        }
+        let size = request.size().unwrap_or_default();
+        match self.app.push_quota(&self.repo).await {
+            Ok(quota) if quota.remaining.is_some_and(|x| size > x) => {
+                skip_body(input).await?;
+                self.out.error(507, "Storage quota exceeded");
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                skip_body(input).await?;
+                self.internal("upload", e);
+                return Ok(());
+            }
+        }
+        let split = match self.app.lfs_chunks(oid).await {
+            Ok(chunks) => !chunks.is_empty() || self.store.splits(size),
+            Err(e) => {
+                skip_body(input).await?;
+                self.internal("upload", e);
+                return Ok(());
+            }
+        };
+        let mut done = false;
+        let written = {
+            let mut body = read_body(input, &mut done);
+            self.store.write(oid, size, split, &mut body).await
+        };
+        if !done {
+            // a failed write stops reading, the rest of the object still has to go
+            skip_body(input).await?;
+        }
+        let chunks = match written {
+            Ok(chunks) => chunks,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                self.out.error(400, &e.to_string());
+                return Ok(());
+            }
+            Err(e) => {
+                self.internal("upload", e.into());
+                return Ok(());
+            }
+        };
+        match self
+            .app
+            .lfs_link(self.repo.uid, oid, size as i64, &chunks)
+            .await
+        {
+            Ok(()) => self.out.status(200, &[], &[]),
+            Err(e) => self.internal("upload", e),
+        }
+        Ok(())
+    }
+    /// Size of an object this repository may serve, `None` after an error was answered.
+    async fn object_size(&mut self, oid: &str) -> Option<Option<u64>> {
+        if !valid_oid(oid) {
+            self.out.error(400, "Invalid object id");
+            return None;
+        }
+        let size = match self.app.lfs_linked(self.repo.uid, oid).await {
+            Ok(true) => self.app.lfs_size(&self.store, oid).await,
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        };
+        match size {
+            Ok(size) => Some(size),
+            Err(e) => {
+                self.internal("object lookup", e);
+                None
+            }
+        }
+    }
+    async fn verify_object(&mut self, request: &LfsRequest, oid: &str) {
+        let Some(size) = self.object_size(oid).await else {
+            return;
+        };
+        match size {
+            Some(size) if Some(size) == request.size() => self.out.status(200, &[], &[]),
+            Some(_) => self.out.error(409, "Object size mismatch"),
+            None => self.out.error(404, "Object does not exist"),
+        }
+    }
+    async fn get_object(&mut self, oid: &str) -> io::Result<()> {
+        let Some(size) = self.object_size(oid).await else {
+            return Ok(());
+        };
+        let Some(size) = size else {
+            self.out.error(404, "Object does not exist");
+            return Ok(());
+        };
+        let chunks = match self.app.lfs_chunks(oid).await {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                self.internal("download", e);
+                return Ok(());
+            }
+        };
+        let mut body = match self.store.read(oid, &chunks, 0, size).await {
+            Ok(body) => body,
+            Err(e) => {
+                self.internal("download", e.into());
+                return Ok(());
+            }
+        };
+        self.out.line("status 200");
+        self.out.line(&format!("size={}", size));
+        self.out.buf.extend_from_slice(DELIM_PKT);
+        // past the status line an error can only end the session
+        while let Some(bytes) = body.next().await {
+            self.out.data(&bytes?);
+            if self.out.buf.len() >= SEND_SIZE {
+                self.out.send().await?;
+            }
+        }
+        self.out.buf.extend_from_slice(FLUSH_PKT);
+        Ok(())
+    }
+    fn can_lock(&mut self) -> bool {
+        if self.access < RepoAccess::Write {
+            self.out.error(403, "Permission denied");
+            return false;
+        }
+        true
+    }
+    async fn lock(&mut self, request: &LfsRequest) {
+        if !self.can_lock() {
+            return;
+        }
+        let Some(path) = request.arg("path").filter(|x| valid_lock_path(x)) else {
+            self.out.error(400, "Invalid lock path");
+            return;
+        };
+        let create = CreateLockRequest {
+            path: path.to_string(),
+            refspec: request.arg("refname").map(|x| LfsRef {
+                name: x.to_string(),
+            }),
+        };
+        match self
+            .app
+            .lfs_lock_create(self.repo.uid, &self.user, create)
+            .await
+        {
+            Ok(Ok(lock)) => self.out.status(201, &lock_args(&lock), &[]),
+            Ok(Err(lock)) => self.out.status(
+                409,
+                &lock_args(&lock),
+                &["already created lock".to_string()],
+            ),
+            Err(e) => self.internal("lock", e),
+        }
+    }
+    async fn list_lock(&mut self, request: &LfsRequest) {
+        let filter = LockFilter {
+            path: request.arg("path").map(str::to_string),
+            id: request.arg("id").map(str::to_string),
+            cursor: request.arg("cursor").map(str::to_string),
+            limit: request.arg("limit").and_then(|x| x.parse().ok()),
+        };
+        let list = match self.app.lfs_lock_list(self.repo.uid, &filter).await {
+            Ok(list) => list,
+            Err(e) => return self.internal("lock list", e),
+        };
+        let args = list
+            .next_cursor
+            .iter()
+            .map(|x| format!("next-cursor={}", x))
+            .collect::<Vec<_>>();
+        let mut lines = vec![];
+        for lock in &list.locks {
+            let owner = if lock.owner.name == self.user.username {
+                "ours"
+            } else {
+                "theirs"
+            };
+            lines.push(format!("lock {}", lock.id));
+            lines.push(format!("path {} {}", lock.id, lock.path));
+            lines.push(format!("locked-at {} {}", lock.id, lock.locked_at));
+            lines.push(format!("ownername {} {}", lock.id, lock.owner.name));
+            lines.push(format!("owner {} {}", lock.id, owner));
+        }
+        self.out.status(200, &args, &lines);
+    }
+    async fn unlock(&mut self, request: &LfsRequest, id: &str) {
+        if !self.can_lock() {
+            return;
+        }
+        let lock = match self.app.lfs_lock_find(self.repo.uid, id).await {
+            Ok(Some(lock)) => lock,
+            Ok(None) => return self.out.error(404, "Lock not found"),
+            Err(e) => return self.internal("unlock", e),
+        };
+        if lock.owner_uid != self.user.uid {
+            if request.arg("force") != Some("true") {
+                return self.out.error(403, "Lock is owned by another user");
+            }
+            if !self.app.repo_admin(&self.repo, &self.user) {
+                return self
+                    .out
+                    .error(403, "Only repository admins can force unlock");
+            }
+        }
+        match self.app.lfs_unlock(lock).await {
+            Ok(lock) => self.out.status(200, &lock_args(&lock), &[]),
+            Err(e) => self.internal("unlock", e),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_lfs_request() {
+    let mut buf = encode(b"put-object 1234\n");
+    buf.extend(encode(b"size=5\n"));
+    buf.extend_from_slice(DELIM_PKT);
+    buf.extend(encode(b"hel"));
+    buf.extend(encode(b"lo"));
+    buf.extend_from_slice(FLUSH_PKT);
+    buf.extend(encode(b"quit\n"));
+    buf.extend_from_slice(FLUSH_PKT);
+    let mut input = &buf[..];
+    let request = read_request(&mut input).await.unwrap().unwrap();
+    assert_eq!(request.command, "put-object 1234");
+    assert_eq!((request.size(), request.body), (Some(5), true));
+    let mut done = false;
+    let mut data = vec![];
+    tokio::io::AsyncReadExt::read_to_end(&mut read_body(&mut input, &mut done), &mut data)
+        .await
+        .unwrap();
+    assert_eq!((data.as_slice(), done), (&b"hello"[..], true));
+    let request = read_request(&mut input).await.unwrap().unwrap();
+    assert_eq!((request.command.as_str(), request.body), ("quit", false));
+    assert!(read_request(&mut input).await.unwrap().is_none());
+}
@@ -1,14 +1,20 @@
 use crate::service::GitServer;
+use crate::transport::ssh::host_key::{host_key_info, load_host_keys};
 use crate::transport::ssh::server::SSHServer;
-use russh::keys::PrivateKey;
-use russh::keys::ssh_key::private::Ed25519Keypair;
+use anyhow::anyhow;
 use russh::server::{Config, Server};
 use russh::{MethodSet, SshId};
 use std::sync::Arc;
 use tracing::info;
 
+pub mod cert;
+pub mod command;
 pub mod handle;
+pub mod host_key;
+pub mod key;
+pub mod lfs;
 pub mod server;
+pub mod upload_pack;
 
 #[derive(Clone)]
 pub struct SSHHandle {
@@ -27,12 +33,22 @@ impl SSHHandle {
     }
     pub async fn run_ssh(&self) -> anyhow::Result<()> {
         info!("SSH Starting...");
-        let ed25519 = self.app.config.ssh.ed25519_hex.clone();
-        let vec = hex::decode(ed25519.as_bytes().to_vec())?;
-        let key_bytes: [u8; 64] = vec.try_into().expect("Invalid key length");
-        let key = Ed25519Keypair::from_bytes(&key_bytes)?;
+        let ssh = self.app.config.ssh.clone();
+        // generating an RSA key takes a moment
+        let keys = tokio::task::spawn_blocking(move || load_host_keys(&ssh, true)).await??;
+        if keys.is_empty() {
+            return Err(anyhow!("no SSH host key configured"));
+        }
+        for key in host_key_info(&keys) {
+            info!(
+                "SSH host key {} {}{}",
+                key.algorithm,
+                key.fingerprint,
+                if key.active { "" } else { " (standby)" }
+            );
+        }
         let mut config = Config::default();
-        config.keys = vec![PrivateKey::from(key)];
+        config.keys = keys;
         let version = format!("SSH-2.0-Gitdata {}", env!("CARGO_PKG_VERSION"));
         config.server_id = SshId::Standard(version);
         config.methods = MethodSet::all();
@@ -20,6 +20,6 @@ impl russh::server::Server for SSHServer {
         if let Some(addr) = addr {
             info!("New SSH connection from {}", addr);
         }
-        SSHandle::new(self.app.clone())
+        SSHandle::new(self.app.clone(), addr)
     }
 }
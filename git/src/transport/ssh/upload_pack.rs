@@ -0,0 +1,315 @@
+use crate::GitContext;
+use crate::service::GitServer;
+use crate::service::limit::PackPermit;
+use crate::service::partial_clone::FilterPolicy;
+use crate::service::protection::BranchProtection;
+use crate::transport::GitService;
+use crate::transport::backend::{GitPack, PackRequest};
+use crate::transport::pack_cache::{CachedBody, MAX_CACHED_REQUEST, PackCache, stateless_round};
+use crate::transport::pkt::{DELIM_PKT, FLUSH_PKT, Pkt, encode, read_pkt_async};
+use crate::transport::protocol::GitProtocol;
+use crate::transport::ssh::handle::SSHandle;
+use futures_util::StreamExt;
+use russh::server::{Handle, Session};
+use russh::{ChannelId, CryptoVec};
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tracing::error;
+
+/// Client input buffered for a session, a fetch request is rarely more than this.
+const REQUEST_BUFFER: usize = 64 * 1024;
+/// Response bytes buffered before they are sent on the channel.
+const RESPONSE_BUFFER: usize = 64 * 1024;
+
+/// How an exec of a git service is served.
+pub(crate) enum ExecPack {
+    /// A protocol v2 upload-pack answered a request at a time, through the pack cache.
+    Rounds(UploadPackRounds),
+    /// One git process for the whole session.
+    Process(Box<dyn GitPack>, PackRequest),
+}
+
+/// A protocol v2 upload-pack session served one request at a time, as HTTP does, so
+/// fetches over SSH are answered from the pack cache too.
+pub(crate) struct UploadPackRounds {
+    pub cache: PackCache,
+    pub backend: Box<dyn GitPack>,
+    pub path: PathBuf,
+    pub protocol: Option<GitProtocol>,
+    pub filter: FilterPolicy,
+    pub label: String,
+}
+
+impl UploadPackRounds {
+    fn request(&self, advertise_refs: bool) -> PackRequest {
+        PackRequest {
+            service: GitService::UploadPack,
+            path: self.path.clone(),
+            protocol: self.protocol.clone(),
+            stateless: true,
+            advertise_refs,
+            protection: BranchProtection::default(),
+            filter: self.filter.clone(),
+            max_input_size: None,
+        }
+    }
+    /// The capability advertisement, then a response per request until the client sends
+    /// an empty request or closes its side.
+    pub(crate) async fn serve<R, W>(&self, input: &mut R, output: &mut W) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let advertisement = stateless_round(
+            self.backend.as_ref(),
+            self.request(true),
+            vec![],
+            self.label.clone(),
+        )?;
+        forward(output, advertisement).await?;
+        while let Some(request) = read_request(input).await? {
+            if request == FLUSH_PKT {
+                break;
+            }
+            let body = if request.len() > MAX_CACHED_REQUEST {
+                stateless_round(
+                    self.backend.as_ref(),
+                    self.request(false),
+                    request,
+                    self.label.clone(),
+                )?
+            } else {
+                self.cache
+                    .serve(
+                        self.backend.as_ref(),
+                        self.request(false),
+                        request,
+                        self.label.clone(),
+                    )
+                    .await?
+            };
+            forward(output, body).await?;
+        }
+        Ok(())
+    }
+}
+
+impl GitServer {
+    /// How an exec of `service` on `git` is served, `protocol` being what the client asked
+    /// for in `GIT_PROTOCOL`. Protocol v0 over SSH negotiates statefully on the channel, its
+    /// rounds do not carry the whole request a cache entry is keyed by, so only v2
+    /// upload-pack goes through the pack cache. A process request has no push checks yet.
+    pub(crate) fn exec_pack(
+        &self,
+        git: &GitContext,
+        service: GitService,
+        protocol: Option<&str>,
+        filter: FilterPolicy,
+        label: String,
+    ) -> ExecPack {
+        let backend = self.pack_backend(git, service, &filter);
+        let allow_v2 = self.allow_v2(service, backend.as_ref());
+        let protocol = protocol.and_then(|x| GitProtocol::parse(x, allow_v2));
+        if service == GitService::UploadPack
+            && protocol.as_ref().is_some_and(|x| x.is_v2())
+            && git.remote.is_none()
+            && let Some(cache) = self.pack_cache()
+        {
+            return ExecPack::Rounds(UploadPackRounds {
+                cache,
+                backend,
+                path: git.path_dir.clone(),
+                protocol,
+                filter,
+                label,
+            });
+        }
+        let request = PackRequest {
+            service,
+            path: git.path_dir.clone(),
+            protocol,
+            stateless: false,
+            advertise_refs: false,
+            protection: BranchProtection::default(),
+            filter,
+            max_input_size: None,
+        };
+        ExecPack::Process(backend, request)
+    }
+}
+
+/// One request as the client sent it, up to and including its flush packet. `None` once
+/// the client closed its side.
+async fn read_request<R: AsyncRead + Unpin>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut request = vec![];
+    loop {
+        let pkt = match read_pkt_async(input).await? {
+            Some(pkt) => pkt,
+            None if request.is_empty() => return Ok(None),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        match pkt {
+            Pkt::Flush => {
+                request.extend_from_slice(FLUSH_PKT);
+                return Ok(Some(request));
+            }
+            Pkt::Delim => request.extend_from_slice(DELIM_PKT),
+            Pkt::ResponseEnd => request.extend_from_slice(b"0002"),
+            Pkt::Data(data) => request.extend(encode(&data)),
+        }
+    }
+}
+
+async fn forward<W: AsyncWrite + Unpin>(output: &mut W, mut body: CachedBody) -> io::Result<()> {
+    while let Some(chunk) = body.next().await {
+        output.write_all(&chunk?).await?;
+    }
+    // the client waits for the whole response before its next request
+    output.flush().await
+}
+
+/// Sends what `responses` yields on the channel until it ends. Dropping `responses` on a
+/// failed send fails the writes of the session as well.
+async fn send(handle: &Handle, channel: ChannelId, mut responses: DuplexStream) -> io::Result<()> {
+    let mut buf = vec![0; RESPONSE_BUFFER];
+    loop {
+        let read = responses.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        handle
+            .data(channel, CryptoVec::from_slice(&buf[..read]))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+    }
+}
+
+impl SSHandle {
+    pub(crate) fn upload_pack_rounds(
+        &mut self,
+        channel: ChannelId,
+        rounds: UploadPackRounds,
+        permit: PackPermit,
+        session: &mut Session,
+    ) {
+        let (writer, mut reader) = tokio::io::duplex(REQUEST_BUFFER);
+        self.stdin.insert(channel, Box::new(writer));
+        session.channel_success(channel).ok();
+        let handle = session.handle();
+        let (mut output, responses) = tokio::io::duplex(RESPONSE_BUFFER);
+        tokio::spawn(async move {
+            let _permit = permit;
+            let serve = async {
+                let served = rounds.serve(&mut reader, &mut output).await;
+                output.shutdown().await.ok();
+                served
+            };
+            let status = match tokio::join!(serve, send(&handle, channel, responses)) {
+                (Ok(()), Ok(())) => 0,
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("{}: {}", rounds.label, e);
+                    128
+                }
+            };
+            handle.exit_status_request(channel, status).await.ok();
+            handle.eof(channel).await.ok();
+            handle.close(channel).await.ok();
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_read_request() {
+    let mut input: &[u8] = b"0014command=ls-refs\n00010009peel\n00000000";
+    assert_eq!(
+        read_request(&mut input).await.unwrap().unwrap(),
+        b"0014command=ls-refs\n00010009peel\n0000"
+    );
+    assert_eq!(read_request(&mut input).await.unwrap().unwrap(), FLUSH_PKT);
+    assert_eq!(read_request(&mut input).await.unwrap(), None);
+    let mut cut: &[u8] = b"0012command=fetch\n";
+    assert!(read_request(&mut cut).await.is_err());
+}
+
+#[tokio::test]
+async fn test_partial_clone_ssh() {
+    use crate::testing::{self, TempDir};
+    use crate::transport::backend::PackIo;
+    use config::AppConfig;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    let root = TempDir::new("ssh-partial-clone");
+    let repo = git2::Repository::init_bare(root.join("repo")).unwrap();
+    testing::commit(&repo, "refs/heads/main", &[("data.csv", b"id\n1\n")], &[]);
+    repo.set_head("refs/heads/main").unwrap();
+    let git = GitContext {
+        path_dir: root.join("repo"),
+        remote: None,
+    };
+    let filter = FilterPolicy {
+        filters: vec!["blob:none".to_string()],
+        tree_max_depth: None,
+    };
+    let mut config = AppConfig::default();
+    config.git.protocol_v2 = true;
+    config.git.pack_cache.enabled = true;
+    config.git.pack_cache.path = root.join("cache");
+    let app = testing::git_server(config);
+    // git:// sends up front what SSH carries in the exec request and GIT_PROTOCOL, the
+    // session is then served the way `exec_request` serves it
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("git://{}/repo.git", listener.local_addr().unwrap());
+    let served = Arc::new(Mutex::new(vec![]));
+    let log = served.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (app, git, filter, log) = (app.clone(), git.clone(), filter.clone(), log.clone());
+            tokio::spawn(async move {
+                let (mut input, mut output) = stream.into_split();
+                let Ok(Some(Pkt::Data(command))) = read_pkt_async(&mut input).await else {
+                    return;
+                };
+                let protocol = command
+                    .split(|x| *x == 0)
+                    .filter_map(|x| std::str::from_utf8(x).ok())
+                    .find(|x| x.starts_with("version="))
+                    .map(|x| x.to_string());
+                let (service, label) = (GitService::UploadPack, "upload-pack repo".to_string());
+                match app.exec_pack(&git, service, protocol.as_deref(), filter, label) {
+                    ExecPack::Rounds(rounds) => {
+                        log.lock().unwrap().push("rounds");
+                        rounds.serve(&mut input, &mut output).await.unwrap();
+                    }
+                    ExecPack::Process(backend, request) => {
+                        log.lock().unwrap().push("process");
+                        let PackIo {
+                            mut stdin,
+                            mut stdout,
+                            stderr: _stderr,
+                            mut child,
+                        } = backend.spawn(request).unwrap();
+                        tokio::spawn(async move {
+                            tokio::io::copy(&mut input, &mut stdin).await.ok();
+                        });
+                        tokio::io::copy(&mut stdout, &mut output).await.unwrap();
+                        child.wait().await.unwrap();
+                    }
+                }
+                output.shutdown().await.ok();
+            });
+        }
+    });
+    for (version, kind) in [("0", "process"), ("2", "rounds")] {
+        let url = url.clone();
+        tokio::task::spawn_blocking(move || {
+            testing::check_partial_clone(&url, version, "data.csv", b"id\n1\n")
+        })
+        .await
+        .unwrap();
+        let served = std::mem::take(&mut *served.lock().unwrap());
+        assert!(!served.is_empty());
+        assert!(served.iter().all(|x| *x == kind), "{:?}", served);
+    }
+}
@@ -11,6 +11,18 @@ mod m20250818_000006_create_user_star_repo_table;
 mod m20250818_000007_create_user_watch_repo_table;
 mod m20250819_000008_update_recommendation_tables;
 mod m20250819_000009_add_repo_stats_triggers;
+mod m20250820_000010_create_branch_protection_table;
+mod m20250821_000011_create_lfs_repo_objects_table;
+mod m20250822_000012_restructure_lfs_locks_table;
+mod m20250823_000013_create_deploy_keys_table;
+mod m20250824_000014_create_ssh_cas_table;
+mod m20250825_000015_ssh_key_fingerprints;
+mod m20250826_000016_git_repo_partial_clone;
+mod m20250827_000017_git_repo_size;
+mod m20250828_000018_create_repo_maintenance_table;
+mod m20250829_000019_create_repo_integrity_table;
+mod m20250830_000020_create_repo_storage_migration_table;
+mod m20250831_000021_backfill_git_repo_size;
 
 pub struct Migrator;
 
@@ -28,6 +40,18 @@ impl MigratorTrait for Migrator {
             Box::new(m20250818_000007_create_user_watch_repo_table::Migration),
             Box::new(m20250819_000008_update_recommendation_tables::Migration),
             Box::new(m20250819_000009_add_repo_stats_triggers::Migration),
+            Box::new(m20250820_000010_create_branch_protection_table::Migration),
+            Box::new(m20250821_000011_create_lfs_repo_objects_table::Migration),
+            Box::new(m20250822_000012_restructure_lfs_locks_table::Migration),
+            Box::new(m20250823_000013_create_deploy_keys_table::Migration),
+            Box::new(m20250824_000014_create_ssh_cas_table::Migration),
+            Box::new(m20250825_000015_ssh_key_fingerprints::Migration),
+            Box::new(m20250826_000016_git_repo_partial_clone::Migration),
+            Box::new(m20250827_000017_git_repo_size::Migration),
+            Box::new(m20250828_000018_create_repo_maintenance_table::Migration),
+            Box::new(m20250829_000019_create_repo_integrity_table::Migration),
+            Box::new(m20250830_000020_create_repo_storage_migration_table::Migration),
+            Box::new(m20250831_000021_backfill_git_repo_size::Migration),
         ]
     }
 }
@@ -0,0 +1,155 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(BranchProtection::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BranchProtection::Uid)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(BranchProtection::RepoUid).uuid().not_null())
+                    .col(ColumnDef::new(BranchProtection::Pattern).string().not_null())
+                    .col(
+                        ColumnDef::new(BranchProtection::BlockForcePush)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtection::BlockDeletion)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtection::RequireLinearHistory)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtection::RestrictPush)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtection::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtection::UpdatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_branch_protection_repo_pattern")
+                    .table(BranchProtection::Table)
+                    .col(BranchProtection::RepoUid)
+                    .col(BranchProtection::Pattern)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // users allowed to push when a rule restricts pushes
+        manager
+            .create_table(
+                Table::create()
+                    .table(BranchProtectionPusher::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BranchProtectionPusher::Uid)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtectionPusher::RuleUid)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtectionPusher::UserUid)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BranchProtectionPusher::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_branch_protection_pusher_rule")
+                            .from(BranchProtectionPusher::Table, BranchProtectionPusher::RuleUid)
+                            .to(BranchProtection::Table, BranchProtection::Uid)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_branch_protection_pusher_rule_user")
+                    .table(BranchProtectionPusher::Table)
+                    .col(BranchProtectionPusher::RuleUid)
+                    .col(BranchProtectionPusher::UserUid)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BranchProtectionPusher::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(BranchProtection::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum BranchProtection {
+    Table,
+    Uid,
+    RepoUid,
+    Pattern,
+    BlockForcePush,
+    BlockDeletion,
+    RequireLinearHistory,
+    RestrictPush,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Iden)]
+pub enum BranchProtectionPusher {
+    Table,
+    Uid,
+    RuleUid,
+    UserUid,
+    CreatedAt,
+}
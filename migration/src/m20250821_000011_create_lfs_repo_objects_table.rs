@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // which repositories may serve an lfs object, objects are stored once per storage
+        manager
+            .create_table(
+                Table::create()
+                    .table(LfsRepoObjects::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(LfsRepoObjects::RepoUid).uuid().not_null())
+                    .col(ColumnDef::new(LfsRepoObjects::Oid).string().not_null())
+                    .col(
+                        ColumnDef::new(LfsRepoObjects::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .name("pk_lfs_repo_objects")
+                            .col(LfsRepoObjects::RepoUid)
+                            .col(LfsRepoObjects::Oid),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_lfs_repo_objects_oid")
+                    .table(LfsRepoObjects::Table)
+                    .col(LfsRepoObjects::Oid)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LfsRepoObjects::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum LfsRepoObjects {
+    Table,
+    RepoUid,
+    Oid,
+    CreatedAt,
+}
@@ -0,0 +1,105 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the old table only kept an opaque blob per lock and was never written
+        manager
+            .drop_table(Table::drop().table(LfsLocks::Table).if_exists().to_owned())
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(LfsLocks::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(LfsLocks::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(LfsLocks::RepoUid).uuid().not_null())
+                    .col(ColumnDef::new(LfsLocks::Path).text().not_null())
+                    .col(ColumnDef::new(LfsLocks::OwnerUid).uuid().not_null())
+                    .col(ColumnDef::new(LfsLocks::RefName).string().null())
+                    .col(
+                        ColumnDef::new(LfsLocks::LockedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_lfs_locks_repo_path")
+                    .table(LfsLocks::Table)
+                    .col(LfsLocks::RepoUid)
+                    .col(LfsLocks::Path)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        // pushes touching a path locked by someone else are only refused when enabled
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(
+                        ColumnDef::new(GitRepo::LfsLockEnforced)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::LfsLockEnforced)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(LfsLocks::Table).to_owned())
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(LfsLocks::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(LfsLocks::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(LfsLocks::Data).text().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum LfsLocks {
+    Table,
+    Id,
+    RepoUid,
+    Path,
+    OwnerUid,
+    RefName,
+    LockedAt,
+    Data,
+}
+
+#[derive(Iden)]
+enum GitRepo {
+    Table,
+    LfsLockEnforced,
+}
@@ -0,0 +1,83 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ssh keys scoped to one repository instead of a user, for CI and deploy machines
+        manager
+            .create_table(
+                Table::create()
+                    .table(DeployKeys::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(DeployKeys::Uid)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(DeployKeys::RepoUid).uuid().not_null())
+                    .col(ColumnDef::new(DeployKeys::Title).string().not_null())
+                    .col(ColumnDef::new(DeployKeys::Fingerprint).string().not_null())
+                    .col(ColumnDef::new(DeployKeys::Content).text().not_null())
+                    .col(
+                        ColumnDef::new(DeployKeys::ReadOnly)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(ColumnDef::new(DeployKeys::CreatedBy).uuid().not_null())
+                    .col(
+                        ColumnDef::new(DeployKeys::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(DeployKeys::LastUsedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_deploy_keys_repo_content")
+                    .table(DeployKeys::Table)
+                    .col(DeployKeys::RepoUid)
+                    .col(DeployKeys::Content)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_deploy_keys_content")
+                    .table(DeployKeys::Table)
+                    .col(DeployKeys::Content)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DeployKeys::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum DeployKeys {
+    Table,
+    Uid,
+    RepoUid,
+    Title,
+    Fingerprint,
+    Content,
+    ReadOnly,
+    CreatedBy,
+    CreatedAt,
+    LastUsedAt,
+}
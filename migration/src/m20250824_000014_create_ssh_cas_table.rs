@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // certificate authorities a user trusts to sign ssh certificates for their account
+        manager
+            .create_table(
+                Table::create()
+                    .table(SshCas::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(SshCas::Uid).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(SshCas::UserUid).uuid().not_null())
+                    .col(ColumnDef::new(SshCas::Title).string().not_null())
+                    .col(ColumnDef::new(SshCas::Fingerprint).string().not_null())
+                    .col(ColumnDef::new(SshCas::Content).text().not_null())
+                    .col(
+                        ColumnDef::new(SshCas::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ssh_cas_user_content")
+                    .table(SshCas::Table)
+                    .col(SshCas::UserUid)
+                    .col(SshCas::Content)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ssh_cas_content")
+                    .table(SshCas::Table)
+                    .col(SshCas::Content)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SshCas::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum SshCas {
+    Table,
+    Uid,
+    UserUid,
+    Title,
+    Fingerprint,
+    Content,
+    CreatedAt,
+}
@@ -0,0 +1,98 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Fingerprints used to hash the key text, these are the ones `ssh-keygen -l` prints.
+/// Rows whose content is not a `<type> <base64>` pair keep theirs and never match.
+fn fingerprint_sql(table: &str) -> String {
+    format!(
+        "UPDATE {table} SET fingerprint = 'SHA256:' || \
+         rtrim(encode(sha256(decode(split_part(content, ' ', 2), 'base64')), 'base64'), '=') \
+         WHERE content ~ '^[a-z0-9@.-]+ [A-Za-z0-9+/]+={{0,2}}$' \
+         AND length(split_part(content, ' ', 2)) % 4 = 0;"
+    )
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SshKeys::Table)
+                    .add_column(ColumnDef::new(SshKeys::LastUsedAt).timestamp().null())
+                    .add_column(ColumnDef::new(SshKeys::ExpiresAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await?;
+        for table in ["ssh_keys", "deploy_keys", "ssh_cas"] {
+            manager
+                .get_connection()
+                .execute_unprepared(&fingerprint_sql(table))
+                .await?;
+        }
+        // the SSH server looks keys up by fingerprint
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ssh_keys_fingerprint")
+                    .table(SshKeys::Table)
+                    .col(SshKeys::Fingerprint)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_deploy_keys_fingerprint")
+                    .table(DeployKeys::Table)
+                    .col(DeployKeys::Fingerprint)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // fingerprints stay in the new format, the old ones cannot be told from the key
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_deploy_keys_fingerprint")
+                    .table(DeployKeys::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_ssh_keys_fingerprint")
+                    .table(SshKeys::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SshKeys::Table)
+                    .drop_column(SshKeys::LastUsedAt)
+                    .drop_column(SshKeys::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum SshKeys {
+    Table,
+    Fingerprint,
+    LastUsedAt,
+    ExpiresAt,
+}
+
+#[derive(Iden)]
+pub enum DeployKeys {
+    Table,
+    Fingerprint,
+}
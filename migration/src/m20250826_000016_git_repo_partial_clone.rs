@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // upload-pack only accepts partial clone filters for repositories that opted in
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(
+                        ColumnDef::new(GitRepo::PartialClone)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::PartialClone)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum GitRepo {
+    Table,
+    PartialClone,
+}
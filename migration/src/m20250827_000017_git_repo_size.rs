@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // bytes of git objects on disk, measured after each push and checked against quotas
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(
+                        ColumnDef::new(GitRepo::Size)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::Size)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum GitRepo {
+    Table,
+    Size,
+}
@@ -0,0 +1,133 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // housekeeping jobs, queued by the scheduler or by hand and kept as history
+        manager
+            .create_table(
+                Table::create()
+                    .table(RepoMaintenanceJobs::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::Uid)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::RepoUid)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::Tasks)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::Trigger)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::Status)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RepoMaintenanceJobs::Error).text().null())
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::StartedAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoMaintenanceJobs::FinishedAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_repo_maintenance_jobs_repo")
+                    .table(RepoMaintenanceJobs::Table)
+                    .col(RepoMaintenanceJobs::RepoUid)
+                    .col(RepoMaintenanceJobs::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_repo_maintenance_jobs_status")
+                    .table(RepoMaintenanceJobs::Table)
+                    .col(RepoMaintenanceJobs::Status)
+                    .col(RepoMaintenanceJobs::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+        // what the scheduler goes by
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(
+                        ColumnDef::new(GitRepo::PushesSinceMaintenance)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column(ColumnDef::new(GitRepo::MaintainedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::PushesSinceMaintenance)
+                    .drop_column(GitRepo::MaintainedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(RepoMaintenanceJobs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum RepoMaintenanceJobs {
+    Table,
+    Uid,
+    RepoUid,
+    Tasks,
+    Trigger,
+    Status,
+    Error,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+}
+
+#[derive(Iden)]
+enum GitRepo {
+    Table,
+    PushesSinceMaintenance,
+    MaintainedAt,
+}
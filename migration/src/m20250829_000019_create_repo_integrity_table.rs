@@ -0,0 +1,101 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // what each verification of a repository found, kept as history
+        manager
+            .create_table(
+                Table::create()
+                    .table(RepoIntegrityReports::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RepoIntegrityReports::Uid)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoIntegrityReports::RepoUid)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoIntegrityReports::Trigger)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoIntegrityReports::Healthy)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoIntegrityReports::Findings)
+                            .json()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoIntegrityReports::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_repo_integrity_reports_repo")
+                    .table(RepoIntegrityReports::Table)
+                    .col(RepoIntegrityReports::RepoUid)
+                    .col(RepoIntegrityReports::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+        // when the periodic verification last took the repository
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(ColumnDef::new(GitRepo::VerifiedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::VerifiedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(RepoIntegrityReports::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum RepoIntegrityReports {
+    Table,
+    Uid,
+    RepoUid,
+    Trigger,
+    Healthy,
+    Findings,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum GitRepo {
+    Table,
+    VerifiedAt,
+}
@@ -0,0 +1,113 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // moves of repositories between storages, with the progress of the running one
+        manager
+            .create_table(
+                Table::create()
+                    .table(RepoStorageMigrations::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::Uid)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::RepoUid)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::Source)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::Target)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::Status)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::Phase)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::BytesTotal)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::BytesCopied)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(RepoStorageMigrations::Error).text().null())
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::UpdatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(RepoStorageMigrations::FinishedAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_repo_storage_migrations_repo")
+                    .table(RepoStorageMigrations::Table)
+                    .col(RepoStorageMigrations::RepoUid)
+                    .col(RepoStorageMigrations::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RepoStorageMigrations::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum RepoStorageMigrations {
+    Table,
+    Uid,
+    RepoUid,
+    Source,
+    Target,
+    Status,
+    Phase,
+    BytesTotal,
+    BytesCopied,
+    Error,
+    CreatedAt,
+    UpdatedAt,
+    FinishedAt,
+}
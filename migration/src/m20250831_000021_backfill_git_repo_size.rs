@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // repositories from before sizes were measured hold 0 and would pass any quota; only
+        // the git service knows where they live, so each gets a cheap maintenance job, which
+        // records the size when it finishes
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "INSERT INTO repo_maintenance_jobs (uid, repo_uid, tasks, trigger, status, created_at)
+                SELECT uuid_generate_v4(), r.uid, 'loose-objects', 'backfill', 'queued', NOW()
+                FROM git_repo r
+                WHERE r.size = 0
+                AND NOT EXISTS (
+                    SELECT 1 FROM repo_maintenance_jobs j
+                    WHERE j.repo_uid = r.uid AND j.status IN ('queued', 'running')
+                );",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "DELETE FROM repo_maintenance_jobs WHERE trigger = 'backfill' AND status = 'queued';",
+            )
+            .await?;
+        Ok(())
+    }
+}